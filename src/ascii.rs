@@ -0,0 +1,129 @@
+//! A renderer that draws nodes as characters for snapshot testing
+use super::*;
+
+type FillFn<E> = Box<dyn Fn(&NodeInner<E>) -> char>;
+
+/// Renders nodes as a grid of characters.
+///
+/// Each node fills its draw rect with the character returned
+/// by the fill function, later nodes drawing over earlier ones.
+/// The root node is drawn too and cells outside of every node
+/// are `#`. This makes it possible to test layouts against plain
+/// strings without a real renderer.
+///
+/// Requires the `ascii` feature.
+///
+/// # Example
+///
+/// ```rust
+/// # #[macro_use] extern crate fungui;
+/// # use fungui::*;
+/// enum MyExt {}
+///
+/// impl Extension for MyExt {
+///     type NodeData = ();
+///     type Value = ();
+///     fn new_data() {}
+///     fn style_properties<'a, F>(_prop: F)
+///         where F: FnMut(StaticKey) + 'a
+///     {}
+///     fn update_data(_: &Styles<Self>, _: &NodeChain<Self>, _: &Rule<Self>, _: &mut ()) -> DirtyFlags {
+///         DirtyFlags::empty()
+///     }
+///     fn reset_unset_data(_: &FnvHashSet<StaticKey>, _: &mut ()) -> DirtyFlags {
+///         DirtyFlags::empty()
+///     }
+/// }
+///
+/// # fn main() {
+/// let mut manager: Manager<MyExt> = Manager::new();
+/// manager.load_styles("example", r#"
+/// panel {
+///     x = 1,
+///     width = 2,
+///     height = 1,
+/// }
+/// "#).unwrap();
+/// manager.add_node(node!(panel(selected=true)));
+/// manager.layout(4, 1);
+///
+/// // Draw selected nodes differently to the rest
+/// let mut render = AsciiRenderer::new(4, 1, |node: &NodeInner<MyExt>| {
+///     if node.get_property::<bool>("selected").unwrap_or(false) {
+///         '@'
+///     } else {
+///         '~'
+///     }
+/// });
+/// manager.render(&mut render);
+/// assert_eq!(render.as_string(), "~@@~");
+/// # }
+/// ```
+pub struct AsciiRenderer<E: Extension> {
+    width: usize,
+    height: usize,
+    data: Vec<char>,
+    offsets: Vec<(i32, i32)>,
+    fill: FillFn<E>,
+}
+
+impl <E: Extension> AsciiRenderer<E> {
+    /// Creates a renderer with the given size in characters using
+    /// `fill` to pick the character each node is drawn with.
+    pub fn new<F>(width: usize, height: usize, fill: F) -> AsciiRenderer<E>
+        where F: Fn(&NodeInner<E>) -> char + 'static
+    {
+        AsciiRenderer {
+            width,
+            height,
+            data: vec!['#'; width * height],
+            offsets: vec![(0, 0)],
+            fill: Box::new(fill),
+        }
+    }
+
+    /// Returns the rendered characters with each row on
+    /// its own line, empty if the renderer has no columns
+    pub fn as_string(&self) -> String {
+        if self.width == 0 {
+            return String::new();
+        }
+        let mut out = String::with_capacity((self.width + 1) * self.height);
+        for line in self.data.chunks(self.width) {
+            out.extend(line);
+            out.push('\n');
+        }
+        out.pop();
+        out
+    }
+}
+
+impl <E: Extension> RenderVisitor<E> for AsciiRenderer<E> {
+    fn visit(&mut self, node: &mut NodeInner<E>) {
+        let c = (self.fill)(node);
+        let (lx, ly) = self.offsets.last().cloned().expect("Missing offset data");
+        let ox = node.draw_rect.x + lx;
+        let oy = node.draw_rect.y + ly;
+        for y in oy .. oy + node.draw_rect.height {
+            for x in ox .. ox + node.draw_rect.width {
+                if x < 0 || y < 0 || x as usize >= self.width || y as usize >= self.height {
+                    continue;
+                }
+                match node.clip_rect {
+                    Some(clip) if !clip.contains(x, y) => continue,
+                    _ => {},
+                }
+                let idx = x as usize + y as usize * self.width;
+                self.data[idx] = c;
+            }
+        }
+        self.offsets.push((
+            ox + node.scroll_position.0 as i32,
+            oy + node.scroll_position.1 as i32,
+        ));
+    }
+
+    fn visit_end(&mut self, _node: &mut NodeInner<E>) {
+        self.offsets.pop();
+    }
+}
@@ -0,0 +1,138 @@
+use super::*;
+
+use serde::{Serialize, Serializer, Deserialize};
+use serde::ser::Error as SerError;
+use std::collections::BTreeMap;
+
+/// A serializable description of a node and its children.
+///
+/// Created via [`Node::to_description`](struct.Node.html#method.to_description)
+/// and turned back into a node with
+/// [`Node::from_description`](struct.Node.html#method.from_description).
+/// This is the format used by `Node::to_json` and `Node::from_json`
+/// so tools that can't parse the description format can still
+/// build node trees.
+///
+/// Requires the `serde` feature.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(tag = "kind", rename_all = "lowercase")]
+pub enum NodeDescription {
+    /// An element node
+    Element {
+        /// The name of the element
+        name: String,
+        /// The properties on the element
+        #[serde(default)]
+        properties: BTreeMap<String, PropertyValue>,
+        /// The child nodes of the element
+        #[serde(default)]
+        children: Vec<NodeDescription>,
+    },
+    /// A text node
+    Text {
+        /// The text of the node
+        text: String,
+        /// The properties on the text node
+        #[serde(default)]
+        properties: BTreeMap<String, PropertyValue>,
+    },
+}
+
+/// A property value in a `NodeDescription` tagged by its type
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(tag = "type", content = "value", rename_all = "lowercase")]
+pub enum PropertyValue {
+    /// A boolean value
+    Bool(bool),
+    /// An integer value
+    Int(i32),
+    /// A floating point value.
+    ///
+    /// Fails to serialize if the value isn't finite as formats
+    /// like JSON can't represent it.
+    Float(#[serde(serialize_with = "serialize_finite")] f64),
+    /// A string value
+    String(String),
+    /// A color value
+    Color(u8, u8, u8, u8),
+    /// A list of values
+    List(Vec<PropertyValue>),
+}
+
+fn serialize_finite<S: Serializer>(v: &f64, s: S) -> Result<S::Ok, S::Error> {
+    if v.is_finite() {
+        s.serialize_f64(*v)
+    } else {
+        Err(S::Error::custom(format!("Can't encode the non-finite float {}", v)))
+    }
+}
+
+impl PropertyValue {
+    /// Converts a node value into a property value.
+    ///
+    /// Returns `None` for actions and extension values, including
+    /// ones nested in lists.
+    fn from_value<E: Extension>(v: &Value<E>) -> Option<PropertyValue> {
+        Some(match *v {
+            Value::Boolean(v) => PropertyValue::Bool(v),
+            Value::Integer(v) => PropertyValue::Int(v),
+            Value::Float(v) => PropertyValue::Float(v),
+            Value::String(ref v) => PropertyValue::String(v.clone()),
+            Value::Color(r, g, b, a) => PropertyValue::Color(r, g, b, a),
+            Value::List(ref v) => PropertyValue::List(
+                v.iter().map(PropertyValue::from_value).collect::<Option<_>>()?
+            ),
+            Value::Action(_) | Value::ExtValue(_) => return None,
+        })
+    }
+
+    fn into_value<E: Extension>(self) -> Value<E> {
+        match self {
+            PropertyValue::Bool(v) => Value::Boolean(v),
+            PropertyValue::Int(v) => Value::Integer(v),
+            PropertyValue::Float(v) => Value::Float(v),
+            PropertyValue::String(v) => Value::String(v),
+            PropertyValue::Color(r, g, b, a) => Value::Color(r, g, b, a),
+            PropertyValue::List(v) => Value::List(v.into_iter().map(PropertyValue::into_value).collect()),
+        }
+    }
+}
+
+impl <E: Extension> Node<E> {
+    /// Creates a description of this node and its children.
+    ///
+    /// Properties holding actions or extension values can't be
+    /// described and are skipped.
+    pub fn to_description(&self) -> NodeDescription {
+        let inner = self.inner.borrow();
+        let properties = inner.properties.iter()
+            .filter_map(|(k, v)| PropertyValue::from_value(v).map(|v| (k.clone(), v)))
+            .collect();
+        match inner.value {
+            NodeValue::Element(ref e) => NodeDescription::Element {
+                name: e.name.clone(),
+                properties,
+                children: e.children.iter().map(|v| v.to_description()).collect(),
+            },
+            NodeValue::Text(ref t) => NodeDescription::Text {
+                text: t.clone(),
+                properties,
+            },
+        }
+    }
+
+    /// Creates a node and its children from a description
+    pub fn from_description(desc: NodeDescription) -> Node<E> {
+        let (node, properties, children) = match desc {
+            NodeDescription::Element { name, properties, children } => (Node::new(name), properties, children),
+            NodeDescription::Text { text, properties } => (Node::new_text(text), properties, Vec::new()),
+        };
+        node.inner.borrow_mut().properties = properties.into_iter()
+            .map(|(k, v)| (k, v.into_value()))
+            .collect();
+        for c in children {
+            node.add_child(Node::from_description(c));
+        }
+        node
+    }
+}
@@ -0,0 +1,170 @@
+use super::*;
+
+#[cfg(feature = "serde")]
+use serde::Serialize;
+
+/// A snapshot of the laid out node tree of a manager.
+///
+/// Created via [`Manager::export_layout`](struct.Manager.html#method.export_layout).
+/// This contains enough information to reconstruct the positions
+/// of every node without access to the renderer which makes it
+/// useful for external inspectors and diffing layouts.
+///
+/// When the `serde` feature is enabled this can be serialized.
+#[derive(Debug, Clone, PartialEq)]
+#[cfg_attr(feature = "serde", derive(Serialize))]
+pub struct LayoutDump {
+    /// The nodes attached to the root of the manager
+    pub nodes: Vec<NodeDump>,
+}
+
+/// A snapshot of a single laid out node
+#[derive(Debug, Clone, PartialEq)]
+#[cfg_attr(feature = "serde", derive(Serialize))]
+pub struct NodeDump {
+    /// The name of the element, `None` for text nodes
+    pub name: Option<String>,
+    /// The text of the node, `None` for elements
+    pub text: Option<String>,
+    /// The properties on the node sorted by key
+    pub properties: Vec<(String, DumpValue)>,
+    /// The name of the layout used by this node
+    pub layout: &'static str,
+    /// The position of the node relative to its parent
+    pub draw_rect: Rect,
+    /// The absolute clipped position of the node, if visible
+    pub render_position: Option<Rect>,
+    /// The scroll offset applied to the children of this node
+    pub scroll_position: (f32, f32),
+    /// Whether this node clips its children
+    pub clip_overflow: bool,
+    /// The child nodes of this node
+    pub children: Vec<NodeDump>,
+}
+
+/// A property value in a `NodeDump`
+#[derive(Debug, Clone, PartialEq)]
+#[cfg_attr(feature = "serde", derive(Serialize))]
+pub enum DumpValue {
+    /// A boolean value
+    Boolean(bool),
+    /// An integer value
+    Integer(i32),
+    /// A floating point value
+    Float(f64),
+    /// A string value
+    String(String),
+    /// A color value
+    Color(u8, u8, u8, u8),
+    /// A list of values
+    List(Vec<DumpValue>),
+    /// An action with its name and arguments
+    Action(String, Vec<DumpValue>),
+    /// An extension defined value.
+    ///
+    /// The value itself isn't included as extensions
+    /// aren't required to be serializable
+    ExtValue,
+}
+
+impl <'a, E> From<&'a Value<E>> for DumpValue
+    where E: Extension
+{
+    fn from(v: &'a Value<E>) -> DumpValue {
+        match *v {
+            Value::Boolean(v) => DumpValue::Boolean(v),
+            Value::Integer(v) => DumpValue::Integer(v),
+            Value::Float(v) => DumpValue::Float(v),
+            Value::String(ref v) => DumpValue::String(v.clone()),
+            Value::Color(r, g, b, a) => DumpValue::Color(r, g, b, a),
+            Value::List(ref v) => DumpValue::List(v.iter().map(DumpValue::from).collect()),
+            Value::Action(ref v) => DumpValue::Action(v.name.clone(), v.args.iter().map(DumpValue::from).collect()),
+            Value::ExtValue(_) => DumpValue::ExtValue,
+        }
+    }
+}
+
+// Text longer than this is cut short in `Node::dump`
+const DUMP_TEXT_LENGTH: usize = 20;
+
+impl <E: Extension> Node<E> {
+    /// Returns an indented view of this node and its children
+    /// for debugging.
+    ///
+    /// Each line has the node's name (or quoted text, cut short
+    /// if long), its properties sorted by key and its `draw_rect`
+    /// from the last layout as `[x, y, width x height]`.
+    pub fn dump(&self) -> String {
+        let mut out = String::new();
+        self.dump_into(0, &mut out);
+        out
+    }
+
+    pub(crate) fn dump_into(&self, depth: usize, out: &mut String) {
+        use std::fmt::Write;
+        let inner = self.inner.borrow();
+        for _ in 0 .. depth {
+            out.push_str("  ");
+        }
+        match inner.value {
+            NodeValue::Element(ref e) => out.push_str(&e.name),
+            NodeValue::Text(ref t) => {
+                let mut text: String = t.chars().take(DUMP_TEXT_LENGTH).collect();
+                if text.len() < t.len() {
+                    text.push_str("...");
+                }
+                let _ = write!(out, "{:?}", text);
+            },
+        }
+        if !inner.properties.is_empty() {
+            let mut properties: Vec<_> = inner.properties.iter().collect();
+            properties.sort_by(|a, b| a.0.cmp(b.0));
+            out.push('(');
+            for (idx, (k, v)) in properties.into_iter().enumerate() {
+                if idx != 0 {
+                    out.push_str(", ");
+                }
+                let _ = write!(out, "{}={}", k, Expr::Value(v.clone()));
+            }
+            out.push(')');
+        }
+        let r = inner.draw_rect;
+        let _ = writeln!(out, " [{}, {}, {}x{}]", r.x, r.y, r.width, r.height);
+        if let NodeValue::Element(ref e) = inner.value {
+            for c in &e.children {
+                c.dump_into(depth + 1, out);
+            }
+        }
+    }
+
+    pub(crate) fn export_layout(&self) -> NodeDump {
+        let inner = self.inner.borrow();
+        let mut properties: Vec<_> = inner.properties.iter()
+            .map(|(k, v)| (k.clone(), DumpValue::from(v)))
+            .collect();
+        properties.sort_by(|a, b| a.0.cmp(&b.0));
+        let (name, text, children) = match inner.value {
+            NodeValue::Element(ref e) => (
+                Some(e.name.clone()),
+                None,
+                e.children.iter().map(|v| v.export_layout()).collect(),
+            ),
+            NodeValue::Text(ref t) => (None, Some(t.clone()), Vec::new()),
+        };
+        NodeDump {
+            name,
+            text,
+            properties,
+            layout: inner.layout.name(),
+            draw_rect: inner.draw_rect,
+            render_position: if inner.done_layout {
+                self.render_position()
+            } else {
+                None
+            },
+            scroll_position: inner.scroll_position,
+            clip_overflow: inner.clip_overflow,
+            children,
+        }
+    }
+}
@@ -1,4 +1,5 @@
-
+use super::{Extension, Node};
+use syntax;
 
 /// The error type used in FunGUI
 #[derive(Debug)]
@@ -8,6 +9,11 @@ pub enum Error<'a> {
         /// The name of the variable
         name: &'a str,
     },
+    /// An unknown function was used
+    UnknownFunction {
+        /// The name of the function
+        name: &'a str,
+    },
     /// An incompatible type was used with the given
     /// operator
     IncompatibleTypeOp {
@@ -43,5 +49,165 @@ pub enum Error<'a> {
         position: i32,
         /// The parameter name
         name: &'static str,
+    },
+    /// The node isn't a child of the node it was
+    /// removed from
+    NodeNotFound,
+    /// `inherit` was used but the parent node has
+    /// no value for the key
+    NothingToInherit {
+        /// The inherited key
+        key: &'static str,
+    },
+}
+
+impl <'a> Error<'a> {
+    /// Converts the error into one that doesn't borrow
+    /// anything.
+    ///
+    /// Borrowed names are moved into a `Custom` error.
+    pub(crate) fn into_owned(self) -> Error<'static> {
+        match self {
+            Error::UnknownVariable{name} => Error::Custom {
+                reason: format!("Unknown variable: {}", name),
+            },
+            Error::UnknownFunction{name} => Error::Custom {
+                reason: format!("Unknown function: {}", name),
+            },
+            Error::IncompatibleTypeOp{op, ty} => Error::IncompatibleTypeOp{op, ty},
+            Error::IncompatibleTypesOp{op, left_ty, right_ty} => Error::IncompatibleTypesOp{op, left_ty, right_ty},
+            Error::Custom{reason} => Error::Custom{reason},
+            Error::CustomStatic{reason} => Error::CustomStatic{reason},
+            Error::MissingParameter{position, name} => Error::MissingParameter{position, name},
+            Error::NodeNotFound => Error::NodeNotFound,
+            Error::NothingToInherit{key} => Error::NothingToInherit{key},
+        }
+    }
+}
+
+/// An error produced while evaluating a style property
+/// during an update.
+///
+/// Collected by the manager and returned as `LayoutError::Style` via
+/// [`Manager::take_errors`](struct.Manager.html#method.take_errors).
+#[derive(Debug)]
+pub struct StyleError {
+    /// The name of the property that failed to evaluate
+    pub key: &'static str,
+    /// The expression that failed to evaluate
+    pub expression: String,
+    /// The reason the evaluation failed
+    pub error: Error<'static>,
+}
+
+impl ::std::fmt::Display for StyleError {
+    fn fmt(&self, f: &mut ::std::fmt::Formatter) -> ::std::fmt::Result {
+        write!(f, "Failed to evaluate `{}` ({}): {:?}", self.key, self.expression, self.error)
+    }
+}
+
+/// A possible problem found while loading styles.
+///
+/// Unlike parse errors these don't stop the styles from loading.
+/// Returned by
+/// [`Manager::take_style_warnings`](struct.Manager.html#method.take_style_warnings)
+/// and can be displayed with [`format_error`](fn.format_error.html)
+/// using the position.
+#[derive(Debug, Clone)]
+pub struct StyleWarning {
+    /// The name the styles were loaded with
+    pub name: String,
+    /// The location of the problem in the styles
+    pub position: syntax::Position,
+    /// A description of the problem
+    pub message: String,
+}
+
+/// A broken invariant in the node tree.
+///
+/// Returned by
+/// [`Manager::check_invariants`](struct.Manager.html#method.check_invariants).
+pub enum InvariantViolation<E: Extension> {
+    /// The node's parent link doesn't point to the node
+    /// that contains it
+    InvalidParent {
+        /// The node with the invalid link
+        node: Node<E>,
+        /// The node that contains it
+        parent: Node<E>,
+    },
+    /// The node is the child of more than one node
+    MultipleParents {
+        /// The node with multiple parents
+        node: Node<E>,
+    },
+    /// The node contains itself
+    Cycle {
+        /// The node that contains itself
+        node: Node<E>,
+    },
+}
+
+/// A problem found while laying out nodes.
+///
+/// Collected by the manager and returned via
+/// [`Manager::take_errors`](struct.Manager.html#method.take_errors).
+pub enum LayoutError<E: Extension> {
+    /// A style property failed to evaluate and was skipped
+    Style(StyleError),
+    /// The layout was still changing after the maximum number
+    /// of passes. Usually caused by a size that depends on itself
+    /// through `parent_width`/`parent_height`.
+    ///
+    /// See [`Manager::set_max_layout_passes`](struct.Manager.html#method.set_max_layout_passes)
+    NotConverged {
+        /// The number of passes that were run
+        passes: usize,
+        /// The nodes that would have been updated again
+        nodes: Vec<Node<E>>,
+    },
+}
+
+fn describe_node<E: Extension>(node: &Node<E>) -> String {
+    match node.name() {
+        Some(name) => name,
+        None => format!("@text({:?})", node.text().as_ref().map_or("", |v| &**v)),
+    }
+}
+
+impl <E: Extension> ::std::fmt::Debug for InvariantViolation<E> {
+    fn fmt(&self, f: &mut ::std::fmt::Formatter) -> ::std::fmt::Result {
+        match self {
+            InvariantViolation::InvalidParent{node, parent} => write!(
+                f, "InvalidParent {{ node: {}, parent: {} }}",
+                describe_node(node), describe_node(parent),
+            ),
+            InvariantViolation::MultipleParents{node} => write!(f, "MultipleParents {{ node: {} }}", describe_node(node)),
+            InvariantViolation::Cycle{node} => write!(f, "Cycle {{ node: {} }}", describe_node(node)),
+        }
+    }
+}
+
+impl <E: Extension> ::std::fmt::Debug for LayoutError<E> {
+    fn fmt(&self, f: &mut ::std::fmt::Formatter) -> ::std::fmt::Result {
+        match self {
+            LayoutError::Style(err) => f.debug_tuple("Style").field(err).finish(),
+            LayoutError::NotConverged{passes, nodes} => f.debug_struct("NotConverged")
+                .field("passes", passes)
+                .field("nodes", &nodes.iter().map(describe_node).collect::<Vec<_>>())
+                .finish(),
+        }
+    }
+}
+
+impl <E: Extension> ::std::fmt::Display for LayoutError<E> {
+    fn fmt(&self, f: &mut ::std::fmt::Formatter) -> ::std::fmt::Result {
+        match self {
+            LayoutError::Style(err) => err.fmt(f),
+            LayoutError::NotConverged{passes, nodes} => write!(
+                f, "The layout didn't settle after {} passes, {} nodes were still changing",
+                passes, nodes.len(),
+            ),
+        }
     }
-}
\ No newline at end of file
+}
@@ -11,7 +11,11 @@ pub enum Expr<E: Extension> {
     Value(Value<E>),
     Variable(String),
     ParentRect(RectPart),
+    ViewportRect(RectPart),
     VariableParent(usize, String),
+    // `var("name", default)`
+    VariableOr(String, Box<Expr<E>>),
+    Inherit(StaticKey),
 
     Neg(Box<Expr<E>>),
     Not(Box<Expr<E>>),
@@ -36,6 +40,92 @@ pub enum Expr<E: Extension> {
     FloatToInt(Box<Expr<E>>),
 
     Call(StaticKey, Vec<Expr<E>>),
+    // A call compiled without the manager's functions, looked
+    // up by name when evaluated
+    CallByName(String, Vec<Expr<E>>),
+}
+
+impl <E> Clone for Expr<E>
+    where E: Extension
+{
+    fn clone(&self) -> Expr<E> {
+        let b = |e: &Expr<E>| Box::new(e.clone());
+        match self {
+            Expr::Value(v) => Expr::Value(v.clone()),
+            Expr::Variable(n) => Expr::Variable(n.clone()),
+            Expr::ParentRect(RectPart::Width) => Expr::ParentRect(RectPart::Width),
+            Expr::ParentRect(RectPart::Height) => Expr::ParentRect(RectPart::Height),
+            Expr::ViewportRect(RectPart::Width) => Expr::ViewportRect(RectPart::Width),
+            Expr::ViewportRect(RectPart::Height) => Expr::ViewportRect(RectPart::Height),
+            Expr::VariableParent(d, n) => Expr::VariableParent(*d, n.clone()),
+            Expr::VariableOr(n, e) => Expr::VariableOr(n.clone(), b(e)),
+            Expr::Inherit(k) => Expr::Inherit(*k),
+
+            Expr::Neg(e) => Expr::Neg(b(e)),
+            Expr::Not(e) => Expr::Not(b(e)),
+            Expr::And(l, r) => Expr::And(b(l), b(r)),
+            Expr::Or(l, r) => Expr::Or(b(l), b(r)),
+            Expr::Xor(l, r) => Expr::Xor(b(l), b(r)),
+
+            Expr::Equal(l, r) => Expr::Equal(b(l), b(r)),
+            Expr::NotEqual(l, r) => Expr::NotEqual(b(l), b(r)),
+            Expr::LessEqual(l, r) => Expr::LessEqual(b(l), b(r)),
+            Expr::GreaterEqual(l, r) => Expr::GreaterEqual(b(l), b(r)),
+            Expr::Less(l, r) => Expr::Less(b(l), b(r)),
+            Expr::Greater(l, r) => Expr::Greater(b(l), b(r)),
+
+            Expr::Add(l, r) => Expr::Add(b(l), b(r)),
+            Expr::Sub(l, r) => Expr::Sub(b(l), b(r)),
+            Expr::Mul(l, r) => Expr::Mul(b(l), b(r)),
+            Expr::Div(l, r) => Expr::Div(b(l), b(r)),
+            Expr::Rem(l, r) => Expr::Rem(b(l), b(r)),
+
+            Expr::IntToFloat(e) => Expr::IntToFloat(b(e)),
+            Expr::FloatToInt(e) => Expr::FloatToInt(b(e)),
+
+            Expr::Call(k, args) => Expr::Call(*k, args.clone()),
+            Expr::CallByName(n, args) => Expr::CallByName(n.clone(), args.clone()),
+        }
+    }
+}
+
+/// A style expression set on a node via `Node::set_expr_property`.
+///
+/// Compiled without the manager so function calls are looked up
+/// by name and every unknown variable reads a property.
+pub(crate) struct NodeExpr<E: Extension> {
+    // `None` for `inherit` as the manager's key is needed to
+    // build `Expr::Inherit`
+    pub expr: Option<Expr<E>>,
+    pub uses_parent_size: bool,
+}
+
+impl <E> Clone for NodeExpr<E>
+    where E: Extension
+{
+    fn clone(&self) -> NodeExpr<E> {
+        NodeExpr {
+            expr: self.expr.clone(),
+            uses_parent_size: self.uses_parent_size,
+        }
+    }
+}
+
+impl <E> NodeExpr<E>
+    where E: Extension
+{
+    pub fn compile(e: syntax::style::ExprType) -> Result<NodeExpr<E>, syntax::PError> {
+        let mut uses_parent_size = false;
+        let expr = if is_inherit(&FnvHashMap::default(), &e) {
+            None
+        } else {
+            Some(Expr::from_style(None, &FnvHashMap::default(), &mut uses_parent_size, e)?)
+        };
+        Ok(NodeExpr {
+            expr,
+            uses_parent_size,
+        })
+    }
 }
 
 impl <E> Display for Expr<E>
@@ -47,10 +137,31 @@ impl <E> Display for Expr<E>
             Expr::Value(Value::Integer(v)) => write!(f, "{}", v),
             Expr::Value(Value::Float(v)) => write!(f, "{}", v),
             Expr::Value(Value::String(v)) => write!(f, "{:?}", v),
+            Expr::Value(Value::Color(r, g, b, a)) => write!(f, "#{:02X}{:02X}{:02X}{:02X}", r, g, b, a),
+            Expr::Value(Value::List(v)) => {
+                write!(f, "list(")?;
+                for (idx, val) in v.iter().enumerate() {
+                    if idx != 0 {
+                        write!(f, ", ")?;
+                    }
+                    write!(f, "{}", Expr::Value(val.clone()))?;
+                }
+                write!(f, ")")
+            },
+            Expr::Value(Value::Action(a)) => {
+                write!(f, "action({:?}", a.name)?;
+                for val in &a.args {
+                    write!(f, ", {}", Expr::Value(val.clone()))?;
+                }
+                write!(f, ")")
+            },
             Expr::Value(Value::ExtValue(_)) => write!(f, "EXT"),
             Expr::Variable(var) => write!(f, "{}", var),
             Expr::VariableParent(d, var) => write!(f, "{}({})", var, d),
+            Expr::VariableOr(var, e) => write!(f, "var({:?}, {})", var, e),
+            Expr::Inherit(_) => write!(f, "inherit"),
             Expr::ParentRect(part) => write!(f, "parent({:?})", part),
+            Expr::ViewportRect(part) => write!(f, "viewport({:?})", part),
 
             Expr::Neg(e) => write!(f, "-({})", e),
             Expr::Not(e) => write!(f, "!({})", e),
@@ -74,23 +185,86 @@ impl <E> Display for Expr<E>
             Expr::IntToFloat(e) => write!(f, "float({})", e),
             Expr::FloatToInt(e) => write!(f, "int({})", e),
 
-            Expr::Call(name, exprs) => {
-                write!(f, "{}(", name.0)?;
-                for e in exprs {
-                    write!(f, "{}, ", e)?;
-                }
-                write!(f, ")")
-            },
+            Expr::Call(StaticKey(name), exprs) => write_call(f, name, exprs),
+            Expr::CallByName(name, exprs) => write_call(f, name, exprs),
         }
     }
 }
 
-fn get_ty<E: Extension>(v: &Value<E>) -> &'static str {
+fn write_call<E: Extension>(f: &mut Formatter, name: &str, exprs: &[Expr<E>]) -> FResult {
+    write!(f, "{}(", name)?;
+    for e in exprs {
+        write!(f, "{}, ", e)?;
+    }
+    write!(f, ")")
+}
+
+/// Lazily evaluates the arguments of a function call.
+///
+/// `nth` is overridden so that skipped arguments are never
+/// evaluated, allowing functions like `if` to short-circuit.
+struct CallArgs<'a, E: Extension + 'a> {
+    args: ::std::slice::Iter<'a, Expr<E>>,
+    styles: &'a Styles<E>,
+    node: &'a NodeChain<'a, E>,
+}
+
+impl <'a, E> Iterator for CallArgs<'a, E>
+    where E: Extension
+{
+    type Item = Result<Value<E>, Error<'a>>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        let (styles, node) = (self.styles, self.node);
+        self.args.next().map(|v| v.eval(styles, node))
+    }
+
+    fn nth(&mut self, n: usize) -> Option<Self::Item> {
+        let (styles, node) = (self.styles, self.node);
+        self.args.nth(n).map(|v| v.eval(styles, node))
+    }
+
+    fn size_hint(&self) -> (usize, Option<usize>) {
+        self.args.size_hint()
+    }
+}
+
+/// Converts the value into a string in the same format
+/// as it would be written in a style rule, strings are
+/// left unquoted.
+///
+/// Returns `None` for lists and extension values.
+pub(crate) fn value_to_string<E: Extension>(v: &Value<E>) -> Option<String> {
+    Some(match v {
+        Value::Boolean(v) => v.to_string(),
+        Value::Integer(v) => v.to_string(),
+        Value::Float(v) => format!("{:?}", v),
+        Value::String(v) => v.clone(),
+        Value::Color(r, g, b, a) => format!("#{:02X}{:02X}{:02X}{:02X}", r, g, b, a),
+        Value::List(_) | Value::Action(_) | Value::ExtValue(_) => return None,
+    })
+}
+
+/// Passes the operator on to the extension if either side is
+/// an extension value, otherwise fails due to incompatible types.
+fn ext_binary_op<'a, E: Extension>(op: BinaryOp, name: &'static str, a: Value<E>, b: Value<E>) -> Result<Value<E>, Error<'a>> {
+    if matches!((&a, &b), (Value::ExtValue(_), _) | (_, Value::ExtValue(_))) {
+        if let Some(v) = E::ext_binary_op(op, &a, &b) {
+            return Ok(v);
+        }
+    }
+    Err(Error::IncompatibleTypesOp{op: name, left_ty: get_ty(&a), right_ty: get_ty(&b)})
+}
+
+pub(crate) fn get_ty<E: Extension>(v: &Value<E>) -> &'static str {
     match v {
         Value::Integer(_) => "integer",
         Value::Float(_) => "float",
         Value::Boolean(_) => "boolean",
         Value::String(_) => "string",
+        Value::Color(..) => "color",
+        Value::List(_) => "list",
+        Value::Action(_) => "action",
         Value::ExtValue(_) => "extension value",
     }
 }
@@ -105,10 +279,20 @@ impl <E> Expr<E>
             Expr::VariableParent(depth, ref n) => {
                 let mut node = node;
                 for _ in 0 .. depth {
-                    node = node.parent.expect("Missing parent, shouldn't happen");
+                    // Only missing when read via `parent.name` or
+                    // `ancestor(depth).name` near the root
+                    node = node.parent.ok_or(Error::CustomStatic{reason: "No parent"})?;
                 }
                 return node.properties.get(n).cloned().ok_or(Error::UnknownVariable{name: n});
             },
+            Expr::VariableOr(ref n, ref e) => match node.properties.get(n) {
+                Some(v) => v.clone(),
+                None => return e.eval(styles, node),
+            },
+            Expr::Inherit(key) => return node.parent
+                .ok_or(Error::CustomStatic{reason: "No parent"})
+                .and_then(|p| p.resolved.get(&key).cloned()
+                    .ok_or(Error::NothingToInherit{key: key.0})),
             Expr::ParentRect(RectPart::Width) => return node.parent
                 .ok_or(Error::CustomStatic{reason: "No parent"})
                 .map(|v| v.draw_rect.width)
@@ -117,6 +301,8 @@ impl <E> Expr<E>
                 .ok_or(Error::CustomStatic{reason: "No parent"})
                 .map(|v| v.draw_rect.height)
                 .map(Value::Integer),
+            Expr::ViewportRect(RectPart::Width) => Value::Integer(styles.viewport.0),
+            Expr::ViewportRect(RectPart::Height) => Value::Integer(styles.viewport.1),
             Expr::Neg(ref e) => match e.eval(styles, node)? {
                 Value::Integer(a) => Value::Integer(-a),
                 Value::Float(a) => Value::Float(-a),
@@ -176,40 +362,184 @@ impl <E> Expr<E>
             Expr::Add(ref a, ref b) => match (a.eval(styles, node)?, b.eval(styles, node)?) {
                 (Value::Integer(a), Value::Integer(b)) => Value::Integer(a + b),
                 (Value::Float(a), Value::Float(b)) => Value::Float(a + b),
-                (a,b) => return Err(Error::IncompatibleTypesOp{op: "+", left_ty: get_ty(&a), right_ty: get_ty(&b)}),
+                (a,b) => return ext_binary_op(BinaryOp::Add, "+", a, b),
             },
             Expr::Sub(ref a, ref b) => match (a.eval(styles, node)?, b.eval(styles, node)?) {
                 (Value::Integer(a), Value::Integer(b)) => Value::Integer(a - b),
                 (Value::Float(a), Value::Float(b)) => Value::Float(a - b),
-                (a,b) => return Err(Error::IncompatibleTypesOp{op: "-", left_ty: get_ty(&a), right_ty: get_ty(&b)}),
+                (a,b) => return ext_binary_op(BinaryOp::Sub, "-", a, b),
             },
             Expr::Mul(ref a, ref b) => match (a.eval(styles, node)?, b.eval(styles, node)?) {
                 (Value::Integer(a), Value::Integer(b)) => Value::Integer(a * b),
                 (Value::Float(a), Value::Float(b)) => Value::Float(a * b),
-                (a,b) => return Err(Error::IncompatibleTypesOp{op: "*", left_ty: get_ty(&a), right_ty: get_ty(&b)}),
+                (a,b) => return ext_binary_op(BinaryOp::Mul, "*", a, b),
             },
             Expr::Div(ref a, ref b) => match (a.eval(styles, node)?, b.eval(styles, node)?) {
                 (Value::Integer(a), Value::Integer(b)) => Value::Integer(a / b),
                 (Value::Float(a), Value::Float(b)) => Value::Float(a / b),
-                (a,b) => return Err(Error::IncompatibleTypesOp{op: "/", left_ty: get_ty(&a), right_ty: get_ty(&b)}),
+                (a,b) => return ext_binary_op(BinaryOp::Div, "/", a, b),
             },
             Expr::Rem(ref a, ref b) => match (a.eval(styles, node)?, b.eval(styles, node)?) {
                 (Value::Integer(a), Value::Integer(b)) => Value::Integer(a % b),
                 (Value::Float(a), Value::Float(b)) => Value::Float(a % b),
-                (a,b) => return Err(Error::IncompatibleTypesOp{op: "%", left_ty: get_ty(&a), right_ty: get_ty(&b)}),
+                (a,b) => return ext_binary_op(BinaryOp::Rem, "%", a, b),
             },
             Expr::Call(ref name, ref args) => {
                 let func = styles.funcs.get(name).expect("Missing func");
 
-                let mut args = args.iter()
-                    .map(move |v| v.eval(styles, node));
-                return func(&mut args)
-            }
+                let mut args = CallArgs {
+                    args: args.iter(),
+                    styles,
+                    node,
+                };
+                return func(node, &mut args)
+            },
+            Expr::CallByName(ref name, ref args) => {
+                let func = styles.static_keys.get(name.as_str())
+                    .and_then(|k| styles.funcs.get(k))
+                    .ok_or(Error::UnknownFunction{name})?;
+
+                let mut args = CallArgs {
+                    args: args.iter(),
+                    styles,
+                    node,
+                };
+                return func(node, &mut args)
+            },
         })
     }
 
-    pub fn from_style<'a>(
+    /// Adds the names of the properties and variables read by this
+    /// expression to `out`
+    pub fn collect_reads(&self, out: &mut FnvHashSet<String>) {
+        match self {
+            Expr::Value(_) => {},
+            Expr::Variable(n) | Expr::VariableParent(_, n) => {
+                out.insert(n.clone());
+            },
+            Expr::ParentRect(RectPart::Width) => { out.insert("parent_width".into()); },
+            Expr::ParentRect(RectPart::Height) => { out.insert("parent_height".into()); },
+            Expr::ViewportRect(RectPart::Width) => { out.insert("viewport_width".into()); },
+            Expr::ViewportRect(RectPart::Height) => { out.insert("viewport_height".into()); },
+            Expr::Inherit(_) => { out.insert("inherit".into()); },
+            Expr::VariableOr(n, e) => {
+                out.insert(n.clone());
+                e.collect_reads(out);
+            },
+            Expr::Neg(e)
+            | Expr::Not(e)
+            | Expr::IntToFloat(e)
+            | Expr::FloatToInt(e) => e.collect_reads(out),
+            Expr::And(l, r)
+            | Expr::Or(l, r)
+            | Expr::Xor(l, r)
+            | Expr::Equal(l, r)
+            | Expr::NotEqual(l, r)
+            | Expr::LessEqual(l, r)
+            | Expr::GreaterEqual(l, r)
+            | Expr::Less(l, r)
+            | Expr::Greater(l, r)
+            | Expr::Add(l, r)
+            | Expr::Sub(l, r)
+            | Expr::Mul(l, r)
+            | Expr::Div(l, r)
+            | Expr::Rem(l, r) => {
+                l.collect_reads(out);
+                r.collect_reads(out);
+            },
+            Expr::Call(_, args)
+            | Expr::CallByName(_, args) => for a in args {
+                a.collect_reads(out);
+            },
+        }
+    }
+
+    /// Checks that every variable and function used in the expression
+    /// exists.
+    ///
+    /// Variables are checked against the passed properties.
+    pub fn check_names<'a>(
+        funcs: &FnvHashMap<StaticKey, SFunc<E>>,
         static_keys: &FnvHashMap<&'static str, StaticKey>,
+        properties: &FnvHashMap<String, Value<E>>,
+        e: &syntax::style::ExprType<'a>,
+    ) -> Result<(), Error<'a>> {
+        use syntax::style::Expr as SExpr;
+        use syntax::style::Value as SVal;
+        let check = |e| Expr::check_names(funcs, static_keys, properties, e);
+        match e.expr {
+            SExpr::Value(SVal::Variable(ref v)) => match v.name {
+                "parent_width" | "parent_height"
+                | "viewport_width" | "viewport_height" => {},
+                name if properties.contains_key(name) => {},
+                name => return Err(Error::UnknownVariable{name}),
+            },
+            SExpr::Value(_) => {},
+            SExpr::Neg(ref e)
+            | SExpr::Not(ref e)
+            | SExpr::IntToFloat(ref e)
+            | SExpr::FloatToInt(ref e) => check(e)?,
+            SExpr::And(ref l, ref r)
+            | SExpr::Or(ref l, ref r)
+            | SExpr::Xor(ref l, ref r)
+            | SExpr::Add(ref l, ref r)
+            | SExpr::Sub(ref l, ref r)
+            | SExpr::Mul(ref l, ref r)
+            | SExpr::Div(ref l, ref r)
+            | SExpr::Rem(ref l, ref r)
+            | SExpr::Equal(ref l, ref r)
+            | SExpr::NotEqual(ref l, ref r)
+            | SExpr::LessEqual(ref l, ref r)
+            | SExpr::GreaterEqual(ref l, ref r)
+            | SExpr::Less(ref l, ref r)
+            | SExpr::Greater(ref l, ref r) => {
+                check(l)?;
+                check(r)?;
+            },
+            SExpr::Call(ref name, ref params) if name.name == "var" => {
+                // The name is checked when compiled, the property
+                // doesn't have to exist
+                for p in params.iter().skip(1) {
+                    check(p)?;
+                }
+            },
+            SExpr::Call(ref name, ref params) => {
+                match static_keys.get(name.name) {
+                    Some(key) if funcs.contains_key(key) => {},
+                    _ => return Err(Error::UnknownFunction{name: name.name}),
+                }
+                for p in params {
+                    check(p)?;
+                }
+            },
+        }
+        Ok(())
+    }
+
+    /// Converts the expression assigned to `key` by a rule.
+    ///
+    /// A bare `inherit` becomes `Expr::Inherit` unless a
+    /// property with that name is in scope.
+    pub fn from_style_key<'a>(
+        key: StaticKey,
+        static_keys: Option<&FnvHashMap<&'static str, StaticKey>>,
+        replacements: &FnvHashMap<String, (usize, String)>,
+        uses_parent_size: &mut bool,
+        e: syntax::style::ExprType<'a>
+    ) -> Result<Expr<E>, syntax::PError<'a>> {
+        if is_inherit(replacements, &e) {
+            return Ok(Expr::Inherit(key));
+        }
+        Expr::from_style(static_keys, replacements, uses_parent_size, e)
+    }
+
+    /// Compiles a parsed expression.
+    ///
+    /// Without `static_keys` the expression is compiled for a node
+    /// instead of a rule: function calls are looked up by name when
+    /// evaluated and every unknown variable reads a property.
+    pub fn from_style<'a>(
+        static_keys: Option<&FnvHashMap<&'static str, StaticKey>>,
         replacements: &FnvHashMap<String, (usize, String)>,
         uses_parent_size: &mut bool,
         e: syntax::style::ExprType<'a>
@@ -222,6 +552,7 @@ impl <E> Expr<E>
                 SVal::Integer(i) => Expr::Value(Value::Integer(i)),
                 SVal::Float(f) => Expr::Value(Value::Float(f)),
                 SVal::String(s) => Expr::Value(Value::String(unescape(s))),
+                SVal::Color(r, g, b, a) => Expr::Value(Value::Color(r, g, b, a)),
                 SVal::Variable(v) => if let Some(r) = replacements.get(v.name) {
                     if r.0 == 0 {
                         Expr::Variable(r.1.clone())
@@ -229,16 +560,28 @@ impl <E> Expr<E>
                         Expr::VariableParent(r.0, r.1.clone())
                     }
                 } else {
-                    *uses_parent_size = true;
                     match v.name {
-                        "parent_width" => Expr::ParentRect(RectPart::Width),
-                        "parent_height" => Expr::ParentRect(RectPart::Height),
+                        // Only changes when the manager is resized which
+                        // already updates every node
+                        "viewport_width" => Expr::ViewportRect(RectPart::Width),
+                        "viewport_height" => Expr::ViewportRect(RectPart::Height),
+                        "parent_width" => {
+                            *uses_parent_size = true;
+                            Expr::ParentRect(RectPart::Width)
+                        },
+                        "parent_height" => {
+                            *uses_parent_size = true;
+                            Expr::ParentRect(RectPart::Height)
+                        },
+                        name if static_keys.is_none() => Expr::Variable(name.to_owned()),
                         _ => return Err(syntax::Errors::new(
                             v.position.into(),
                             syntax::Error::Message(syntax::Info::Borrowed("Unknown variable")),
                         ))
                     }
                 },
+                SVal::ParentVariable(0, v) => Expr::Variable(v.name.to_owned()),
+                SVal::ParentVariable(depth, v) => Expr::VariableParent(depth, v.name.to_owned()),
             },
             SExpr::Neg(e) => Expr::Neg(Box::new(Expr::from_style(static_keys, replacements, uses_parent_size, *e)?)),
 
@@ -305,19 +648,124 @@ impl <E> Expr<E>
             SExpr::IntToFloat(e) => Expr::IntToFloat(Box::new(Expr::from_style(static_keys, replacements, uses_parent_size, *e)?)),
             SExpr::FloatToInt(e) => Expr::FloatToInt(Box::new(Expr::from_style(static_keys, replacements, uses_parent_size, *e)?)),
 
+            SExpr::Call(ref name, ref params) if name.name == "var" && params.len() != 2 => return Err(syntax::Errors::new(
+                name.position.into(),
+                syntax::Error::Message(syntax::Info::Borrowed("`var` expects a property name and a default value")),
+            )),
+            SExpr::Call(name, params) if name.name == "var" => {
+                let mut params = params.into_iter();
+                let var = params.next().expect("Missing name");
+                let default = params.next().expect("Missing default");
+                match var.expr {
+                    SExpr::Value(SVal::String(n)) => Expr::VariableOr(
+                        unescape(n),
+                        Box::new(Expr::from_style(static_keys, replacements, uses_parent_size, default)?),
+                    ),
+                    _ => return Err(syntax::Errors::new(
+                        var.position.into(),
+                        syntax::Error::Message(syntax::Info::Borrowed("The property name passed to `var` must be a string")),
+                    )),
+                }
+            },
             SExpr::Call(name, params) => {
-                let key = static_keys.get(name.name).ok_or_else(|| {
-                    syntax::Errors::new(
-                        name.position.into(),
-                        syntax::Error::Message(syntax::Info::Borrowed("Unknown function")),
-                    )
-                })?;
-                Expr::Call(*key, params.into_iter()
+                let key = match static_keys {
+                    Some(keys) => Some(*keys.get(name.name).ok_or_else(|| {
+                        syntax::Errors::new(
+                            name.position.into(),
+                            syntax::Error::Message(syntax::Info::Borrowed("Unknown function")),
+                        )
+                    })?),
+                    None => None,
+                };
+                let params = params.into_iter()
                     .map(|v| Expr::from_style(static_keys, replacements, uses_parent_size, v))
-                    .collect::<Result<Vec<_>, _>>()?
-                )
+                    .collect::<Result<Vec<_>, _>>()?;
+                match key {
+                    Some(key) => Expr::Call(key, params),
+                    None => Expr::CallByName(name.name.to_owned(), params),
+                }
             },
 
         })
     }
+}
+
+/// Returns whether the expression is just the `inherit` keyword
+pub(crate) fn is_inherit(replacements: &FnvHashMap<String, (usize, String)>, e: &syntax::style::ExprType) -> bool {
+    use syntax::style::Expr as SExpr;
+    use syntax::style::Value as SVal;
+    match e.expr {
+        SExpr::Value(SVal::Variable(ref v)) => v.name == "inherit" && !replacements.contains_key(v.name),
+        _ => false,
+    }
+}
+
+/// Infers the type of the constant parts of an expression.
+///
+/// Reports arithmetic between integer and float constants which
+/// would otherwise only fail once evaluated. Returns `None` when
+/// the type depends on variables or functions.
+pub(crate) fn check_types<'a, F>(e: &syntax::style::ExprType<'a>, report: &mut F) -> Option<&'static str>
+    where F: FnMut(syntax::Position, String)
+{
+    use syntax::style::Expr as SExpr;
+    use syntax::style::Value as SVal;
+    let (op, l, r) = match e.expr {
+        SExpr::Value(SVal::Boolean(_)) => return Some("boolean"),
+        SExpr::Value(SVal::Integer(_)) => return Some("integer"),
+        SExpr::Value(SVal::Float(_)) => return Some("float"),
+        SExpr::Value(SVal::String(_)) => return Some("string"),
+        SExpr::Value(SVal::Color(..)) => return Some("color"),
+        SExpr::Value(SVal::Variable(_))
+        | SExpr::Value(SVal::ParentVariable(..)) => return None,
+        SExpr::Neg(ref e) => return check_types(e, report),
+        SExpr::IntToFloat(ref e) => {
+            check_types(e, report);
+            return Some("float");
+        },
+        SExpr::FloatToInt(ref e) => {
+            check_types(e, report);
+            return Some("integer");
+        },
+        SExpr::Not(ref e) => {
+            check_types(e, report);
+            return Some("boolean");
+        },
+        SExpr::And(ref l, ref r)
+        | SExpr::Or(ref l, ref r)
+        | SExpr::Xor(ref l, ref r)
+        | SExpr::Equal(ref l, ref r)
+        | SExpr::NotEqual(ref l, ref r)
+        | SExpr::LessEqual(ref l, ref r)
+        | SExpr::GreaterEqual(ref l, ref r)
+        | SExpr::Less(ref l, ref r)
+        | SExpr::Greater(ref l, ref r) => {
+            check_types(l, report);
+            check_types(r, report);
+            return Some("boolean");
+        },
+        SExpr::Call(_, ref args) => {
+            for a in args {
+                check_types(a, report);
+            }
+            return None;
+        },
+        SExpr::Add(ref l, ref r) => ("+", l, r),
+        SExpr::Sub(ref l, ref r) => ("-", l, r),
+        SExpr::Mul(ref l, ref r) => ("*", l, r),
+        SExpr::Div(ref l, ref r) => ("/", l, r),
+        SExpr::Rem(ref l, ref r) => ("%", l, r),
+    };
+    match (check_types(l, report), check_types(r, report)) {
+        (Some(a), Some(b)) if a == b => Some(a),
+        (Some(a @ "integer"), Some(b @ "float"))
+        | (Some(a @ "float"), Some(b @ "integer")) => {
+            report(e.position, format!(
+                "Can't use `{}` on {} and {}, convert one side with `float(...)` or `int(...)`",
+                op, a, b,
+            ));
+            None
+        },
+        _ => None,
+    }
 }
\ No newline at end of file
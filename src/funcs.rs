@@ -0,0 +1,510 @@
+//! Builtin functions that can be used in style rules
+use super::*;
+
+type Args<'a, 'b, E> = &'b mut (dyn Iterator<Item=FResult<'a, Value<E>>> + 'a);
+
+/// Returns the next parameter or an error if it is missing
+fn param<'a, E>(args: Args<'a, '_, E>, position: i32, name: &'static str) -> FResult<'a, Value<E>>
+    where E: Extension
+{
+    args.next()
+        .ok_or(Error::MissingParameter {
+            position,
+            name,
+        })
+        .and_then(|v| v)
+}
+
+/// `clamp(val, low, high)`
+///
+/// Returns `val` limited to the range `low` to `high`. If `low` is
+/// greater than `high` then `low` is returned.
+pub(crate) fn clamp<'a, E>(args: Args<'a, '_, E>) -> FResult<'a, Value<E>>
+    where E: Extension
+{
+    let val = param(args, 0, "value")?;
+    let low = param(args, 1, "low")?;
+    let high = param(args, 2, "high")?;
+    Ok(match (val, low, high) {
+        (Value::Integer(v), Value::Integer(l), Value::Integer(h)) => Value::Integer(
+            if l > h { l } else { v.max(l).min(h) }
+        ),
+        (Value::Float(v), Value::Float(l), Value::Float(h)) => Value::Float(
+            if l > h { l } else { v.max(l).min(h) }
+        ),
+        (ref v @ Value::Integer(_), ref o, _)
+        | (ref v @ Value::Float(_), ref o, _) if get_ty(v) != get_ty(o) => return Err(Error::IncompatibleTypesOp {
+            op: "clamp",
+            left_ty: get_ty(v),
+            right_ty: get_ty(o),
+        }),
+        (ref v @ Value::Integer(_), _, ref o)
+        | (ref v @ Value::Float(_), _, ref o) => return Err(Error::IncompatibleTypesOp {
+            op: "clamp",
+            left_ty: get_ty(v),
+            right_ty: get_ty(o),
+        }),
+        (ref v, _, _) => return Err(Error::IncompatibleTypeOp {
+            op: "clamp",
+            ty: get_ty(v),
+        }),
+    })
+}
+
+fn fold_numbers<'a, E, FI, FF>(
+    args: Args<'a, '_, E>,
+    op: &'static str,
+    fi: FI, ff: FF,
+) -> FResult<'a, Value<E>>
+    where E: Extension,
+          FI: Fn(i32, i32) -> i32,
+          FF: Fn(f64, f64) -> f64,
+{
+    let mut current = param(args, 0, "value")?;
+    match current {
+        Value::Integer(_) | Value::Float(_) => {},
+        ref v => return Err(Error::IncompatibleTypeOp {
+            op,
+            ty: get_ty(v),
+        }),
+    }
+    // At least two values are required
+    let mut next = Some(param(args, 1, "value"));
+    while let Some(val) = next.take().or_else(|| args.next()) {
+        current = match (current, val?) {
+            (Value::Integer(a), Value::Integer(b)) => Value::Integer(fi(a, b)),
+            (Value::Float(a), Value::Float(b)) => Value::Float(ff(a, b)),
+            (a, b) => return Err(Error::IncompatibleTypesOp {
+                op,
+                left_ty: get_ty(&a),
+                right_ty: get_ty(&b),
+            }),
+        };
+    }
+    Ok(current)
+}
+
+/// `min(a, b, ...)`
+///
+/// Returns the smallest of two or more integers or floats.
+pub(crate) fn min<'a, E>(args: Args<'a, '_, E>) -> FResult<'a, Value<E>>
+    where E: Extension
+{
+    fold_numbers(args, "min", |a, b| a.min(b), |a, b| a.min(b))
+}
+
+/// `max(a, b, ...)`
+///
+/// Returns the largest of two or more integers or floats.
+pub(crate) fn max<'a, E>(args: Args<'a, '_, E>) -> FResult<'a, Value<E>>
+    where E: Extension
+{
+    fold_numbers(args, "max", |a, b| a.max(b), |a, b| a.max(b))
+}
+
+/// `lerp(a, b, t)`
+///
+/// Linearly interpolates between the floats `a` and `b` by
+/// `t`. `t` is clamped to the range `0.0` to `1.0`.
+pub(crate) fn lerp<'a, E>(args: Args<'a, '_, E>) -> FResult<'a, Value<E>>
+    where E: Extension
+{
+    let a = param(args, 0, "a")?;
+    let b = param(args, 1, "b")?;
+    let t = param(args, 2, "t")?;
+    match (a, b, t) {
+        (Value::Float(a), Value::Float(b), Value::Float(t)) => {
+            let t = t.clamp(0.0, 1.0);
+            Ok(Value::Float(a + (b - a) * t))
+        },
+        (Value::Float(_), Value::Float(_), ref t) => Err(Error::IncompatibleTypeOp {
+            op: "lerp",
+            ty: get_ty(t),
+        }),
+        (ref a @ Value::Float(_), ref b, _) => Err(Error::IncompatibleTypesOp {
+            op: "lerp",
+            left_ty: get_ty(a),
+            right_ty: get_ty(b),
+        }),
+        (ref a, _, _) => Err(Error::IncompatibleTypeOp {
+            op: "lerp",
+            ty: get_ty(a),
+        }),
+    }
+}
+
+fn map_float<'a, E, F>(args: Args<'a, '_, E>, op: &'static str, f: F) -> FResult<'a, Value<E>>
+    where E: Extension,
+          F: Fn(f64) -> f64,
+{
+    match param(args, 0, "value")? {
+        Value::Float(v) => Ok(Value::Float(f(v))),
+        Value::Integer(_) => Err(Error::Custom {
+            reason: format!("`{}` only accepts floats, integers are already whole numbers", op),
+        }),
+        ref v => Err(Error::IncompatibleTypeOp {
+            op,
+            ty: get_ty(v),
+        }),
+    }
+}
+
+/// `round(val)`
+///
+/// Rounds the float to the nearest whole number. Half-way
+/// values are rounded away from zero, e.g. `-2.5` becomes
+/// `-3.0`.
+pub(crate) fn round<'a, E>(args: Args<'a, '_, E>) -> FResult<'a, Value<E>>
+    where E: Extension
+{
+    map_float(args, "round", f64::round)
+}
+
+/// `floor(val)`
+///
+/// Rounds the float down to the nearest whole number.
+pub(crate) fn floor<'a, E>(args: Args<'a, '_, E>) -> FResult<'a, Value<E>>
+    where E: Extension
+{
+    map_float(args, "floor", f64::floor)
+}
+
+/// `ceil(val)`
+///
+/// Rounds the float up to the nearest whole number.
+pub(crate) fn ceil<'a, E>(args: Args<'a, '_, E>) -> FResult<'a, Value<E>>
+    where E: Extension
+{
+    map_float(args, "ceil", f64::ceil)
+}
+
+/// Returns the next parameter as a float or an error if it
+/// is missing or another type
+fn float_param<'a, E>(args: Args<'a, '_, E>, position: i32, name: &'static str, op: &'static str) -> FResult<'a, f64>
+    where E: Extension
+{
+    match param(args, position, name)? {
+        Value::Float(v) => Ok(v),
+        ref v => Err(Error::IncompatibleTypeOp {
+            op,
+            ty: get_ty(v),
+        }),
+    }
+}
+
+/// `sin(val)`
+///
+/// Returns the sine of the float in radians.
+pub(crate) fn sin<'a, E>(args: Args<'a, '_, E>) -> FResult<'a, Value<E>>
+    where E: Extension
+{
+    float_param(args, 0, "value", "sin").map(|v| Value::Float(v.sin()))
+}
+
+/// `cos(val)`
+///
+/// Returns the cosine of the float in radians.
+pub(crate) fn cos<'a, E>(args: Args<'a, '_, E>) -> FResult<'a, Value<E>>
+    where E: Extension
+{
+    float_param(args, 0, "value", "cos").map(|v| Value::Float(v.cos()))
+}
+
+/// `tan(val)`
+///
+/// Returns the tangent of the float in radians.
+pub(crate) fn tan<'a, E>(args: Args<'a, '_, E>) -> FResult<'a, Value<E>>
+    where E: Extension
+{
+    float_param(args, 0, "value", "tan").map(|v| Value::Float(v.tan()))
+}
+
+/// `sqrt(val)`
+///
+/// Returns the square root of the float. Fails if the
+/// float is negative.
+pub(crate) fn sqrt<'a, E>(args: Args<'a, '_, E>) -> FResult<'a, Value<E>>
+    where E: Extension
+{
+    let v = float_param(args, 0, "value", "sqrt")?;
+    if v < 0.0 {
+        return Err(Error::Custom {
+            reason: format!("Can't take the square root of the negative number {}", v),
+        });
+    }
+    Ok(Value::Float(v.sqrt()))
+}
+
+/// `pow(base, exp)`
+///
+/// Raises the float `base` to the power of the float `exp`.
+/// Fails if the result isn't a number, e.g. a negative base
+/// with a fractional exponent.
+pub(crate) fn pow<'a, E>(args: Args<'a, '_, E>) -> FResult<'a, Value<E>>
+    where E: Extension
+{
+    let base = float_param(args, 0, "base", "pow")?;
+    let exp = float_param(args, 1, "exp", "pow")?;
+    let v = base.powf(exp);
+    if v.is_nan() {
+        return Err(Error::Custom {
+            reason: format!("{} to the power of {} isn't a number", base, exp),
+        });
+    }
+    Ok(Value::Float(v))
+}
+
+/// `abs(val)`
+///
+/// Returns the absolute value of an integer or float.
+pub(crate) fn abs<'a, E>(args: Args<'a, '_, E>) -> FResult<'a, Value<E>>
+    where E: Extension
+{
+    match param(args, 0, "value")? {
+        Value::Integer(v) => Ok(Value::Integer(v.wrapping_abs())),
+        Value::Float(v) => Ok(Value::Float(v.abs())),
+        ref v => Err(Error::IncompatibleTypeOp {
+            op: "abs",
+            ty: get_ty(v),
+        }),
+    }
+}
+
+/// `if(cond, a, b)`
+///
+/// Returns `a` if `cond` is true otherwise `b`. Only the
+/// returned branch is evaluated, the other is skipped.
+pub(crate) fn if_<'a, E>(args: Args<'a, '_, E>) -> FResult<'a, Value<E>>
+    where E: Extension
+{
+    let cond = match param(args, 0, "condition")? {
+        Value::Boolean(b) => b,
+        ref v => return Err(Error::IncompatibleTypeOp {
+            op: "if",
+            ty: get_ty(v),
+        }),
+    };
+    if cond {
+        param(args, 1, "true")
+    } else {
+        // Skips the true branch without evaluating it
+        args.nth(1)
+            .ok_or(Error::MissingParameter {
+                position: 2,
+                name: "false",
+            })
+            .and_then(|v| v)
+    }
+}
+
+/// `concat(a, b, ...)`
+///
+/// Joins any number of strings together.
+pub(crate) fn concat<'a, E>(args: Args<'a, '_, E>) -> FResult<'a, Value<E>>
+    where E: Extension
+{
+    let mut out = String::new();
+    for val in args {
+        match val? {
+            Value::String(s) => out.push_str(&s),
+            ref v => return Err(Error::IncompatibleTypeOp {
+                op: "concat",
+                ty: get_ty(v),
+            }),
+        }
+    }
+    Ok(Value::String(out))
+}
+
+/// `list(a, b, ...)`
+///
+/// Collects any number of values into a list.
+pub(crate) fn list<'a, E>(args: Args<'a, '_, E>) -> FResult<'a, Value<E>>
+    where E: Extension
+{
+    args.collect::<Result<_, _>>()
+        .map(Value::List)
+}
+
+/// `action(name, ...)`
+///
+/// Creates an action with the given name passing the
+/// rest of the parameters as its arguments.
+pub(crate) fn action<'a, E>(args: Args<'a, '_, E>) -> FResult<'a, Value<E>>
+    where E: Extension
+{
+    let name = match param(args, 0, "name")? {
+        Value::String(s) => s,
+        ref v => return Err(Error::IncompatibleTypeOp {
+            op: "action",
+            ty: get_ty(v),
+        }),
+    };
+    Ok(Value::Action(Action {
+        name,
+        args: args.collect::<Result<_, _>>()?,
+    }))
+}
+
+/// `format(template, ...)`
+///
+/// Replaces each `{}` in the template with the following
+/// parameters in order. `{{` and `}}` can be used to output
+/// a literal `{` or `}`.
+pub(crate) fn format<'a, E>(args: Args<'a, '_, E>) -> FResult<'a, Value<E>>
+    where E: Extension
+{
+    let template = match param(args, 0, "template")? {
+        Value::String(s) => s,
+        ref v => return Err(Error::IncompatibleTypeOp {
+            op: "format",
+            ty: get_ty(v),
+        }),
+    };
+    let mut out = String::with_capacity(template.len());
+    let mut position = 1;
+    let mut chars = template.chars().peekable();
+    while let Some(c) = chars.next() {
+        match (c, chars.peek()) {
+            ('{', Some('{')) | ('}', Some('}')) => {
+                chars.next();
+                out.push(c);
+            },
+            ('{', Some('}')) => {
+                chars.next();
+                let val = param(args, position, "value")?;
+                match value_to_string(&val) {
+                    Some(v) => out.push_str(&v),
+                    None => return Err(Error::IncompatibleTypeOp {
+                        op: "format",
+                        ty: get_ty(&val),
+                    }),
+                }
+                position += 1;
+            },
+            _ => out.push(c),
+        }
+    }
+    Ok(Value::String(out))
+}
+
+/// Returns the first parameter as a property name
+fn property_name<'a, E>(args: Args<'a, '_, E>, op: &'static str) -> FResult<'a, String>
+    where E: Extension
+{
+    match param(args, 0, "name")? {
+        Value::String(s) => Ok(s),
+        ref v => Err(Error::IncompatibleTypeOp {
+            op,
+            ty: get_ty(v),
+        }),
+    }
+}
+
+/// `has_property(name)`
+///
+/// Returns whether the node has the named property set.
+pub(crate) fn has_property<'a, E>(node: &NodeChain<E>, args: Args<'a, '_, E>) -> FResult<'a, Value<E>>
+    where E: Extension
+{
+    let name = property_name(args, "has_property")?;
+    Ok(Value::Boolean(node.properties.contains_key(&name)))
+}
+
+/// `prop_type(name)`
+///
+/// Returns the type of the named property on the node as a
+/// string, e.g. `"integer"`, or `"ext"` for extension values.
+/// Fails if the property isn't set.
+pub(crate) fn prop_type<'a, E>(node: &NodeChain<E>, args: Args<'a, '_, E>) -> FResult<'a, Value<E>>
+    where E: Extension
+{
+    let name = property_name(args, "prop_type")?;
+    match node.properties.get(&name) {
+        Some(Value::ExtValue(_)) => Ok(Value::String("ext".into())),
+        Some(v) => Ok(Value::String(get_ty(v).into())),
+        None => Err(Error::Custom {
+            reason: format!("Property `{}` isn't set", name),
+        }),
+    }
+}
+
+/// `str(val)`
+///
+/// Converts a boolean, integer, float, string or color to
+/// a string in the same format `format` uses.
+pub(crate) fn str<'a, E>(args: Args<'a, '_, E>) -> FResult<'a, Value<E>>
+    where E: Extension
+{
+    let val = param(args, 0, "value")?;
+    match value_to_string(&val) {
+        Some(v) => Ok(Value::String(v)),
+        None => Err(Error::IncompatibleTypeOp {
+            op: "str",
+            ty: get_ty(&val),
+        }),
+    }
+}
+
+/// Returns the first parameter as a string to be parsed
+fn parse_param<'a, E>(args: Args<'a, '_, E>, op: &'static str) -> FResult<'a, String>
+    where E: Extension
+{
+    match param(args, 0, "value")? {
+        Value::String(s) => Ok(s),
+        ref v => Err(Error::IncompatibleTypeOp {
+            op,
+            ty: get_ty(v),
+        }),
+    }
+}
+
+/// `to_int(val)`
+///
+/// Parses the string as a decimal integer. Surrounding
+/// whitespace is ignored.
+pub(crate) fn to_int<'a, E>(args: Args<'a, '_, E>) -> FResult<'a, Value<E>>
+    where E: Extension
+{
+    let s = parse_param(args, "to_int")?;
+    s.trim().parse()
+        .map(Value::Integer)
+        .map_err(|_| Error::Custom {
+            reason: format!("{:?} isn't a valid integer", s),
+        })
+}
+
+/// `to_float(val)`
+///
+/// Parses the string as a float. Surrounding whitespace
+/// is ignored.
+pub(crate) fn to_float<'a, E>(args: Args<'a, '_, E>) -> FResult<'a, Value<E>>
+    where E: Extension
+{
+    let s = parse_param(args, "to_float")?;
+    s.trim().parse()
+        .map(Value::Float)
+        .map_err(|_| Error::Custom {
+            reason: format!("{:?} isn't a valid float", s),
+        })
+}
+
+/// `theme(name)`
+///
+/// Returns the theme value with the given name.
+pub(crate) fn theme<'a, E>(values: &FnvHashMap<String, Value<E>>, args: Args<'a, '_, E>) -> FResult<'a, Value<E>>
+    where E: Extension
+{
+    let name = match param(args, 0, "name")? {
+        Value::String(s) => s,
+        ref v => return Err(Error::IncompatibleTypeOp {
+            op: "theme",
+            ty: get_ty(v),
+        }),
+    };
+    values.get(&name)
+        .cloned()
+        .ok_or_else(|| Error::Custom {
+            reason: format!("Unknown theme value: {}", name),
+        })
+}
@@ -139,10 +139,26 @@ pub trait LayoutEngine<E>
         DirtyFlags::empty()
     }
 
+    /// Reports the intrinsic width and height of this node based
+    /// on its children, `None` for either if it has none.
+    ///
+    /// Called before `start_layout` with the children already
+    /// measured. Their intrinsic sizes are available through
+    /// `NodeAccess::intrinsic_size`. The reported size is used as
+    /// the node's size unless the parent's layout sets one itself.
+    fn measure(&mut self, _children: ChildAccess<Self, E>) -> (Option<i32>, Option<i32>) {
+        (None, None)
+    }
+
     /// Begins the layout for this node
     ///
-    /// Called after the parent node's layout has called its `do_layout` method
-    fn start_layout(&mut self, _ext: &mut E::NodeData, current: Rect, _flags: DirtyFlags, _children: ChildAccess<Self, E>) -> Rect {
+    /// Called after the parent node's layout has called its `do_layout` method.
+    ///
+    /// `constraints` contains the `min_*`/`max_*` sizes set on this node. `current`
+    /// will already be within them however layouts that size themselves based on
+    /// their children should also respect them. The constraints are applied again
+    /// after `finish_layout` so they will always take priority over explicit sizes.
+    fn start_layout(&mut self, _ext: &mut E::NodeData, current: Rect, _constraints: SizeConstraints, _flags: DirtyFlags, _children: ChildAccess<Self, E>) -> Rect {
         current
     }
 
@@ -165,12 +181,27 @@ pub trait LayoutEngine<E>
     fn finish_layout(&mut self, _ext: &mut E::NodeData, current: Rect, _flags: DirtyFlags, _children: ChildAccess<Self, E>) -> Rect {
         current
     }
+
+    /// Called after `finish_layout` to request extra work from
+    /// the manager
+    ///
+    /// Returning `DirtyFlags::REQUEST_PARENT` marks the parent node's
+    /// properties as changed and runs another update and layout
+    /// pass, the same way a node using `parent_width` does when its
+    /// parent's size changes. Like that loop the passes only stop
+    /// once nothing requests another one, so a layout should only
+    /// request a pass when its result changed since the last one
+    /// otherwise `layout` will never return.
+    fn requested_flags(&mut self) -> DirtyFlags {
+        DirtyFlags::empty()
+    }
 }
 
 /// Provides access to a child node and its stored layout data
 pub struct ChildAccess<'a, L: LayoutEngine<E> + ?Sized, E: Extension + 'a> {
     _l: PhantomData<L>,
     nodes: &'a [Node<E>],
+    text: &'a dyn TextMeasure,
 }
 
 /// Helper struct to split a `RefMut` on a `NodeInner` whilst
@@ -195,6 +226,13 @@ impl <'a, L, E> NodeAccess<'a, L, E>
                 .expect("Child has incorrect data")
         )
     }
+
+    /// Returns the intrinsic width and height reported by the
+    /// child's own layout in `measure`
+    #[inline]
+    pub fn intrinsic_size(&self) -> (Option<i32>, Option<i32>) {
+        self.node.intrinsic_size
+    }
 }
 
 impl <'a, L, E> ChildAccess<'a, L, E>
@@ -221,6 +259,23 @@ impl <'a, L, E> ChildAccess<'a, L, E>
             _l: PhantomData,
         }))
     }
+
+    /// Returns the text measurer set on the manager
+    #[inline]
+    pub fn text_measure(&self) -> &dyn TextMeasure {
+        self.text
+    }
+
+    /// Returns the size of the child's text drawn with the named
+    /// font at the given size, `None` if the child isn't a text node
+    pub fn text_size(&self, idx: usize, font: &str, size: i32) -> Option<(i32, i32)> {
+        let n = self.nodes.get(idx)?;
+        let inner = n.inner.borrow();
+        match inner.value {
+            NodeValue::Text(ref t) => Some(self.text.measure(t, font, size)),
+            NodeValue::Element(_) => None,
+        }
+    }
 }
 
 
@@ -235,10 +290,12 @@ pub(crate) trait BoxLayoutEngine<E>
     fn check_parent_flags(&mut self, flags: DirtyFlags) -> DirtyFlags;
     fn check_child_flags(&mut self, flags: DirtyFlags) -> DirtyFlags;
 
-    fn start_layout(&mut self, _ext: &mut E::NodeData, current: Rect, flags: DirtyFlags, children: &[Node<E>]) -> Rect;
+    fn measure(&mut self, children: &[Node<E>], text: &dyn TextMeasure) -> (Option<i32>, Option<i32>);
+    fn start_layout(&mut self, _ext: &mut E::NodeData, current: Rect, constraints: SizeConstraints, flags: DirtyFlags, children: &[Node<E>], text: &dyn TextMeasure) -> Rect;
     fn do_layout(&mut self, value: &NodeValue<E>, _ext: &mut E::NodeData, data: &mut Box<Any>, current: Rect, flags: DirtyFlags) -> Rect;
     fn do_layout_end(&mut self, value: &NodeValue<E>, _ext: &mut E::NodeData, data: &mut Box<Any>, current: Rect, flags: DirtyFlags) -> Rect;
-    fn finish_layout(&mut self, _ext: &mut E::NodeData, current: Rect, flags: DirtyFlags, children: &[Node<E>]) -> Rect;
+    fn finish_layout(&mut self, _ext: &mut E::NodeData, current: Rect, flags: DirtyFlags, children: &[Node<E>], text: &dyn TextMeasure) -> Rect;
+    fn requested_flags(&mut self) -> DirtyFlags;
 }
 
 impl <E, T> BoxLayoutEngine<E> for T
@@ -278,8 +335,11 @@ impl <E, T> BoxLayoutEngine<E> for T
         LayoutEngine::check_child_flags(self, flags)
     }
 
-    fn start_layout(&mut self, ext: &mut E::NodeData, current: Rect, flags: DirtyFlags, children: &[Node<E>]) -> Rect {
-        LayoutEngine::start_layout(self, ext, current, flags, ChildAccess{_l: PhantomData, nodes: children})
+    fn measure(&mut self, children: &[Node<E>], text: &dyn TextMeasure) -> (Option<i32>, Option<i32>) {
+        LayoutEngine::measure(self, ChildAccess{_l: PhantomData, nodes: children, text})
+    }
+    fn start_layout(&mut self, ext: &mut E::NodeData, current: Rect, constraints: SizeConstraints, flags: DirtyFlags, children: &[Node<E>], text: &dyn TextMeasure) -> Rect {
+        LayoutEngine::start_layout(self, ext, current, constraints, flags, ChildAccess{_l: PhantomData, nodes: children, text})
     }
     fn do_layout(&mut self, value: &NodeValue<E>, ext: &mut E::NodeData, data: &mut Box<Any>, current: Rect, flags: DirtyFlags) -> Rect {
         let data = data.downcast_mut::<<Self as LayoutEngine<E>>::ChildData>().expect("Failed to access child data");
@@ -289,13 +349,21 @@ impl <E, T> BoxLayoutEngine<E> for T
         let data = data.downcast_mut::<<Self as LayoutEngine<E>>::ChildData>().expect("Failed to access child data");
         LayoutEngine::do_layout_end(self, value, ext, data, current, flags)
     }
-    fn finish_layout(&mut self, ext: &mut E::NodeData, current: Rect, flags: DirtyFlags, children: &[Node<E>]) -> Rect {
-        LayoutEngine::finish_layout(self, ext, current, flags, ChildAccess{_l: PhantomData, nodes: children})
+    fn finish_layout(&mut self, ext: &mut E::NodeData, current: Rect, flags: DirtyFlags, children: &[Node<E>], text: &dyn TextMeasure) -> Rect {
+        LayoutEngine::finish_layout(self, ext, current, flags, ChildAccess{_l: PhantomData, nodes: children, text})
+    }
+    fn requested_flags(&mut self) -> DirtyFlags {
+        LayoutEngine::requested_flags(self)
     }
 }
 
+// `min_width`/`max_width` etc aren't handled here as the
+// node applies its `SizeConstraints` after `do_layout`
 #[derive(Default)]
 pub(crate) struct AbsoluteLayout {
+    // The size of the node using this layout, used to
+    // resolve percentage sizes of children
+    pub(crate) size: (i32, i32),
 }
 #[derive(Default)]
 pub(crate) struct AbsoluteLayoutChild {
@@ -303,6 +371,8 @@ pub(crate) struct AbsoluteLayoutChild {
     y: Option<i32>,
     width: Option<i32>,
     height: Option<i32>,
+    width_percent: Option<f64>,
+    height_percent: Option<f64>,
 }
 
 /// The "x" static key used by the absolute layout
@@ -333,6 +403,10 @@ pub static WIDTH: StaticKey = StaticKey("width");
 /// across crates/modules don't always point to the same
 /// value which is a requirement for static keys.
 pub static HEIGHT: StaticKey = StaticKey("height");
+/// The "width_percent" static key used by the absolute layout
+pub static WIDTH_PERCENT: StaticKey = StaticKey("width_percent");
+/// The "height_percent" static key used by the absolute layout
+pub static HEIGHT_PERCENT: StaticKey = StaticKey("height_percent");
 
 impl <E> LayoutEngine<E> for AbsoluteLayout
     where E: Extension
@@ -347,6 +421,8 @@ impl <E> LayoutEngine<E> for AbsoluteLayout
         prop(Y);
         prop(WIDTH);
         prop(HEIGHT);
+        prop(WIDTH_PERCENT);
+        prop(HEIGHT_PERCENT);
     }
 
     fn new_child_data() -> AbsoluteLayoutChild {
@@ -386,6 +462,20 @@ impl <E> LayoutEngine<E> for AbsoluteLayout
                 flags |= DirtyFlags::SIZE;
             }
         });
+        eval!(styles, nc, rule.WIDTH_PERCENT => val => {
+            let new = val.convert();
+            if data.width_percent != new {
+                data.width_percent = new;
+                flags |= DirtyFlags::SIZE;
+            }
+        });
+        eval!(styles, nc, rule.HEIGHT_PERCENT => val => {
+            let new = val.convert();
+            if data.height_percent != new {
+                data.height_percent = new;
+                flags |= DirtyFlags::SIZE;
+            }
+        });
         flags
     }
 
@@ -394,6 +484,14 @@ impl <E> LayoutEngine<E> for AbsoluteLayout
     }
     fn reset_unset_child_data(&mut self, used_keys: &FnvHashSet<StaticKey>, data: &mut Self::ChildData) -> DirtyFlags {
         let mut flags = DirtyFlags::empty();
+        if !used_keys.contains(&WIDTH_PERCENT) && data.width_percent.is_some() {
+            data.width_percent = None;
+            flags |= DirtyFlags::SIZE;
+        }
+        if !used_keys.contains(&HEIGHT_PERCENT) && data.height_percent.is_some() {
+            data.height_percent = None;
+            flags |= DirtyFlags::SIZE;
+        }
         if !used_keys.contains(&X) && data.x.is_some() {
             data.x = None;
             flags |= DirtyFlags::POSITION;
@@ -417,8 +515,467 @@ impl <E> LayoutEngine<E> for AbsoluteLayout
     fn do_layout(&mut self, _value: &NodeValue<E>, _ext: &mut E::NodeData, data: &mut Self::ChildData, mut current: Rect, _flags: DirtyFlags) -> Rect {
         data.x.map(|v| current.x = v);
         data.y.map(|v| current.y = v);
+        // Percentages are resolved here against the size from
+        // `start_layout` so they don't need an extra layout pass
+        // like `parent_width` does. Explicit sizes take priority.
+        if let Some(v) = data.width_percent {
+            current.width = (self.size.0 as f64 * v / 100.0) as i32;
+        }
+        if let Some(v) = data.height_percent {
+            current.height = (self.size.1 as f64 * v / 100.0) as i32;
+        }
         data.width.map(|v| current.width = v);
         data.height.map(|v| current.height = v);
         current
     }
-}
\ No newline at end of file
+
+    fn start_layout(&mut self, _ext: &mut E::NodeData, current: Rect, _constraints: SizeConstraints, _flags: DirtyFlags, _children: ChildAccess<Self, E>) -> Rect {
+        self.size = (current.width, current.height);
+        current
+    }
+}
+
+/// The "spacing" static key used by the row and column layouts
+pub static SPACING: StaticKey = StaticKey("spacing");
+/// The "grow" static key used by the row and column layouts
+pub static GROW: StaticKey = StaticKey("grow");
+/// The "reverse" static key used by the row and column layouts
+pub static REVERSE: StaticKey = StaticKey("reverse");
+
+/// The axis a `LinearLayout` stacks its children along
+pub(crate) trait LinearAxis {
+    const NAME: &'static str;
+    const VERTICAL: bool;
+}
+
+pub(crate) struct Row;
+impl LinearAxis for Row {
+    const NAME: &'static str = "row";
+    const VERTICAL: bool = false;
+}
+
+pub(crate) struct Column;
+impl LinearAxis for Column {
+    const NAME: &'static str = "column";
+    const VERTICAL: bool = true;
+}
+
+/// Stacks children one after another along an axis.
+///
+/// Children are sized by `width`/`height`, defaulting to their
+/// intrinsic size (or zero) along the axis and the full size of
+/// the parent across it. Without a size of its own the layout
+/// sizes itself to fit its children.
+/// Space left over is split between children by their `grow`
+/// weight. With `reverse` set the last child is placed first.
+pub(crate) struct LinearLayout<A> {
+    spacing: i32,
+    reverse: bool,
+    _axis: PhantomData<A>,
+}
+
+impl <A> Default for LinearLayout<A> {
+    fn default() -> LinearLayout<A> {
+        LinearLayout {
+            spacing: 0,
+            reverse: false,
+            _axis: PhantomData,
+        }
+    }
+}
+
+#[derive(Default)]
+pub(crate) struct LinearLayoutChild {
+    width: Option<i32>,
+    height: Option<i32>,
+    grow: f64,
+    // Computed in `start_layout`
+    rect: Rect,
+}
+
+impl <E, A> LayoutEngine<E> for LinearLayout<A>
+    where E: Extension,
+          A: LinearAxis,
+{
+    type ChildData = LinearLayoutChild;
+
+    fn name() -> &'static str { A::NAME }
+    fn style_properties<'a, F>(mut prop: F)
+        where F: FnMut(StaticKey) + 'a
+    {
+        prop(SPACING);
+        prop(REVERSE);
+        prop(GROW);
+        prop(WIDTH);
+        prop(HEIGHT);
+    }
+
+    fn new_child_data() -> LinearLayoutChild {
+        LinearLayoutChild::default()
+    }
+
+    fn update_data(&mut self, styles: &Styles<E>, nc: &NodeChain<E>, rule: &Rule<E>) -> DirtyFlags {
+        let mut flags = DirtyFlags::empty();
+        eval!(styles, nc, rule.SPACING => val => {
+            let new = val.convert().unwrap_or(0);
+            if self.spacing != new {
+                self.spacing = new;
+                flags |= DirtyFlags::LAYOUT;
+            }
+        });
+        eval!(styles, nc, rule.REVERSE => val => {
+            let new = val.convert().unwrap_or(false);
+            if self.reverse != new {
+                self.reverse = new;
+                flags |= DirtyFlags::LAYOUT;
+            }
+        });
+        flags
+    }
+    fn update_child_data(&mut self, styles: &Styles<E>, nc: &NodeChain<E>, rule: &Rule<E>, data: &mut Self::ChildData) -> DirtyFlags {
+        let mut flags = DirtyFlags::empty();
+        eval!(styles, nc, rule.WIDTH => val => {
+            let new = val.convert();
+            if data.width != new {
+                data.width = new;
+                flags |= DirtyFlags::SIZE;
+            }
+        });
+        eval!(styles, nc, rule.HEIGHT => val => {
+            let new = val.convert();
+            if data.height != new {
+                data.height = new;
+                flags |= DirtyFlags::SIZE;
+            }
+        });
+        eval!(styles, nc, rule.GROW => val => {
+            let new = val.convert().unwrap_or(0.0);
+            if data.grow != new {
+                data.grow = new;
+                flags |= DirtyFlags::SIZE;
+            }
+        });
+        flags
+    }
+
+    fn reset_unset_data(&mut self, used_keys: &FnvHashSet<StaticKey>) -> DirtyFlags {
+        let mut flags = DirtyFlags::empty();
+        if !used_keys.contains(&SPACING) && self.spacing != 0 {
+            self.spacing = 0;
+            flags |= DirtyFlags::LAYOUT;
+        }
+        if !used_keys.contains(&REVERSE) && self.reverse {
+            self.reverse = false;
+            flags |= DirtyFlags::LAYOUT;
+        }
+        flags
+    }
+    fn reset_unset_child_data(&mut self, used_keys: &FnvHashSet<StaticKey>, data: &mut Self::ChildData) -> DirtyFlags {
+        let mut flags = DirtyFlags::empty();
+        if !used_keys.contains(&WIDTH) && data.width.is_some() {
+            data.width = None;
+            flags |= DirtyFlags::SIZE;
+        }
+        if !used_keys.contains(&HEIGHT) && data.height.is_some() {
+            data.height = None;
+            flags |= DirtyFlags::SIZE;
+        }
+        if !used_keys.contains(&GROW) && data.grow != 0.0 {
+            data.grow = 0.0;
+            flags |= DirtyFlags::SIZE;
+        }
+        flags
+    }
+
+    fn measure(&mut self, children: ChildAccess<Self, E>) -> (Option<i32>, Option<i32>) {
+        if children.len() == 0 {
+            return (None, None);
+        }
+        // The sum of the sizes along the axis and the largest
+        // explicit size across it
+        let mut main_size = self.spacing * (children.len() as i32 - 1);
+        let mut cross_size = None;
+        for idx in 0 .. children.len() {
+            let (_, _, mut node) = children.get(idx).expect("Missing child");
+            let intrinsic = node.intrinsic_size();
+            let (_, data) = node.split();
+            let (main, cross) = if A::VERTICAL {
+                (data.height.or(intrinsic.1), data.width.or(intrinsic.0))
+            } else {
+                (data.width.or(intrinsic.0), data.height.or(intrinsic.1))
+            };
+            main_size += main.unwrap_or(0);
+            if let Some(cross) = cross {
+                cross_size = Some(cross_size.map_or(cross, |v: i32| v.max(cross)));
+            }
+        }
+        if A::VERTICAL {
+            (cross_size, Some(main_size))
+        } else {
+            (Some(main_size), cross_size)
+        }
+    }
+
+    fn start_layout(&mut self, _ext: &mut E::NodeData, current: Rect, _constraints: SizeConstraints, _flags: DirtyFlags, children: ChildAccess<Self, E>) -> Rect {
+        let (main_size, cross_size) = if A::VERTICAL {
+            (current.height, current.width)
+        } else {
+            (current.width, current.height)
+        };
+        let main = |data: &LinearLayoutChild, intrinsic: (Option<i32>, Option<i32>)| if A::VERTICAL {
+            data.height.or(intrinsic.1)
+        } else {
+            data.width.or(intrinsic.0)
+        };
+
+        // Work out how much space is left over for growing children
+        let mut used = self.spacing * (children.len() as i32 - 1).max(0);
+        let mut total_grow = 0.0;
+        for idx in 0 .. children.len() {
+            let (_, _, mut node) = children.get(idx).expect("Missing child");
+            let intrinsic = node.intrinsic_size();
+            let (_, data) = node.split();
+            used += main(data, intrinsic).unwrap_or(0);
+            if data.grow > 0.0 {
+                total_grow += data.grow;
+            }
+        }
+        let free = (main_size - used).max(0) as f64;
+
+        let mut offset = 0;
+        for idx in 0 .. children.len() {
+            // Only the placement is reversed, the children keep
+            // their order for rendering and queries
+            let idx = if self.reverse { children.len() - 1 - idx } else { idx };
+            let (_, _, mut node) = children.get(idx).expect("Missing child");
+            let intrinsic = node.intrinsic_size();
+            let (_, data) = node.split();
+            let mut size = main(data, intrinsic).unwrap_or(0);
+            if data.grow > 0.0 {
+                size += (free * data.grow / total_grow) as i32;
+            }
+            data.rect = if A::VERTICAL {
+                Rect {
+                    x: 0,
+                    y: offset,
+                    width: data.width.unwrap_or(cross_size),
+                    height: size,
+                }
+            } else {
+                Rect {
+                    x: offset,
+                    y: 0,
+                    width: size,
+                    height: data.height.unwrap_or(cross_size),
+                }
+            };
+            offset += size + self.spacing;
+        }
+        current
+    }
+
+    fn do_layout(&mut self, _value: &NodeValue<E>, _ext: &mut E::NodeData, data: &mut Self::ChildData, _current: Rect, _flags: DirtyFlags) -> Rect {
+        data.rect
+    }
+}
+
+/// The "columns" static key used by the grid layout
+pub static COLUMNS: StaticKey = StaticKey("columns");
+/// The "rows" static key used by the grid layout
+pub static ROWS: StaticKey = StaticKey("rows");
+/// The "margin" static key used by the grid layout
+pub static MARGIN: StaticKey = StaticKey("margin");
+/// The "force_size" static key used by the grid layout
+pub static FORCE_SIZE: StaticKey = StaticKey("force_size");
+
+/// Places children into a grid of equally sized cells.
+///
+/// Children fill the cells left to right and then top to bottom.
+/// Without `rows` set there are as many rows as needed to fit
+/// every child. `margin` is left around the edge of the grid and
+/// `spacing` between cells. With `force_size` set children are
+/// sized to fill their cell, otherwise their `width`/`height`
+/// are used defaulting to the size of the cell.
+#[derive(Default)]
+pub(crate) struct GridLayout {
+    columns: Option<i32>,
+    rows: Option<i32>,
+    spacing: i32,
+    margin: i32,
+    force_size: bool,
+    // Computed in `start_layout`
+    current_columns: i32,
+    cell_size: (i32, i32),
+}
+
+#[derive(Default)]
+pub(crate) struct GridLayoutChild {
+    width: Option<i32>,
+    height: Option<i32>,
+    // The index of the cell the child is placed in, set in
+    // `start_layout`
+    cell: i32,
+}
+
+impl <E> LayoutEngine<E> for GridLayout
+    where E: Extension
+{
+    type ChildData = GridLayoutChild;
+
+    fn name() -> &'static str { "grid" }
+    fn style_properties<'a, F>(mut prop: F)
+        where F: FnMut(StaticKey) + 'a
+    {
+        prop(COLUMNS);
+        prop(ROWS);
+        prop(SPACING);
+        prop(MARGIN);
+        prop(FORCE_SIZE);
+        prop(WIDTH);
+        prop(HEIGHT);
+    }
+
+    fn new_child_data() -> GridLayoutChild {
+        GridLayoutChild::default()
+    }
+
+    fn update_data(&mut self, styles: &Styles<E>, nc: &NodeChain<E>, rule: &Rule<E>) -> DirtyFlags {
+        let mut flags = DirtyFlags::empty();
+        eval!(styles, nc, rule.COLUMNS => val => {
+            let new = val.convert();
+            if self.columns != new {
+                self.columns = new;
+                flags |= DirtyFlags::LAYOUT;
+            }
+        });
+        eval!(styles, nc, rule.ROWS => val => {
+            let new = val.convert();
+            if self.rows != new {
+                self.rows = new;
+                flags |= DirtyFlags::LAYOUT;
+            }
+        });
+        eval!(styles, nc, rule.SPACING => val => {
+            let new = val.convert().unwrap_or(0);
+            if self.spacing != new {
+                self.spacing = new;
+                flags |= DirtyFlags::LAYOUT;
+            }
+        });
+        eval!(styles, nc, rule.MARGIN => val => {
+            let new = val.convert().unwrap_or(0);
+            if self.margin != new {
+                self.margin = new;
+                flags |= DirtyFlags::LAYOUT;
+            }
+        });
+        eval!(styles, nc, rule.FORCE_SIZE => val => {
+            let new = val.convert().unwrap_or(false);
+            if self.force_size != new {
+                self.force_size = new;
+                flags |= DirtyFlags::LAYOUT;
+            }
+        });
+        flags
+    }
+    fn update_child_data(&mut self, styles: &Styles<E>, nc: &NodeChain<E>, rule: &Rule<E>, data: &mut Self::ChildData) -> DirtyFlags {
+        let mut flags = DirtyFlags::empty();
+        eval!(styles, nc, rule.WIDTH => val => {
+            let new = val.convert();
+            if data.width != new {
+                data.width = new;
+                flags |= DirtyFlags::SIZE;
+            }
+        });
+        eval!(styles, nc, rule.HEIGHT => val => {
+            let new = val.convert();
+            if data.height != new {
+                data.height = new;
+                flags |= DirtyFlags::SIZE;
+            }
+        });
+        flags
+    }
+
+    fn reset_unset_data(&mut self, used_keys: &FnvHashSet<StaticKey>) -> DirtyFlags {
+        let mut flags = DirtyFlags::empty();
+        if !used_keys.contains(&COLUMNS) && self.columns.is_some() {
+            self.columns = None;
+            flags |= DirtyFlags::LAYOUT;
+        }
+        if !used_keys.contains(&ROWS) && self.rows.is_some() {
+            self.rows = None;
+            flags |= DirtyFlags::LAYOUT;
+        }
+        if !used_keys.contains(&SPACING) && self.spacing != 0 {
+            self.spacing = 0;
+            flags |= DirtyFlags::LAYOUT;
+        }
+        if !used_keys.contains(&MARGIN) && self.margin != 0 {
+            self.margin = 0;
+            flags |= DirtyFlags::LAYOUT;
+        }
+        if !used_keys.contains(&FORCE_SIZE) && self.force_size {
+            self.force_size = false;
+            flags |= DirtyFlags::LAYOUT;
+        }
+        flags
+    }
+    fn reset_unset_child_data(&mut self, used_keys: &FnvHashSet<StaticKey>, data: &mut Self::ChildData) -> DirtyFlags {
+        let mut flags = DirtyFlags::empty();
+        if !used_keys.contains(&WIDTH) && data.width.is_some() {
+            data.width = None;
+            flags |= DirtyFlags::SIZE;
+        }
+        if !used_keys.contains(&HEIGHT) && data.height.is_some() {
+            data.height = None;
+            flags |= DirtyFlags::SIZE;
+        }
+        flags
+    }
+
+    // The grid may be sized relative to its parent in which
+    // case resizing the parent moves every cell
+    fn check_parent_flags(&mut self, flags: DirtyFlags) -> DirtyFlags {
+        if flags.contains(DirtyFlags::SIZE) {
+            DirtyFlags::LAYOUT
+        } else {
+            DirtyFlags::empty()
+        }
+    }
+
+    fn start_layout(&mut self, _ext: &mut E::NodeData, current: Rect, _constraints: SizeConstraints, _flags: DirtyFlags, children: ChildAccess<Self, E>) -> Rect {
+        let columns = self.columns.unwrap_or(1).max(1);
+        let rows = match self.rows {
+            Some(rows) if rows > 0 => rows,
+            _ => ((children.len() as i32).saturating_add(columns - 1) / columns).max(1),
+        };
+        let (margin, spacing) = (self.margin, self.spacing);
+        // Saturating as the counts and spacing come from styles
+        let cell = |size: i32, count: i32| {
+            let count = count.max(1);
+            let free = size.saturating_sub(margin.saturating_mul(2))
+                .saturating_sub(spacing.saturating_mul(count - 1));
+            (free / count).max(0)
+        };
+        self.current_columns = columns;
+        self.cell_size = (cell(current.width, columns), cell(current.height, rows));
+        for idx in 0 .. children.len() {
+            let (_, _, mut node) = children.get(idx).expect("Missing child");
+            node.split().1.cell = idx as i32;
+        }
+        current
+    }
+
+    fn do_layout(&mut self, _value: &NodeValue<E>, _ext: &mut E::NodeData, data: &mut Self::ChildData, _current: Rect, _flags: DirtyFlags) -> Rect {
+        let idx = data.cell;
+        let (column, row) = (idx % self.current_columns, idx / self.current_columns);
+        let (width, height) = self.cell_size;
+        Rect {
+            x: self.margin.saturating_add(column.saturating_mul(width.saturating_add(self.spacing))),
+            y: self.margin.saturating_add(row.saturating_mul(height.saturating_add(self.spacing))),
+            width: if self.force_size { width } else { data.width.unwrap_or(width) },
+            height: if self.force_size { height } else { data.height.unwrap_or(height) },
+        }
+    }
+}
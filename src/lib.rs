@@ -22,6 +22,10 @@
 //! multiple times. For example an `url` property may be used on a text
 //! node to allow the style rules to color it differently or make it clickable.
 //!
+//! Whitespace around text is kept as written by default. Setting the `trim`
+//! property to `true` on a text node (e.g. `"  hello "(trim=true)`) strips
+//! leading and trailing whitespace when the node is created.
+//!
 //! ## Example
 //!
 //! An example of the node format:
@@ -60,6 +64,41 @@
 //! their values. This allows for a `title` inside an `alert` to act differently to
 //! a `title` inside an `window` for example.
 //!
+//! Matchers separated by `>` must be direct children of each other whilst matchers
+//! separated by whitespace match at any depth, e.g. `form(valid=false) button`
+//! matches any `button` inside of an invalid `form`. Properties matched after a
+//! whitespace separator can only be compared against constants, not captured as
+//! variables.
+//!
+//! Properties can also be compared using `!=`, `<`, `<=`, `>` and `>=`, e.g.
+//! `slider(value>50)`. `<`, `<=`, `>` and `>=` only match integer or float
+//! properties whilst `!=` also matches when the property isn't set. Prefixing a
+//! property with `!` (e.g. `button(!disabled)`) matches only when it isn't set.
+//!
+//! Matchers can also check the `$hover` and `$focus` properties set by
+//! `Manager::set_hover` and `Manager::set_focus`, e.g. `button($hover=true)`.
+//!
+//! A variable is bound by the first property that captures it. Using the same
+//! variable again later in the rule, either on the same node or a node below it,
+//! matches only when that property is equal to the bound value, e.g.
+//! `list(selected=s) > item(index=s)` matches the item whose `index` is the
+//! list's `selected` value. Expressions in the rule use the bound value.
+//!
+//! Properties of ancestors can be read in expressions without matching them using
+//! `parent.name` or `ancestor(depth).name`, e.g. `ancestor(2).value` reads `value`
+//! from the node's grandparent. Evaluating the expression fails if the ancestor
+//! doesn't exist or doesn't have the property.
+//!
+//! When multiple rules set the same property the most specific rule wins. The
+//! specificity of a rule is the number of matchers it has plus the number of
+//! properties they check, e.g. `panel > button(focused=true)` has a specificity
+//! of 3. Rules with the same specificity are ordered by when they were loaded
+//! with later rules winning.
+//!
+//! A style can be marked with `!important` (e.g. `width = 50 !important`) to
+//! override the same style in any rule without it. Important styles are ordered
+//! between themselves in the same way as normal styles.
+//!
 //! Once a match is found the style rules are applied to the node. Rules can be a
 //! simple constant value or an expression. Expressions perform basic math (`+-/*%`)
 //! and boolean operations (`|| && <= ` etc), reference properties that were matched
@@ -73,6 +112,10 @@
 //! defining a number as `5` will be an integer whilst `5.0` will be a float. For
 //! variables you can cast using `int(val)` or `float(val)`.
 //!
+//! Colors can be written as `#RRGGBB` or `#RRGGBBAA` and are converted to
+//! a `(u8, u8, u8, u8)` tuple. Strings in the same format are also accepted
+//! when converting to a color.
+//!
 //! ### Special variables
 //!
 //! There are two special variables that can be used without using them in a matching
@@ -83,6 +126,64 @@
 //! causing a slowdown however this will generally only happen the first time the
 //! node has its layout computed.
 //!
+//! `viewport_width` and `viewport_height` are the size passed to `Manager::layout`
+//! and can be used at any depth. These only change when the viewport is resized so
+//! they don't have the extra cost of the `parent_*` variables.
+//!
+//! Setting a key to `inherit` uses the value the parent node ended up
+//! with for the same key, even if a less specific rule would set it
+//! to something else. It has to be the whole value, not part of a larger
+//! expression, and fails to evaluate if the parent has no value for the key.
+//!
+//! ### Builtin functions
+//!
+//! A few functions are always available to style rules:
+//!
+//! * `clamp(val, low, high)` - Limits `val` to between `low` and `high`.
+//!   All parameters must be either integers or floats.
+//! * `min(a, b, ...)`/`max(a, b, ...)` - Returns the smallest/largest of two
+//!   or more integers or floats.
+//! * `if(cond, a, b)` - Returns `a` if the boolean `cond` is true otherwise `b`.
+//!   Only the returned value is evaluated so errors in the other branch
+//!   (e.g. a missing variable) are ignored.
+//! * `concat(a, b, ...)` - Joins any number of strings together.
+//! * `format(template, ...)` - Replaces each `{}` in the template string
+//!   with the following parameters which may be strings, integers, floats
+//!   or booleans. `{{` and `}}` output a literal `{` or `}`.
+//! * `list(a, b, ...)` - Collects any number of values into a list which
+//!   can be read as a `Vec`.
+//! * `action(name, ...)` - Creates an `Action` with the given name and
+//!   the rest of the parameters as its arguments. See `Node::trigger`.
+//! * `lerp(a, b, t)` - Linearly interpolates between the floats `a` and `b`
+//!   by `t` which is clamped to `0.0` to `1.0`.
+//! * `round(val)`/`floor(val)`/`ceil(val)` - Rounds a float to the nearest,
+//!   next lowest or next highest whole number. `round` rounds half-way
+//!   values away from zero. The result is still a float, wrap it in
+//!   `int(...)` for an integer as `int(...)` on its own truncates towards
+//!   zero, e.g. `int(round(2.7))` is `3` whilst `int(2.7)` is `2`.
+//! * `sin(val)`/`cos(val)`/`tan(val)` - Trigonometric functions of a float
+//!   in radians.
+//! * `sqrt(val)` - The square root of a float, negative values are an error.
+//! * `pow(base, exp)` - Raises the float `base` to the float `exp`. Fails if
+//!   the result isn't a number.
+//! * `abs(val)` - The absolute value of an integer or float.
+//! * `var(name, default)` - Returns the value of the node's property with the
+//!   given name or `default` if it isn't set. The name must be a string
+//!   constant, e.g. `var("size", 5)`, and the property doesn't have to be
+//!   matched by the rule.
+//! * `str(val)` - Converts a boolean, integer, float, string or color to a
+//!   string in the same format as `format`.
+//! * `to_int(val)`/`to_float(val)` - Parses a string as an integer or float,
+//!   failing if the string isn't a valid number.
+//! * `has_property(name)` - Returns whether the node has the named property
+//!   set, regardless of its value.
+//! * `prop_type(name)` - Returns the type of the named property as a string,
+//!   one of `"integer"`, `"float"`, `"boolean"`, `"string"`, `"color"`,
+//!   `"list"`, `"action"` or `"ext"`. Fails if it isn't set so
+//!   it is usually combined with `has_property` via `if`.
+//! * `theme(name)` - Returns the theme value with the given name as set via
+//!   `Manager::set_theme_value`.
+//!
 //! ## Example
 //!
 //! An example of the style format:
@@ -130,6 +231,34 @@
 //! These can be added via `add_layout_engine` and selected using the `layout` style
 //! property.
 //!
+//! The builtin layouts are:
+//!
+//! * `absolute` - The default. Children are placed using their `x`, `y`, `width`
+//!   and `height` properties. `width_percent` and `height_percent` size a child
+//!   relative to its parent without the extra cost of `parent_width`/`parent_height`
+//!   but are ignored if `width`/`height` are also set.
+//! * `row`/`column` - Children are stacked horizontally/vertically with `spacing`
+//!   (set on the parent) between each one. Children are sized by their `width` and
+//!   `height` properties, filling the parent across the axis if unset, and split any
+//!   space left over by their `grow` weight. Setting `reverse = true` on the parent
+//!   places the children starting from the last one.
+//! * `grid` - Children are placed left to right and then top to bottom into
+//!   `columns` by `rows` equally sized cells with `spacing` between them and
+//!   `margin` around the edge. Without `rows` the grid has enough rows for every
+//!   child. Children are sized by their `width` and `height` properties, filling
+//!   their cell if unset or if `force_size = true` is set on the parent.
+//!
+//! Every node may also limit its size via the `min_width`, `max_width`, `min_height`
+//! and `max_height` style properties. These apply to whatever size the layouts
+//! pick for the node and take priority over explicit sizes like `width`.
+//!
+//! Children are rendered in the order they were added unless they set `z_index`,
+//! in which case they are rendered from the lowest `z_index` to the highest.
+//!
+//! The `opacity` style property takes a float between `0.0` and `1.0` and is
+//! multiplied with the opacity of the node's ancestors. Renderers can read the
+//! combined value from `NodeInner::render_opacity`.
+//!
 //! # Extension
 //!
 //! The `Extension` trait paired with the `RenderVisitor` trait is the main way that
@@ -165,14 +294,18 @@
 #![warn(missing_docs)]
 
 extern crate fnv;
-extern crate fungui_syntax as syntax;
+pub extern crate fungui_syntax as syntax;
 extern crate ref_filter_map;
 extern crate bitflags;
+#[cfg(feature = "serde")]
+extern crate serde;
+#[cfg(feature = "serde")]
+extern crate serde_json;
 
 mod query;
 pub use query::Query;
 mod error;
-pub use error::Error;
+pub use error::{Error, StyleError, StyleWarning, InvariantViolation, LayoutError};
 #[macro_use]
 mod macros;
 #[cfg(any(test, feature="tests"))]
@@ -183,14 +316,34 @@ mod expr;
 use expr::*;
 mod layout;
 use layout::*;
+mod funcs;
+mod text;
+pub use text::{TextMeasure, MonospaceMeasure};
+mod dump;
+pub use dump::{LayoutDump, NodeDump, DumpValue};
+#[cfg(feature = "serde")]
+mod description;
+#[cfg(feature = "serde")]
+pub use description::{NodeDescription, PropertyValue};
+#[cfg(any(test, feature = "ascii"))]
+mod ascii;
+#[cfg(any(test, feature = "ascii"))]
+pub use ascii::AsciiRenderer;
+#[cfg(any(test, feature = "svg"))]
+mod svg;
+#[cfg(any(test, feature = "svg"))]
+pub use svg::{SvgRenderer, SvgStyle, SvgColor};
 
 pub use layout::{
     LayoutEngine, ChildAccess,
     NodeAccess,
-    X, Y, WIDTH, HEIGHT
+    X, Y, WIDTH, HEIGHT,
+    WIDTH_PERCENT, HEIGHT_PERCENT,
+    SPACING, GROW, REVERSE,
+    COLUMNS, ROWS, MARGIN, FORCE_SIZE,
 };
 
-pub use style::{Rule, Styles};
+pub use style::{Rule, Styles, RuleUsage};
 // TODO: Really shouldn't need this
 pub use fnv::FnvHashSet;
 
@@ -198,12 +351,17 @@ use fnv::FnvHashMap;
 use std::rc::{Rc, Weak};
 use std::cell::{Ref, RefMut, RefCell};
 use std::any::Any;
+use std::time::{Duration, Instant};
 use std::hash::{Hash, Hasher};
 use bitflags::bitflags;
 pub use syntax::{format_error, format_parse_error};
 
 /// An alias for a common return type used in FunGUI
 pub type FResult<'a, T> = Result<T, Error<'a>>;
+type PropertyWatcher<E> = Rc<dyn Fn(&Node<E>)>;
+type ActionFn<E> = Rc<RefCell<dyn FnMut(&Node<E>, &[Value<E>])>>;
+type ActionRegistry<E> = Rc<RefCell<FnvHashMap<String, ActionFn<E>>>>;
+type NodePtr<E> = *const RefCell<NodeInner<E>>;
 
 /// An unchanging key
 ///
@@ -242,6 +400,11 @@ bitflags! {
         const TEXT     = 0b0001_0000;
         /// Marks the node's children as changed
         const CHILDREN = 0b0010_0000;
+        /// Set by a layout via `LayoutEngine::requested_flags` to
+        /// have the node's parent updated and laid out again
+        const REQUEST_PARENT = 0b0100_0000;
+        /// Marks the node's opacity as changed
+        const OPACITY  = 0b1000_0000;
 
         // Extra ones for layouts to use
         /// Extra flag for layouts to use
@@ -342,6 +505,186 @@ pub trait Extension {
     /// This is useful to marking a node as needing a redraw when it
     /// moves.
     fn check_flags(_data: &mut Self::NodeData, _flags: DirtyFlags) { }
+
+    /// Called when a binary operator is used in a style rule where
+    /// at least one operand is an extension value.
+    ///
+    /// This allows extensions to define operators for their own
+    /// values, e.g. adding or scaling colors. Returning `None`
+    /// causes the usual incompatible types error.
+    fn ext_binary_op(_op: BinaryOp, _left: &Value<Self>, _right: &Value<Self>) -> Option<Value<Self>>
+        where Self: Sized
+    {
+        None
+    }
+}
+
+/// A binary operator passed to `Extension::ext_binary_op`
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum BinaryOp {
+    /// `a + b`
+    Add,
+    /// `a - b`
+    Sub,
+    /// `a * b`
+    Mul,
+    /// `a / b`
+    Div,
+    /// `a % b`
+    Rem,
+}
+
+/// The result of a `Manager::layout_within` call
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum LayoutStatus {
+    /// Every node has been laid out
+    Complete,
+    /// The time budget ran out before every node was laid
+    /// out. Calling `layout_within` or `layout` again with the
+    /// same size continues from where it stopped.
+    Incomplete,
+}
+
+#[derive(Clone, Copy)]
+enum LayoutStep {
+    Update(usize),
+    // Each root child is measured right before it is laid out
+    Measure(usize),
+    Layout(usize),
+    Clip(usize),
+}
+
+/// The progress of a layout of a root node's children, kept
+/// if the layout ran out of time
+struct PendingLayout {
+    size: (i32, i32),
+    flags: DirtyFlags,
+    force: bool,
+    step: LayoutStep,
+    // Where the walk stopped within the root child `step` is on
+    path: Vec<WalkFrame>,
+    properties_changed: bool,
+    // The number of update and layout passes run so far
+    passes: usize,
+    // Whether any node was changed by this layout
+    changed: bool,
+    // The root's `tree_version` when the layout started, the saved
+    // child indices are only valid whilst it is unchanged
+    tree_version: u32,
+}
+
+impl PendingLayout {
+    fn new(size: (i32, i32), flags: DirtyFlags, force: bool) -> PendingLayout {
+        PendingLayout {
+            size,
+            flags,
+            force,
+            step: LayoutStep::Update(0),
+            path: Vec::new(),
+            properties_changed: false,
+            passes: 0,
+            changed: force || !flags.is_empty(),
+            tree_version: 0,
+        }
+    }
+}
+
+/// Limits how long a walk over the nodes may run for.
+///
+/// Once the deadline passes the walk stops before the next node
+/// and unwinds, each node on the way up saving how far through
+/// its children it got in `path`. The next walk follows the path
+/// back down to continue from the node it stopped before.
+struct Budget {
+    deadline: Option<Instant>,
+    // At least one node is visited per walk so a layout
+    // always finishes eventually
+    started: bool,
+    stopped: bool,
+    // The nodes the walk stopped within, innermost first
+    path: Vec<WalkFrame>,
+}
+
+/// The progress of a node part way through its children
+#[derive(Clone, Copy)]
+struct WalkFrame {
+    // The index of the next child to visit
+    child: usize,
+    // The combined flags of the children updated so far
+    child_flags: DirtyFlags,
+    styles_updated: bool,
+    parent_dirty: bool,
+    // Whether a child laid out so far needs another pass
+    properties_changed: bool,
+}
+
+/// The changes to a node's parent passed down by `do_update`
+#[derive(Clone, Copy)]
+struct ParentUpdate {
+    styles_updated: bool,
+    parent_dirty: bool,
+    flags: DirtyFlags,
+}
+
+impl Default for WalkFrame {
+    fn default() -> WalkFrame {
+        WalkFrame {
+            child: 0,
+            child_flags: DirtyFlags::empty(),
+            styles_updated: false,
+            parent_dirty: false,
+            properties_changed: false,
+        }
+    }
+}
+
+impl Budget {
+    fn unlimited() -> Budget {
+        Budget::new(None, Vec::new())
+    }
+
+    fn new(deadline: Option<Instant>, path: Vec<WalkFrame>) -> Budget {
+        Budget {
+            deadline,
+            started: false,
+            stopped: false,
+            path,
+        }
+    }
+
+    /// Returns the saved progress of the node being visited
+    /// if the walk is resuming within it
+    fn resume(&mut self) -> Option<WalkFrame> {
+        self.path.pop()
+    }
+
+    /// Returns whether the walk should stop before visiting
+    /// the next node
+    fn out_of_time(&mut self) -> bool {
+        if self.stopped {
+            return true;
+        }
+        // Still following the path down to where it stopped
+        if !self.path.is_empty() {
+            return false;
+        }
+        if !self.started {
+            self.started = true;
+            return false;
+        }
+        match self.deadline {
+            Some(d) if Instant::now() >= d => {
+                self.stopped = true;
+                true
+            },
+            _ => false,
+        }
+    }
+
+    /// Saves the progress of a node the walk stopped within
+    fn save(&mut self, frame: WalkFrame) {
+        self.path.push(frame);
+    }
 }
 
 /// Stores loaded nodes and manages the layout.
@@ -352,14 +695,58 @@ pub struct Manager<E: Extension> {
     styles: Styles<E>,
     last_size: (i32, i32),
     dirty: bool,
+    // Set when `layout_within` runs out of time
+    pending_layout: Option<PendingLayout>,
+    // The root's `tree_version` at the end of the last layout,
+    // removed nodes don't change any that are left so this is
+    // how `layout` knows they need redrawing
+    tree_version: u32,
+    // Areas changed by layouts, drained by `take_dirty_rects`
+    dirty_rects: Vec<Rect>,
+    // Shared with the `theme` function
+    theme: Rc<RefCell<FnvHashMap<String, Value<E>>>>,
+    // Shared with the root node for `Node::trigger`
+    actions: ActionRegistry<E>,
+    // The nodes with the `$hover` and `$focus` properties set
+    hover: Option<Node<E>>,
+    focus: Option<Node<E>>,
+    // Extra roots added via `add_surface`
+    surfaces: FnvHashMap<String, Surface<E>>,
+    // Properties that mark a node as interactive for
+    // `pick_interactive`
+    interactive: FnvHashSet<String>,
+}
+
+/// A root node laid out separately to the manager's root
+struct Surface<E: Extension> {
+    root: Node<E>,
+    last_size: (i32, i32),
+    // Set when the styles change, cleared by `layout_surface`
+    dirty: bool,
+    // Like `Manager::tree_version`
+    tree_version: u32,
 }
 
 static CLIP_OVERFLOW: StaticKey = StaticKey("clip_overflow");
 static SCROLL_X: StaticKey = StaticKey("scroll_x");
 static SCROLL_Y: StaticKey = StaticKey("scroll_y");
 static LAYOUT: StaticKey = StaticKey("layout");
+static MIN_WIDTH: StaticKey = StaticKey("min_width");
+static MAX_WIDTH: StaticKey = StaticKey("max_width");
+static MIN_HEIGHT: StaticKey = StaticKey("min_height");
+static MAX_HEIGHT: StaticKey = StaticKey("max_height");
+static Z_INDEX: StaticKey = StaticKey("z_index");
+static OPACITY: StaticKey = StaticKey("opacity");
+
+// The number of areas kept for `take_dirty_rects` before they are
+// replaced by the whole viewport
+const MAX_DIRTY_RECTS: usize = 256;
 
-impl<E: Extension> Manager<E> {
+/// The name of the surface made up of the manager's own root,
+/// the one `add_node`, `layout` and `render` use.
+pub const MAIN_SURFACE: &str = "main";
+
+impl<E: Extension + 'static> Manager<E> {
     /// Creates a new manager with an empty root node.
     pub fn new() -> Manager<E> {
         let mut static_keys = FnvHashMap::default();
@@ -369,6 +756,12 @@ impl<E: Extension> Manager<E> {
             prop(SCROLL_X);
             prop(SCROLL_Y);
             prop(LAYOUT);
+            prop(MIN_WIDTH);
+            prop(MAX_WIDTH);
+            prop(MIN_HEIGHT);
+            prop(MAX_HEIGHT);
+            prop(Z_INDEX);
+            prop(OPACITY);
             E::style_properties(prop);
         }
         let mut m = Manager {
@@ -380,12 +773,58 @@ impl<E: Extension> Manager<E> {
                 funcs: FnvHashMap::default(),
                 layouts: FnvHashMap::default(),
                 next_rule_id: 0,
+                loaded_styles: FnvHashMap::default(),
                 used_keys: FnvHashSet::default(),
+                errors: RefCell::new(Vec::new()),
+                warnings: Vec::new(),
+                viewport: (0, 0),
+                inherited_keys: FnvHashSet::default(),
+                action_keys: FnvHashSet::default(),
+                text_measure: Box::new(MonospaceMeasure),
+                max_layout_passes: 64,
             },
             last_size: (0, 0),
+            pending_layout: None,
+            tree_version: 0,
+            dirty_rects: Vec::new(),
             dirty: true,
+            theme: Rc::new(RefCell::new(FnvHashMap::default())),
+            actions: Rc::new(RefCell::new(FnvHashMap::default())),
+            hover: None,
+            focus: None,
+            surfaces: FnvHashMap::default(),
+            interactive: FnvHashSet::default(),
         };
+        m.root.inner.borrow_mut().actions = Some(m.actions.clone());
         m.add_layout_engine(AbsoluteLayout::default);
+        m.add_layout_engine(LinearLayout::<Row>::default);
+        m.add_layout_engine(LinearLayout::<Column>::default);
+        m.add_layout_engine(GridLayout::default);
+        m.add_func_raw("clamp", funcs::clamp);
+        m.add_func_raw("min", funcs::min);
+        m.add_func_raw("max", funcs::max);
+        m.add_func_raw("if", funcs::if_);
+        m.add_func_raw("concat", funcs::concat);
+        m.add_func_raw("format", funcs::format);
+        m.add_func_raw("list", funcs::list);
+        m.add_func_raw("lerp", funcs::lerp);
+        m.add_func_raw("round", funcs::round);
+        m.add_func_raw("floor", funcs::floor);
+        m.add_func_raw("ceil", funcs::ceil);
+        m.add_func_raw("sin", funcs::sin);
+        m.add_func_raw("cos", funcs::cos);
+        m.add_func_raw("tan", funcs::tan);
+        m.add_func_raw("sqrt", funcs::sqrt);
+        m.add_func_raw("pow", funcs::pow);
+        m.add_func_raw("abs", funcs::abs);
+        m.add_func_raw("action", funcs::action);
+        m.add_func_raw("str", funcs::str);
+        m.add_func_raw("to_int", funcs::to_int);
+        m.add_func_raw("to_float", funcs::to_float);
+        m.add_func_ctx("has_property", funcs::has_property);
+        m.add_func_ctx("prop_type", funcs::prop_type);
+        let theme = m.theme.clone();
+        m.add_func_raw("theme", move |args| funcs::theme(&theme.borrow(), args));
 
         m
     }
@@ -407,10 +846,43 @@ impl<E: Extension> Manager<E> {
     /// Add a function that can be called by style rules
     ///
     /// Arguments are only parsed when obtained from the iterator
-    /// making unused parameters cheap.
+    /// making unused parameters cheap. Arguments skipped over via
+    /// `nth` are never evaluated.
     pub fn add_func_raw<F>(&mut self, name: &'static str, func: F)
     where
         F: for<'a> Fn(&mut (Iterator<Item=FResult<'a, Value<E>>> + 'a)) -> FResult<'a, Value<E>> + 'static,
+    {
+        self.add_func_ctx(name, move |_, args| func(args));
+    }
+
+    /// Add a function that can be called by style rules which
+    /// can also access the node it is evaluated for.
+    ///
+    /// Works like `add_func_raw` but the function is passed the
+    /// node's `NodeChain` which can be used to read its name and
+    /// properties as well as those of its ancestors. Sizes aren't
+    /// available as rules aren't evaluated again when they change,
+    /// pass `parent_width` or `parent_height` in as a parameter
+    /// instead.
+    ///
+    /// # Example
+    ///
+    /// ```rust
+    /// # use fungui::*;
+    /// # fn test<E: Extension + 'static>(manager: &mut Manager<E>) {
+    /// // Returns whether the node has the named property
+    /// manager.add_func_ctx("has_property", |node, args| {
+    ///     match args.next() {
+    ///         Some(Ok(Value::String(name))) => Ok(Value::Boolean(node.get_property::<Value<E>>(&name).is_some())),
+    ///         _ => Err(Error::CustomStatic { reason: "Expected a property name" }),
+    ///     }
+    /// });
+    /// # }
+    /// # fn main() {}
+    /// ```
+    pub fn add_func_ctx<F>(&mut self, name: &'static str, func: F)
+    where
+        F: for<'a> Fn(&NodeChain<E>, &mut (dyn Iterator<Item=FResult<'a, Value<E>>> + 'a)) -> FResult<'a, Value<E>> + 'static,
     {
         let key = self.styles.static_keys.entry(name).or_insert(StaticKey(name));
         self.styles.funcs.insert(*key, Box::new(func));
@@ -421,8 +893,18 @@ impl<E: Extension> Manager<E> {
     /// The node is created from the passed string.
     /// See [`from_str`](struct.Node.html#from_str)
     pub fn add_node_str<'a>(&mut self, node: &'a str) -> Result<(), syntax::PError<'a>> {
-        self.add_node(Node::from_str(node)?);
-        Ok(())
+        self.add_node_str_ret(node).map(|_| ())
+    }
+
+    /// Adds the node to the root node of this manager returning
+    /// the created node.
+    ///
+    /// Works like `add_node_str` but keeps a handle to the node
+    /// so it can be changed later without querying for it.
+    pub fn add_node_str_ret<'a>(&mut self, node: &'a str) -> Result<Node<E>, syntax::PError<'a>> {
+        let node = Node::from_str(node)?;
+        self.add_node(node.clone());
+        Ok(node)
     }
 
     /// Adds the node to the root node of this manager
@@ -431,8 +913,23 @@ impl<E: Extension> Manager<E> {
     }
 
     /// Removes the node from the root node of this manager
-    pub fn remove_node(&mut self, node: Node<E>) {
-        self.root.remove_child(node);
+    ///
+    /// Returns true if the node was removed
+    pub fn remove_node(&mut self, node: Node<E>) -> bool {
+        self.root.remove_child(node)
+    }
+
+    /// Removes the node from the root node of this manager,
+    /// returning `Error::NodeNotFound` if it wasn't attached
+    /// to the root.
+    ///
+    /// Useful for catching nodes being removed twice.
+    pub fn remove_node_checked(&mut self, node: Node<E>) -> Result<(), Error<'static>> {
+        if self.remove_node(node) {
+            Ok(())
+        } else {
+            Err(Error::NodeNotFound)
+        }
     }
 
     /// Starts a query from the root of this manager
@@ -440,6 +937,27 @@ impl<E: Extension> Manager<E> {
         query::Query::new(self.root.clone())
     }
 
+    /// Runs the query built by `build` from the root of this
+    /// manager and collects every match along with its
+    /// absolute position.
+    ///
+    /// Must be called after a `layout` call. Matches are returned
+    /// in the same order as `Query::matches` and any that aren't
+    /// visible (see `Node::render_position`) are skipped.
+    ///
+    /// ```ignore
+    /// let buttons = manager.query_all(|q| q.name("button"));
+    /// ```
+    pub fn query_all<'a, F>(&self, build: F) -> Vec<(Node<E>, Rect)>
+        where F: FnOnce(query::Query<'a, E>) -> query::Query<'a, E>,
+              E: 'a,
+    {
+        build(query::Query::new(self.root.clone()))
+            .matches()
+            .filter_map(|n| n.render_position().map(|r| (n, r)))
+            .collect()
+    }
+
     /// Starts a query looking for elements at the target
     /// location.
     pub fn query_at(&self, x: i32, y: i32) -> query::Query<'static, E> {
@@ -450,167 +968,1085 @@ impl<E: Extension> Manager<E> {
         }
     }
 
+    /// Returns the topmost node at the target location, `None`
+    /// if there isn't a node there.
+    ///
+    /// Nodes drawn later, either added later or with a higher
+    /// `z_index`, are above earlier ones and children are above
+    /// their parents. Parts of nodes clipped by an ancestor with
+    /// `clip_overflow` set can't be hit. Must be called after a
+    /// `layout` call.
+    pub fn hit_test(&self, x: i32, y: i32) -> Option<Node<E>> {
+        // `query_at` visits children drawn later first and returns
+        // children before their parents so the first match is the
+        // topmost one. The root is always returned last and is skipped.
+        self.query_at(x, y)
+            .matches()
+            .next()
+            .filter(|v| !v.is_same(&self.root))
+    }
+
+    /// Marks nodes with the given property as interactive for
+    /// `pick_interactive`.
+    ///
+    /// A node is interactive if any of these properties is set to
+    /// something other than `false`, e.g. `button(on_click="...")`.
+    pub fn add_interactive_property(&mut self, name: &str) {
+        self.interactive.insert(name.into());
+    }
+
+    /// Returns the topmost interactive node at the target location,
+    /// `None` if there isn't one there.
+    ///
+    /// Works like `hit_test` but skips nodes without one of the
+    /// properties added via `add_interactive_property`. A point over
+    /// a decorative child of an interactive node, e.g. an icon
+    /// within a button, returns the interactive node.
+    pub fn pick_interactive(&self, x: i32, y: i32) -> Option<Node<E>> {
+        self.query_at(x, y)
+            .matches()
+            .filter(|v| !v.is_same(&self.root))
+            .find(|v| {
+                let inner = v.inner.borrow();
+                self.interactive.iter()
+                    .filter_map(|k| inner.properties.get(k))
+                    .any(|v| *v != Value::Boolean(false))
+            })
+    }
+
     /// Loads a set of styles from the given string.
     ///
-    /// The name can be used to remove the loaded styles later
+    /// The name can be used to remove the loaded styles later.
+    /// Loading styles with a name that is already loaded replaces
+    /// the previous rules with the new ones, making it suitable
+    /// for reloading a changed style sheet. If the new styles fail
+    /// to load then the previous rules are left in place.
     pub fn load_styles<'a>(
         &mut self,
         name: &str,
         style_rules: &'a str,
     ) -> Result<(), syntax::PError<'a>> {
-        let styles = syntax::style::Document::parse(style_rules)?;
+        let styles = Self::parse_styles(style_rules)?;
+        self.load_styles_document(name, styles)
+    }
+
+    /// Replaces the styles loaded with the given name.
+    ///
+    /// The new styles are parsed and converted before the old rules
+    /// are removed so, unlike `remove_styles` followed by
+    /// `load_styles`, there is never a point where neither set is
+    /// loaded. On failure the old styles are left untouched.
+    pub fn reload_styles<'a>(
+        &mut self,
+        name: &str,
+        style_rules: &'a str,
+    ) -> Result<(), syntax::PError<'a>> {
+        self.load_styles(name, style_rules)
+    }
+
+    /// Parses a set of styles without loading them.
+    ///
+    /// This allows tools to inspect or modify the parsed rules
+    /// before loading them via `load_styles_document`.
+    pub fn parse_styles<'a>(style_rules: &'a str) -> Result<syntax::style::Document<'a>, syntax::PError<'a>> {
+        syntax::style::Document::parse(style_rules)
+    }
+
+    /// Loads a set of styles that have already been parsed.
+    ///
+    /// Behaves the same as `load_styles` otherwise.
+    pub fn load_styles_document<'a>(
+        &mut self,
+        name: &str,
+        styles: syntax::style::Document<'a>,
+    ) -> Result<(), syntax::PError<'a>> {
         self.styles.load_styles(name, styles)?;
-        self.dirty = true;
+        self.mark_dirty();
         Ok(())
     }
 
     /// Removes the set of styles with the given name
     pub fn remove_styles(&mut self, name: &str) {
         self.styles.rules.remove_all_by_name(name);
-        self.dirty = true;
+        self.styles.loaded_styles.remove(name);
+        self.mark_dirty();
     }
 
-    /// Positions the nodes in this manager.
+    /// Enables or disables the set of styles with the given name
+    /// without unloading them.
     ///
-    /// This will update nodes based on their properties and then
-    /// position them based on their selected layout.
-    pub fn layout(&mut self, width: i32, height: i32) {
-        let size = (width, height);
-        let flags = if self.last_size != size {
-            self.last_size = size;
-            DirtyFlags::SIZE
-        } else {
-            DirtyFlags::empty()
-        };
-
-        let mut inner = self.root.inner.borrow_mut();
-        inner.draw_rect = Rect{x: 0, y: 0, width, height};
+    /// Rules in disabled styles never match but are kept compiled
+    /// so enabling them again is cheap. Loading styles with the
+    /// same name again replaces them with enabled rules.
+    pub fn set_styles_enabled(&mut self, name: &str, enabled: bool) {
+        if self.styles.rules.set_enabled(name, enabled) {
+            self.mark_dirty();
+        }
+    }
 
-        let p = NodeChain {
-            parent: None,
-            value: NCValue::Element("root"),
-            draw_rect: inner.draw_rect,
-            properties: &FnvHashMap::default(),
-        };
+    /// Returns the names of the currently loaded styles in
+    /// sorted order
+    pub fn loaded_style_names(&self) -> Vec<String> {
+        let mut names: Vec<_> = self.styles.loaded_styles.keys()
+            .cloned()
+            .collect();
+        names.sort();
+        names
+    }
 
-        let mut layout = AbsoluteLayout::default();
+    /// Returns the style keys set and the properties read by
+    /// every loaded rule, in load order.
+    ///
+    /// Useful for finding keys that nothing uses or properties
+    /// that are never set.
+    pub fn rule_usage(&self) -> Vec<RuleUsage> {
+        self.styles.rules.usage()
+    }
 
-        // This is a loop due to the `parent_X` support requiring
-        // the layout to be computed so it can be used in style rules
-        // creating a chicken/egg problem. If they aren't used then
-        // this will only execute once.
-        loop {
-            let mut properties_changed = false;
+    /// Returns the number of rules loaded with the given name.
+    ///
+    /// Returns 0 if no styles are loaded with the name.
+    pub fn rule_count(&self, name: &str) -> usize {
+        self.styles.loaded_styles.get(name)
+            .cloned()
+            .unwrap_or(0)
+    }
 
-            if let NodeValue::Element(ref v) = inner.value {
-                for c in &v.children {
-                    c.do_update(&mut self.styles, &p, &mut layout, self.dirty, flags == DirtyFlags::SIZE, flags);
-                }
+    /// Sets the number of update and layout passes `layout` runs
+    /// before giving up on the layout settling. Defaults to 64.
+    ///
+    /// Sizes depending on `parent_width`/`parent_height` need
+    /// extra passes, a size that depends on itself may never
+    /// settle. When the limit is hit the layout is left as it is
+    /// and the nodes still changing are reported via
+    /// [`take_errors`](#method.take_errors).
+    pub fn set_max_layout_passes(&mut self, passes: usize) {
+        self.styles.max_layout_passes = passes.max(1);
+    }
 
-                for c in &v.children {
-                    properties_changed |= c.layout(&self.styles, &mut layout);
-                }
-            }
+    /// Sets the measurer layouts use to size and wrap text.
+    ///
+    /// Every node will be laid out again on the next `layout` call.
+    pub fn set_text_measure<T>(&mut self, measure: T)
+        where T: TextMeasure + 'static
+    {
+        self.styles.text_measure = Box::new(measure);
+        self.mark_dirty();
+    }
 
-            self.dirty = false;
-            if !properties_changed {
-                break;
-            }
+    /// Sets a theme value that can be used in style rules
+    /// via `theme(name)`.
+    ///
+    /// Every node will be updated on the next `layout` call
+    /// to pick up the new value. Setting `None` removes the
+    /// value like `remove_theme_value`.
+    pub fn set_theme_value<V>(&mut self, name: &str, v: V)
+        where V: ConvertValue<E>
+    {
+        match V::to_optional_value(v) {
+            Some(value) => {
+                self.theme.borrow_mut().insert(name.into(), value);
+                self.mark_dirty();
+            },
+            None => self.remove_theme_value(name),
         }
     }
 
-    /// Renders the nodes in this manager by passing the draw position/size
-    /// and style properties to the visitor
-    pub fn render<V>(&mut self, visitor: &mut V)
-    where
-        V: RenderVisitor<E>,
-    {
-        self.root.render(visitor);
+    /// Sets the node the cursor is over, `None` if there isn't one.
+    ///
+    /// The `$hover` property is set to `true` on the node and removed
+    /// from the previously hovered one so style rules can match
+    /// `button($hover=true)`. Descriptions can't set `$` properties
+    /// so this never replaces a node's own `hover` property.
+    pub fn set_hover(&mut self, node: Option<Node<E>>) {
+        Manager::move_state("$hover", &mut self.hover, node);
     }
-}
 
-/// The position and size of an node
-#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
-pub struct Rect {
-    /// The x position of the node
-    pub x: i32,
-    /// The y position of the node
-    pub y: i32,
-    /// The width of the node
-    pub width: i32,
-    /// The height of the node
-    pub height: i32,
-}
+    /// Returns the node passed to the last `set_hover` call
+    pub fn hovered(&self) -> Option<Node<E>> {
+        self.hover.clone()
+    }
 
-/// Called for every node in a manager to allow them to
-/// be rendered.
-pub trait RenderVisitor<E: Extension> {
-    /// Called per node before visiting their children
-    fn visit(&mut self, node: &mut NodeInner<E>);
-    /// Called per node after visiting their children
-    fn visit_end(&mut self, node: &mut NodeInner<E>);
-}
+    /// Sets the node that has focus, `None` if there isn't one.
+    ///
+    /// Works like `set_hover` using the `$focus` property.
+    pub fn set_focus(&mut self, node: Option<Node<E>>) {
+        Manager::move_state("$focus", &mut self.focus, node);
+    }
 
-/// A node representing an element or text.
-///
-/// Can be cloned to duplicate the reference to the node.
-pub struct Node<E: Extension> {
-    inner: Rc<RefCell<NodeInner<E>>>,
-}
+    /// Returns the node passed to the last `set_focus` call
+    pub fn focused(&self) -> Option<Node<E>> {
+        self.focus.clone()
+    }
 
-impl<E: Extension> Clone for Node<E> {
-    fn clone(&self) -> Self {
-        Node {
-            inner: self.inner.clone(),
+    /// Moves focus to the next element with `focusable=true` in
+    /// document order, wrapping around to the first one at the end.
+    ///
+    /// Elements clipped to nothing are skipped so this must be
+    /// called after a `layout` call. Returns the newly focused node,
+    /// the focus is left unchanged if there isn't one.
+    pub fn focus_next(&mut self) -> Option<Node<E>> {
+        self.move_focus(false)
+    }
+
+    /// Moves focus to the previous element with `focusable=true`
+    /// in document order, wrapping around to the last one at the start.
+    ///
+    /// Works like `focus_next` otherwise.
+    pub fn focus_prev(&mut self) -> Option<Node<E>> {
+        self.move_focus(true)
+    }
+
+    fn move_focus(&mut self, reverse: bool) -> Option<Node<E>> {
+        let mut nodes: Vec<_> = self.root.descendants()
+            .filter(|v| match v.inner.borrow().value {
+                NodeValue::Element(_) => v.get_property::<bool>("focusable").unwrap_or(false),
+                NodeValue::Text(_) => false,
+            })
+            .filter(|v| v.render_position().is_some())
+            .collect();
+        if reverse {
+            nodes.reverse();
         }
+        let current = self.focus.as_ref()
+            .and_then(|f| nodes.iter().position(|v| v.is_same(f)));
+        let next = match current {
+            Some(idx) => nodes.get(idx + 1).or_else(|| nodes.first()),
+            None => nodes.first(),
+        }.cloned()?;
+        self.set_focus(Some(next.clone()));
+        Some(next)
     }
-}
 
-/// Tries to find and evalulate a given style property in a rule.
-///
-/// This will skip properties that have already been set previously
-/// in the update. Should only be used during an `update_(child_)data`
-/// call.
-///
-/// ```ignore
-/// eval!(styles, nc, rule.MY_PROP => val => {
-///     // This will only run if MY_PROP was set in the rule
-///     // val will be a `Value` containing what the property
-///     // was set too
-/// });
-/// ```
-#[macro_export]
-macro_rules! eval {
-    ($styles:expr, $n:expr, $rule:ident.$key:expr => $ret:ident => $ok:block) => {
-        if !$styles.key_was_used(&$key) {
-            if let Some(e) = $rule.styles.get(&$key) {
-                match e.eval($styles, &$n) {
-                    Ok($ret) => $ok,
-                    Err(err) => {
-                        // TODO: Collect errors for the user to display
-                        // instead of printing
-                        println!("Failed to evalulate expression ({}): {:?}", e, err);
-                    }
-                }
+    fn move_state(key: &str, current: &mut Option<Node<E>>, node: Option<Node<E>>) {
+        match (current.as_ref(), node.as_ref()) {
+            (Some(a), Some(b)) if a.is_same(b) => return,
+            (None, None) => return,
+            _ => {},
+        }
+        let old = ::std::mem::replace(current, node.clone());
+        // Both nodes are changed before calling any watchers so
+        // they never see the state on both or neither node
+        let mut changed = Vec::with_capacity(2);
+        if let Some(old) = old {
+            let removed = {
+                let mut inner = old.inner.borrow_mut();
+                let removed = inner.properties.remove(key).is_some();
+                inner.properties_changed |= removed;
+                removed
+            };
+            if removed {
+                changed.push(old);
             }
         }
-    };
-}
+        if let Some(node) = node {
+            let added = {
+                let mut inner = node.inner.borrow_mut();
+                let added = inner.properties.insert(key.into(), Value::Boolean(true)) != Some(Value::Boolean(true));
+                inner.properties_changed |= added;
+                added
+            };
+            if added {
+                changed.push(node);
+            }
+        }
+        for n in changed {
+            n.notify_watchers(key);
+        }
+    }
 
-impl<E: Extension> Node<E> {
+    /// Registers a function to be called when an action with
+    /// the given name is triggered via `Node::trigger`.
+    ///
+    /// The function is passed the node the action was triggered
+    /// on and the action's arguments. Replaces any function
+    /// previously registered with the same name.
+    pub fn register_action<F>(&mut self, name: &str, f: F)
+        where F: FnMut(&Node<E>, &[Value<E>]) + 'static
+    {
+        self.actions.borrow_mut().insert(name.into(), Rc::new(RefCell::new(f)));
+    }
 
-    fn do_update(
-        &self,
-        styles: &mut Styles<E>,
+    /// Removes a theme value previously set via `set_theme_value`
+    pub fn remove_theme_value(&mut self, name: &str) {
+        if self.theme.borrow_mut().remove(name).is_some() {
+            self.mark_dirty();
+        }
+    }
+
+    /// Returns and clears the warnings found while loading styles.
+    ///
+    /// Currently this reports arithmetic that mixes integer and
+    /// float constants, e.g. `width = 5 / 2.0`, which would always
+    /// fail when evaluated.
+    pub fn take_style_warnings(&mut self) -> Vec<StyleWarning> {
+        ::std::mem::take(&mut self.styles.warnings)
+    }
+
+    /// Returns and clears the errors produced while laying out
+    /// nodes.
+    ///
+    /// Properties that fail to evaluate are skipped during
+    /// `layout` and their errors are collected here instead,
+    /// along with layouts that never settle. Only the first 256
+    /// errors since the last call are kept.
+    pub fn take_errors(&mut self) -> Vec<LayoutError<E>> {
+        ::std::mem::take(self.styles.errors.get_mut())
+    }
+
+    /// Checks the node tree for broken invariants.
+    ///
+    /// This verifies that every child's parent link points back
+    /// to the node containing it, that no node is contained by
+    /// more than one node and that there are no cycles. Intended
+    /// as a debugging aid when extending the crate, debug builds
+    /// also check this after every completed layout.
+    pub fn check_invariants(&self) -> Result<(), Vec<InvariantViolation<E>>> {
+        fn check<E: Extension>(
+            node: &Node<E>,
+            seen: &mut FnvHashSet<NodePtr<E>>,
+            path: &mut Vec<NodePtr<E>>,
+            violations: &mut Vec<InvariantViolation<E>>,
+        ) {
+            path.push(Rc::as_ptr(&node.inner));
+            for c in node.children() {
+                let ptr = Rc::as_ptr(&c.inner);
+                if path.contains(&ptr) {
+                    violations.push(InvariantViolation::Cycle { node: c });
+                    continue;
+                }
+                if !seen.insert(ptr) {
+                    violations.push(InvariantViolation::MultipleParents { node: c });
+                    continue;
+                }
+                match c.parent() {
+                    Some(ref p) if p.is_same(node) => {},
+                    _ => violations.push(InvariantViolation::InvalidParent {
+                        node: c.clone(),
+                        parent: node.clone(),
+                    }),
+                }
+                check(&c, seen, path, violations);
+            }
+            path.pop();
+        }
+
+        let mut violations = Vec::new();
+        check(&self.root, &mut FnvHashSet::default(), &mut Vec::new(), &mut violations);
+        if violations.is_empty() {
+            Ok(())
+        } else {
+            Err(violations)
+        }
+    }
+
+    /// Positions the nodes in this manager.
+    ///
+    /// This will update nodes based on their properties and then
+    /// position them based on their selected layout.
+    ///
+    /// Returns whether anything changed since the last layout,
+    /// e.g. a node moved, was removed or a property was updated.
+    /// Renderers can use this to skip rebuilding when nothing
+    /// changed. Always true for the first call.
+    pub fn layout(&mut self, width: i32, height: i32) -> bool {
+        self.layout_impl(width, height, None).1
+    }
+
+    /// Positions the nodes in this manager like `layout` but stops
+    /// once the time budget is used up.
+    ///
+    /// Returns `LayoutStatus::Incomplete` if the budget ran out, the
+    /// next call to `layout_within` or `layout` with the same size
+    /// continues the work instead of starting again. Adding or
+    /// removing nodes in between starts it again. At least one
+    /// step of work is done per call so a layout will always finish
+    /// eventually.
+    ///
+    /// The budget is checked between every node so a single large
+    /// subtree can also be split between calls.
+    pub fn layout_within(&mut self, width: i32, height: i32, budget: Duration) -> LayoutStatus {
+        self.layout_impl(width, height, Some(Instant::now() + budget)).0
+    }
+
+    /// Lays out a single node and its children without walking
+    /// the rest of the tree.
+    ///
+    /// Useful for small changes to a large tree, e.g. moving a
+    /// node while it is being dragged. The node is updated against
+    /// its parent's state from the last layout. If its size changes
+    /// then its parent is laid out as well, continuing upwards until
+    /// a node's size stays the same.
+    ///
+    /// Falls back to a full `layout` at the last size if the styles
+    /// changed since the last layout, a `layout_within` call is
+    /// unfinished or the node isn't attached to this manager.
+    pub fn layout_node(&mut self, node: &Node<E>) {
+        let size = self.last_size;
+        if self.dirty || self.pending_layout.is_some() || !self.is_attached(node) {
+            self.layout(size.0, size.1);
+            return;
+        }
+        let mut current = node.clone();
+        loop {
+            let parent = current.parent().expect("Attached node missing its parent");
+            let prev = current.inner.borrow().draw_rect;
+            if Rc::ptr_eq(&parent.inner, &self.root.inner) {
+                let p = NodeChain {
+                    parent: None,
+                    value: NCValue::Element("root"),
+                    draw_rect: Rect{x: 0, y: 0, width: size.0, height: size.1},
+                    properties: &FnvHashMap::default(),
+                    resolved: &FnvHashMap::default(),
+                };
+                let mut layout = AbsoluteLayout {
+                    size,
+                };
+                current.relayout(&mut self.styles, &p, &mut layout);
+                current.update_clip_rect(None, (0, 0), &mut self.dirty_rects, &mut Budget::unlimited());
+                break;
+            }
+
+            let styles = &mut self.styles;
+            let mut flags = DirtyFlags::empty();
+            let grandparent = parent.parent().expect("Attached node missing its parent");
+            grandparent.with_chain(&mut |gc| {
+                let inner: &mut _ = &mut *parent.inner.borrow_mut();
+                let p = NodeChain {
+                    parent: Some(gc),
+                    value: inner.value.as_chain(),
+                    draw_rect: inner.draw_rect,
+                    properties: &inner.properties,
+                    resolved: &inner.resolved,
+                };
+                flags = current.relayout(styles, &p, &mut *inner.layout);
+            });
+
+            let rect = current.inner.borrow().draw_rect;
+            if (rect.width, rect.height) == (prev.width, prev.height) && !flags.contains(DirtyFlags::SIZE) {
+                let (clip, offset) = parent.child_clip();
+                current.update_clip_rect(clip, offset, &mut self.dirty_rects, &mut Budget::unlimited());
+                break;
+            }
+            current = parent;
+        }
+        self.cap_dirty_rects();
+    }
+
+    /// Marks every node in the manager and its surfaces as
+    /// needing an update on their next layout
+    fn mark_dirty(&mut self) {
+        self.dirty = true;
+        for s in self.surfaces.values_mut() {
+            s.dirty = true;
+        }
+    }
+
+    /// Adds a new root node laid out and rendered separately to
+    /// the manager's root, returning it.
+    ///
+    /// Surfaces are useful for independent layers such as tooltips
+    /// or modals that have their own size. They share the manager's
+    /// styles and the root node matches style rules as `root`.
+    /// Adding a surface with a name already in use replaces it,
+    /// apart from `MAIN_SURFACE` which always returns the manager's
+    /// root.
+    pub fn add_surface(&mut self, name: &str) -> Node<E> {
+        if name == MAIN_SURFACE {
+            return self.root.clone();
+        }
+        let root = Node::root();
+        root.inner.borrow_mut().actions = Some(self.actions.clone());
+        self.surfaces.insert(name.into(), Surface {
+            root: root.clone(),
+            last_size: (0, 0),
+            dirty: true,
+            tree_version: 0,
+        });
+        root
+    }
+
+    /// Returns the root node of the surface with the given name
+    ///
+    /// `MAIN_SURFACE` is the manager's root.
+    pub fn surface(&self, name: &str) -> Option<Node<E>> {
+        if name == MAIN_SURFACE {
+            return Some(self.root.clone());
+        }
+        self.surfaces.get(name).map(|v| v.root.clone())
+    }
+
+    /// Removes the surface with the given name, returning
+    /// whether it existed
+    ///
+    /// `MAIN_SURFACE` can't be removed.
+    pub fn remove_surface(&mut self, name: &str) -> bool {
+        self.surfaces.remove(name).is_some()
+    }
+
+    /// Positions the nodes in the named surface like `layout`
+    /// does for the manager's root.
+    ///
+    /// Returns whether anything changed since the surface's last
+    /// layout, `false` if there isn't a surface with the name.
+    /// Areas changed within a surface aren't reported by
+    /// `take_dirty_rects` as it only covers the manager's root.
+    /// `MAIN_SURFACE` is laid out by `layout`.
+    pub fn layout_surface(&mut self, name: &str, width: i32, height: i32) -> bool {
+        if name == MAIN_SURFACE {
+            return self.layout(width, height);
+        }
+        let surface = match self.surfaces.get_mut(name) {
+            Some(v) => v,
+            None => return false,
+        };
+        let size = (width, height);
+        let flags = if surface.last_size != size {
+            surface.last_size = size;
+            DirtyFlags::SIZE
+        } else {
+            DirtyFlags::empty()
+        };
+        let force = ::std::mem::replace(&mut surface.dirty, false);
+        let mut state = PendingLayout::new(size, flags, force);
+
+        // Viewport sizes within the surface are relative to it
+        let viewport = ::std::mem::replace(&mut self.styles.viewport, size);
+        Manager::layout_root(&mut self.styles, &surface.root, &mut state, None, &mut Vec::new());
+        self.styles.viewport = viewport;
+        surface.root.inner.borrow_mut().removed_rects.clear();
+        let tree_version = surface.root.inner.borrow().tree_version;
+        let changed = state.changed || tree_version != surface.tree_version;
+        surface.tree_version = tree_version;
+        changed
+    }
+
+    /// Renders the nodes in the named surface like `render` does
+    /// for the manager's root.
+    ///
+    /// Returns `false` if there isn't a surface with the name.
+    /// `MAIN_SURFACE` is rendered by `render`.
+    pub fn render_surface<V>(&mut self, name: &str, visitor: &mut V) -> bool
+    where
+        V: RenderVisitor<E>,
+    {
+        if name == MAIN_SURFACE {
+            self.render(visitor);
+            return true;
+        }
+        match self.surfaces.get(name) {
+            Some(s) => {
+                s.root.render(visitor, 1.0);
+                true
+            },
+            None => false,
+        }
+    }
+
+    fn is_attached(&self, node: &Node<E>) -> bool {
+        let mut current = node.parent();
+        while let Some(p) = current {
+            if Rc::ptr_eq(&p.inner, &self.root.inner) {
+                return true;
+            }
+            current = p.parent();
+        }
+        false
+    }
+
+    fn layout_impl(&mut self, width: i32, height: i32, deadline: Option<Instant>) -> (LayoutStatus, bool) {
+        let size = (width, height);
+        let tree_version = self.root.inner.borrow().tree_version;
+        let mut state = match self.pending_layout.take() {
+            // Restarted if styles or nodes were changed after it was started
+            Some(v) if v.size == size && (v.force || !self.dirty) && v.tree_version == tree_version => v,
+            pending => {
+                // A restarted layout keeps the size change of the one
+                // it replaces as `last_size` has already been updated
+                let mut flags = pending.map_or(DirtyFlags::empty(), |v| v.flags);
+                if self.last_size != size {
+                    self.last_size = size;
+                    self.styles.viewport = size;
+                    flags |= DirtyFlags::SIZE;
+                }
+                PendingLayout {
+                    tree_version,
+                    .. PendingLayout::new(size, flags, self.dirty)
+                }
+            },
+        };
+        self.dirty_rects.append(&mut self.root.inner.borrow_mut().removed_rects);
+        let status = Manager::layout_root(&mut self.styles, &self.root, &mut state, deadline, &mut self.dirty_rects);
+        // Cleared once the first pass has updated every node
+        if !state.force {
+            self.dirty = false;
+        }
+        self.cap_dirty_rects();
+        let mut changed = state.changed;
+        if status == LayoutStatus::Incomplete {
+            self.pending_layout = Some(state);
+        } else {
+            changed |= state.tree_version != self.tree_version;
+            self.tree_version = state.tree_version;
+            debug_assert!(self.check_invariants().is_ok(), "Broken node tree: {:?}", self.check_invariants());
+        }
+        (status, changed)
+    }
+
+    /// Updates and lays out the children of a root node, either
+    /// the manager's or a surface's, continuing from `state`.
+    ///
+    /// Stops early if the deadline passes, `state` can be passed
+    /// again to continue.
+    fn layout_root(styles: &mut Styles<E>, root: &Node<E>, state: &mut PendingLayout, deadline: Option<Instant>, dirty_rects: &mut Vec<Rect>) -> LayoutStatus {
+        let size = state.size;
+
+        let mut inner = root.inner.borrow_mut();
+        inner.draw_rect = Rect{x: 0, y: 0, width: size.0, height: size.1};
+
+        let p = NodeChain {
+            parent: None,
+            value: NCValue::Element("root"),
+            draw_rect: inner.draw_rect,
+            properties: &FnvHashMap::default(),
+            resolved: &FnvHashMap::default(),
+        };
+
+        let mut layout = AbsoluteLayout {
+            size,
+        };
+
+        let children: &[Node<E>] = if let NodeValue::Element(ref v) = inner.value {
+            &v.children
+        } else {
+            &[]
+        };
+
+        let mut budget = Budget::new(deadline, ::std::mem::take(&mut state.path));
+        loop {
+            if budget.out_of_time() {
+                state.path = budget.path;
+                return LayoutStatus::Incomplete;
+            }
+            // Each step only moves on once the node finished,
+            // otherwise the same node is continued next time
+            match state.step {
+                LayoutStep::Update(idx) => if let Some(c) = children.get(idx) {
+                    let update = ParentUpdate {
+                        styles_updated: state.force,
+                        parent_dirty: state.flags == DirtyFlags::SIZE,
+                        flags: state.flags,
+                    };
+                    c.do_update(styles, &p, &mut layout, update, &mut budget);
+                    if !budget.stopped {
+                        state.step = LayoutStep::Update(idx + 1);
+                    }
+                } else {
+                    state.step = LayoutStep::Measure(0);
+                },
+                LayoutStep::Measure(idx) => if let Some(c) = children.get(idx) {
+                    c.measure(&*styles.text_measure, &mut budget);
+                    if !budget.stopped {
+                        state.step = LayoutStep::Layout(idx);
+                    }
+                } else {
+                    state.force = false;
+                    // This repeats due to the `parent_X` support requiring
+                    // the layout to be computed so it can be used in style rules
+                    // creating a chicken/egg problem. If they aren't used then
+                    // this will only execute once.
+                    state.passes += 1;
+                    state.step = if !state.properties_changed {
+                        LayoutStep::Clip(0)
+                    } else if state.passes >= styles.max_layout_passes {
+                        styles.report_unsettled(state.passes, children);
+                        LayoutStep::Clip(0)
+                    } else {
+                        LayoutStep::Update(0)
+                    };
+                    state.properties_changed = false;
+                },
+                LayoutStep::Layout(idx) => if let Some(c) = children.get(idx) {
+                    let properties_changed = c.layout(styles, &mut layout, &mut state.changed, &mut budget);
+                    if !budget.stopped {
+                        state.properties_changed |= properties_changed;
+                        state.step = LayoutStep::Measure(idx + 1);
+                    }
+                } else {
+                    state.step = LayoutStep::Measure(idx);
+                },
+                LayoutStep::Clip(idx) => if let Some(c) = children.get(idx) {
+                    c.update_clip_rect(None, (0, 0), dirty_rects, &mut budget);
+                    if !budget.stopped {
+                        state.step = LayoutStep::Clip(idx + 1);
+                    }
+                } else {
+                    return LayoutStatus::Complete;
+                },
+            }
+        }
+    }
+
+    /// Evaluates a style expression against the given node without
+    /// modifying any styles.
+    ///
+    /// The node's properties can be used as variables along with
+    /// `parent_width` and `parent_height`. Should be called after
+    /// a `layout` call for the parent's size to be correct.
+    pub fn evaluate<'a>(&self, node: &Node<E>, expr: &'a str) -> FResult<'a, Value<E>> {
+        let expr = syntax::style::ExprType::parse(expr)
+            .map_err(|err| Error::Custom {
+                reason: format!("Failed to parse expression: {}", err),
+            })?;
+
+        let inner = node.inner.borrow();
+        Expr::check_names(&self.styles.funcs, &self.styles.static_keys, &inner.properties, &expr)?;
+        let replacements = inner.properties.keys()
+            .map(|k| (k.clone(), (0, k.clone())))
+            .collect();
+        let expr: Expr<E> = Expr::from_style(Some(&self.styles.static_keys), &replacements, &mut false, expr)
+            .map_err(|err| Error::Custom {
+                reason: format!("Failed to parse expression: {}", err),
+            })?;
+
+        let parent = node.parent();
+        let parent_inner = parent.as_ref().map(|v| v.inner.borrow());
+        let parent_chain = parent_inner.as_ref().map(|p| NodeChain {
+            parent: None,
+            value: p.value.as_chain(),
+            draw_rect: p.draw_rect,
+            properties: &p.properties,
+            resolved: &p.resolved,
+        });
+        let nc = NodeChain {
+            parent: parent_chain.as_ref(),
+            value: inner.value.as_chain(),
+            draw_rect: inner.draw_rect,
+            properties: &inner.properties,
+            resolved: &inner.resolved,
+        };
+        expr.eval(&self.styles, &nc)
+            .map_err(|err| err.into_owned())
+    }
+
+    /// Returns a snapshot of the positions and properties of every
+    /// node in this manager.
+    ///
+    /// Should be called after a `layout` call.
+    pub fn export_layout(&self) -> LayoutDump {
+        LayoutDump {
+            nodes: self.root.children()
+                .iter()
+                .map(|v| v.export_layout())
+                .collect(),
+        }
+    }
+
+    /// Returns an indented view of every node in this manager
+    /// for debugging.
+    ///
+    /// See [`Node::dump`](struct.Node.html#method.dump) for the format.
+    pub fn dump_tree(&self) -> String {
+        let mut out = String::new();
+        for c in self.root.children() {
+            c.dump_into(0, &mut out);
+        }
+        out
+    }
+
+    /// Returns the nodes flagged as dirty by the last `layout`
+    /// call along with their flags, in depth first order.
+    ///
+    /// Useful for checking that a change only invalidates the
+    /// nodes it should. If the layout had to update the nodes
+    /// more than once (e.g. due to `parent_width`) only the
+    /// flags from the final update are included.
+    ///
+    /// Requires the `debug` feature.
+    #[cfg(feature = "debug")]
+    pub fn dirty_report(&self) -> Vec<(Node<E>, DirtyFlags)> {
+        self.root.descendants()
+            .filter_map(|n| {
+                let flags = n.inner.borrow().dirty_flags;
+                if flags.is_empty() {
+                    None
+                } else {
+                    Some((n, flags))
+                }
+            })
+            .collect()
+    }
+
+    /// Returns and clears the areas that changed during the
+    /// layouts since the last call.
+    ///
+    /// A node that moved or resized reports both its old and its
+    /// new area in absolute coordinates. Nodes that are removed
+    /// report the areas they and their children last covered.
+    /// Once more than 256 areas build up they are replaced by the
+    /// whole viewport.
+    pub fn take_dirty_rects(&mut self) -> Vec<Rect> {
+        self.dirty_rects.append(&mut self.root.inner.borrow_mut().removed_rects);
+        self.cap_dirty_rects();
+        ::std::mem::take(&mut self.dirty_rects)
+    }
+
+    // Stops the areas growing without bound if `take_dirty_rects`
+    // is never called. Nothing else is worth keeping once the whole
+    // viewport is dirty.
+    fn cap_dirty_rects(&mut self) {
+        let size = self.last_size;
+        let viewport = Rect{x: 0, y: 0, width: size.0, height: size.1};
+        if self.dirty_rects.len() > MAX_DIRTY_RECTS
+            || (self.dirty_rects.len() > 1 && self.dirty_rects[0] == viewport)
+        {
+            self.dirty_rects = vec![viewport];
+        }
+    }
+
+    /// Renders the nodes in this manager by passing the draw position/size
+    /// and style properties to the visitor
+    pub fn render<V>(&mut self, visitor: &mut V)
+    where
+        V: RenderVisitor<E>,
+    {
+        self.root.render(visitor, 1.0);
+    }
+
+    /// Renders the nodes in this manager like `render` but skips
+    /// nodes that aren't visible.
+    ///
+    /// A node is skipped if it is outside of the area passed to
+    /// the last `layout` call or is completely hidden by a parent
+    /// clipping its children. Its children are skipped with it
+    /// unless one of them can be seen, which is only possible if
+    /// the node doesn't clip them. Partially visible nodes are
+    /// still visited and can use their `clip_rect` to clip
+    /// themselves.
+    pub fn render_visible<V>(&mut self, visitor: &mut V)
+    where
+        V: RenderVisitor<E>,
+    {
+        let inner: &mut _ = &mut *self.root.inner.borrow_mut();
+        let viewport = inner.draw_rect;
+        inner.render_opacity = inner.opacity;
+        visitor.visit(inner);
+        let opacity = inner.render_opacity;
+        if let NodeValue::Element(ref v) = inner.value {
+            Node::in_render_order(&v.children, |c| c.render_visible(visitor, viewport, (0, 0), opacity));
+        }
+        visitor.visit_end(inner);
+    }
+}
+
+/// The position and size of an node
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize))]
+pub struct Rect {
+    /// The x position of the node
+    pub x: i32,
+    /// The y position of the node
+    pub y: i32,
+    /// The width of the node
+    pub width: i32,
+    /// The height of the node
+    pub height: i32,
+}
+
+impl Rect {
+    /// Returns the smallest rect that contains both this
+    /// rect and the passed one.
+    pub fn union(self, other: Rect) -> Rect {
+        use std::cmp::{min, max};
+        let x = min(self.x, other.x);
+        let y = min(self.y, other.y);
+        Rect {
+            x,
+            y,
+            width: max(self.x + self.width, other.x + other.width) - x,
+            height: max(self.y + self.height, other.y + other.height) - y,
+        }
+    }
+
+    /// Returns the area shared by both this rect and the
+    /// passed one.
+    ///
+    /// If they don't overlap then the returned rect will
+    /// have a width and/or height of zero.
+    pub fn intersection(self, other: Rect) -> Rect {
+        use std::cmp::{min, max};
+        let x = max(self.x, other.x);
+        let y = max(self.y, other.y);
+        Rect {
+            x,
+            y,
+            width: max(0, min(self.x + self.width, other.x + other.width) - x),
+            height: max(0, min(self.y + self.height, other.y + other.height) - y),
+        }
+    }
+
+    /// Returns whether the given point is within this rect
+    pub fn contains(&self, x: i32, y: i32) -> bool {
+        x >= self.x && x < self.x + self.width
+            && y >= self.y && y < self.y + self.height
+    }
+}
+
+/// The minimum and maximum size of a node as set by the
+/// `min_width`, `max_width`, `min_height` and `max_height`
+/// style properties.
+///
+/// These are applied after the size of the node has been
+/// computed so they take priority over explicit sizes (e.g.
+/// `width`). If the minimum is larger than the maximum then
+/// the minimum is used.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub struct SizeConstraints {
+    /// The minimum width of the node
+    pub min_width: Option<i32>,
+    /// The maximum width of the node
+    pub max_width: Option<i32>,
+    /// The minimum height of the node
+    pub min_height: Option<i32>,
+    /// The maximum height of the node
+    pub max_height: Option<i32>,
+}
+
+impl SizeConstraints {
+    /// Returns the passed rect with its size limited by
+    /// these constraints.
+    pub fn apply(&self, mut rect: Rect) -> Rect {
+        if let Some(max) = self.max_width {
+            rect.width = rect.width.min(max);
+        }
+        if let Some(min) = self.min_width {
+            rect.width = rect.width.max(min);
+        }
+        if let Some(max) = self.max_height {
+            rect.height = rect.height.min(max);
+        }
+        if let Some(min) = self.min_height {
+            rect.height = rect.height.max(min);
+        }
+        rect
+    }
+}
+
+/// Called for every node in a manager to allow them to
+/// be rendered.
+pub trait RenderVisitor<E: Extension> {
+    /// Called per node before visiting their children
+    fn visit(&mut self, node: &mut NodeInner<E>);
+    /// Called per node after visiting their children
+    fn visit_end(&mut self, node: &mut NodeInner<E>);
+}
+
+/// A node representing an element or text.
+///
+/// Can be cloned to duplicate the reference to the node.
+pub struct Node<E: Extension> {
+    inner: Rc<RefCell<NodeInner<E>>>,
+}
+
+impl<E: Extension> Clone for Node<E> {
+    fn clone(&self) -> Self {
+        Node {
+            inner: self.inner.clone(),
+        }
+    }
+}
+
+/// Tries to find and evalulate a given style property in a rule.
+///
+/// This will skip properties that have already been set previously
+/// in the update. Should only be used during an `update_(child_)data`
+/// call. Failed evaluations are reported to the styles and can be
+/// retrieved via `Manager::take_errors`.
+///
+/// ```ignore
+/// eval!(styles, nc, rule.MY_PROP => val => {
+///     // This will only run if MY_PROP was set in the rule
+///     // val will be a `Value` containing what the property
+///     // was set too
+/// });
+/// ```
+#[macro_export]
+macro_rules! eval {
+    ($styles:expr, $n:expr, $rule:ident.$key:expr => $ret:ident => $ok:block) => {
+        if !$styles.key_was_used(&$key) {
+            if let Some(e) = $rule.styles.get(&$key) {
+                match e.eval($styles, &$n) {
+                    Ok($ret) => $ok,
+                    Err(err) => $styles.report_error($key, e.to_string(), err),
+                }
+            }
+        }
+    };
+}
+
+impl<E: Extension> Node<E> {
+
+    fn do_update(
+        &self,
+        styles: &mut Styles<E>,
         parent: &NodeChain<E>,
         parent_layout: &mut dyn BoxLayoutEngine<E>,
-        mut styles_updated: bool, mut parent_dirty: bool,
-        parent_flags: DirtyFlags,
+        update: ParentUpdate,
+        budget: &mut Budget,
     ) -> DirtyFlags
+    {
+        let inner: &mut _ = &mut *self.inner.borrow_mut();
+        let mut frame = match budget.resume() {
+            Some(v) => v,
+            None => Node::update_self(inner, styles, parent, parent_layout, update),
+        };
+        let p = NodeChain {
+            parent: Some(parent),
+            value: inner.value.as_chain(),
+            draw_rect: inner.draw_rect,
+            properties: &inner.properties,
+            resolved: &inner.resolved,
+        };
+        if let NodeValue::Element(ref v) = inner.value {
+            while let Some(c) = v.children.get(frame.child) {
+                if budget.out_of_time() {
+                    budget.save(frame);
+                    return DirtyFlags::empty();
+                }
+                let update = ParentUpdate {
+                    styles_updated: frame.styles_updated,
+                    parent_dirty: frame.parent_dirty,
+                    flags: inner.dirty_flags,
+                };
+                let flags = c.do_update(styles, &p, &mut *inner.layout, update, budget);
+                if budget.stopped {
+                    budget.save(frame);
+                    return DirtyFlags::empty();
+                }
+                frame.child_flags |= flags;
+                frame.child += 1;
+            }
+        }
+        inner.dirty_flags |= inner.layout.check_child_flags(frame.child_flags);
+
+        E::check_flags(&mut inner.ext, inner.dirty_flags);
+
+        inner.dirty_flags
+    }
+
+    /// Updates the node's own state for `do_update` before its
+    /// children are updated
+    fn update_self(
+        inner: &mut NodeInner<E>,
+        styles: &mut Styles<E>,
+        parent: &NodeChain<E>,
+        parent_layout: &mut dyn BoxLayoutEngine<E>,
+        update: ParentUpdate,
+    ) -> WalkFrame
     {
         use std::mem::replace;
 
-        let inner: &mut _ = &mut *self.inner.borrow_mut();
+        let ParentUpdate { mut styles_updated, mut parent_dirty, flags: parent_flags } = update;
         let props_dirty = replace(&mut inner.properties_changed, false);
         let rules_dirty = replace(&mut inner.rules_dirty, false);
         inner.dirty_flags = DirtyFlags::empty();
@@ -630,18 +2066,57 @@ impl<E: Extension> Node<E> {
                 value: inner.value.as_chain(),
                 draw_rect: inner.draw_rect,
                 properties: &inner.properties,
+                resolved: &inner.resolved,
             };
             styles.rules.get_possible_matches(&c, &mut inner.possible_rules);
         }
+        if styles_updated {
+            // Rebuilt as the style keys may have changed
+            inner.remove_expr_rule();
+        }
+        if inner.expr_rule.is_none() && !inner.expr_properties.is_empty() {
+            let rule = styles.expr_rule(&inner.expr_properties);
+            let pos = match inner.possible_rules.binary_search_by_key(&rule.order(), |v| v.order()) {
+                Ok(v) | Err(v) => v,
+            };
+            inner.possible_rules.insert(pos, rule.clone());
+            inner.expr_rule = Some(rule);
+        }
         if parent_dirty || props_dirty {
             parent_dirty = true;
+            let mut resolved = std::mem::take(&mut inner.resolved);
+            resolved.clear();
             let c = NodeChain {
                 parent: Some(parent),
                 value: inner.value.as_chain(),
                 draw_rect: inner.draw_rect,
                 properties: &inner.properties,
+                resolved: &inner.resolved,
             };
             styles.used_keys.clear();
+            // Only keys that some rule inherits or sets to an action
+            // are stored, picking the value of the first rule setting
+            // them like `eval!`
+            if !styles.inherited_keys.is_empty() || !styles.action_keys.is_empty() {
+                for rule in inner.possible_rules.iter().rev() {
+                    if !rule.test(&c) {
+                        continue;
+                    }
+                    for key in styles.inherited_keys.iter().chain(&styles.action_keys) {
+                        if styles.used_keys.contains(key) {
+                            continue;
+                        }
+                        if let Some(e) = rule.styles.get(key) {
+                            let val = e.eval(styles, &c).ok();
+                            styles.used_keys.insert(*key);
+                            if let Some(val) = val {
+                                resolved.insert(*key, val);
+                            }
+                        }
+                    }
+                }
+                styles.used_keys.clear();
+            }
             inner.uses_parent_size = false;
             for rule in inner.possible_rules.iter().rev() {
                 if rule.test(&c) {
@@ -675,6 +2150,48 @@ impl<E: Extension> Node<E> {
                     eval!(styles, c, rule.CLIP_OVERFLOW => val => {
                         inner.clip_overflow = val.convert().unwrap_or(false);
                     });
+                    eval!(styles, c, rule.OPACITY => val => {
+                        let new = val.convert::<f64>().unwrap_or(1.0).clamp(0.0, 1.0) as f32;
+                        if inner.opacity != new {
+                            inner.opacity = new;
+                            inner.dirty_flags |= DirtyFlags::OPACITY;
+                        }
+                    });
+                    eval!(styles, c, rule.Z_INDEX => val => {
+                        let new = val.convert().unwrap_or(0);
+                        if inner.z_index != new {
+                            inner.z_index = new;
+                            inner.dirty_flags |= DirtyFlags::POSITION;
+                        }
+                    });
+                    eval!(styles, c, rule.MIN_WIDTH => val => {
+                        let new = val.convert();
+                        if inner.size_constraints.min_width != new {
+                            inner.size_constraints.min_width = new;
+                            inner.dirty_flags |= DirtyFlags::SIZE;
+                        }
+                    });
+                    eval!(styles, c, rule.MAX_WIDTH => val => {
+                        let new = val.convert();
+                        if inner.size_constraints.max_width != new {
+                            inner.size_constraints.max_width = new;
+                            inner.dirty_flags |= DirtyFlags::SIZE;
+                        }
+                    });
+                    eval!(styles, c, rule.MIN_HEIGHT => val => {
+                        let new = val.convert();
+                        if inner.size_constraints.min_height != new {
+                            inner.size_constraints.min_height = new;
+                            inner.dirty_flags |= DirtyFlags::SIZE;
+                        }
+                    });
+                    eval!(styles, c, rule.MAX_HEIGHT => val => {
+                        let new = val.convert();
+                        if inner.size_constraints.max_height != new {
+                            inner.size_constraints.max_height = new;
+                            inner.dirty_flags |= DirtyFlags::SIZE;
+                        }
+                    });
                     inner.dirty_flags |= E::update_data(styles, &c, rule, &mut inner.ext);
                     inner.dirty_flags |= inner.layout.update_data(styles, &c, rule);
                     inner.dirty_flags |= parent_layout.update_child_data(styles, &c, rule, &mut inner.parent_data);
@@ -682,9 +2199,34 @@ impl<E: Extension> Node<E> {
                     styles.used_keys.extend(rule.styles.keys());
                 }
             }
+            inner.resolved = resolved;
             if !styles.used_keys.contains(&CLIP_OVERFLOW) {
                 inner.clip_overflow = false;
             }
+            if !styles.used_keys.contains(&OPACITY) && inner.opacity != 1.0 {
+                inner.opacity = 1.0;
+                inner.dirty_flags |= DirtyFlags::OPACITY;
+            }
+            if !styles.used_keys.contains(&Z_INDEX) && inner.z_index != 0 {
+                inner.z_index = 0;
+                inner.dirty_flags |= DirtyFlags::POSITION;
+            }
+            if !styles.used_keys.contains(&MIN_WIDTH) && inner.size_constraints.min_width.is_some() {
+                inner.size_constraints.min_width = None;
+                inner.dirty_flags |= DirtyFlags::SIZE;
+            }
+            if !styles.used_keys.contains(&MAX_WIDTH) && inner.size_constraints.max_width.is_some() {
+                inner.size_constraints.max_width = None;
+                inner.dirty_flags |= DirtyFlags::SIZE;
+            }
+            if !styles.used_keys.contains(&MIN_HEIGHT) && inner.size_constraints.min_height.is_some() {
+                inner.size_constraints.min_height = None;
+                inner.dirty_flags |= DirtyFlags::SIZE;
+            }
+            if !styles.used_keys.contains(&MAX_HEIGHT) && inner.size_constraints.max_height.is_some() {
+                inner.size_constraints.max_height = None;
+                inner.dirty_flags |= DirtyFlags::SIZE;
+            }
             if !styles.used_keys.contains(&SCROLL_X) {
                 inner.scroll_position.0 = 0.0;
                 inner.dirty_flags |= DirtyFlags::SCROLL;
@@ -696,50 +2238,108 @@ impl<E: Extension> Node<E> {
             inner.dirty_flags |= E::reset_unset_data(&styles.used_keys, &mut inner.ext);
             inner.dirty_flags |= inner.layout.reset_unset_data(&styles.used_keys);
             inner.dirty_flags |= parent_layout.reset_unset_child_data(&styles.used_keys, &mut inner.parent_data);
-
-        }
-        inner.dirty_flags |= inner.layout.check_parent_flags(parent_flags);
-        let mut child_flags = DirtyFlags::empty();
-        let p = NodeChain {
-            parent: Some(parent),
-            value: inner.value.as_chain(),
-            draw_rect: inner.draw_rect,
-            properties: &inner.properties,
+
+        }
+        inner.dirty_flags |= inner.layout.check_parent_flags(parent_flags);
+        WalkFrame {
+            styles_updated,
+            parent_dirty,
+            .. WalkFrame::default()
+        }
+    }
+
+    /// Computes the intrinsic size of this node and its children.
+    ///
+    /// Children are measured first so a layout's `measure` can
+    /// use the sizes of its children before they are laid out.
+    fn measure(&self, text: &dyn TextMeasure, budget: &mut Budget) {
+        let inner: &mut _ = &mut *self.inner.borrow_mut();
+        let nodes = if let NodeValue::Element(ref v) = inner.value {
+            v.children.as_slice()
+        } else {
+            &[]
         };
-        if let NodeValue::Element(ref v) = inner.value {
-            for c in &v.children {
-                child_flags |= c.do_update(styles, &p, &mut *inner.layout, styles_updated, parent_dirty, inner.dirty_flags);
+        let mut frame = budget.resume().unwrap_or_default();
+        while let Some(c) = nodes.get(frame.child) {
+            if budget.out_of_time() {
+                budget.save(frame);
+                return;
             }
+            c.measure(text, budget);
+            if budget.stopped {
+                budget.save(frame);
+                return;
+            }
+            frame.child += 1;
         }
-        inner.dirty_flags |= inner.layout.check_child_flags(child_flags);
-
-        E::check_flags(&mut inner.ext, inner.dirty_flags);
-
-        inner.dirty_flags
+        inner.intrinsic_size = inner.layout.measure(nodes, text);
     }
 
     fn layout(
         &self,
         styles: &Styles<E>,
         parent_layout: &mut dyn BoxLayoutEngine<E>,
+        changed: &mut bool,
+        budget: &mut Budget,
     ) -> bool {
         let inner: &mut _ = &mut *self.inner.borrow_mut();
-        inner.done_layout = true;
         let nodes = if let NodeValue::Element(ref v) = inner.value {
             v.children.as_slice()
         } else {
             &[]
         };
-        inner.draw_rect = parent_layout.do_layout(&inner.value, &mut inner.ext, &mut inner.parent_data, inner.draw_rect, inner.dirty_flags);
-        inner.draw_rect = inner.layout.start_layout(&mut inner.ext, inner.draw_rect, inner.dirty_flags, nodes);
+        let mut frame = match budget.resume() {
+            Some(v) => v,
+            None => {
+                inner.done_layout = true;
+                // Applied before the parent's layout so any size it
+                // sets explicitly takes priority
+                if let Some(w) = inner.intrinsic_size.0 {
+                    inner.draw_rect.width = w;
+                }
+                if let Some(h) = inner.intrinsic_size.1 {
+                    inner.draw_rect.height = h;
+                }
+                inner.draw_rect = parent_layout.do_layout(&inner.value, &mut inner.ext, &mut inner.parent_data, inner.draw_rect, inner.dirty_flags);
+                inner.draw_rect = inner.size_constraints.apply(inner.draw_rect);
+                inner.draw_rect = inner.layout.start_layout(&mut inner.ext, inner.draw_rect, inner.size_constraints, inner.dirty_flags, nodes, &*styles.text_measure);
+                WalkFrame::default()
+            },
+        };
 
-        let mut properties_changed = false;
-        for c in nodes {
-            properties_changed |= c.layout(styles, &mut *inner.layout);
+        while let Some(c) = nodes.get(frame.child) {
+            if budget.out_of_time() {
+                budget.save(frame);
+                return false;
+            }
+            let properties_changed = c.layout(styles, &mut *inner.layout, changed, budget);
+            if budget.stopped {
+                budget.save(frame);
+                return false;
+            }
+            frame.properties_changed |= properties_changed;
+            frame.child += 1;
+            let mut c = c.inner.borrow_mut();
+            if c.dirty_flags.contains(DirtyFlags::REQUEST_PARENT) {
+                c.dirty_flags.remove(DirtyFlags::REQUEST_PARENT);
+                inner.properties_changed = true;
+            }
         }
-        inner.draw_rect = inner.layout.finish_layout(&mut inner.ext, inner.draw_rect, inner.dirty_flags, nodes);
+        let mut properties_changed = frame.properties_changed;
+        inner.draw_rect = inner.layout.finish_layout(&mut inner.ext, inner.draw_rect, inner.dirty_flags, nodes, &*styles.text_measure);
+        let requested = inner.layout.requested_flags() & DirtyFlags::REQUEST_PARENT;
+        if !requested.is_empty() {
+            // Returning true makes the caller run another
+            // update and layout pass
+            inner.dirty_flags |= requested;
+            properties_changed = true;
+        }
+        inner.draw_rect = inner.size_constraints.apply(inner.draw_rect);
         inner.draw_rect = parent_layout.do_layout_end(&inner.value, &mut inner.ext, &mut inner.parent_data, inner.draw_rect, inner.dirty_flags);
 
+        if !inner.dirty_flags.is_empty() || inner.draw_rect != inner.prev_rect {
+            *changed = true;
+        }
         if inner.draw_rect != inner.prev_rect {
             for c in nodes {
                 let mut c = c.inner.borrow_mut();
@@ -753,20 +2353,247 @@ impl<E: Extension> Node<E> {
         properties_changed
     }
 
-    fn render<V>(&self, visitor: &mut V)
+    /// Updates and lays out this node and its children against
+    /// the parent's state from the last layout, returning the
+    /// node's dirty flags.
+    fn relayout(
+        &self,
+        styles: &mut Styles<E>,
+        parent: &NodeChain<E>,
+        parent_layout: &mut dyn BoxLayoutEngine<E>,
+    ) -> DirtyFlags {
+        let mut flags = DirtyFlags::empty();
+        // Repeats for the same reason as `Manager::layout`
+        for passes in 1 .. {
+            let update = ParentUpdate {
+                styles_updated: false,
+                parent_dirty: false,
+                flags: DirtyFlags::empty(),
+            };
+            flags |= self.do_update(styles, parent, parent_layout, update, &mut Budget::unlimited());
+            self.measure(&*styles.text_measure, &mut Budget::unlimited());
+            if !self.layout(styles, parent_layout, &mut false, &mut Budget::unlimited()) {
+                break;
+            }
+            if passes >= styles.max_layout_passes {
+                styles.report_unsettled(passes, ::std::slice::from_ref(self));
+                break;
+            }
+        }
+        flags
+    }
+
+    /// Adds this node and its descendants that are waiting on
+    /// another update to `out`
+    fn collect_unsettled(&self, out: &mut Vec<Node<E>>) {
+        let inner = self.inner.borrow();
+        if inner.properties_changed {
+            out.push(self.clone());
+        }
+        if let NodeValue::Element(ref v) = inner.value {
+            for c in &v.children {
+                c.collect_unsettled(out);
+            }
+        }
+    }
+
+    /// Calls the function with the `NodeChain` of this node
+    /// built from the node's ancestors
+    fn with_chain(&self, f: &mut dyn FnMut(&NodeChain<E>)) {
+        let inner = self.inner.borrow();
+        match self.parent() {
+            Some(p) => p.with_chain(&mut |pc| f(&NodeChain {
+                parent: Some(pc),
+                value: inner.value.as_chain(),
+                draw_rect: inner.draw_rect,
+                properties: &inner.properties,
+                resolved: &inner.resolved,
+            })),
+            // Matches the root chain used by `Manager::layout`
+            None => f(&NodeChain {
+                parent: None,
+                value: NCValue::Element("root"),
+                draw_rect: inner.draw_rect,
+                properties: &FnvHashMap::default(),
+                resolved: &FnvHashMap::default(),
+            }),
+        }
+    }
+
+    /// Returns the clip rect and offset this node passes to its
+    /// children in `update_clip_rect`
+    fn child_clip(&self) -> (Option<Rect>, (i32, i32)) {
+        let (clip, rect) = {
+            let inner = self.inner.borrow();
+            (inner.clip_rect, inner.draw_rect)
+        };
+        let mut offset = (0, 0);
+        let mut current = Some(self.clone());
+        while let Some(node) = current {
+            current = node.parent();
+            // The root isn't offset by `update_clip_rect`
+            if current.is_none() {
+                break;
+            }
+            let inner = node.inner.borrow();
+            offset.0 += inner.draw_rect.x + inner.scroll_position.0 as i32;
+            offset.1 += inner.draw_rect.y + inner.scroll_position.1 as i32;
+        }
+        let inner = self.inner.borrow();
+        let child_clip = if inner.clip_overflow {
+            let rect = Rect {
+                x: offset.0 - inner.scroll_position.0 as i32,
+                y: offset.1 - inner.scroll_position.1 as i32,
+                .. rect
+            };
+            Some(clip.map_or(rect, |v| v.intersection(rect)))
+        } else {
+            clip
+        };
+        (child_clip, offset)
+    }
+
+    fn update_clip_rect(&self, clip: Option<Rect>, offset: (i32, i32), dirty_rects: &mut Vec<Rect>, budget: &mut Budget) {
+        let inner: &mut _ = &mut *self.inner.borrow_mut();
+        let rect = Rect {
+            x: offset.0 + inner.draw_rect.x,
+            y: offset.1 + inner.draw_rect.y,
+            .. inner.draw_rect
+        };
+        let mut frame = budget.resume();
+        if frame.is_none() {
+            inner.clip_rect = clip;
+        }
+        if frame.is_none() && rect != inner.prev_absolute_rect {
+            // Both the area the node left and the area it now
+            // covers need redrawing
+            let prev = ::std::mem::replace(&mut inner.prev_absolute_rect, rect);
+            for r in &[prev, rect] {
+                if r.width > 0 && r.height > 0 {
+                    dirty_rects.push(*r);
+                }
+            }
+        }
+        let child_clip = if inner.clip_overflow {
+            Some(clip.map_or(rect, |v| v.intersection(rect)))
+        } else {
+            clip
+        };
+        let child_offset = (
+            rect.x + inner.scroll_position.0 as i32,
+            rect.y + inner.scroll_position.1 as i32,
+        );
+        if let NodeValue::Element(ref v) = inner.value {
+            let frame = frame.get_or_insert_with(WalkFrame::default);
+            while let Some(c) = v.children.get(frame.child) {
+                if budget.out_of_time() {
+                    budget.save(*frame);
+                    return;
+                }
+                c.update_clip_rect(child_clip, child_offset, dirty_rects, budget);
+                if budget.stopped {
+                    budget.save(*frame);
+                    return;
+                }
+                frame.child += 1;
+            }
+        }
+    }
+
+    fn render<V>(&self, visitor: &mut V, parent_opacity: f32)
     where
         V: RenderVisitor<E>,
     {
         let inner: &mut _ = &mut *self.inner.borrow_mut();
+        inner.render_opacity = parent_opacity * inner.opacity;
         visitor.visit(inner);
+        let opacity = inner.render_opacity;
         if let NodeValue::Element(ref v) = inner.value {
-            for c in &v.children {
-                c.render(visitor);
-            }
+            Node::in_render_order(&v.children, |c| c.render(visitor, opacity));
+        }
+        visitor.visit_end(inner);
+    }
+
+    /// Calls the function with each node sorted by `z_index`,
+    /// keeping their order when they have the same `z_index`
+    fn in_render_order<F>(children: &[Node<E>], mut f: F)
+        where F: FnMut(&Node<E>)
+    {
+        match Node::render_order(children) {
+            Some(order) => for idx in order {
+                f(&children[idx]);
+            },
+            None => children.iter().for_each(f),
+        }
+    }
+
+    /// Returns the indices of the nodes sorted by `z_index` or
+    /// `None` if none of them set one
+    fn render_order(children: &[Node<E>]) -> Option<Vec<usize>> {
+        if children.iter().all(|c| c.inner.borrow().z_index == 0) {
+            return None;
+        }
+        let mut order: Vec<_> = (0 .. children.len()).collect();
+        order.sort_by_key(|&idx| children[idx].inner.borrow().z_index);
+        Some(order)
+    }
+
+    fn render_visible<V>(&self, visitor: &mut V, viewport: Rect, offset: (i32, i32), parent_opacity: f32)
+    where
+        V: RenderVisitor<E>,
+    {
+        let inner: &mut _ = &mut *self.inner.borrow_mut();
+        let (visible, child_offset) = Node::visible_area(inner, viewport, offset);
+        let children: &[Node<E>] = if let NodeValue::Element(ref v) = inner.value {
+            &v.children
+        } else {
+            &[]
+        };
+        // Children can be outside of their parent unless it clips them
+        if !visible && (inner.clip_overflow || !Node::any_visible(children, viewport, child_offset)) {
+            return;
+        }
+        inner.render_opacity = parent_opacity * inner.opacity;
+        visitor.visit(inner);
+        let opacity = inner.render_opacity;
+        if let NodeValue::Element(ref v) = inner.value {
+            Node::in_render_order(&v.children, |c| c.render_visible(visitor, viewport, child_offset, opacity));
         }
         visitor.visit_end(inner);
     }
 
+    /// Returns whether any of the nodes or their descendants can
+    /// be seen within the viewport
+    fn any_visible(nodes: &[Node<E>], viewport: Rect, offset: (i32, i32)) -> bool {
+        nodes.iter().any(|n| {
+            let inner = n.inner.borrow();
+            let (visible, child_offset) = Node::visible_area(&inner, viewport, offset);
+            visible || (!inner.clip_overflow && match inner.value {
+                NodeValue::Element(ref v) => Node::any_visible(&v.children, viewport, child_offset),
+                NodeValue::Text(..) => false,
+            })
+        })
+    }
+
+    /// Returns whether the node can be seen within the viewport
+    /// and the offset of its children
+    fn visible_area(inner: &NodeInner<E>, viewport: Rect, offset: (i32, i32)) -> (bool, (i32, i32)) {
+        // Matches the positions computed by `update_clip_rect`
+        let rect = Rect {
+            x: offset.0 + inner.draw_rect.x,
+            y: offset.1 + inner.draw_rect.y,
+            .. inner.draw_rect
+        };
+        let visible = inner.clip_rect
+            .map_or(rect, |v| v.intersection(rect))
+            .intersection(viewport);
+        let child_offset = (
+            rect.x + inner.scroll_position.0 as i32,
+            rect.y + inner.scroll_position.1 as i32,
+        );
+        (visible.width > 0 && visible.height > 0, child_offset)
+    }
+
     /// Creates a new element with the given name.
     pub fn new<S>(name: S) -> Node<E>
     where
@@ -796,6 +2623,41 @@ impl<E: Extension> Node<E> {
         }
     }
 
+    /// Creates a copy of this node and all of its children.
+    ///
+    /// The copy has no parent so it can be added anywhere.
+    /// Names, text and properties (including ones set with
+    /// `set_expr_property`) are copied but the extension's data
+    /// is recreated via `Extension::new_data` and property
+    /// watchers aren't copied.
+    pub fn clone_deep(&self) -> Node<E> {
+        let node = self.clone_shallow();
+        if let NodeValue::Element(ref e) = self.inner.borrow().value {
+            for c in &e.children {
+                node.add_child(c.clone_deep());
+            }
+        }
+        node
+    }
+
+    /// Creates a copy of this node without its children.
+    ///
+    /// Copies the same values as `clone_deep`, leaving the new
+    /// node empty so it can be filled with different children.
+    pub fn clone_shallow(&self) -> Node<E> {
+        let inner = self.inner.borrow();
+        let node = match inner.value {
+            NodeValue::Element(ref e) => Node::new(e.name.clone()),
+            NodeValue::Text(ref t) => Node::new_text(t.clone()),
+        };
+        {
+            let mut new = node.inner.borrow_mut();
+            new.properties = inner.properties.clone();
+            new.expr_properties = inner.expr_properties.clone();
+        }
+        node
+    }
+
     /// Returns an immutable reference to the
     /// node's inner value
     #[inline]
@@ -825,10 +2687,11 @@ impl<E: Extension> Node<E> {
                 inner.rules_dirty = true;
             }
             e.children.insert(0, node);
-            true
         } else {
-            false
+            return false;
         }
+        self.tree_changed(None);
+        true
     }
 
     /// Adds the passed node as a child to this node.
@@ -845,10 +2708,40 @@ impl<E: Extension> Node<E> {
                 inner.rules_dirty = true;
             }
             e.children.push(node);
-            true
         } else {
-            false
+            return false;
+        }
+        self.tree_changed(None);
+        true
+    }
+
+    /// Inserts the passed node as a child of this node at
+    /// the given index, shifting later children along.
+    ///
+    /// An index equal to the number of children appends the
+    /// node. Indices past the end are rejected instead of being
+    /// clamped.
+    ///
+    /// Returns true if the node was added
+    pub fn insert_child_at(&self, index: usize, node: Node<E>) -> bool {
+        if node.inner.borrow().parent.is_some() {
+            return false;
+        }
+        if let NodeValue::Element(ref mut e) = self.inner.borrow_mut().value {
+            if index > e.children.len() {
+                return false;
+            }
+            {
+                let mut inner = node.inner.borrow_mut();
+                inner.parent = Some(Rc::downgrade(&self.inner));
+                inner.rules_dirty = true;
+            }
+            e.children.insert(index, node);
+        } else {
+            return false;
         }
+        self.tree_changed(None);
+        true
     }
 
     /// Removes the passed node as a child from this node.
@@ -863,17 +2756,81 @@ impl<E: Extension> Node<E> {
             .map_or(false, |v| Rc::ptr_eq(&v, &self.inner)) {
             return false;
         }
-        let inner: &mut NodeInner<_> = &mut *self.inner.borrow_mut();
-        if let NodeValue::Element(ref mut e) = inner.value {
+        if let NodeValue::Element(ref mut e) = self.inner.borrow_mut().value {
             e.children.retain(|v| !Rc::ptr_eq(&v.inner, &node.inner));
             {
                 let mut inner = node.inner.borrow_mut();
                 inner.parent = None;
                 inner.rules_dirty = true;
             }
-            true
         } else {
-            false
+            return false;
+        }
+        self.tree_changed(Some(&node));
+        true
+    }
+
+    /// Replaces the child `old` of this node with `new`,
+    /// keeping its position in the children.
+    ///
+    /// Returns true if the node was replaced. Fails if `old`
+    /// isn't a child of this node or `new` already has a parent.
+    pub fn replace_child(&self, old: &Node<E>, new: Node<E>) -> bool {
+        if new.inner.borrow().parent.is_some() {
+            return false;
+        }
+        if let NodeValue::Element(ref mut e) = self.inner.borrow_mut().value {
+            let idx = match e.children.iter().position(|v| v.is_same(old)) {
+                Some(idx) => idx,
+                None => return false,
+            };
+            {
+                let mut inner = old.inner.borrow_mut();
+                inner.parent = None;
+                inner.rules_dirty = true;
+            }
+            {
+                let mut inner = new.inner.borrow_mut();
+                inner.parent = Some(Rc::downgrade(&self.inner));
+                inner.rules_dirty = true;
+            }
+            e.children[idx] = new;
+        } else {
+            return false;
+        }
+        self.tree_changed(Some(old));
+        true
+    }
+
+    // Changes the `tree_version` of the root of this node's tree so
+    // a layout paused part way through it is restarted. The areas
+    // covered by a removed node are kept on the root for the
+    // manager's `take_dirty_rects`.
+    fn tree_changed(&self, removed: Option<&Node<E>>) {
+        let mut root = self.clone();
+        while let Some(p) = root.parent() {
+            root = p;
+        }
+        let mut inner = root.inner.borrow_mut();
+        inner.tree_version = inner.tree_version.wrapping_add(1);
+        if let Some(node) = removed {
+            node.take_absolute_rects(&mut inner.removed_rects);
+        }
+    }
+
+    // Moves the last drawn area of this node and its children into
+    // `rects`. Cleared so that the node reports its whole area again
+    // if it is added back.
+    fn take_absolute_rects(&self, rects: &mut Vec<Rect>) {
+        let inner: &mut _ = &mut *self.inner.borrow_mut();
+        let rect = ::std::mem::replace(&mut inner.prev_absolute_rect, Rect{x: 0, y: 0, width: 0, height: 0});
+        if rect.width > 0 && rect.height > 0 {
+            rects.push(rect);
+        }
+        if let NodeValue::Element(ref e) = inner.value {
+            for c in &e.children {
+                c.take_absolute_rects(rects);
+            }
         }
     }
 
@@ -888,6 +2845,26 @@ impl<E: Extension> Node<E> {
         }
     }
 
+    /// Returns the first child of this node if any.
+    #[inline]
+    pub fn first_child(&self) -> Option<Node<E>> {
+        if let NodeValue::Element(ref e) = self.inner.borrow().value {
+            e.children.first().cloned()
+        } else {
+            None
+        }
+    }
+
+    /// Returns the last child of this node if any.
+    #[inline]
+    pub fn last_child(&self) -> Option<Node<E>> {
+        if let NodeValue::Element(ref e) = self.inner.borrow().value {
+            e.children.last().cloned()
+        } else {
+            None
+        }
+    }
+
     /// Returns the parent node of this node.
     pub fn parent(&self) -> Option<Node<E>> {
         let inner = self.inner.borrow();
@@ -898,6 +2875,25 @@ impl<E: Extension> Node<E> {
             .map(|v| Node { inner: v })
     }
 
+    /// Returns the closest ancestor of this node that matches
+    /// the predicate, starting from the parent.
+    ///
+    /// ```ignore
+    /// let scrollable = node.nearest_ancestor(|v| v.get_property::<bool>("can_scroll").unwrap_or(false));
+    /// ```
+    pub fn nearest_ancestor<F>(&self, pred: F) -> Option<Node<E>>
+        where F: Fn(&Node<E>) -> bool
+    {
+        let mut current = self.parent();
+        while let Some(node) = current {
+            if pred(&node) {
+                return Some(node);
+            }
+            current = node.parent();
+        }
+        None
+    }
+
     /// Returns the name of the node if it has one
     #[inline]
     pub fn name(&self) -> Option<String> {
@@ -943,6 +2939,28 @@ impl<E: Extension> Node<E> {
         }
     }
 
+    /// Appends to the text of the node if it is a text node.
+    ///
+    /// Cheaper than `set_text` for text that grows over time as
+    /// the existing text is kept. Returns whether this is a text
+    /// node, appending an empty string doesn't mark the text as
+    /// changed.
+    pub fn append_text<S>(&self, txt: S) -> bool
+        where S: AsRef<str>,
+    {
+        let inner: &mut NodeInner<_> = &mut *self.inner.borrow_mut();
+        if let NodeValue::Text(ref mut t) = inner.value {
+            let txt = txt.as_ref();
+            if !txt.is_empty() {
+                t.push_str(txt);
+                inner.text_changed = true;
+            }
+            true
+        } else {
+            false
+        }
+    }
+
     /// Returns whether this node has had its layout computed
     /// at least once
     pub fn has_layout(&self) -> bool {
@@ -996,14 +3014,164 @@ impl<E: Extension> Node<E> {
         Some(rect)
     }
 
-    /// Removes the property on the node.
-    pub fn remove_property(&self, key: &str) {
-        let mut inner = self.inner.borrow_mut();
-        inner.properties.remove(key);
+    /// Returns the smallest rect containing this node and all
+    /// of its descendants in absolute coordinates.
+    ///
+    /// Unlike `render_position` this isn't clipped by parents so
+    /// children that extend outside of this node are included.
+    /// Returns `None` if the node hasn't had its layout computed.
+    pub fn bounding_box(&self) -> Option<Rect> {
+        let inner = self.inner.borrow();
+        if !inner.done_layout {
+            return None;
+        }
+        let mut rect = inner.draw_rect;
+        let mut cur = inner.parent.as_ref().and_then(|v| v.upgrade());
+        while let Some(p) = cur {
+            let inner = p.borrow();
+            rect.x += inner.draw_rect.x + inner.scroll_position.0 as i32;
+            rect.y += inner.draw_rect.y + inner.scroll_position.1 as i32;
+            cur = inner.parent.as_ref().and_then(|v| v.upgrade());
+        }
+        Some(Node::bounding_box_inner(&inner, rect))
+    }
+
+    fn bounding_box_inner(inner: &NodeInner<E>, rect: Rect) -> Rect {
+        let mut bounds = rect;
+        if let NodeValue::Element(ref e) = inner.value {
+            for c in &e.children {
+                let c = c.inner.borrow();
+                let mut c_rect = c.draw_rect;
+                c_rect.x += rect.x + inner.scroll_position.0 as i32;
+                c_rect.y += rect.y + inner.scroll_position.1 as i32;
+                bounds = bounds.union(Node::bounding_box_inner(&c, c_rect));
+            }
+        }
+        bounds
+    }
+
+    /// Removes the property on the node.
+    pub fn remove_property(&self, key: &str) {
+        let removed = {
+            let mut inner = self.inner.borrow_mut();
+            let removed = inner.properties.remove(key).is_some();
+            if removed {
+                inner.properties_changed = true;
+            }
+            removed
+        };
+        if removed {
+            self.notify_watchers(key);
+        }
+    }
+
+    /// Calls the function registered via `Manager::register_action`
+    /// for the action stored in the given property.
+    ///
+    /// The property is looked up on the node first. If it isn't
+    /// set there, the action set for the key by the style rules
+    /// matching this node (e.g. `on_click = action("visit", url),`)
+    /// is used instead. Style rules are only applied during
+    /// `layout`, and the key has to be a style property of the
+    /// extension for the rule to load.
+    ///
+    /// Returns `Ok(false)` without doing anything if neither sets
+    /// the property. Fails if the property isn't an action, this node
+    /// isn't attached to a manager, no function is registered
+    /// with the action's name or the action is triggered again
+    /// from within its own function.
+    pub fn trigger(&self, key: &str) -> Result<bool, Error<'static>> {
+        let value = self.get_property::<Value<E>>(key)
+            .or_else(|| self.inner.borrow().resolved.iter()
+                .find(|&(k, _)| k.0 == key)
+                .map(|(_, v)| v.clone()));
+        let action = match value {
+            Some(Value::Action(a)) => a,
+            Some(ref v) => return Err(Error::IncompatibleTypeOp {
+                op: "trigger",
+                ty: get_ty(v),
+            }),
+            None => return Ok(false),
+        };
+        let mut root = self.clone();
+        while let Some(p) = root.parent() {
+            root = p;
+        }
+        let actions = root.inner.borrow().actions.clone()
+            .ok_or(Error::CustomStatic {
+                reason: "Node isn't attached to a manager",
+            })?;
+        // Cloned out so the action can register or trigger others
+        let func = actions.borrow().get(&action.name).cloned()
+            .ok_or_else(|| Error::Custom {
+                reason: format!("Unknown action: {}", action.name),
+            })?;
+        let mut func = func.try_borrow_mut()
+            .map_err(|_| Error::Custom {
+                reason: format!("Action {} triggered from within itself", action.name),
+            })?;
+        (func)(self, &action.args);
+        Ok(true)
+    }
+
+    /// Sets a style property on this node to the given expression.
+    ///
+    /// The expression is evaluated during `layout` like a style
+    /// rule that only matches this node, taking priority over every
+    /// rule apart from `!important` ones. The node's properties and
+    /// `parent_width`/`parent_height` can be used as variables.
+    ///
+    /// The expression is compiled here and syntax errors are
+    /// returned. Other errors (e.g. unknown variables or functions)
+    /// are reported via `Manager::take_errors` when evaluated.
+    pub fn set_expr_property<'a>(&self, key: &str, src: &'a str) -> Result<(), syntax::PError<'a>> {
+        let expr = NodeExpr::compile(syntax::style::ExprType::parse(src)?)?;
+        let mut inner = self.inner.borrow_mut();
+        inner.properties_changed = true;
+        inner.expr_properties.insert(key.into(), expr);
+        inner.remove_expr_rule();
+        Ok(())
+    }
+
+    /// Removes a style expression previously set with
+    /// `set_expr_property`
+    pub fn remove_expr_property(&self, key: &str) {
+        let mut inner = self.inner.borrow_mut();
+        if inner.expr_properties.remove(key).is_some() {
+            inner.properties_changed = true;
+            inner.remove_expr_rule();
+        }
+    }
+
+    /// Registers a callback that is called whenever the given
+    /// property is changed via `set_property` or `remove_property`.
+    ///
+    /// Changes to other properties don't invoke the callback.
+    pub fn watch_property<F>(&self, key: &str, callback: F)
+        where F: Fn(&Node<E>) + 'static
+    {
+        self.inner.borrow_mut().watchers
+            .entry(key.into())
+            .or_default()
+            .push(Rc::new(callback));
+    }
+
+    fn notify_watchers(&self, key: &str) {
+        // Cloned so the callbacks are free to access the node
+        let watchers = match self.inner.borrow().watchers.get(key) {
+            Some(v) => v.clone(),
+            None => return,
+        };
+        for watcher in watchers {
+            watcher(self);
+        }
     }
 
     /// Returns a copy of the value for the given property
     /// if it exists.
+    ///
+    /// Reading as `Option<T>` returns `Some(None)` for a missing
+    /// property, separating it from one with the wrong type.
     #[inline]
     pub fn get_property<V>(&self, key: &str) -> Option<V>
         where V: ConvertValue<E>
@@ -1026,13 +3194,56 @@ impl<E: Extension> Node<E> {
     }
 
     /// Sets the value of a given property
+    ///
+    /// The node is only flagged as changed if the value differs
+    /// from the current one. Setting `None` removes the property
+    /// like `remove_property`.
     #[inline]
     pub fn set_property<V>(&self, key: &str, v: V)
         where V: ConvertValue<E>
     {
-        let mut inner = self.inner.borrow_mut();
-        inner.properties_changed = true;
-        inner.properties.insert(key.into(), V::to_value(v));
+        let value = match V::to_optional_value(v) {
+            Some(v) => v,
+            None => return self.remove_property(key),
+        };
+        {
+            let mut inner = self.inner.borrow_mut();
+            // Setting the same value again doesn't need an update
+            if inner.properties.get(key) == Some(&value) {
+                return;
+            }
+            inner.properties_changed = true;
+            inner.properties.insert(key.into(), value);
+        }
+        self.notify_watchers(key);
+    }
+
+    /// Sets the values of several properties at once.
+    ///
+    /// Cheaper than calling `set_property` for each value as the
+    /// node is only borrowed once. The node is only flagged as
+    /// changed if at least one value differs from its current one
+    /// and watchers are only called for properties whose value
+    /// changed.
+    pub fn set_properties<I>(&self, props: I)
+        where I: IntoIterator<Item=(String, Value<E>)>
+    {
+        let mut changed = Vec::new();
+        {
+            let mut inner = self.inner.borrow_mut();
+            for (key, value) in props {
+                if inner.properties.get(&key) != Some(&value) {
+                    inner.properties.insert(key.clone(), value);
+                    changed.push(key);
+                }
+            }
+            if !changed.is_empty() {
+                inner.properties_changed = true;
+            }
+        }
+        for key in changed {
+            self.notify_watchers(&key);
+        }
     }
 
     /// Sets the value of a given property without flagging
@@ -1042,14 +3253,17 @@ impl<E: Extension> Node<E> {
     /// and not used in style rules.
     ///
     /// As a general convention this properties should use keys
-    /// begining with `$` (e.g. `$cycle`) as these are not accepted
-    /// by the style parser.
+    /// begining with `$` (e.g. `$cycle`) as these are only accepted
+    /// by the style parser in rule matchers.
     #[inline]
     pub fn raw_set_property<V>(&self, key: &str, v: V)
         where V: ConvertValue<E>
     {
         let mut inner = self.inner.borrow_mut();
-        inner.properties.insert(key.into(), V::to_value(v));
+        match V::to_optional_value(v) {
+            Some(value) => inner.properties.insert(key.into(), value),
+            None => inner.properties.remove(key),
+        };
     }
 
     /// Creates a weak reference to this node.
@@ -1059,6 +3273,19 @@ impl<E: Extension> Node<E> {
         }
     }
 
+    /// Returns an iterator over every descendant of this node
+    /// in depth-first pre-order.
+    ///
+    /// This node itself isn't included. Both element and text
+    /// nodes are returned.
+    pub fn descendants(&self) -> Descendants<E> {
+        let mut iter = Descendants {
+            stack: Vec::new(),
+        };
+        iter.push_children(self);
+        iter
+    }
+
     /// Begins a query on this node
     pub fn query(&self) -> query::Query<E> {
         query::Query::new(self.clone())
@@ -1069,6 +3296,108 @@ impl<E: Extension> Node<E> {
         syntax::desc::Document::parse(s).map(|v| Node::from_document(v))
     }
 
+    /// Creates a node from a string enforcing the given limits.
+    ///
+    /// Should be used for descriptions from untrusted sources.
+    /// See [`Document::parse_limited`](../fungui_syntax/desc/struct.Document.html#method.parse_limited)
+    pub fn from_str_limited(s: &str, limits: syntax::desc::Limits) -> Result<Node<E>, syntax::desc::LimitedParseError<'_>> {
+        syntax::desc::Document::parse_limited(s, limits).map(Node::from_document)
+    }
+
+    /// Serializes this node and its children to the same
+    /// format accepted by `from_str`.
+    ///
+    /// Properties are written sorted by key. Lists, actions and
+    /// extension values along with non-finite floats have no representation
+    /// in the format and are skipped, as are properties with keys that
+    /// aren't identifiers (e.g. `$hover` set by `Manager::set_hover`).
+    /// A text node on its own isn't a valid document so only elements
+    /// can be parsed back.
+    pub fn to_desc_string(&self) -> String {
+        let mut out = String::new();
+        self.write_desc(&mut out, 0);
+        out
+    }
+
+    fn write_desc(&self, out: &mut String, depth: usize) {
+        use std::fmt::Write;
+        let inner = self.inner.borrow();
+        for _ in 0 .. depth {
+            out.push_str("    ");
+        }
+        match inner.value {
+            NodeValue::Element(ref e) => out.push_str(&e.name),
+            NodeValue::Text(ref t) => {
+                out.push('"');
+                out.push_str(&escape(t));
+                out.push('"');
+            },
+        }
+
+        // Keys the parser won't accept, including `$` state keys
+        // such as `$hover`, are skipped
+        let is_ident = |k: &str| !k.is_empty() && k.chars().all(|c| c.is_alphanumeric() || c == '_');
+        let mut properties: Vec<_> = inner.properties.iter()
+            .filter(|(k, _)| is_ident(k))
+            .filter_map(|(k, v)| Some((k, match *v {
+                Value::Boolean(v) => v.to_string(),
+                Value::Integer(v) => v.to_string(),
+                Value::Float(v) if v.is_finite() => {
+                    // Floats must contain a `.` to not be parsed as integers
+                    let v = v.to_string();
+                    if v.contains('.') { v } else { v + ".0" }
+                },
+                Value::String(ref v) => format!("\"{}\"", escape(v)),
+                Value::Color(r, g, b, a) => format!("#{:02X}{:02X}{:02X}{:02X}", r, g, b, a),
+                _ => return None,
+            })))
+            .collect();
+        if !properties.is_empty() {
+            properties.sort_by(|a, b| a.0.cmp(b.0));
+            out.push('(');
+            for (idx, (k, v)) in properties.into_iter().enumerate() {
+                if idx != 0 {
+                    out.push_str(", ");
+                }
+                let _ = write!(out, "{}={}", k, v);
+            }
+            out.push(')');
+        }
+
+        if let NodeValue::Element(ref e) = inner.value {
+            if !e.children.is_empty() {
+                out.push_str(" {\n");
+                for c in &e.children {
+                    c.write_desc(out, depth + 1);
+                }
+                for _ in 0 .. depth {
+                    out.push_str("    ");
+                }
+                out.push('}');
+            }
+        }
+        out.push('\n');
+    }
+
+    /// Creates a node from a JSON encoded `NodeDescription`.
+    ///
+    /// Requires the `serde` feature.
+    #[cfg(feature = "serde")]
+    pub fn from_json(s: &str) -> Result<Node<E>, serde_json::Error> {
+        serde_json::from_str(s).map(Node::from_description)
+    }
+
+    /// Encodes this node and its children as a JSON `NodeDescription`.
+    ///
+    /// Fails if a float property is infinite or NaN as JSON can't
+    /// represent them.
+    ///
+    /// Requires the `serde` feature.
+    #[cfg(feature = "serde")]
+    pub fn to_json(&self) -> Result<String, serde_json::Error> {
+        serde_json::to_string(&self.to_description())
+    }
+
     /// Creates a node from a parsed document.
     pub fn from_document(desc: syntax::desc::Document) -> Node<E> {
         Node::from_doc_element(desc.root)
@@ -1078,7 +3407,15 @@ impl<E: Extension> Node<E> {
         desc: &str,
         properties: FnvHashMap<syntax::Ident, syntax::desc::ValueType>,
     ) -> Node<E> {
-        let text = unescape(desc);
+        let trim = properties.iter()
+            .any(|(n, v)| n.name == "trim" && match v.value {
+                syntax::desc::Value::Boolean(b) => b,
+                _ => false,
+            });
+        let mut text = unescape(desc);
+        if trim {
+            text = text.trim().to_owned();
+        }
         Node {
             inner: Rc::new(RefCell::new(NodeInner {
                 value: NodeValue::Text(text),
@@ -1152,6 +3489,24 @@ fn unescape(v: &str) -> String {
     text
 }
 
+/// The inverse of `unescape`
+fn escape(v: &str) -> String {
+    let mut text = String::with_capacity(v.len());
+    for c in v.chars() {
+        match c {
+            '\t' => text.push_str("\\t"),
+            '\n' => text.push_str("\\n"),
+            '\r' => text.push_str("\\r"),
+            '"' | '\\' => {
+                text.push('\\');
+                text.push(c);
+            },
+            _ => text.push(c),
+        }
+    }
+    text
+}
+
 /// A weak reference to a node.
 pub struct WeakNode<E: Extension> {
     inner: Weak<RefCell<NodeInner<E>>>,
@@ -1173,6 +3528,32 @@ impl<E: Extension> Clone for WeakNode<E> {
     }
 }
 
+/// A depth-first (pre-order) iterator over the descendants
+/// of a node.
+///
+/// Created via [`Node::descendants`](struct.Node.html#method.descendants).
+pub struct Descendants<E: Extension> {
+    // Nodes left to visit, the next node is last
+    stack: Vec<Node<E>>,
+}
+
+impl <E: Extension> Descendants<E> {
+    fn push_children(&mut self, node: &Node<E>) {
+        if let NodeValue::Element(ref e) = node.inner.borrow().value {
+            self.stack.extend(e.children.iter().rev().cloned());
+        }
+    }
+}
+
+impl <E: Extension> Iterator for Descendants<E> {
+    type Item = Node<E>;
+    fn next(&mut self) -> Option<Node<E>> {
+        let node = self.stack.pop()?;
+        self.push_children(&node);
+        Some(node)
+    }
+}
+
 /// The inner data of a single node.
 ///
 /// `Node` is a wrapper around this to allow it to be passed
@@ -1181,7 +3562,25 @@ pub struct NodeInner<E: Extension> {
     parent: Option<Weak<RefCell<NodeInner<E>>>>,
     properties: FnvHashMap<String, Value<E>>,
     properties_changed: bool,
+    watchers: FnvHashMap<String, Vec<PropertyWatcher<E>>>,
+    // Style expressions set via `Node::set_expr_property`
+    expr_properties: FnvHashMap<String, NodeExpr<E>>,
+    // The rule built from `expr_properties`, also stored in
+    // `possible_rules`
+    expr_rule: Option<Rc<Rule<E>>>,
     possible_rules: Vec<Rc<Rule<E>>>,
+    // The values of `Styles::inherited_keys` and
+    // `Styles::action_keys` set by the rules applied to this node,
+    // read by children using `inherit` and by `Node::trigger`
+    resolved: FnvHashMap<StaticKey, Value<E>>,
+    // Only set on the root node of a manager
+    actions: Option<ActionRegistry<E>>,
+    // Changed on the root of a tree whenever a node is added to
+    // or removed from it
+    tree_version: u32,
+    // Only used on the root of a tree. The areas covered by nodes
+    // removed from the tree since the last layout.
+    removed_rects: Vec<Rect>,
     done_layout: bool,
     // Set when added/removed from a node
     rules_dirty: bool,
@@ -1200,6 +3599,11 @@ pub struct NodeInner<E: Extension> {
     parent_data: Box<dyn Any>,
     uses_parent_size: bool,
     prev_rect: Rect,
+    // The size reported by the layout's `measure` in the
+    // last layout pass
+    intrinsic_size: (Option<i32>, Option<i32>),
+    // The absolute position from the last `update_clip_rect`
+    prev_absolute_rect: Rect,
     /// The current draw position of this node
     pub draw_rect: Rect,
     /// The scroll offset of all elements inside this one
@@ -1207,6 +3611,24 @@ pub struct NodeInner<E: Extension> {
     /// Whether this element clips child elements that overflow
     /// its bounds
     pub clip_overflow: bool,
+    /// The order this node is rendered in compared to its
+    /// siblings, higher values are drawn later
+    pub z_index: i32,
+    /// The opacity set on this node via the `opacity` style
+    pub opacity: f32,
+    /// The opacity of this node multiplied by the opacity of
+    /// all of its ancestors.
+    ///
+    /// Updated before the node is passed to a `RenderVisitor`.
+    pub render_opacity: f32,
+    /// The area this element is visible within in absolute
+    /// coordinates due to parents clipping their children.
+    ///
+    /// `None` if no parent clips this element. Updated after
+    /// every `layout` call.
+    pub clip_rect: Option<Rect>,
+    /// The minimum and maximum size of this element
+    pub size_constraints: SizeConstraints,
     /// The location that this element should be drawn at as
     /// decided by the layout engine
     pub draw_position: Rect,
@@ -1225,16 +3647,30 @@ impl <E> Default for NodeInner<E>
             value: NodeValue::Text(String::new()),
             properties: FnvHashMap::default(),
             properties_changed: true,
+            watchers: FnvHashMap::default(),
+            expr_properties: FnvHashMap::default(),
+            expr_rule: None,
             possible_rules: Vec::new(),
+            resolved: FnvHashMap::default(),
+            actions: None,
+            tree_version: 0,
+            removed_rects: Vec::new(),
             done_layout: false,
             rules_dirty: true,
             text_changed: false,
             dirty_flags: DirtyFlags::empty(),
             uses_parent_size: false,
             prev_rect: Rect{x: 0, y: 0, width: 0, height: 0},
+            intrinsic_size: (None, None),
+            prev_absolute_rect: Rect{x: 0, y: 0, width: 0, height: 0},
             draw_rect: Rect{x: 0, y: 0, width: 0, height: 0},
             scroll_position: (0.0, 0.0),
             clip_overflow: false,
+            z_index: 0,
+            opacity: 1.0,
+            render_opacity: 1.0,
+            clip_rect: None,
+            size_constraints: SizeConstraints::default(),
             draw_position: Rect{x: 0, y: 0, width: 0, height: 0},
             ext: E::new_data(),
         }
@@ -1244,13 +3680,22 @@ impl <E> Default for NodeInner<E>
 impl <E> NodeInner<E>
     where E: Extension
 {
+    /// Removes the rule built from the expression properties so
+    /// it is rebuilt on the next update
+    fn remove_expr_rule(&mut self) {
+        if let Some(old) = self.expr_rule.take() {
+            self.possible_rules.retain(|v| !Rc::ptr_eq(v, &old));
+        }
+    }
+
     #[inline]
     fn get_property_impl<V>(props: &FnvHashMap<String, Value<E>>, key: &str) -> Option<V>
         where V: ConvertValue<E>
     {
-        props.get(key)
-            .cloned()
-            .and_then(|v| V::from_value(v))
+        match props.get(key) {
+            Some(v) => V::from_value(v.clone()),
+            None => V::from_missing(),
+        }
     }
 
     /// Returns a copy of the value for the given property
@@ -1279,6 +3724,16 @@ impl <E> NodeInner<E>
         Self::get_property_ref_impl::<V>(&self.properties, key)
     }
 
+    /// Returns the flags describing what changed on this node
+    /// during the last `Manager::layout` call and clears them.
+    ///
+    /// This should be called after `layout` (e.g. by the render
+    /// visitor) so that each change is only handled once. The
+    /// flags are recomputed at the start of every `layout` call.
+    pub fn take_dirty_flags(&mut self) -> DirtyFlags {
+        ::std::mem::replace(&mut self.dirty_flags, DirtyFlags::empty())
+    }
+
     /// Returns the text of the node if it is a text node.
     pub fn text(&self) -> Option<&str> {
         match self.value {
@@ -1324,6 +3779,7 @@ pub struct NodeChain<'a, E: Extension + 'a> {
     value: NCValue<'a>,
     draw_rect: Rect,
     properties: &'a FnvHashMap<String, Value<E>>,
+    resolved: &'a FnvHashMap<StaticKey, Value<E>>,
 }
 
 impl <'a, E> NodeChain<'a, E>
@@ -1336,6 +3792,27 @@ impl <'a, E> NodeChain<'a, E>
             _ => None,
         }
     }
+
+    /// Returns the name of the node if it is an element.
+    pub fn name(&self) -> Option<&'a str> {
+        match self.value {
+            NCValue::Element(v) => Some(v),
+            _ => None,
+        }
+    }
+
+    /// Returns the node's parent if it has one.
+    pub fn parent(&self) -> Option<&'a NodeChain<'a, E>> {
+        self.parent
+    }
+
+    /// Returns the value of the given property converted to
+    /// the requested type if it is set.
+    pub fn get_property<V>(&self, key: &str) -> Option<V>
+        where V: ConvertValue<E>
+    {
+        NodeInner::<E>::get_property_impl::<V>(self.properties, key)
+    }
 }
 
 #[derive(Debug)]
@@ -1363,10 +3840,72 @@ pub enum Value<E: Extension> {
     Float(f64),
     /// A string value
     String(String),
+    /// A color value with red, green, blue and alpha channels
+    Color(u8, u8, u8, u8),
+    /// A list of values
+    List(Vec<Value<E>>),
+    /// A named action, see `Node::trigger`
+    Action(Action<E>),
     /// An extension defined value
     ExtValue(E::Value),
 }
 
+/// A named action with arguments.
+///
+/// Created in style rules via `action(name, args...)` or
+/// directly and stored in a property. Calling `Node::trigger`
+/// on the property calls the function registered for the name
+/// via `Manager::register_action`.
+pub struct Action<E: Extension> {
+    /// The name of the action
+    pub name: String,
+    /// The arguments passed to the action's function
+    pub args: Vec<Value<E>>,
+}
+
+impl <E: Extension> Action<E> {
+    /// Creates an action with the given name and arguments
+    pub fn new<S>(name: S, args: Vec<Value<E>>) -> Action<E>
+        where S: Into<String>
+    {
+        Action {
+            name: name.into(),
+            args,
+        }
+    }
+}
+
+impl <E> ::std::fmt::Debug for Action<E>
+    where E: Extension + ::std::fmt::Debug,
+          E::Value: ::std::fmt::Debug,
+{
+    fn fmt(&self, f: &mut ::std::fmt::Formatter) -> ::std::fmt::Result {
+        f.debug_struct("Action")
+            .field("name", &self.name)
+            .field("args", &self.args)
+            .finish()
+    }
+}
+
+impl <E> Clone for Action<E>
+    where E: Extension
+{
+    fn clone(&self) -> Action<E> {
+        Action {
+            name: self.name.clone(),
+            args: self.args.clone(),
+        }
+    }
+}
+
+impl <E> PartialEq for Action<E>
+    where E: Extension
+{
+    fn eq(&self, rhs: &Action<E>) -> bool {
+        self.name == rhs.name && self.args == rhs.args
+    }
+}
+
 impl <E> Value<E>
     where E: Extension
 {
@@ -1396,6 +3935,9 @@ impl <E> Clone for Value<E>
             Value::Integer(v) => Value::Integer(v),
             Value::Float(v) => Value::Float(v),
             Value::String(ref v) => Value::String(v.clone()),
+            Value::Color(r, g, b, a) => Value::Color(r, g, b, a),
+            Value::List(ref v) => Value::List(v.clone()),
+            Value::Action(ref v) => Value::Action(v.clone()),
             Value::ExtValue(ref v) => Value::ExtValue(v.clone()),
         }
     }
@@ -1411,12 +3953,71 @@ impl <E> PartialEq for Value<E>
             (&Integer(a), &Integer(b)) => a == b,
             (&Float(a), &Float(b)) => a == b,
             (&String(ref a), &String(ref b)) => a == b,
+            (&Color(r1, g1, b1, a1), &Color(r2, g2, b2, a2)) => (r1, g1, b1, a1) == (r2, g2, b2, a2),
+            (List(a), List(b)) => a == b,
+            (Action(a), Action(b)) => a == b,
             (&ExtValue(ref a), &ExtValue(ref b)) => a == b,
             _ => false,
         }
     }
 }
 
+/// Only implemented when the extension's value is `Eq`.
+///
+/// Floats follow the usual IEEE rules so a `NaN` is never equal
+/// to anything, including itself. Values containing `NaN` can
+/// still be stored in sets and maps but will never be found again.
+impl <E> Eq for Value<E>
+    where E: Extension,
+          E::Value: Eq,
+{}
+
+impl <E> Hash for Value<E>
+    where E: Extension,
+          E::Value: Hash,
+{
+    fn hash<H: Hasher>(&self, state: &mut H) {
+        use Value::*;
+        match self {
+            Boolean(v) => {
+                state.write_u8(0);
+                v.hash(state);
+            },
+            Integer(v) => {
+                state.write_u8(1);
+                v.hash(state);
+            },
+            Float(v) => {
+                state.write_u8(2);
+                // `0.0` and `-0.0` are equal so must hash the same
+                let v = if *v == 0.0 { 0.0f64 } else { *v };
+                v.to_bits().hash(state);
+            },
+            String(v) => {
+                state.write_u8(3);
+                v.hash(state);
+            },
+            Color(r, g, b, a) => {
+                state.write_u8(4);
+                (r, g, b, a).hash(state);
+            },
+            List(v) => {
+                state.write_u8(5);
+                v.hash(state);
+            },
+            ExtValue(v) => {
+                state.write_u8(6);
+                v.hash(state);
+            },
+            Action(v) => {
+                state.write_u8(7);
+                v.name.hash(state);
+                v.args.hash(state);
+            },
+        }
+    }
+}
+
 impl <'a, E> From<syntax::desc::ValueType<'a>> for Value<E>
     where E: Extension
 {
@@ -1426,6 +4027,7 @@ impl <'a, E> From<syntax::desc::ValueType<'a>> for Value<E>
             syntax::desc::Value::Integer(val) => Value::Integer(val),
             syntax::desc::Value::Float(val) => Value::Float(val),
             syntax::desc::Value::String(val) => Value::String(unescape(val)),
+            syntax::desc::Value::Color(r, g, b, a) => Value::Color(r, g, b, a),
         }
     }
 }
@@ -1445,6 +4047,21 @@ pub trait ConvertValue<E: Extension>: Sized {
     fn from_value_ref(v: &Value<E>) -> Option<&Self::RefType>;
     /// Converts the value into a `Value`
     fn to_value(v: Self) -> Value<E>;
+    /// Converts the value into a `Value`, `None` if the value
+    /// stands for an unset property.
+    ///
+    /// Used when setting properties, where `None` removes the
+    /// property instead. Returns `Some(to_value(v))` by default.
+    fn to_optional_value(v: Self) -> Option<Value<E>> {
+        Some(Self::to_value(v))
+    }
+    /// Called instead of `from_value` when reading a property
+    /// that isn't set.
+    ///
+    /// Returns `None` by default.
+    fn from_missing() -> Option<Self> {
+        None
+    }
 }
 
 impl <E> ConvertValue<E> for i32
@@ -1469,6 +4086,101 @@ impl <E> ConvertValue<E> for i32
     }
 }
 
+/// Converts from integers or floats. Floats are truncated
+/// towards zero and saturate at the bounds of `i64`.
+///
+/// Values that don't fit in an integer value are stored
+/// as a float.
+impl <E> ConvertValue<E> for i64
+    where E: Extension
+{
+    type RefType = i32;
+    fn from_value(v: Value<E>) -> Option<i64> {
+        match v {
+            Value::Integer(i) => Some(i64::from(i)),
+            Value::Float(f) => Some(f as i64),
+            _ => None,
+        }
+    }
+    fn from_value_ref(v: &Value<E>) -> Option<&Self::RefType> {
+        match v {
+            Value::Integer(i) => Some(i),
+            _ => None,
+        }
+    }
+    fn to_value(v: Self) -> Value<E> {
+        if v >= i64::from(i32::MIN) && v <= i64::from(i32::MAX) {
+            Value::Integer(v as i32)
+        } else {
+            Value::Float(v as f64)
+        }
+    }
+}
+
+/// Converts from integers or floats. Negative values are
+/// rejected, floats are truncated towards zero and saturate
+/// at `u32::MAX`.
+///
+/// Values that don't fit in an integer value are stored
+/// as a float.
+impl <E> ConvertValue<E> for u32
+    where E: Extension
+{
+    type RefType = i32;
+    fn from_value(v: Value<E>) -> Option<u32> {
+        match v {
+            Value::Integer(i) if i >= 0 => Some(i as u32),
+            Value::Float(f) if f >= 0.0 => Some(f as u32),
+            _ => None,
+        }
+    }
+    fn from_value_ref(v: &Value<E>) -> Option<&Self::RefType> {
+        match v {
+            Value::Integer(i) if *i >= 0 => Some(i),
+            _ => None,
+        }
+    }
+    fn to_value(v: Self) -> Value<E> {
+        if v <= i32::MAX as u32 {
+            Value::Integer(v as i32)
+        } else {
+            Value::Float(f64::from(v))
+        }
+    }
+}
+
+/// Converts from integers or floats. Negative values are
+/// rejected, floats are truncated towards zero and saturate
+/// at `usize::MAX`.
+///
+/// Values that don't fit in an integer value are stored
+/// as a float.
+impl <E> ConvertValue<E> for usize
+    where E: Extension
+{
+    type RefType = i32;
+    fn from_value(v: Value<E>) -> Option<usize> {
+        match v {
+            Value::Integer(i) if i >= 0 => Some(i as usize),
+            Value::Float(f) if f >= 0.0 => Some(f as usize),
+            _ => None,
+        }
+    }
+    fn from_value_ref(v: &Value<E>) -> Option<&Self::RefType> {
+        match v {
+            Value::Integer(i) if *i >= 0 => Some(i),
+            _ => None,
+        }
+    }
+    fn to_value(v: Self) -> Value<E> {
+        if v <= i32::MAX as usize {
+            Value::Integer(v as i32)
+        } else {
+            Value::Float(v as f64)
+        }
+    }
+}
+
 impl <E> ConvertValue<E> for f64
     where E: Extension
 {
@@ -1554,6 +4266,74 @@ impl <E> ConvertValue<E> for String
         Value::String(v)
     }
 }
+/// Converts from a list where every item can be converted
+/// to `T`.
+impl <E, T> ConvertValue<E> for Vec<T>
+    where E: Extension,
+          T: ConvertValue<E>,
+{
+    type RefType = [Value<E>];
+    fn from_value(v: Value<E>) -> Option<Vec<T>> {
+        match v {
+            Value::List(v) => v.into_iter()
+                .map(T::from_value)
+                .collect(),
+            _ => None,
+        }
+    }
+    fn from_value_ref(v: &Value<E>) -> Option<&Self::RefType> {
+        match v {
+            Value::List(v) => Some(v),
+            _ => None,
+        }
+    }
+    fn to_value(v: Self) -> Value<E> {
+        Value::List(v.into_iter().map(T::to_value).collect())
+    }
+}
+
+/// Converts from color values or strings of the form
+/// `#RRGGBB`/`#RRGGBBAA` as `(red, green, blue, alpha)`.
+impl <E> ConvertValue<E> for (u8, u8, u8, u8)
+    where E: Extension
+{
+    type RefType = (u8, u8, u8, u8);
+    fn from_value(v: Value<E>) -> Option<(u8, u8, u8, u8)> {
+        match v {
+            Value::Color(r, g, b, a) => Some((r, g, b, a)),
+            Value::String(ref s) => syntax::parse_color(s),
+            _ => None,
+        }
+    }
+    fn from_value_ref(_v: &Value<E>) -> Option<&Self::RefType> {
+        None
+    }
+    fn to_value(v: Self) -> Value<E> {
+        Value::Color(v.0, v.1, v.2, v.3)
+    }
+}
+
+impl <E> ConvertValue<E> for Action<E>
+    where E: Extension
+{
+    type RefType = Action<E>;
+    fn from_value(v: Value<E>) -> Option<Action<E>> {
+        match v {
+            Value::Action(a) => Some(a),
+            _ => None,
+        }
+    }
+    fn from_value_ref(v: &Value<E>) -> Option<&Self::RefType> {
+        match v {
+            Value::Action(a) => Some(a),
+            _ => None,
+        }
+    }
+    fn to_value(v: Self) -> Value<E> {
+        Value::Action(v)
+    }
+}
+
 impl <E> ConvertValue<E> for Value<E>
     where E: Extension
 {
@@ -1567,4 +4347,38 @@ impl <E> ConvertValue<E> for Value<E>
     fn to_value(v: Self) -> Value<E> {
         v
     }
+}
+
+/// Allows telling an unset property apart from one set to
+/// the wrong type.
+///
+/// When reading a property as `Option<T>`:
+///
+/// * A missing property returns `Some(None)`
+/// * A property that converts to `T` returns `Some(Some(v))`
+/// * A property that can't be converted to `T` returns `None`
+///
+/// Setting a property or theme value to `None` removes it.
+/// `None` can't be stored in a `Value` so calling `to_value`
+/// directly with `None` will panic.
+impl <E, T> ConvertValue<E> for Option<T>
+    where E: Extension,
+          T: ConvertValue<E>,
+{
+    type RefType = T::RefType;
+    fn from_value(v: Value<E>) -> Option<Option<T>> {
+        T::from_value(v).map(Some)
+    }
+    fn from_value_ref(v: &Value<E>) -> Option<&Self::RefType> {
+        T::from_value_ref(v)
+    }
+    fn to_value(v: Self) -> Value<E> {
+        T::to_value(v.expect("`None` can't be converted into a value"))
+    }
+    fn to_optional_value(v: Self) -> Option<Value<E>> {
+        v.map(T::to_value)
+    }
+    fn from_missing() -> Option<Option<T>> {
+        Some(None)
+    }
 }
\ No newline at end of file
@@ -153,6 +153,10 @@ macro_rules! node {
 /// Allows for the creation of queries in a similar format
 /// as style rules.
 ///
+/// `*` can be used in place of a name to match any element and
+/// `>>` can be used in place of `>` to match at any depth below
+/// the previous element.
+///
 /// # Examples
 ///
 /// ```rust
@@ -188,48 +192,48 @@ macro_rules! query {
 
     (@target($query:expr), @text (
         $($key:ident = $val:expr),*
-    ) > $($other:tt)*) => (
-        query!(@target($query.text()
+    ) $($other:tt)*) => (
+        query!(@next($query.text()
         $(
             .property(stringify!($key), $val)
-        )*.child()), $($other)*)
+        )*), $($other)*)
     );
-    (@target($query:expr), @text > $($other:tt)*) => (
-        query!(@target($query.text().child()), $($other)*)
+    (@target($query:expr), @text $($other:tt)*) => (
+        query!(@next($query.text()), $($other)*)
     );
-    (@target($query:expr), @text (
+
+    (@target($query:expr), * (
         $($key:ident = $val:expr),*
-    )) => (
-        $query.text()
+    ) $($other:tt)*) => (
+        query!(@next($query.any_name()
         $(
             .property(stringify!($key), $val)
-        )*
+        )*), $($other)*)
     );
-    (@target($query:expr), @text) => (
-        $query.text()
+    (@target($query:expr), * $($other:tt)*) => (
+        query!(@next($query.any_name()), $($other)*)
     );
 
     (@target($query:expr), $name:ident (
         $($key:ident = $val:expr),*
-    ) > $($other:tt)*) => (
-        query!(@target($query.name(stringify!($name))
+    ) $($other:tt)*) => (
+        query!(@next($query.name(stringify!($name))
         $(
             .property(stringify!($key), $val)
-        )*.child()), $($other)*)
+        )*), $($other)*)
     );
-    (@target($query:expr), $name:ident > $($other:tt)*) => (
-        query!(@target($query.name(stringify!($name)).child()), $($other)*)
+    (@target($query:expr), $name:ident $($other:tt)*) => (
+        query!(@next($query.name(stringify!($name))), $($other)*)
     );
-    (@target($query:expr), $name:ident (
-        $($key:ident = $val:expr),*
-    )) => (
-        $query.name(stringify!($name))
-        $(
-            .property(stringify!($key), $val)
-        )*
+
+    (@next($query:expr), ) => (
+        $query
+    );
+    (@next($query:expr), > $($other:tt)*) => (
+        query!(@target($query.child()), $($other)*)
     );
-    (@target($query:expr), $name:ident) => (
-        $query.name(stringify!($name))
+    (@next($query:expr), >> $($other:tt)*) => (
+        query!(@target($query.descendant()), $($other)*)
     );
 
     ($node:expr, $($other:tt)*) => ({
@@ -22,12 +22,26 @@ pub(crate) struct AtLocation {
 pub(crate) enum Rule<'a, E: Extension + 'a> {
     /// Matches against child nodes
     Child,
+    /// Matches against nodes at any depth below
+    Descendant,
     /// Matches against the element's name
     Name(Cow<'a, str>),
+    /// Matches against any element
+    AnyElement,
     /// Matches against a property
     Property(Cow<'a, str>, ValueRef<'a, E>),
     /// Matches against a text node
     Text,
+    /// Matches against an element without children
+    Empty,
+    /// Matches against an element with at least one child
+    HasChildren,
+    /// Matches against the node's index within its parent,
+    /// negative indices count from the end
+    NthChild(isize),
+    /// Matches against nodes whose index within its parent
+    /// is `offset` modulo `step`
+    NthChildMod(usize, usize),
 }
 
 pub enum ValueRef<'a, E: Extension + 'a> {
@@ -35,6 +49,7 @@ pub enum ValueRef<'a, E: Extension + 'a> {
     Integer(i32),
     Float(f64),
     String(Cow<'a, str>),
+    Color(u8, u8, u8, u8),
     ExtValue(&'a E::Value),
 }
 
@@ -47,6 +62,7 @@ impl <'a, E> Clone for ValueRef<'a, E>
             ValueRef::Integer(v) => ValueRef::Integer(*v),
             ValueRef::Float(v) => ValueRef::Float(*v),
             ValueRef::String(v) => ValueRef::String(v.clone()),
+            ValueRef::Color(r, g, b, a) => ValueRef::Color(*r, *g, *b, *a),
             ValueRef::ExtValue(v) => ValueRef::ExtValue(*v),
         }
     }
@@ -99,6 +115,13 @@ impl <'a, E> AsValueRef<'a, E> for f32
         ValueRef::Float(self as f64)
     }
 }
+impl <'a, E> AsValueRef<'a, E> for (u8, u8, u8, u8)
+    where E: Extension
+{
+    fn as_value_ref(self) -> ValueRef<'a, E> {
+        ValueRef::Color(self.0, self.1, self.2, self.3)
+    }
+}
 impl <'a, E> AsValueRef<'a, E> for bool
     where E: Extension
 {
@@ -140,6 +163,14 @@ impl<'a, E> Query<'a, E>
         self
     }
 
+    /// Matches against any element regardless of its name,
+    /// fails if the current node is text.
+    #[inline]
+    pub fn any_name(mut self) -> Query<'a, E> {
+        self.rules.push(Rule::AnyElement);
+        self
+    }
+
     /// Matches against a text node otherwise it fails
     #[inline]
     pub fn text(mut self) -> Query<'a, E> {
@@ -147,6 +178,49 @@ impl<'a, E> Query<'a, E>
         self
     }
 
+    /// Matches against an element that has no child nodes,
+    /// fails otherwise.
+    #[inline]
+    pub fn empty(mut self) -> Query<'a, E> {
+        self.rules.push(Rule::Empty);
+        self
+    }
+
+    /// Matches against an element that has at least one
+    /// child node, fails otherwise.
+    #[inline]
+    pub fn has_children(mut self) -> Query<'a, E> {
+        self.rules.push(Rule::HasChildren);
+        self
+    }
+
+    /// Matches against the node at the given index within its
+    /// parent's children, fails otherwise.
+    ///
+    /// Indices start at `0` and negative indices count backwards
+    /// from the last child, e.g. `-1` matches the last child.
+    #[inline]
+    pub fn nth_child(mut self, n: isize) -> Query<'a, E> {
+        self.rules.push(Rule::NthChild(n));
+        self
+    }
+
+    /// Matches against nodes where the index within its parent's
+    /// children is equal to `offset` modulo `step`.
+    ///
+    /// For example `nth_child_mod(2, 0)` matches every other node
+    /// starting with the first.
+    ///
+    /// # Panics
+    ///
+    /// Panics if `step` is `0`.
+    #[inline]
+    pub fn nth_child_mod(mut self, step: usize, offset: usize) -> Query<'a, E> {
+        assert!(step != 0, "nth_child_mod step must not be zero");
+        self.rules.push(Rule::NthChildMod(step, offset));
+        self
+    }
+
     /// Matches against a property on the current node compares
     /// the value. Fails if the property is missing or the value
     /// doesn't match.
@@ -171,6 +245,17 @@ impl<'a, E> Query<'a, E>
         self
     }
 
+    /// Moves the matcher to a node at any depth below
+    /// the current one.
+    ///
+    /// All other methods (`name`/`text`/`property`) will
+    /// apply to the descendant after this
+    #[inline]
+    pub fn descendant(mut self) -> Query<'a, E> {
+        self.rules.push(Rule::Descendant);
+        self
+    }
+
     /// Returns a iterator over the possible matches
     #[inline]
     pub fn matches(self) -> QueryIterator<'a, E> {
@@ -202,13 +287,28 @@ impl<'a, E> Query<'a, E>
             }
         };
         let offset = num_children(&self.root) as isize - 1;
+        // Location queries start at the manager's root which is
+        // never clipped so its position is also its origin
+        let origin = (rect.x, rect.y);
+        let order = child_order(&self.root);
         QueryIterator {
-            nodes: vec![(self.root, offset, rect)],
+            nodes: vec![(self.root, offset, rect, origin, order)],
             rules: self.rules,
             location: self.location,
         }
     }
 
+    /// Returns a iterator over the possible matches along
+    /// with their depth.
+    ///
+    /// The depth is the number of levels below the node the
+    /// query was started on, which has a depth of `0`.
+    #[inline]
+    pub fn matches_with_depth(self) -> impl Iterator<Item=(Node<E>, usize)> + 'a {
+        let mut iter = self.matches();
+        ::std::iter::from_fn(move || iter.next_with_depth())
+    }
+
     /// Returns a single match if any.
     ///
     /// Alias for `matches().next()`
@@ -218,8 +318,13 @@ impl<'a, E> Query<'a, E>
     }
 }
 
+// The node, the index of the next child to visit, the visible
+// area of the node, the unclipped position of the node and
+// the order its children are rendered in if not the default
+type StackEntry<E> = (Node<E>, isize, Rect, (i32, i32), Option<Vec<usize>>);
+
 pub struct QueryIterator<'a, E: Extension + 'a> {
-    nodes: Vec<(Node<E>, isize, Rect)>,
+    nodes: Vec<StackEntry<E>>,
     rules: Vec<Rule<'a, E>>,
     location: Option<AtLocation>,
 }
@@ -234,15 +339,36 @@ fn num_children<E: Extension>(node: &Node<E>) -> usize {
     }
 }
 
+/// Returns the order the node's children are rendered in so
+/// the topmost child is visited first
+#[inline]
+fn child_order<E: Extension>(node: &Node<E>) -> Option<Vec<usize>> {
+    let inner = node.inner.borrow();
+    if let NodeValue::Element(ref e) = inner.value {
+        Node::render_order(&e.children)
+    } else {
+        None
+    }
+}
+
 impl<'a, E> Iterator for QueryIterator<'a, E>
     where E: Extension
 {
     type Item = Node<E>;
+    #[inline]
     fn next(&mut self) -> Option<Node<E>> {
+        self.next_with_depth().map(|v| v.0)
+    }
+}
+
+impl<'a, E> QueryIterator<'a, E>
+    where E: Extension
+{
+    fn next_with_depth(&mut self) -> Option<(Node<E>, usize)> {
         enum Action<E: Extension> {
             Nothing,
             Pop,
-            Push(Node<E>, Rect),
+            Push(Node<E>, Rect, (i32, i32)),
             Remove(Node<E>),
         }
 
@@ -254,21 +380,22 @@ impl<'a, E> Iterator for QueryIterator<'a, E>
                     let inner = cur.0.inner.borrow();
                     if let NodeValue::Element(ref e) = inner.value {
                         cur.1 -= 1;
-                        if let Some(node) = e.children.get((cur.1 + 1) as usize) {
+                        let idx = (cur.1 + 1) as usize;
+                        let idx = cur.4.as_ref().map_or(idx, |order| order[idx]);
+                        if let Some(node) = e.children.get(idx) {
                             if let Some(loc) = self.location {
-                                let mut rect = cur.2;
                                 let p_rect = cur.2;
-                                let p = node.parent()?.inner;
-                                let inner = p.borrow();
                                 let self_inner = node.inner.borrow();
 
-                                rect.x += self_inner.draw_rect.x;
-                                rect.y += self_inner.draw_rect.y;
-                                rect.width = self_inner.draw_rect.width;
-                                rect.height = self_inner.draw_rect.height;
-
-                                rect.x += inner.scroll_position.0 as i32;
-                                rect.y += inner.scroll_position.1 as i32;
+                                // Positioned relative to the parent's origin as
+                                // `p_rect` may have been moved by clipping
+                                let mut rect = Rect {
+                                    x: cur.3 .0 + self_inner.draw_rect.x + inner.scroll_position.0 as i32,
+                                    y: cur.3 .1 + self_inner.draw_rect.y + inner.scroll_position.1 as i32,
+                                    width: self_inner.draw_rect.width,
+                                    height: self_inner.draw_rect.height,
+                                };
+                                let origin = (rect.x, rect.y);
                                 if inner.clip_overflow {
                                     if rect.x < p_rect.x {
                                         rect.width -= p_rect.x - rect.x;
@@ -290,7 +417,7 @@ impl<'a, E> Iterator for QueryIterator<'a, E>
                                 {
                                     Action::Nothing
                                 } else {
-                                    Action::Push(node.clone(), rect)
+                                    Action::Push(node.clone(), rect, origin)
                                 }
                             } else {
                                 Action::Push(
@@ -301,6 +428,7 @@ impl<'a, E> Iterator for QueryIterator<'a, E>
                                         width: 0,
                                         height: 0,
                                     },
+                                    (0, 0),
                                 )
                             }
                         } else {
@@ -314,62 +442,133 @@ impl<'a, E> Iterator for QueryIterator<'a, E>
                 return None;
             };
 
-            let node = match action {
+            let (node, depth) = match action {
                 Action::Nothing => continue 'search,
                 Action::Pop => {
                     self.nodes.pop();
                     continue 'search;
                 }
-                Action::Push(node, rect) => {
+                Action::Push(node, rect, origin) => {
+                    let order = child_order(&node);
                     self.nodes
-                        .push((node.clone(), num_children(&node) as isize - 1, rect));
+                        .push((node.clone(), num_children(&node) as isize - 1, rect, origin, order));
                     continue 'search;
                 }
                 Action::Remove(node) => {
                     self.nodes.pop();
-                    node
+                    // The remaining stack is the node's ancestors
+                    (node, self.nodes.len())
                 }
             };
 
-            let mut cur = node.clone();
-            for rule in self.rules.iter().rev() {
-                match rule {
-                    Rule::Text => if let NodeValue::Element(_) = cur.inner.borrow().value {
-                        continue 'search;
-                    },
-                    Rule::Name(n) => if let NodeValue::Element(ref e) = cur.inner.borrow().value {
-                        if e.name != *n {
-                            continue 'search;
-                        }
-                    } else {
-                        continue 'search;
-                    },
-                    Rule::Property(ref k, ref val) => {
-                        let inner = cur.inner.borrow();
-                        let ok = match (inner.properties.get(&**k), val) {
-                            (Some(Value::Integer(a)), ValueRef::Integer(b)) => a == b,
-                            (Some(Value::Float(a)), ValueRef::Float(b)) => a == b,
-                            (Some(Value::Boolean(a)), ValueRef::Boolean(b)) => a == b,
-                            (Some(Value::String(a)), ValueRef::String(b)) => a == b,
-                            (Some(Value::ExtValue(a)), ValueRef::ExtValue(b)) => a == *b,
-                            _ => false,
-                        };
-                        if !ok {
-                            continue 'search;
-                        }
-                    }
-                    Rule::Child => {
-                        // Reversed so go up a level instead
-                        let parent = cur.inner.borrow().parent.as_ref().and_then(|v| v.upgrade());
-                        if let Some(parent) = parent {
-                            cur = Node { inner: parent };
-                        }
+            if test_rules(&self.rules, node.clone()) {
+                return Some((node, depth));
+            }
+        }
+    }
+}
+
+/// Returns the index of the node within its parent and
+/// the number of children the parent has.
+fn child_index<E>(node: &Node<E>) -> Option<(usize, usize)>
+    where E: Extension
+{
+    let parent = node.parent()?;
+    let inner = parent.inner.borrow();
+    if let NodeValue::Element(ref e) = inner.value {
+        e.children.iter()
+            .position(|v| v.is_same(node))
+            .map(|idx| (idx, e.children.len()))
+    } else {
+        None
+    }
+}
+
+/// Tests the rules against the node, working backwards
+/// from the last rule.
+fn test_rules<E>(rules: &[Rule<E>], node: Node<E>) -> bool
+    where E: Extension
+{
+    let mut cur = node;
+    for (idx, rule) in rules.iter().enumerate().rev() {
+        match rule {
+            Rule::Text => if let NodeValue::Element(_) = cur.inner.borrow().value {
+                return false;
+            },
+            Rule::Name(n) => if let NodeValue::Element(ref e) = cur.inner.borrow().value {
+                if e.name != *n {
+                    return false;
+                }
+            } else {
+                return false;
+            },
+            Rule::AnyElement => if let NodeValue::Text(_) = cur.inner.borrow().value {
+                return false;
+            },
+            Rule::Empty => if let NodeValue::Element(ref e) = cur.inner.borrow().value {
+                if !e.children.is_empty() {
+                    return false;
+                }
+            } else {
+                return false;
+            },
+            Rule::HasChildren => if let NodeValue::Element(ref e) = cur.inner.borrow().value {
+                if e.children.is_empty() {
+                    return false;
+                }
+            } else {
+                return false;
+            },
+            Rule::Property(ref k, ref val) => {
+                let inner = cur.inner.borrow();
+                let ok = match (inner.properties.get(&**k), val) {
+                    (Some(Value::Integer(a)), ValueRef::Integer(b)) => a == b,
+                    (Some(Value::Float(a)), ValueRef::Float(b)) => a == b,
+                    (Some(Value::Boolean(a)), ValueRef::Boolean(b)) => a == b,
+                    (Some(Value::String(a)), ValueRef::String(b)) => a == b,
+                    (Some(&Value::Color(r1, g1, b1, a1)), ValueRef::Color(r2, g2, b2, a2)) => (r1, g1, b1, a1) == (*r2, *g2, *b2, *a2),
+                    (Some(Value::ExtValue(a)), ValueRef::ExtValue(b)) => a == *b,
+                    _ => false,
+                };
+                if !ok {
+                    return false;
+                }
+            }
+            Rule::NthChild(n) => {
+                let ok = match child_index(&cur) {
+                    Some((idx, _)) if *n >= 0 => idx == *n as usize,
+                    Some((idx, len)) => len as isize + *n == idx as isize,
+                    None => false,
+                };
+                if !ok {
+                    return false;
+                }
+            }
+            Rule::NthChildMod(step, offset) => match child_index(&cur) {
+                Some((idx, _)) if idx % step == *offset => {},
+                _ => return false,
+            },
+            Rule::Descendant => {
+                // Any ancestor may match the remaining rules
+                let mut parent = cur.parent();
+                while let Some(p) = parent {
+                    if test_rules(&rules[..idx], p.clone()) {
+                        return true;
                     }
+                    parent = p.parent();
+                }
+                return false;
+            }
+            Rule::Child => {
+                // Reversed so go up a level instead
+                let parent = cur.inner.borrow().parent.as_ref().and_then(|v| v.upgrade());
+                if let Some(parent) = parent {
+                    cur = Node { inner: parent };
                 }
             }
-            return Some(node);
         }
     }
+    true
 }
 
 #[test]
@@ -399,3 +598,159 @@ panel {
         assert_eq!(&*n.get_property_ref::<String>("type").unwrap(), "warning");
     }
 }
+
+#[test]
+fn test_empty() {
+    let doc = syntax::desc::Document::parse(
+        r#"
+panel {
+    list(name="empty")
+    list(name="full") {
+        item
+    }
+    "text"
+}
+
+"#,
+    ).unwrap();
+    let node = Node::<tests::TestExt>::from_document(doc);
+
+    let empty: Vec<_> = node.query()
+        .name("list")
+        .empty()
+        .matches()
+        .map(|v| v.get_property::<String>("name").unwrap())
+        .collect();
+    assert_eq!(empty, vec!["empty".to_owned()]);
+
+    let full: Vec<_> = node.query()
+        .name("list")
+        .has_children()
+        .matches()
+        .map(|v| v.get_property::<String>("name").unwrap())
+        .collect();
+    assert_eq!(full, vec!["full".to_owned()]);
+
+    // Text nodes are neither
+    assert!(node.query().text().empty().next().is_none());
+    assert!(node.query().text().has_children().next().is_none());
+}
+
+#[test]
+fn test_any_name() {
+    let doc = syntax::desc::Document::parse(
+        r#"
+panel {
+    button(focused=true, id="a")
+    label(focused=false, id="b")
+    list(id="c") {
+        item(focused=true, id="d")
+    }
+    "text"(focused=true)
+}
+
+"#,
+    ).unwrap();
+    let node = Node::<tests::TestExt>::from_document(doc);
+
+    let focused: Vec<_> = query!(node, * (focused=true))
+        .matches()
+        .map(|v| v.get_property::<String>("id").unwrap())
+        .collect();
+    assert_eq!(focused, vec!["d".to_owned(), "a".to_owned()]);
+
+    let children: Vec<_> = query!(node, panel > * > item)
+        .matches()
+        .map(|v| v.get_property::<String>("id").unwrap())
+        .collect();
+    assert_eq!(children, vec!["d".to_owned()]);
+}
+
+#[test]
+fn test_descendant() {
+    let doc = syntax::desc::Document::parse(
+        r#"
+root {
+    panel {
+        wrapper {
+            "nested"
+        }
+        "direct"
+    }
+    other {
+        "outside"
+    }
+}
+
+"#,
+    ).unwrap();
+    let node = Node::<tests::TestExt>::from_document(doc);
+
+    let text: Vec<_> = query!(node, panel >> @text)
+        .matches()
+        .map(|v| v.text().unwrap().to_string())
+        .collect();
+    assert_eq!(text, vec!["direct".to_owned(), "nested".to_owned()]);
+
+    let text: Vec<_> = query!(node, root >> wrapper > @text)
+        .matches()
+        .map(|v| v.text().unwrap().to_string())
+        .collect();
+    assert_eq!(text, vec!["nested".to_owned()]);
+
+    assert!(query!(node, other >> wrapper).next().is_none());
+}
+
+#[test]
+fn test_nth_child() {
+    let node: Node<tests::TestExt> = node! {
+        list {
+            item(id=0)
+            item(id=1)
+            item(id=2)
+            item(id=3)
+            item(id=4)
+        }
+    };
+    let ids = |query: Query<tests::TestExt>| -> Vec<i32> {
+        query.matches()
+            .map(|v| v.get_property::<i32>("id").unwrap())
+            .collect()
+    };
+
+    assert_eq!(ids(node.query().name("list").child().nth_child(0)), vec![0]);
+    assert_eq!(ids(node.query().name("list").child().nth_child(2)), vec![2]);
+    assert_eq!(ids(node.query().name("list").child().nth_child(-1)), vec![4]);
+    assert_eq!(ids(node.query().name("list").child().nth_child(-5)), vec![0]);
+    assert!(ids(node.query().name("list").child().nth_child(5)).is_empty());
+    assert!(ids(node.query().name("list").child().nth_child(-6)).is_empty());
+
+    assert_eq!(ids(node.query().name("item").nth_child_mod(2, 0)), vec![4, 2, 0]);
+    assert_eq!(ids(node.query().name("item").nth_child_mod(2, 1)), vec![3, 1]);
+}
+
+#[test]
+fn test_matches_with_depth() {
+    let node: Node<tests::TestExt> = node! {
+        root(id=0) {
+            panel(id=1) {
+                item(id=2)
+                wrapper(id=2) {
+                    item(id=3)
+                }
+            }
+            item(id=1)
+        }
+    };
+
+    let depths: Vec<_> = node.query()
+        .name("item")
+        .matches_with_depth()
+        .map(|(n, d)| (n.get_property::<i32>("id").unwrap(), d))
+        .collect();
+    assert_eq!(depths, vec![(1, 1), (3, 3), (2, 2)]);
+
+    for (n, d) in node.query().matches_with_depth() {
+        assert_eq!(n.get_property::<i32>("id"), Some(d as i32));
+    }
+}
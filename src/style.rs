@@ -1,8 +1,9 @@
 use super::*;
 
 use std::hash::{Hash, Hasher};
+use std::cell::Cell;
 
-pub(crate) type SFunc<E> = Box<for<'a> Fn(&mut (Iterator<Item=Result<Value<E>, Error<'a>>> + 'a)) -> Result<Value<E>, Error<'a>> + 'static>;
+pub(crate) type SFunc<E> = Box<for<'a> Fn(&NodeChain<E>, &mut (Iterator<Item=Result<Value<E>, Error<'a>>> + 'a)) -> Result<Value<E>, Error<'a>> + 'static>;
 
 /// Stores rules, functions and layouts needed for computing styles
 pub struct Styles<E: Extension> {
@@ -12,10 +13,38 @@ pub struct Styles<E: Extension> {
     pub(crate) funcs: FnvHashMap<StaticKey, SFunc<E>>,
     pub(crate) layouts: FnvHashMap<&'static str, Box<Fn() -> Box<BoxLayoutEngine<E>>>>,
     pub(crate) next_rule_id: u32,
+    // The number of rules loaded under each name
+    pub(crate) loaded_styles: FnvHashMap<String, usize>,
     // Stored here for reuse to save on allocations
     pub(crate) used_keys: FnvHashSet<StaticKey>,
+    // Errors from evaluating properties and laying out nodes,
+    // drained by `Manager::take_errors`
+    pub(crate) errors: RefCell<Vec<LayoutError<E>>>,
+    // Problems found while loading styles, drained by
+    // `Manager::take_style_warnings`
+    pub(crate) warnings: Vec<StyleWarning>,
+    // The size passed to the last `Manager::layout` call
+    pub(crate) viewport: (i32, i32),
+    // Keys that are set to `inherit` by at least one rule. Nodes
+    // store their resolved values for these keys so that their
+    // children can look them up. Never shrinks, a stale key only
+    // costs an extra evaluation.
+    pub(crate) inherited_keys: FnvHashSet<StaticKey>,
+    // Keys that are set to an `action(...)` call by at least one
+    // rule. Stored like `inherited_keys` so that `Node::trigger`
+    // can find them.
+    pub(crate) action_keys: FnvHashSet<StaticKey>,
+    // Used by layouts to size text
+    pub(crate) text_measure: Box<dyn TextMeasure>,
+    // The number of update and layout passes run before giving up
+    // on a layout settling
+    pub(crate) max_layout_passes: usize,
 }
 
+// The number of errors kept for `Manager::take_errors`, later ones
+// are dropped until they are taken
+const MAX_ERRORS: usize = 256;
+
 impl <E: Extension> Styles<E> {
     #[inline]
     #[doc(hidden)]
@@ -23,14 +52,121 @@ impl <E: Extension> Styles<E> {
         self.used_keys.contains(key)
     }
 
+    #[inline]
+    #[doc(hidden)]
+    pub fn report_error(&self, key: StaticKey, expression: String, error: Error) {
+        let mut errors = self.errors.borrow_mut();
+        if errors.len() >= MAX_ERRORS {
+            return;
+        }
+        errors.push(LayoutError::Style(StyleError {
+            key: key.0,
+            expression,
+            error: error.into_owned(),
+        }));
+    }
+
+    /// Reports that the layout of the nodes didn't settle within
+    /// the maximum number of passes
+    pub(crate) fn report_unsettled(&mut self, passes: usize, nodes: &[Node<E>]) {
+        if self.errors.get_mut().len() >= MAX_ERRORS {
+            return;
+        }
+        let mut unsettled = Vec::new();
+        for n in nodes {
+            n.collect_unsettled(&mut unsettled);
+        }
+        self.errors.get_mut().push(LayoutError::NotConverged {
+            passes,
+            nodes: unsettled,
+        });
+    }
+
     pub(crate) fn load_styles<'a>(&mut self, name: &str, doc: syntax::style::Document<'a>) -> Result<(), syntax::PError<'a>>{
+        let start = self.next_rule_id;
+        let mut warnings = Vec::new();
         for rule in doc.rules {
+            for e in rule.styles.values().chain(rule.important.values()) {
+                check_types(e, &mut |position, message| warnings.push(StyleWarning {
+                    name: name.into(),
+                    position,
+                    message,
+                }));
+            }
             let id = self.next_rule_id;
             self.next_rule_id = self.next_rule_id.wrapping_add(1);
-            self.rules.add(id, &mut self.static_keys, name, rule)?;
+            if let Err(err) = self.rules.add(id, &mut self.static_keys, name, rule) {
+                // Drop the partially loaded rules, keeping the previous set
+                let added = self.next_rule_id.wrapping_sub(start);
+                self.rules.retain(&|r| r.name != name || r.id.wrapping_sub(start) >= added);
+                return Err(err);
+            }
         }
+        // Replace any rules previously loaded with the same name
+        let added = self.next_rule_id.wrapping_sub(start);
+        self.rules.retain(&|r| r.name != name || r.id.wrapping_sub(start) < added);
+        self.loaded_styles.insert(name.into(), added as usize);
+        self.warnings.extend(warnings);
+        let inherited_keys = &mut self.inherited_keys;
+        let action_keys = &mut self.action_keys;
+        self.rules.for_each(&mut |rule| {
+            inherited_keys.extend(rule.styles.iter()
+                .filter(|&(_, e)| matches!(*e, Expr::Inherit(_)))
+                .map(|(k, _)| *k));
+            action_keys.extend(rule.styles.iter()
+                .filter(|&(_, e)| is_action(e))
+                .map(|(k, _)| *k));
+        });
         Ok(())
     }
+
+    /// Builds a rule that always matches from the expression
+    /// properties of a node.
+    ///
+    /// Keys that aren't used by any style are skipped.
+    pub(crate) fn expr_rule(&mut self, exprs: &FnvHashMap<String, NodeExpr<E>>) -> Rc<Rule<E>> {
+        let mut styles = FnvHashMap::with_capacity_and_hasher(exprs.len(), Default::default());
+        let mut uses_parent_size = false;
+        for (k, e) in exprs {
+            let key = match self.static_keys.get(k.as_str()) {
+                Some(v) => *v,
+                None => continue,
+            };
+            uses_parent_size |= e.uses_parent_size;
+            let expr = match e.expr {
+                Some(ref v) => {
+                    if is_action(v) {
+                        self.action_keys.insert(key);
+                    }
+                    v.clone()
+                },
+                None => {
+                    self.inherited_keys.insert(key);
+                    Expr::Inherit(key)
+                },
+            };
+            styles.insert(key, expr);
+        }
+        Rc::new(Rule {
+            id: u32::MAX,
+            // Above every normal rule but below important ones
+            specificity: IMPORTANT - 1,
+            name: String::new(),
+            matchers: Rc::new(Vec::new()),
+            styles,
+            uses_parent_size,
+            enabled: Cell::new(true),
+        })
+    }
+}
+
+// Whether the expression creates an action via the built-in
+// `action` function
+fn is_action<E: Extension>(e: &Expr<E>) -> bool {
+    match *e {
+        Expr::Call(StaticKey(name), _) => name == "action",
+        _ => false,
+    }
 }
 
 #[derive(Clone, Eq, Debug)]
@@ -122,7 +258,46 @@ pub enum ValueMatcher {
     Integer(i32),
     Float(f64),
     String(String),
+    Color(u8, u8, u8, u8),
     Exists,
+    /// The property must not be set
+    Absent,
+    /// The property must be unset or not match
+    NotEqual(Box<ValueMatcher>),
+    Greater(f64),
+    GreaterEqual(f64),
+    Less(f64),
+    LessEqual(f64),
+    /// The property must equal the variable bound by the
+    /// property on the ancestor this many levels up
+    Bound(usize, String),
+}
+
+impl ValueMatcher {
+    fn test<E: Extension>(&self, val: Option<&Value<E>>) -> bool {
+        let number = match val {
+            Some(Value::Integer(i)) => Some(f64::from(*i)),
+            Some(Value::Float(f)) => Some(*f),
+            _ => None,
+        };
+        match (self, val, number) {
+            (ValueMatcher::Absent, val, _) => val.is_none(),
+            (ValueMatcher::NotEqual(vm), val, _) => !vm.test(val),
+            (ValueMatcher::Greater(a), _, Some(b)) => b > *a,
+            (ValueMatcher::GreaterEqual(a), _, Some(b)) => b >= *a,
+            (ValueMatcher::Less(a), _, Some(b)) => b < *a,
+            (ValueMatcher::LessEqual(a), _, Some(b)) => b <= *a,
+            (ValueMatcher::Boolean(a), Some(Value::Boolean(b)), _) => *a == *b,
+            (ValueMatcher::Integer(a), Some(Value::Integer(b)), _) => *a == *b,
+            (ValueMatcher::Integer(a), Some(Value::Float(b)), _) => *a as f64 == *b,
+            (ValueMatcher::Float(a), Some(Value::Float(b)), _) => *a == *b,
+            (ValueMatcher::Float(a), Some(Value::Integer(b)), _) => *a == *b as f64,
+            (ValueMatcher::String(ref a), Some(Value::String(ref b)), _) => a == b,
+            (ValueMatcher::Color(r1, g1, b1, a1), Some(Value::Color(r2, g2, b2, a2)), _) => (r1, g1, b1, a1) == (r2, g2, b2, a2),
+            (ValueMatcher::Exists, Some(_), _) => true,
+            (_, _, _) => false,
+        }
+    }
 }
 
 impl <E> Rules<E>
@@ -136,9 +311,16 @@ impl <E> Rules<E>
     }
 
     fn add<'a>(&mut self, id: u32, keys: &mut FnvHashMap<&'static str, StaticKey>, name: &str, rule: syntax::style::Rule<'a>) -> Result<(), syntax::PError<'a>> {
-        // Work in reverse to make lookups faster
+        use syntax::style::Combinator;
+        // Work in reverse to make lookups faster.
+        //
+        // Only matchers up to the first descendant combinator are
+        // at a fixed depth so the rest are left to `Rule::test`
+        let fixed = rule.combinators.iter().rev()
+            .position(|v| *v == Combinator::Descendant)
+            .map_or(rule.matchers.len(), |v| v + 1);
         let mut current = self;
-        for m in rule.matchers.iter().rev() {
+        for m in rule.matchers.iter().rev().take(fixed) {
             let key = match m.0 {
                 syntax::style::Matcher::Text => RuleKeyBorrow::Text,
                 syntax::style::Matcher::Element(ref e) => RuleKeyBorrow::Element(e.name.name.into()),
@@ -147,34 +329,152 @@ impl <E> Rules<E>
             let next = tmp.next.entry(RuleKey{inner: key}).or_insert_with(Rules::new);
             current = next;
         }
+        // Variables are bound by their first use, any later uses
+        // (which can only be on the same node or its descendants)
+        // must match the bound value instead
+        let mut bindings: FnvHashMap<&str, (usize, &str)> = FnvHashMap::default();
+        for (depth, m) in rule.matchers.iter().enumerate() {
+            let depth = rule.matchers.len() - 1 - depth;
+            for pm in &m.1 {
+                if let Some(syntax::style::Value::Variable(n)) = pm.value.as_ref().map(|v| &v.value) {
+                    bindings.entry(n.name).or_insert((depth, pm.name.name));
+                }
+            }
+        }
+        let bindings: FnvHashMap<String, (usize, String)> = bindings.into_iter()
+            .map(|(n, (depth, k))| (n.to_owned(), (depth, k.to_owned())))
+            .collect();
         let mut property_replacer = FnvHashMap::default();
         let mut matchers = Vec::with_capacity(rule.matchers.len());
-        for (depth, m) in rule.matchers.into_iter().rev().enumerate() {
+        let combinators = ::std::iter::once(Combinator::Child)
+            .chain(rule.combinators.into_iter().rev());
+        for ((depth, m), combinator) in rule.matchers.into_iter().rev().enumerate().zip(combinators) {
             let key = match m.0 {
                 syntax::style::Matcher::Text => RuleKeyBorrow::Text,
                 syntax::style::Matcher::Element(ref e) => RuleKeyBorrow::Element(e.name.name.into()),
             };
             let mut properties = Vec::with_capacity(m.1.len());
-            for (k, v) in m.1 {
+            for pm in m.1 {
                 use syntax::style::Value as SVal;
+                use syntax::style::MatchOp;
+                let k = pm.name;
+                // Documents can be built by hand so the value might
+                // not match the operator
+                let v = match (pm.op, pm.value) {
+                    (MatchOp::Absent, None) => {
+                        properties.push((k.name.to_owned(), ValueMatcher::Absent));
+                        continue;
+                    },
+                    (MatchOp::Absent, Some(v)) => return Err(syntax::Errors::new(
+                        v.position.into(),
+                        syntax::Error::Message(syntax::Info::Borrowed("Properties matched with `!` can't have a value")),
+                    )),
+                    (_, None) => return Err(syntax::Errors::new(
+                        k.position.into(),
+                        syntax::Error::Message(syntax::Info::Borrowed("Properties can only be matched without a value using `!`")),
+                    )),
+                    (_, Some(v)) => v,
+                };
                 let val = match v.value {
                     SVal::Boolean(b) => ValueMatcher::Boolean(b),
                     SVal::Integer(i) => ValueMatcher::Integer(i),
                     SVal::Float(f) => ValueMatcher::Float(f),
                     SVal::String(s) => ValueMatcher::String(unescape(s)),
+                    SVal::Color(r, g, b, a) => ValueMatcher::Color(r, g, b, a),
                     SVal::Variable(n) => {
-                        property_replacer.insert(n.name.to_owned(), (depth, k.name.to_owned()));
-                        ValueMatcher::Exists
-                    }
+                        // The depth of the matched node isn't known
+                        // ahead of time
+                        if depth >= fixed {
+                            return Err(syntax::Errors::new(
+                                n.position.into(),
+                                syntax::Error::Message(syntax::Info::Borrowed("Variables can't be used after a descendant combinator")),
+                            ));
+                        }
+                        if pm.op != MatchOp::Equal {
+                            return Err(syntax::Errors::new(
+                                n.position.into(),
+                                syntax::Error::Message(syntax::Info::Borrowed("Variables can only be captured using `=`")),
+                            ));
+                        }
+                        let (bound_depth, ref bound_key) = bindings[n.name];
+                        if bound_depth == depth && bound_key == k.name {
+                            property_replacer.insert(n.name.to_owned(), (depth, k.name.to_owned()));
+                            ValueMatcher::Exists
+                        } else {
+                            ValueMatcher::Bound(bound_depth - depth, bound_key.clone())
+                        }
+                    },
+                    SVal::ParentVariable(_, n) => return Err(syntax::Errors::new(
+                        n.position.into(),
+                        syntax::Error::Message(syntax::Info::Borrowed("Parent variables can only be used in expressions")),
+                    )),
+                };
+                let number = match val {
+                    ValueMatcher::Integer(i) => Some(f64::from(i)),
+                    ValueMatcher::Float(f) => Some(f),
+                    _ => None,
+                };
+                let val = match (pm.op, number) {
+                    // `Absent` was handled above
+                    (MatchOp::Equal, _) | (MatchOp::Absent, _) => val,
+                    (MatchOp::NotEqual, _) => ValueMatcher::NotEqual(Box::new(val)),
+                    (MatchOp::Greater, Some(n)) => ValueMatcher::Greater(n),
+                    (MatchOp::GreaterEqual, Some(n)) => ValueMatcher::GreaterEqual(n),
+                    (MatchOp::Less, Some(n)) => ValueMatcher::Less(n),
+                    (MatchOp::LessEqual, Some(n)) => ValueMatcher::LessEqual(n),
+                    (_, None) => return Err(syntax::Errors::new(
+                        v.position.into(),
+                        syntax::Error::Message(syntax::Info::Borrowed("Only integers and floats can be compared with `<` or `>`")),
+                    )),
                 };
                 properties.push((k.name.to_owned(), val));
             }
-            matchers.push((RuleKey{inner: key}, properties));
+            matchers.push((RuleKey{inner: key}, properties, combinator));
         }
 
-        let mut styles = FnvHashMap::with_capacity_and_hasher(rule.styles.len(), Default::default());
+        let specificity = matchers.iter()
+            .map(|v| 1 + v.1.len() as u32)
+            .sum();
+        let (styles, uses_parent_size) = Self::convert_styles(keys, &property_replacer, rule.styles)?;
+        let important = if rule.important.is_empty() {
+            None
+        } else {
+            Some(Self::convert_styles(keys, &property_replacer, rule.important)?)
+        };
+        let matchers = Rc::new(matchers);
+        current.matches.push(Rc::new(Rule {
+            id,
+            specificity,
+            name: name.into(),
+            matchers: matchers.clone(),
+            styles,
+            uses_parent_size,
+            enabled: Cell::new(true),
+        }));
+        // Important styles are split into their own rule that is
+        // ordered before every normal rule
+        if let Some((styles, uses_parent_size)) = important {
+            current.matches.push(Rc::new(Rule {
+                id,
+                specificity: specificity | IMPORTANT,
+                name: name.into(),
+                matchers,
+                styles,
+                uses_parent_size,
+                enabled: Cell::new(true),
+            }));
+        }
+        Ok(())
+    }
+
+    fn convert_styles<'a>(
+        keys: &FnvHashMap<&'static str, StaticKey>,
+        property_replacer: &FnvHashMap<String, (usize, String)>,
+        rule_styles: FnvHashMap<syntax::Ident<'a>, syntax::style::ExprType<'a>>,
+    ) -> Result<(FnvHashMap<StaticKey, Expr<E>>, bool), syntax::PError<'a>> {
+        let mut styles = FnvHashMap::with_capacity_and_hasher(rule_styles.len(), Default::default());
         let mut uses_parent_size = false;
-        for (k, e) in rule.styles {
+        for (k, e) in rule_styles {
             let key = match keys.get(k.name) {
                 Some(val) => val,
                 None => return Err(syntax::Errors::new(
@@ -182,24 +482,34 @@ impl <E> Rules<E>
                     syntax::Error::Message(syntax::Info::Borrowed("Unknown style key")),
                 )),
             };
-            styles.insert(*key, Expr::from_style(keys, &property_replacer, &mut uses_parent_size, e)?);
+            styles.insert(*key, Expr::from_style_key(*key, Some(keys), property_replacer, &mut uses_parent_size, e)?);
         }
-        current.matches.push(Rc::new(Rule {
-            id,
-            name: name.into(),
-            matchers,
-            styles,
-            uses_parent_size,
-        }));
-        Ok(())
+        Ok((styles, uses_parent_size))
+    }
+
+    /// Enables or disables every rule loaded with the given name,
+    /// returning whether any rule changed
+    pub(crate) fn set_enabled(&self, name: &str, enabled: bool) -> bool {
+        let mut changed = false;
+        self.for_each(&mut |rule| if rule.name == name && rule.enabled.get() != enabled {
+            rule.enabled.set(enabled);
+            changed = true;
+        });
+        changed
     }
 
     // Kinda expensive but shouldn't be common
     pub fn remove_all_by_name(&mut self, name: &str) {
+        self.retain(&|v| v.name != name);
+    }
+
+    fn retain<F>(&mut self, f: &F)
+        where F: Fn(&Rule<E>) -> bool
+    {
         self.next.values_mut().for_each(|v| {
-            v.remove_all_by_name(name);
+            v.retain(f);
         });
-        self.matches.retain(|v| v.name != name);
+        self.matches.retain(|v| f(v));
     }
 
     pub(super) fn get_possible_matches(&self, node: &NodeChain<E>, out: &mut Vec<Rc<Rule<E>>>) {
@@ -216,61 +526,172 @@ impl <E> Rules<E>
                 } else {
                     break
                 };
-                out.extend(current.matches.iter().cloned());
+                out.extend(current.matches.iter().filter(|v| v.enabled.get()).cloned());
             }
             node = n.parent;
         }
-        out.sort_unstable_by_key(|v| v.id);
+        // Applied in reverse so more specific and then later
+        // rules take priority
+        out.sort_unstable_by_key(|v| v.order());
     }
 }
 
+/// The keys a loaded style rule sets and reads.
+///
+/// Returned by
+/// [`Manager::rule_usage`](struct.Manager.html#method.rule_usage).
+#[derive(Debug, Clone, PartialEq)]
+pub struct RuleUsage {
+    /// The name the rule was loaded with
+    pub name: String,
+    /// The style keys set by the rule, sorted
+    pub sets: Vec<&'static str>,
+    /// The properties matched on or used as variables by the rule,
+    /// including `parent_width` style variables, sorted
+    pub reads: Vec<String>,
+}
+
+impl <E: Extension> Rules<E> {
+    /// Returns the usage of every rule in load order
+    pub(crate) fn usage(&self) -> Vec<RuleUsage> {
+        // Rules with important styles are split in two but
+        // share an id
+        let mut rules: FnvHashMap<u32, (String, FnvHashSet<&'static str>, FnvHashSet<String>)> = FnvHashMap::default();
+        self.for_each(&mut |rule| {
+            let entry = rules.entry(rule.id)
+                .or_insert_with(|| (rule.name.clone(), FnvHashSet::default(), FnvHashSet::default()));
+            entry.1.extend(rule.styles.keys().map(|v| v.0));
+            for m in rule.matchers.iter() {
+                entry.2.extend(m.1.iter().map(|v| v.0.clone()));
+            }
+            for e in rule.styles.values() {
+                e.collect_reads(&mut entry.2);
+            }
+        });
+        let mut rules: Vec<_> = rules.into_iter().collect();
+        rules.sort_by_key(|v| v.0);
+        rules.into_iter()
+            .map(|(_, (name, sets, reads))| {
+                let mut sets: Vec<_> = sets.into_iter().collect();
+                sets.sort_unstable();
+                let mut reads: Vec<_> = reads.into_iter().collect();
+                reads.sort();
+                RuleUsage {
+                    name,
+                    sets,
+                    reads,
+                }
+            })
+            .collect()
+    }
+
+    fn for_each(&self, f: &mut dyn FnMut(&Rule<E>)) {
+        for rule in &self.matches {
+            f(rule);
+        }
+        for next in self.next.values() {
+            next.for_each(f);
+        }
+    }
+}
+
+/// Added to the specificity of rules containing `!important`
+/// styles so they are ordered before all normal rules
+const IMPORTANT: u32 = 1 << 31;
+
+/// A matcher in a rule along with how it is reached
+/// from the previous matcher
+pub(crate) type RuleMatcher = (RuleKey, Vec<(String, ValueMatcher)>, syntax::style::Combinator);
+
 /// A rule which contains a set of matchers to compare against
 /// the properties of a node and parents and a set of styles to
 /// apply if matched.
 pub struct Rule<E: Extension> {
     id: u32,
+    // The number of matchers plus the number of properties
+    // they check
+    specificity: u32,
     name: String,
-    pub(crate) matchers: Vec<(RuleKey, Vec<(String, ValueMatcher)>)>,
+    // In reverse order
+    pub(crate) matchers: Rc<Vec<RuleMatcher>>,
     #[doc(hidden)]
     // Used by the `eval!` macro
     pub styles: FnvHashMap<StaticKey, Expr<E>>,
     pub(crate) uses_parent_size: bool,
+    // Cleared by `Manager::set_styles_enabled` to skip the
+    // rule without unloading it
+    pub(crate) enabled: Cell<bool>,
 }
 
 impl <E> Rule<E>
     where E: Extension
 {
     pub(super) fn test(&self, node: &NodeChain<E>) -> bool {
-        let mut node = Some(node);
-        for (_rkey, props) in &self.matchers {
-            if let Some(n) = node.take() {
-                // Key doesn't need checking because `get_possible_matches` will filter
-                // that
-
-                for (key, vm) in props {
-                    if let Some(val) = n.properties.get(key) {
-                        let same = match (vm, val) {
-                            (ValueMatcher::Boolean(a), Value::Boolean(b)) => *a == *b,
-                            (ValueMatcher::Integer(a), Value::Integer(b)) => *a == *b,
-                            (ValueMatcher::Integer(a), Value::Float(b)) => *a as f64 == *b,
-                            (ValueMatcher::Float(a), Value::Float(b)) => *a == *b,
-                            (ValueMatcher::Float(a), Value::Integer(b)) => *a == *b as f64,
-                            (ValueMatcher::String(ref a), Value::String(ref b)) => a == b,
-                            (ValueMatcher::Exists, _) => true,
-                            (_, _) => false,
-                        };
-                        if !same {
-                            return false;
-                        }
-                    } else {
-                        return false;
-                    }
+        Self::test_matchers(&self.matchers, node, false)
+    }
+
+    /// The key rules are sorted by before being applied
+    pub(super) fn order(&self) -> (u32, u32) {
+        (self.specificity, self.id)
+    }
+
+    fn test_matchers(
+        matchers: &[RuleMatcher],
+        node: &NodeChain<E>,
+        check_key: bool,
+    ) -> bool {
+        use syntax::style::Combinator;
+        let (rkey, props) = match matchers.first() {
+            Some(v) => (&v.0, &v.1),
+            None => return true,
+        };
+        // The key only needs checking past a descendant combinator,
+        // before that `get_possible_matches` will have filtered it
+        if check_key {
+            let key = match node.value {
+                NCValue::Text(_) => RuleKeyBorrow::Text,
+                NCValue::Element(e) => RuleKeyBorrow::ElementBorrow(e),
+            };
+            if *rkey != key {
+                return false;
+            }
+        }
+
+        for (key, vm) in props {
+            let matches = if let ValueMatcher::Bound(up, ref bound_key) = *vm {
+                let mut bound = Some(node);
+                for _ in 0 .. up {
+                    bound = bound.and_then(|v| v.parent);
+                }
+                match (node.properties.get(key), bound.and_then(|v| v.properties.get(bound_key))) {
+                    (Some(a), Some(b)) => a == b,
+                    _ => false,
                 }
-                node = n.parent;
             } else {
+                vm.test(node.properties.get(key))
+            };
+            if !matches {
                 return false;
             }
         }
-        true
+
+        let rest = &matchers[1..];
+        match rest.first() {
+            None => true,
+            Some(&(_, _, Combinator::Child)) => match node.parent {
+                Some(p) => Self::test_matchers(rest, p, check_key),
+                None => false,
+            },
+            Some(&(_, _, Combinator::Descendant)) => {
+                let mut cur = node.parent;
+                while let Some(p) = cur {
+                    if Self::test_matchers(rest, p, true) {
+                        return true;
+                    }
+                    cur = p.parent;
+                }
+                false
+            },
+        }
     }
 }
\ No newline at end of file
@@ -0,0 +1,190 @@
+//! A renderer that draws nodes as an SVG document
+use super::*;
+use std::fmt::Write;
+
+type StyleFn<E> = Box<dyn Fn(&NodeInner<E>) -> SvgStyle>;
+type ShapeFn<E> = Box<dyn Fn(&NodeInner<E>, Rect, &mut String)>;
+
+/// A color as red, green, blue and alpha components
+pub type SvgColor = (u8, u8, u8, u8);
+
+/// How a node is drawn by an `SvgRenderer`
+#[derive(Debug, Clone, Copy, PartialEq, Default)]
+pub struct SvgStyle {
+    /// The color an element is filled with or text is drawn in
+    pub fill: Option<SvgColor>,
+    /// The color and width of an element's border
+    pub stroke: Option<(SvgColor, i32)>,
+}
+
+/// Renders nodes as an SVG document.
+///
+/// Elements with a fill or stroke become a `<rect>` and text
+/// nodes become `<text>` at the node's absolute position. Nodes
+/// are emitted in render order so later nodes draw over earlier
+/// ones. Clipping isn't applied.
+///
+/// The style function picks how each node is drawn as the values
+/// it uses are decided by the extension. Extensions can add
+/// their own shapes using `with_shapes`.
+///
+/// Requires the `svg` feature.
+///
+/// # Example
+///
+/// ```rust
+/// # #[macro_use] extern crate fungui;
+/// # use fungui::*;
+/// enum MyExt {}
+///
+/// impl Extension for MyExt {
+///     type NodeData = ();
+///     type Value = ();
+///     fn new_data() {}
+///     fn style_properties<'a, F>(_prop: F)
+///         where F: FnMut(StaticKey) + 'a
+///     {}
+///     fn update_data(_: &Styles<Self>, _: &NodeChain<Self>, _: &Rule<Self>, _: &mut ()) -> DirtyFlags {
+///         DirtyFlags::empty()
+///     }
+///     fn reset_unset_data(_: &FnvHashSet<StaticKey>, _: &mut ()) -> DirtyFlags {
+///         DirtyFlags::empty()
+///     }
+/// }
+///
+/// # fn main() {
+/// let mut manager: Manager<MyExt> = Manager::new();
+/// manager.load_styles("example", r#"
+/// panel {
+///     x = 1,
+///     width = 2,
+///     height = 1,
+/// }
+/// "#).unwrap();
+/// manager.add_node(node!(panel(selected=true)));
+/// manager.layout(4, 1);
+///
+/// // Only fill selected nodes
+/// let mut render = SvgRenderer::new(4, 1, |node: &NodeInner<MyExt>| SvgStyle {
+///     fill: node.get_property::<bool>("selected")
+///         .filter(|v| *v)
+///         .map(|_| (0, 0, 255, 255)),
+///     stroke: None,
+/// });
+/// manager.render(&mut render);
+/// assert!(render.as_string().contains(r#"<rect x="1" y="0" width="2" height="1" fill="rgb(0,0,255)"/>"#));
+/// # }
+/// ```
+pub struct SvgRenderer<E: Extension> {
+    width: i32,
+    height: i32,
+    body: String,
+    offsets: Vec<(i32, i32)>,
+    style: StyleFn<E>,
+    shapes: Option<ShapeFn<E>>,
+}
+
+impl <E: Extension> SvgRenderer<E> {
+    /// Creates a renderer for a document of the given size using
+    /// `style` to pick how each node is drawn.
+    pub fn new<F>(width: i32, height: i32, style: F) -> SvgRenderer<E>
+        where F: Fn(&NodeInner<E>) -> SvgStyle + 'static
+    {
+        SvgRenderer {
+            width,
+            height,
+            body: String::new(),
+            offsets: vec![(0, 0)],
+            style: Box::new(style),
+            shapes: None,
+        }
+    }
+
+    /// Sets a function called for every node after it is drawn
+    /// with the node's absolute rect. Any SVG elements written
+    /// to the string are added to the document.
+    pub fn with_shapes<F>(mut self, shapes: F) -> SvgRenderer<E>
+        where F: Fn(&NodeInner<E>, Rect, &mut String) + 'static
+    {
+        self.shapes = Some(Box::new(shapes));
+        self
+    }
+
+    /// Returns the rendered SVG document
+    pub fn as_string(&self) -> String {
+        format!(
+            "<svg xmlns=\"http://www.w3.org/2000/svg\" width=\"{w}\" height=\"{h}\" viewBox=\"0 0 {w} {h}\">\n{}</svg>\n",
+            self.body,
+            w = self.width,
+            h = self.height,
+        )
+    }
+}
+
+fn write_color(out: &mut String, attr: &str, c: SvgColor) {
+    let _ = write!(out, " {}=\"rgb({},{},{})\"", attr, c.0, c.1, c.2);
+    if c.3 != 255 {
+        let _ = write!(out, " {}-opacity=\"{}\"", attr, f64::from(c.3) / 255.0);
+    }
+}
+
+fn escape_xml(out: &mut String, text: &str) {
+    for c in text.chars() {
+        match c {
+            '<' => out.push_str("&lt;"),
+            '>' => out.push_str("&gt;"),
+            '&' => out.push_str("&amp;"),
+            '"' => out.push_str("&quot;"),
+            _ => out.push(c),
+        }
+    }
+}
+
+impl <E: Extension> RenderVisitor<E> for SvgRenderer<E> {
+    fn visit(&mut self, node: &mut NodeInner<E>) {
+        let (lx, ly) = self.offsets.last().cloned().expect("Missing offset data");
+        let rect = Rect {
+            x: node.draw_rect.x + lx,
+            y: node.draw_rect.y + ly,
+            .. node.draw_rect
+        };
+        let style = (self.style)(node);
+        match node.value {
+            NodeValue::Element(_) => if style.fill.is_some() || style.stroke.is_some() {
+                let out = &mut self.body;
+                let _ = write!(out, "<rect x=\"{}\" y=\"{}\" width=\"{}\" height=\"{}\"",
+                    rect.x, rect.y, rect.width, rect.height);
+                match style.fill {
+                    Some(c) => write_color(out, "fill", c),
+                    None => out.push_str(" fill=\"none\""),
+                }
+                if let Some((c, width)) = style.stroke {
+                    write_color(out, "stroke", c);
+                    let _ = write!(out, " stroke-width=\"{}\"", width);
+                }
+                out.push_str("/>\n");
+            },
+            NodeValue::Text(ref t) => {
+                let out = &mut self.body;
+                let _ = write!(out, "<text x=\"{}\" y=\"{}\" dominant-baseline=\"hanging\"", rect.x, rect.y);
+                if let Some(c) = style.fill {
+                    write_color(out, "fill", c);
+                }
+                out.push('>');
+                escape_xml(out, t);
+                out.push_str("</text>\n");
+            },
+        }
+        if let Some(shapes) = self.shapes.as_ref() {
+            shapes(node, rect, &mut self.body);
+        }
+        self.offsets.push((
+            rect.x + node.scroll_position.0 as i32,
+            rect.y + node.scroll_position.1 as i32,
+        ));
+    }
+
+    fn visit_end(&mut self, _node: &mut NodeInner<E>) {
+        self.offsets.pop();
+    }
+}
@@ -1,13 +1,20 @@
 #![allow(missing_docs)]
 use super::*;
 
+#[derive(Debug)]
 pub enum TestExt{}
 
 static CHAR: StaticKey = StaticKey("char");
+static ON_CLICK: StaticKey = StaticKey("on_click");
+
+thread_local! {
+    // The number of rules applied to nodes by `update_data`
+    static UPDATES: ::std::cell::Cell<usize> = const { ::std::cell::Cell::new(0) };
+}
 
 impl Extension for TestExt {
     type NodeData = TestData;
-    type Value = ();
+    type Value = i32;
     fn new_data() -> TestData {
         TestData {
             render_char: '#',
@@ -18,9 +25,11 @@ impl Extension for TestExt {
         where F: FnMut(StaticKey) + 'a
     {
         prop(CHAR);
+        prop(ON_CLICK);
     }
 
     fn update_data(styles: &Styles<TestExt>, nc: &NodeChain<TestExt>, rule: &Rule<TestExt>, data: &mut Self::NodeData) -> DirtyFlags {
+        UPDATES.with(|v| v.set(v.get() + 1));
         eval!(styles, nc, rule.CHAR => val => {
             if let Some(c) = val.convert::<String>() {
                 data.render_char = c.chars().next().unwrap_or('~');
@@ -38,62 +47,32 @@ impl Extension for TestExt {
         }
         DirtyFlags::empty()
     }
+
+    fn ext_binary_op(op: BinaryOp, left: &Value<TestExt>, right: &Value<TestExt>) -> Option<Value<TestExt>> {
+        match (op, left, right) {
+            (BinaryOp::Add, Value::ExtValue(a), Value::ExtValue(b)) => Some(Value::ExtValue(a + b)),
+            (BinaryOp::Mul, Value::ExtValue(a), Value::Integer(b)) => Some(Value::ExtValue(a * b)),
+            _ => None,
+        }
+    }
 }
 
 pub struct TestData {
     render_char: char,
 }
 
-pub struct AsciiRender {
-    width: usize,
-    height: usize,
-    data: Vec<char>,
-    offsets: Vec<(i32, i32)>,
-}
+/// Creates an `AsciiRenderer` that draws the `char` style
+/// of each node
+pub struct AsciiRender;
 
 impl AsciiRender {
-    pub fn new(width: usize, height: usize) -> AsciiRender {
-        let data = vec!['#'; width * height];
-        AsciiRender {
-            width,
-            height,
-            data,
-            offsets: vec![(0, 0)],
-        }
-    }
-
-    pub fn as_string(&self) -> String {
-        let mut out = String::with_capacity(self.width * self.height);
-        for line in self.data.chunks(self.width) {
-            out.extend(line);
-            out.push('\n');
-        }
-        out.pop();
-        out
-    }
-}
-
-impl RenderVisitor<TestExt> for AsciiRender {
-
-    fn visit(&mut self, node: &mut NodeInner<TestExt>) {
-        let c = node.ext.render_char;
-        let (lx, ly) = self.offsets.last().cloned().expect("Missing offset data");
-        let ox = node.draw_rect.x + lx;
-        let oy = node.draw_rect.y + ly;
-        for y in 0 .. node.draw_rect.height {
-            for x in 0 .. node.draw_rect.width {
-                let idx = (ox + x) as usize + (oy + y) as usize * self.width;
-                self.data[idx] = c;
-            }
-        }
-        self.offsets.push((ox, oy));
-    }
-    fn visit_end(&mut self, _node: &mut NodeInner<TestExt>) {
-        self.offsets.pop();
+    // Kept as `new` so tests read as if this were the renderer itself
+    #[allow(clippy::new_ret_no_self)]
+    pub fn new(width: usize, height: usize) -> AsciiRenderer<TestExt> {
+        AsciiRenderer::new(width, height, |node: &NodeInner<TestExt>| node.ext.render_char)
     }
 }
 
-
 #[test]
 fn test() {
     let mut manager: Manager<TestExt> = Manager::new();
@@ -165,4 +144,4180 @@ inner {
 "##.trim();
 
     assert_eq!(layout, expected_output);
-}
\ No newline at end of file
+}
+
+#[test]
+fn test_bounding_box() {
+    let mut manager: Manager<TestExt> = Manager::new();
+    let src = r#"
+parent {
+    x = 2,
+    y = 1,
+    width = 4,
+    height = 3,
+}
+parent > child {
+    x = 3,
+    y = -1,
+    width = 4,
+    height = 2,
+}
+    "#;
+    if let Err(err) = manager.load_styles("test", src) {
+        let stdout = std::io::stdout();
+        format_parse_error(stdout.lock(), src.lines(), err).unwrap();
+        panic!("Styles failed to parse");
+    }
+    let node = node! {
+        parent {
+            child
+        }
+    };
+    assert_eq!(node.bounding_box(), None);
+    manager.add_node(node.clone());
+
+    manager.layout(20, 8);
+
+    assert_eq!(node.bounding_box(), Some(Rect {
+        x: 2,
+        y: 0,
+        width: 7,
+        height: 4,
+    }));
+}
+
+#[test]
+fn test_clamp() {
+    let mut manager: Manager<TestExt> = Manager::new();
+    let src = r#"
+clamped {
+    width = clamp(parent_width - 40, 100, 600),
+    height = clamp(5, 10, 2),
+}
+clamped_float {
+    width = int(clamp(float(parent_width) * 0.5, 10.0, 20.0)),
+    height = 1,
+}
+    "#;
+    if let Err(err) = manager.load_styles("test", src) {
+        let stdout = std::io::stdout();
+        format_parse_error(stdout.lock(), src.lines(), err).unwrap();
+        panic!("Styles failed to parse");
+    }
+    let node = node!(clamped);
+    let node_float = node!(clamped_float);
+    manager.add_node(node.clone());
+    manager.add_node(node_float.clone());
+
+    manager.layout(1000, 100);
+    assert_eq!(node.raw_position(), Rect { x: 0, y: 0, width: 600, height: 10 });
+    assert_eq!(node_float.raw_position().width, 20);
+
+    manager.layout(300, 100);
+    assert_eq!(node.raw_position(), Rect { x: 0, y: 0, width: 260, height: 10 });
+    assert_eq!(node_float.raw_position().width, 20);
+
+    manager.layout(30, 100);
+    assert_eq!(node.raw_position(), Rect { x: 0, y: 0, width: 100, height: 10 });
+    assert_eq!(node_float.raw_position().width, 15);
+}
+
+#[test]
+fn test_clamp_mixed_types() {
+    let mut args = vec![
+        Ok(Value::Integer(5)),
+        Ok(Value::Float(1.0)),
+        Ok(Value::Integer(10)),
+    ].into_iter();
+    match funcs::clamp::<TestExt>(&mut args) {
+        Err(Error::IncompatibleTypesOp { op: "clamp", left_ty: "integer", right_ty: "float" }) => {},
+        Err(err) => panic!("Unexpected error: {:?}", err),
+        Ok(_) => panic!("Expected an error"),
+    }
+}
+
+#[test]
+fn test_text_properties() {
+    let mut manager: Manager<TestExt> = Manager::new();
+    let src = r#"
+content > @text {
+    char = "a",
+}
+content > @text(url=url) {
+    char = "b",
+}
+    "#;
+    if let Err(err) = manager.load_styles("test", src) {
+        let stdout = std::io::stdout();
+        format_parse_error(stdout.lock(), src.lines(), err).unwrap();
+        panic!("Styles failed to parse");
+    }
+    let node = Node::from_str(r#"
+content {
+    "Click "
+    "here"(url="http://example.com")
+}
+    "#).unwrap();
+    manager.add_node(node.clone());
+
+    manager.layout(20, 8);
+
+    let children = node.children();
+    assert_eq!(children[0].borrow().ext.render_char, 'a');
+    assert_eq!(children[1].borrow().ext.render_char, 'b');
+
+    children[1].remove_property("url");
+    children[0].set_property("url", "http://example.com".to_owned());
+    manager.layout(20, 8);
+
+    assert_eq!(children[0].borrow().ext.render_char, 'b');
+    assert_eq!(children[1].borrow().ext.render_char, 'a');
+}
+
+#[test]
+fn test_min_max() {
+    let mut manager: Manager<TestExt> = Manager::new();
+    let src = r#"
+sized(line_height=lh, content_height=ch) {
+    width = min(parent_width, 50, 30 + 5),
+    height = max(lh, ch),
+}
+sized_float {
+    width = int(max(1.5, 7.5, 3.0)),
+    height = int(min(4.0, 2.5)),
+}
+    "#;
+    if let Err(err) = manager.load_styles("test", src) {
+        let stdout = std::io::stdout();
+        format_parse_error(stdout.lock(), src.lines(), err).unwrap();
+        panic!("Styles failed to parse");
+    }
+    let node = node!(sized(line_height = 12, content_height = 40));
+    let node_float = node!(sized_float);
+    manager.add_node(node.clone());
+    manager.add_node(node_float.clone());
+
+    manager.layout(100, 100);
+    assert_eq!(node.raw_position(), Rect { x: 0, y: 0, width: 35, height: 40 });
+    assert_eq!(node_float.raw_position(), Rect { x: 0, y: 0, width: 7, height: 2 });
+
+    manager.layout(20, 100);
+    assert_eq!(node.raw_position().width, 20);
+}
+
+#[test]
+fn test_min_max_errors() {
+    let mut args = vec![
+        Ok(Value::Integer(5)),
+    ].into_iter();
+    match funcs::min::<TestExt>(&mut args) {
+        Err(Error::MissingParameter { position: 1, .. }) => {},
+        Err(err) => panic!("Unexpected error: {:?}", err),
+        Ok(_) => panic!("Expected an error"),
+    }
+
+    let mut args = vec![
+        Ok(Value::Float(5.0)),
+        Ok(Value::Float(3.0)),
+        Ok(Value::Integer(1)),
+    ].into_iter();
+    match funcs::max::<TestExt>(&mut args) {
+        Err(Error::IncompatibleTypesOp { op: "max", left_ty: "float", right_ty: "integer" }) => {},
+        Err(err) => panic!("Unexpected error: {:?}", err),
+        Ok(_) => panic!("Expected an error"),
+    }
+}
+
+#[test]
+fn test_export_layout() {
+    let mut manager: Manager<TestExt> = Manager::new();
+    let src = r#"
+alert {
+    x = 5,
+    y = 2,
+    width = 50,
+    height = 40,
+}
+alert > title {
+    width = parent_width,
+    height = 10,
+}
+alert > content {
+    y = 10,
+    width = parent_width,
+    height = 20,
+    clip_overflow = true,
+}
+    "#;
+    if let Err(err) = manager.load_styles("test", src) {
+        let stdout = std::io::stdout();
+        format_parse_error(stdout.lock(), src.lines(), err).unwrap();
+        panic!("Styles failed to parse");
+    }
+    manager.add_node_str(r#"
+alert(level="warning") {
+    title {
+        "This is an alert"
+    }
+    content {
+        "here"(url="http://example.com")
+    }
+}
+    "#).unwrap();
+
+    manager.layout(100, 100);
+
+    let dump = manager.export_layout();
+    assert_eq!(dump.nodes.len(), 1);
+    let alert = &dump.nodes[0];
+    assert_eq!(alert.name.as_deref(), Some("alert"));
+    assert_eq!(alert.properties, vec![("level".to_owned(), DumpValue::String("warning".to_owned()))]);
+    assert_eq!(alert.layout, "absolute");
+    assert_eq!(alert.draw_rect, Rect { x: 5, y: 2, width: 50, height: 40 });
+    assert_eq!(alert.children.len(), 2);
+
+    let title = &alert.children[0];
+    assert_eq!(title.name.as_deref(), Some("title"));
+    assert_eq!(title.draw_rect, Rect { x: 0, y: 0, width: 50, height: 10 });
+    assert_eq!(title.children[0].text.as_deref(), Some("This is an alert"));
+
+    let content = &alert.children[1];
+    assert_eq!(content.name.as_deref(), Some("content"));
+    assert_eq!(content.draw_rect, Rect { x: 0, y: 10, width: 50, height: 20 });
+    assert_eq!(content.render_position, Some(Rect { x: 5, y: 12, width: 50, height: 20 }));
+    assert!(content.clip_overflow);
+    assert_eq!(content.children[0].name, None);
+    assert_eq!(content.children[0].properties, vec![("url".to_owned(), DumpValue::String("http://example.com".to_owned()))]);
+}
+
+#[test]
+fn test_if() {
+    let mut manager: Manager<TestExt> = Manager::new();
+    let src = r#"
+sized(flag=flag) {
+    width = if(flag, 10, 20),
+    height = if(!flag, 1.5, 3.5),
+}
+    "#;
+    if let Err(err) = manager.load_styles("test", src) {
+        let stdout = std::io::stdout();
+        format_parse_error(stdout.lock(), src.lines(), err).unwrap();
+        panic!("Styles failed to parse");
+    }
+    let node = node!(sized(flag = true));
+    manager.add_node(node.clone());
+
+    manager.layout(100, 100);
+    assert_eq!(node.raw_position(), Rect { x: 0, y: 0, width: 10, height: 3 });
+
+    node.set_property("flag", false);
+    manager.layout(100, 100);
+    assert_eq!(node.raw_position(), Rect { x: 0, y: 0, width: 20, height: 1 });
+}
+
+#[test]
+fn test_if_short_circuit() {
+    let manager: Manager<TestExt> = Manager::new();
+    let properties = FnvHashMap::default();
+    let nc = NodeChain {
+        parent: None,
+        value: NCValue::Element("test"),
+        draw_rect: Rect::default(),
+        properties: &properties,
+        resolved: &FnvHashMap::default(),
+    };
+    let key = manager.styles.static_keys["if"];
+    let missing = || Expr::Variable("missing".to_owned());
+
+    let expr = Expr::Call(key, vec![
+        Expr::Value(Value::Boolean(true)),
+        Expr::Value(Value::Integer(1)),
+        missing(),
+    ]);
+    assert_eq!(expr.eval(&manager.styles, &nc).ok(), Some(Value::Integer(1)));
+
+    let expr = Expr::Call(key, vec![
+        Expr::Value(Value::Boolean(false)),
+        missing(),
+        Expr::Value(Value::Integer(2)),
+    ]);
+    assert_eq!(expr.eval(&manager.styles, &nc).ok(), Some(Value::Integer(2)));
+
+    let expr = Expr::Call(key, vec![
+        Expr::Value(Value::Boolean(false)),
+        Expr::Value(Value::Integer(1)),
+        missing(),
+    ]);
+    match expr.eval(&manager.styles, &nc) {
+        Err(Error::UnknownVariable { name: "missing" }) => {},
+        Err(err) => panic!("Unexpected error: {:?}", err),
+        Ok(_) => panic!("Expected an error"),
+    }
+}
+
+#[test]
+fn test_size_constraints() {
+    let mut manager: Manager<TestExt> = Manager::new();
+    let src = r#"
+container {
+    width = parent_width,
+    height = 2,
+    max_width = 30,
+    min_height = 5,
+}
+container > inner {
+    width = parent_width,
+    height = parent_height,
+}
+container(small=true) {
+    width = 10,
+    min_width = 20,
+    max_width = 15,
+}
+    "#;
+    if let Err(err) = manager.load_styles("test", src) {
+        let stdout = std::io::stdout();
+        format_parse_error(stdout.lock(), src.lines(), err).unwrap();
+        panic!("Styles failed to parse");
+    }
+    let node = node! {
+        container {
+            inner
+        }
+    };
+    manager.add_node(node.clone());
+
+    manager.layout(100, 100);
+    assert_eq!(node.raw_position(), Rect { x: 0, y: 0, width: 30, height: 5 });
+    assert_eq!(node.children()[0].raw_position(), Rect { x: 0, y: 0, width: 30, height: 5 });
+
+    manager.layout(20, 100);
+    assert_eq!(node.raw_position(), Rect { x: 0, y: 0, width: 20, height: 5 });
+
+    node.set_property("small", true);
+    manager.layout(20, 100);
+    assert_eq!(node.raw_position(), Rect { x: 0, y: 0, width: 20, height: 5 });
+
+    node.remove_property("small");
+    manager.layout(100, 100);
+    assert_eq!(node.raw_position(), Rect { x: 0, y: 0, width: 30, height: 5 });
+}
+
+#[test]
+fn test_concat_format() {
+    let string = |v: &str| Ok(Value::String(v.to_owned()));
+
+    let mut args = vec![string("icons/"), string("warning"), string(".png")].into_iter();
+    assert_eq!(funcs::concat::<TestExt>(&mut args).ok(), Some(Value::String("icons/warning.png".to_owned())));
+
+    let mut args = vec![string("icons/"), Ok(Value::Integer(5))].into_iter();
+    match funcs::concat::<TestExt>(&mut args) {
+        Err(Error::IncompatibleTypeOp { op: "concat", ty: "integer" }) => {},
+        Err(err) => panic!("Unexpected error: {:?}", err),
+        Ok(_) => panic!("Expected an error"),
+    }
+
+    let mut args = vec![
+        string("{}: {} {} {} {{}}"),
+        string("test"),
+        Ok(Value::Integer(5)),
+        Ok(Value::Float(1.5)),
+        Ok(Value::Boolean(true)),
+    ].into_iter();
+    assert_eq!(funcs::format::<TestExt>(&mut args).ok(), Some(Value::String("test: 5 1.5 true {}".to_owned())));
+
+    let mut args = vec![string("{} and {}"), string("one")].into_iter();
+    match funcs::format::<TestExt>(&mut args) {
+        Err(Error::MissingParameter { position: 2, .. }) => {},
+        Err(err) => panic!("Unexpected error: {:?}", err),
+        Ok(_) => panic!("Expected an error"),
+    }
+}
+
+#[test]
+fn test_concat_style() {
+    let mut manager: Manager<TestExt> = Manager::new();
+    let src = r#"
+icon(kind=kind) {
+    char = concat("@", kind),
+}
+    "#;
+    if let Err(err) = manager.load_styles("test", src) {
+        let stdout = std::io::stdout();
+        format_parse_error(stdout.lock(), src.lines(), err).unwrap();
+        panic!("Styles failed to parse");
+    }
+    let node = node!(icon(kind = "warning".to_owned()));
+    manager.add_node(node.clone());
+
+    manager.layout(100, 100);
+    assert_eq!(node.borrow().ext.render_char, '@');
+}
+
+#[test]
+fn test_clip_rect() {
+    let mut manager: Manager<TestExt> = Manager::new();
+    let src = r#"
+clip {
+    x = 1,
+    y = 1,
+    width = 4,
+    height = 3,
+    char = "@",
+    clip_overflow = true,
+}
+clip > inner {
+    x = 2,
+    y = 1,
+    width = 6,
+    height = 4,
+    char = "+",
+}
+    "#;
+    if let Err(err) = manager.load_styles("test", src) {
+        let stdout = std::io::stdout();
+        format_parse_error(stdout.lock(), src.lines(), err).unwrap();
+        panic!("Styles failed to parse");
+    }
+    let node = node! {
+        clip {
+            inner
+        }
+    };
+    manager.add_node(node.clone());
+
+    manager.layout(10, 6);
+
+    assert_eq!(node.borrow().clip_rect, None);
+    assert_eq!(node.children()[0].borrow().clip_rect, Some(Rect { x: 1, y: 1, width: 4, height: 3 }));
+
+    let mut render = AsciiRender::new(10, 6);
+    manager.render(&mut render);
+
+    let layout = render.as_string();
+    println!("Layout: \n{}", layout);
+
+    let expected_output = r##"
+##########
+#@@@@#####
+#@@++#####
+#@@++#####
+##########
+##########
+"##.trim();
+
+    assert_eq!(layout, expected_output);
+}
+
+#[test]
+fn test_evaluate() {
+    let mut manager: Manager<TestExt> = Manager::new();
+    let src = r#"
+parent {
+    width = 40,
+    height = 10,
+}
+    "#;
+    if let Err(err) = manager.load_styles("test", src) {
+        let stdout = std::io::stdout();
+        format_parse_error(stdout.lock(), src.lines(), err).unwrap();
+        panic!("Styles failed to parse");
+    }
+    let node = node! {
+        parent {
+            child(size = 3)
+        }
+    };
+    manager.add_node(node.clone());
+    manager.layout(100, 100);
+
+    let child = node.children()[0].clone();
+    assert_eq!(manager.evaluate(&child, "parent_width / 2").ok(), Some(Value::Integer(20)));
+    assert_eq!(manager.evaluate(&child, "max(size, parent_height) * 2").ok(), Some(Value::Integer(20)));
+    assert_eq!(manager.evaluate(&node, "parent_width").ok(), Some(Value::Integer(100)));
+
+    match manager.evaluate(&child, "size + missing") {
+        Err(Error::UnknownVariable { name: "missing" }) => {},
+        Err(err) => panic!("Unexpected error: {:?}", err),
+        Ok(_) => panic!("Expected an error"),
+    }
+    match manager.evaluate(&child, "missing(size)") {
+        Err(Error::UnknownFunction { name: "missing" }) => {},
+        Err(err) => panic!("Unexpected error: {:?}", err),
+        Ok(_) => panic!("Expected an error"),
+    }
+    match manager.evaluate(&child, "size + 1.0") {
+        Err(Error::IncompatibleTypesOp { op: "+", .. }) => {},
+        Err(err) => panic!("Unexpected error: {:?}", err),
+        Ok(_) => panic!("Expected an error"),
+    }
+}
+
+#[test]
+fn test_take_errors() {
+    let mut manager: Manager<TestExt> = Manager::new();
+    let src = r#"
+root > panel {
+    width = 10 / "two",
+    height = 5,
+}
+    "#;
+    if let Err(err) = manager.load_styles("test", src) {
+        let stdout = std::io::stdout();
+        format_parse_error(stdout.lock(), src.lines(), err).unwrap();
+        panic!("Styles failed to parse");
+    }
+    manager.add_node(node! {
+        panel
+    });
+    manager.layout(100, 100);
+
+    let errors = manager.take_errors();
+    assert_eq!(errors.len(), 1);
+    match errors[0] {
+        LayoutError::Style(StyleError {
+            key: "width",
+            error: Error::IncompatibleTypesOp { op: "/", left_ty: "integer", right_ty: "string" },
+            ..
+        }) => {},
+        ref err => panic!("Unexpected error: {:?}", err),
+    }
+    assert!(errors[0].to_string().contains("width"));
+
+    // Drained after taking
+    assert!(manager.take_errors().is_empty());
+
+    // Only the first errors are kept until they are taken
+    for _ in 0 .. 300 {
+        manager.add_node(node!(panel));
+    }
+    manager.layout(100, 100);
+    assert_eq!(manager.take_errors().len(), 256);
+}
+
+#[test]
+fn test_insert_child_at() {
+    let node: Node<TestExt> = node! {
+        list {
+            a
+            c
+        }
+    };
+    assert!(node.insert_child_at(1, node!(b)));
+    assert!(node.insert_child_at(0, node!(start)));
+    assert!(node.insert_child_at(4, node!(end)));
+
+    let names: Vec<_> = node.children().iter()
+        .map(|v| v.name().unwrap())
+        .collect();
+    assert_eq!(names, vec!["start", "a", "b", "c", "end"]);
+
+    // Past the end is rejected and the node remains unparented
+    let extra = node!(extra);
+    assert!(!node.insert_child_at(6, extra.clone()));
+    assert!(extra.parent().is_none());
+    assert_eq!(node.children().len(), 5);
+
+    // Nodes that already have a parent are rejected
+    let child = node.children()[0].clone();
+    assert!(!node.insert_child_at(0, child));
+}
+
+#[test]
+fn test_take_dirty_flags() {
+    let mut manager: Manager<TestExt> = Manager::new();
+    let src = r#"
+root > panel {
+    width = 10,
+    height = 5,
+}
+    "#;
+    if let Err(err) = manager.load_styles("test", src) {
+        let stdout = std::io::stdout();
+        format_parse_error(stdout.lock(), src.lines(), err).unwrap();
+        panic!("Styles failed to parse");
+    }
+    let node = node! {
+        panel
+    };
+    manager.add_node(node.clone());
+    manager.layout(100, 100);
+
+    let flags = node.borrow_mut().take_dirty_flags();
+    assert!(flags.contains(DirtyFlags::SIZE));
+    // Only returned once per layout
+    assert_eq!(node.borrow_mut().take_dirty_flags(), DirtyFlags::empty());
+}
+
+#[test]
+fn test_replace_child() {
+    let node: Node<TestExt> = node! {
+        list {
+            a
+            b
+            c
+        }
+    };
+    let old = node.children()[1].clone();
+    let new = node!(d);
+    assert!(node.replace_child(&old, new.clone()));
+
+    let names: Vec<_> = node.children().iter()
+        .map(|v| v.name().unwrap())
+        .collect();
+    assert_eq!(names, vec!["a", "d", "c"]);
+    assert!(old.parent().is_none());
+    assert!(new.parent().unwrap().is_same(&node));
+
+    // `old` is no longer a child
+    assert!(!node.replace_child(&old, node!(e)));
+
+    // The replacement already has a parent
+    let a = node.children()[0].clone();
+    let c = node.children()[2].clone();
+    assert!(!node.replace_child(&a, c));
+
+    let names: Vec<_> = node.children().iter()
+        .map(|v| v.name().unwrap())
+        .collect();
+    assert_eq!(names, vec!["a", "d", "c"]);
+}
+
+#[test]
+fn test_query_all() {
+    let mut manager: Manager<TestExt> = Manager::new();
+    let src = r#"
+root > menu {
+    x = 5,
+    y = 5,
+    width = 50,
+    height = 50,
+}
+menu > item(index=i) {
+    x = 0,
+    y = i * 10,
+    width = 20,
+    height = 10,
+}
+    "#;
+    if let Err(err) = manager.load_styles("test", src) {
+        let stdout = std::io::stdout();
+        format_parse_error(stdout.lock(), src.lines(), err).unwrap();
+        panic!("Styles failed to parse");
+    }
+    manager.add_node(node! {
+        menu {
+            item(index = 0)
+            item(index = 1)
+            other
+            item(index = 2)
+        }
+    });
+    manager.layout(100, 100);
+
+    let items = manager.query_all(|q| q.name("item"));
+    let found: Vec<_> = items.iter()
+        .map(|(n, r)| (n.get_property::<i32>("index").unwrap(), *r))
+        .collect();
+    // Same order as `matches`, last child first
+    assert_eq!(found, vec![
+        (2, Rect { x: 5, y: 25, width: 20, height: 10 }),
+        (1, Rect { x: 5, y: 15, width: 20, height: 10 }),
+        (0, Rect { x: 5, y: 5, width: 20, height: 10 }),
+    ]);
+
+    assert!(manager.query_all(|q| q.name("missing")).is_empty());
+}
+
+#[test]
+fn test_descendant_matcher() {
+    let mut manager: Manager<TestExt> = Manager::new();
+    let src = r#"
+button {
+    width = 10,
+    height = 10,
+}
+form(valid=false) button {
+    width = 20,
+}
+    "#;
+    if let Err(err) = manager.load_styles("test", src) {
+        let stdout = std::io::stdout();
+        format_parse_error(stdout.lock(), src.lines(), err).unwrap();
+        panic!("Styles failed to parse");
+    }
+    let invalid = node! {
+        form(valid=false) {
+            wrapper {
+                inner {
+                    button
+                }
+            }
+        }
+    };
+    let valid = node! {
+        form(valid=true) {
+            wrapper {
+                button
+            }
+        }
+    };
+    let outside = node! {
+        button
+    };
+    manager.add_node(invalid.clone());
+    manager.add_node(valid.clone());
+    manager.add_node(outside.clone());
+    manager.layout(100, 100);
+
+    let width = |n: &Node<TestExt>| n.query()
+        .name("button")
+        .matches()
+        .next()
+        .unwrap()
+        .raw_position()
+        .width;
+    assert_eq!(width(&invalid), 20);
+    assert_eq!(width(&valid), 10);
+    assert_eq!(outside.raw_position().width, 10);
+
+    // Changing the property on the ancestor updates the match
+    invalid.set_property("valid", true);
+    manager.layout(100, 100);
+    assert_eq!(width(&invalid), 10);
+
+    // The depth isn't fixed so variables can't be captured
+    let src = r#"
+form(valid=v) button {
+    width = v,
+}
+    "#;
+    assert!(manager.load_styles("error", src).is_err());
+}
+
+#[test]
+fn test_descendants() {
+    let node: Node<TestExt> = node! {
+        root {
+            a {
+                b
+                @text("text")
+            }
+            c {
+                d {
+                    e
+                }
+            }
+            f
+        }
+    };
+    let order: Vec<_> = node.descendants()
+        .map(|v| v.name().or_else(|| v.text().map(|v| v.to_owned())).unwrap())
+        .collect();
+    assert_eq!(order, vec!["a", "b", "text", "c", "d", "e", "f"]);
+
+    let leaf: Node<TestExt> = node!(leaf);
+    assert_eq!(leaf.descendants().count(), 0);
+}
+
+#[test]
+fn test_first_last_child() {
+    let empty: Node<TestExt> = node!(empty);
+    assert!(empty.first_child().is_none());
+    assert!(empty.last_child().is_none());
+
+    let text: Node<TestExt> = node!(@text("hello"));
+    assert!(text.first_child().is_none());
+    assert!(text.last_child().is_none());
+
+    let one: Node<TestExt> = node! {
+        one {
+            only
+        }
+    };
+    assert_eq!(one.first_child().and_then(|v| v.name()), Some("only".to_owned()));
+    assert!(one.first_child().unwrap().is_same(&one.last_child().unwrap()));
+
+    let many: Node<TestExt> = node! {
+        many {
+            a
+            b
+            c
+        }
+    };
+    assert_eq!(many.first_child().and_then(|v| v.name()), Some("a".to_owned()));
+    assert_eq!(many.last_child().and_then(|v| v.name()), Some("c".to_owned()));
+}
+
+#[test]
+fn test_absolute_constraints() {
+    let mut manager: Manager<TestExt> = Manager::new();
+    let src = r#"
+panel {
+    x = 5,
+    y = 5,
+    width = 80,
+    height = 80,
+}
+panel > item {
+    x = 10,
+    y = 10,
+    width = 60,
+    height = 2,
+}
+    "#;
+    let constraints = r#"
+panel > item {
+    max_width = 40,
+    min_height = 4,
+}
+    "#;
+    for &(name, src) in &[("test", src), ("constraints", constraints)] {
+        if let Err(err) = manager.load_styles(name, src) {
+            let stdout = std::io::stdout();
+            format_parse_error(stdout.lock(), src.lines(), err).unwrap();
+            panic!("Styles failed to parse");
+        }
+    }
+    let node = node! {
+        panel {
+            item
+        }
+    };
+    manager.add_node(node.clone());
+    let item = node.first_child().unwrap();
+
+    manager.layout(100, 100);
+    assert_eq!(item.raw_position(), Rect { x: 10, y: 10, width: 40, height: 4 });
+
+    // Unset constraints stop applying
+    manager.remove_styles("constraints");
+    manager.layout(100, 100);
+    assert_eq!(item.raw_position(), Rect { x: 10, y: 10, width: 60, height: 2 });
+}
+
+#[test]
+fn test_text_trim() {
+    let node = Node::<TestExt>::from_str(r#"
+panel {
+    "  preserved "
+    "  trimmed \n"(trim=true)
+    " kept "(trim=false)
+}
+    "#).unwrap();
+    let text: Vec<_> = node.children().iter()
+        .map(|v| v.text().unwrap().to_owned())
+        .collect();
+    assert_eq!(text, vec!["  preserved ", "trimmed", " kept "]);
+}
+
+#[test]
+fn test_linear_layout() {
+    let mut manager: Manager<TestExt> = Manager::new();
+    let src = r#"
+root > row {
+    layout = "row",
+    x = 1,
+    y = 1,
+    width = 12,
+    height = 2,
+    spacing = 1,
+}
+row > item {
+    width = 2,
+    char = "a",
+}
+row > item(fill=true) {
+    grow = 1,
+    char = "b",
+}
+root > column {
+    layout = "column",
+    x = 14,
+    y = 0,
+    width = 3,
+    height = 8,
+    spacing = 2,
+}
+column > item {
+    height = 1,
+    char = "c",
+}
+column > item(short=true) {
+    width = 1,
+}
+    "#;
+    if let Err(err) = manager.load_styles("test", src) {
+        let stdout = std::io::stdout();
+        format_parse_error(stdout.lock(), src.lines(), err).unwrap();
+        panic!("Styles failed to parse");
+    }
+    manager.add_node(node! {
+        row {
+            item
+            item(fill = true)
+            item
+        }
+    });
+    manager.add_node(node! {
+        column {
+            item
+            item(short = true)
+            item
+        }
+    });
+
+    manager.layout(20, 8);
+
+    let mut render = AsciiRender::new(20, 8);
+    manager.render(&mut render);
+
+    let layout = render.as_string();
+    println!("Layout: \n{}", layout);
+
+    let expected_output = r##"
+##############ccc###
+#aa~bbbbbb~aa#~~~###
+#aa~bbbbbb~aa#~~~###
+##############c~~###
+##############~~~###
+##############~~~###
+##############ccc###
+##############~~~###
+"##.trim();
+
+    assert_eq!(layout, expected_output);
+}
+
+#[test]
+fn test_viewport_size() {
+    let mut manager: Manager<TestExt> = Manager::new();
+    let src = r#"
+outer {
+    x = 2,
+    width = 10,
+    height = 10,
+}
+outer > middle {
+    width = 5,
+    height = 5,
+}
+middle > inner {
+    width = viewport_width / 2,
+    height = viewport_height - 1,
+}
+    "#;
+    if let Err(err) = manager.load_styles("test", src) {
+        let stdout = std::io::stdout();
+        format_parse_error(stdout.lock(), src.lines(), err).unwrap();
+        panic!("Styles failed to parse");
+    }
+    let node = node! {
+        outer {
+            middle {
+                inner
+            }
+        }
+    };
+    manager.add_node(node.clone());
+    let inner = node.query().name("inner").next().unwrap();
+
+    manager.layout(40, 8);
+    assert_eq!(inner.raw_position(), Rect { x: 0, y: 0, width: 20, height: 7 });
+
+    manager.layout(60, 4);
+    assert_eq!(inner.raw_position(), Rect { x: 0, y: 0, width: 30, height: 3 });
+
+    assert_eq!(manager.evaluate(&inner, "viewport_width").ok(), Some(Value::Integer(60)));
+}
+
+#[test]
+fn test_percent_size() {
+    let mut manager: Manager<TestExt> = Manager::new();
+    let src = r#"
+root > panel {
+    width = 40,
+    height = 30,
+}
+panel > percent {
+    width_percent = 50,
+    height_percent = 25.0,
+}
+panel > parent {
+    width = parent_width / 2,
+    height = parent_height / 4,
+}
+panel > both {
+    width = 5,
+    width_percent = 50,
+    height_percent = 10,
+}
+root > full {
+    width_percent = 100,
+    height_percent = 50,
+}
+    "#;
+    if let Err(err) = manager.load_styles("test", src) {
+        let stdout = std::io::stdout();
+        format_parse_error(stdout.lock(), src.lines(), err).unwrap();
+        panic!("Styles failed to parse");
+    }
+    let node = node! {
+        panel {
+            percent
+            parent
+            both
+        }
+    };
+    let full = node!(full);
+    manager.add_node(node.clone());
+    manager.add_node(full.clone());
+    manager.layout(100, 100);
+
+    let get = |name| node.query().name(name).next().unwrap();
+    let percent = get("percent");
+    let parent = get("parent");
+    assert_eq!(percent.raw_position(), Rect { x: 0, y: 0, width: 20, height: 7 });
+    assert_eq!(percent.raw_position(), parent.raw_position());
+    // Only `parent_*` requires the extra layout pass
+    assert!(!percent.inner.borrow().uses_parent_size);
+    assert!(parent.inner.borrow().uses_parent_size);
+
+    // Explicit sizes take priority
+    assert_eq!(get("both").raw_position(), Rect { x: 0, y: 0, width: 5, height: 3 });
+
+    // Relative to the manager's size at the root
+    assert_eq!(full.raw_position(), Rect { x: 0, y: 0, width: 100, height: 50 });
+    manager.layout(50, 20);
+    assert_eq!(full.raw_position(), Rect { x: 0, y: 0, width: 50, height: 10 });
+}
+
+#[test]
+fn test_theme() {
+    let mut manager: Manager<TestExt> = Manager::new();
+    let src = r#"
+panel {
+    width = theme("panel_width"),
+    height = 2,
+    char = theme("primary"),
+}
+    "#;
+    if let Err(err) = manager.load_styles("test", src) {
+        let stdout = std::io::stdout();
+        format_parse_error(stdout.lock(), src.lines(), err).unwrap();
+        panic!("Styles failed to parse");
+    }
+    manager.set_theme_value("panel_width", 3);
+    manager.set_theme_value("primary", "@".to_owned());
+    let node = node!(panel);
+    manager.add_node(node.clone());
+
+    manager.layout(5, 2);
+    let mut render = AsciiRender::new(5, 2);
+    manager.render(&mut render);
+    assert_eq!(render.as_string(), "@@@##\n@@@##");
+
+    manager.set_theme_value("panel_width", 4);
+    manager.set_theme_value("primary", "+".to_owned());
+    manager.layout(5, 2);
+    let mut render = AsciiRender::new(5, 2);
+    manager.render(&mut render);
+    assert_eq!(render.as_string(), "++++#\n++++#");
+
+    manager.remove_theme_value("panel_width");
+    manager.layout(5, 2);
+    assert!(manager.take_errors().iter().any(|e| matches!(*e, LayoutError::Style(StyleError { key: "width", .. }))));
+}
+
+#[test]
+fn test_convert_option() {
+    let node: Node<TestExt> = node!(panel(count = 0, name = String::new()));
+
+    // Absent
+    assert_eq!(node.get_property::<Option<i32>>("missing"), Some(None));
+    assert_eq!(node.get_property::<i32>("missing"), None);
+    // Present and valid, even when falsy
+    assert_eq!(node.get_property::<Option<i32>>("count"), Some(Some(0)));
+    assert_eq!(node.get_property::<Option<String>>("name"), Some(Some(String::new())));
+    // Present with the wrong type
+    assert_eq!(node.get_property::<Option<bool>>("count"), None);
+
+    node.set_property("count", Some(5));
+    assert_eq!(node.get_property::<i32>("count"), Some(5));
+    // Setting `None` removes the property
+    node.set_property::<Option<i32>>("count", None);
+    assert_eq!(node.get_property::<Option<i32>>("count"), Some(None));
+}
+
+#[test]
+fn test_lerp() {
+    let lerp = |a, b, t| {
+        let mut args = vec![Ok(a), Ok(b), Ok(t)].into_iter();
+        funcs::lerp::<TestExt>(&mut args)
+    };
+    assert_eq!(lerp(Value::Float(0.0), Value::Float(10.0), Value::Float(0.5)).ok(), Some(Value::Float(5.0)));
+    assert_eq!(lerp(Value::Float(2.0), Value::Float(4.0), Value::Float(0.25)).ok(), Some(Value::Float(2.5)));
+    // `t` is clamped
+    assert_eq!(lerp(Value::Float(0.0), Value::Float(10.0), Value::Float(1.5)).ok(), Some(Value::Float(10.0)));
+    assert_eq!(lerp(Value::Float(0.0), Value::Float(10.0), Value::Float(-2.0)).ok(), Some(Value::Float(0.0)));
+
+    match lerp(Value::Float(0.0), Value::Integer(10), Value::Float(0.5)) {
+        Err(Error::IncompatibleTypesOp { op: "lerp", left_ty: "float", right_ty: "integer" }) => {},
+        Err(err) => panic!("Unexpected error: {:?}", err),
+        Ok(_) => panic!("Expected an error"),
+    }
+    match lerp(Value::Float(0.0), Value::Float(10.0), Value::Boolean(true)) {
+        Err(Error::IncompatibleTypeOp { op: "lerp", ty: "boolean" }) => {},
+        Err(err) => panic!("Unexpected error: {:?}", err),
+        Ok(_) => panic!("Expected an error"),
+    }
+}
+
+#[test]
+fn test_math_functions() {
+    type Args<'a> = dyn Iterator<Item=Result<Value<TestExt>, Error<'a>>> + 'a;
+    fn call<'a>(
+        f: fn(&mut Args<'a>) -> Result<Value<TestExt>, Error<'a>>,
+        args: Vec<Value<TestExt>>,
+    ) -> Result<Value<TestExt>, Error<'a>> {
+        let mut args = args.into_iter().map(Ok);
+        f(&mut args)
+    }
+    let float = |v: Result<Value<TestExt>, Error>| match v {
+        Ok(Value::Float(f)) => f,
+        v => panic!("Unexpected result: {:?}", v),
+    };
+    let close = |a: f64, b: f64| assert!((a - b).abs() < 1e-9, "{} != {}", a, b);
+
+    close(float(call(funcs::pow, vec![Value::Float(2.0), Value::Float(10.0)])), 1024.0);
+    close(float(call(funcs::sqrt, vec![Value::Float(9.0)])), 3.0);
+    close(float(call(funcs::sin, vec![Value::Float(::std::f64::consts::FRAC_PI_2)])), 1.0);
+    close(float(call(funcs::cos, vec![Value::Float(::std::f64::consts::PI)])), -1.0);
+    close(float(call(funcs::tan, vec![Value::Float(::std::f64::consts::FRAC_PI_4)])), 1.0);
+    close(float(call(funcs::abs, vec![Value::Float(-2.5)])), 2.5);
+    assert_eq!(call(funcs::abs, vec![Value::Integer(-3)]).ok(), Some(Value::Integer(3)));
+
+    // Domain errors
+    match call(funcs::sqrt, vec![Value::Float(-1.0)]) {
+        Err(Error::Custom { .. }) => {},
+        v => panic!("Unexpected result: {:?}", v),
+    }
+    match call(funcs::pow, vec![Value::Float(-8.0), Value::Float(0.5)]) {
+        Err(Error::Custom { .. }) => {},
+        v => panic!("Unexpected result: {:?}", v),
+    }
+    match call(funcs::sin, vec![Value::Integer(1)]) {
+        Err(Error::IncompatibleTypeOp { op: "sin", ty: "integer" }) => {},
+        v => panic!("Unexpected result: {:?}", v),
+    }
+    match call(funcs::pow, vec![Value::Float(2.0)]) {
+        Err(Error::MissingParameter { position: 1, name: "exp" }) => {},
+        v => panic!("Unexpected result: {:?}", v),
+    }
+}
+
+#[test]
+fn test_round_floor_ceil() {
+    let round = |v| {
+        let mut args = vec![Ok(v)].into_iter();
+        funcs::round::<TestExt>(&mut args)
+    };
+    let floor = |v| {
+        let mut args = vec![Ok(v)].into_iter();
+        funcs::floor::<TestExt>(&mut args)
+    };
+    let ceil = |v| {
+        let mut args = vec![Ok(v)].into_iter();
+        funcs::ceil::<TestExt>(&mut args)
+    };
+
+    assert_eq!(round(Value::Float(1.4)).ok(), Some(Value::Float(1.0)));
+    assert_eq!(round(Value::Float(1.6)).ok(), Some(Value::Float(2.0)));
+    assert_eq!(round(Value::Float(-1.6)).ok(), Some(Value::Float(-2.0)));
+    // Half-way values round away from zero
+    assert_eq!(round(Value::Float(2.5)).ok(), Some(Value::Float(3.0)));
+    assert_eq!(round(Value::Float(-2.5)).ok(), Some(Value::Float(-3.0)));
+
+    assert_eq!(floor(Value::Float(1.9)).ok(), Some(Value::Float(1.0)));
+    assert_eq!(floor(Value::Float(-1.1)).ok(), Some(Value::Float(-2.0)));
+    assert_eq!(ceil(Value::Float(1.1)).ok(), Some(Value::Float(2.0)));
+    assert_eq!(ceil(Value::Float(-1.9)).ok(), Some(Value::Float(-1.0)));
+
+    match round(Value::Integer(3)) {
+        Err(Error::Custom { reason }) => assert!(reason.contains("already whole numbers"), "{}", reason),
+        v => panic!("Unexpected result: {:?}", v),
+    }
+    match floor(Value::Boolean(true)) {
+        Err(Error::IncompatibleTypeOp { op: "floor", ty: "boolean" }) => {},
+        v => panic!("Unexpected result: {:?}", v),
+    }
+
+    // Usable from styles
+    let mut manager: Manager<TestExt> = Manager::new();
+    let src = r#"
+panel(w=w) {
+    x = int(w),
+    width = int(round(w)),
+    height = int(ceil(w / 2.0)),
+}
+    "#;
+    if let Err(err) = manager.load_styles("test", src) {
+        let stdout = std::io::stdout();
+        format_parse_error(stdout.lock(), src.lines(), err).unwrap();
+        panic!("Styles failed to parse");
+    }
+    let node = node!(panel(w=4.5));
+    manager.add_node(node.clone());
+    manager.layout(10, 10);
+    // `int` on its own truncates
+    assert_eq!(node.borrow().draw_rect, Rect { x: 4, y: 0, width: 5, height: 3 });
+}
+
+#[test]
+fn test_convert_color() {
+    let node: Node<TestExt> = node!(panel(fill = (0x11, 0x22, 0x33, 0x44), old = "#FF8000".to_owned()));
+
+    assert_eq!(node.get_property::<(u8, u8, u8, u8)>("fill"), Some((0x11, 0x22, 0x33, 0x44)));
+    assert_eq!(node.get_property::<Value<TestExt>>("fill"), Some(Value::Color(0x11, 0x22, 0x33, 0x44)));
+    // String colors are still accepted
+    assert_eq!(node.get_property::<(u8, u8, u8, u8)>("old"), Some((0xFF, 0x80, 0x00, 0xFF)));
+    assert_eq!(node.get_property::<(u8, u8, u8, u8)>("missing"), None);
+
+    let mut manager: Manager<TestExt> = Manager::new();
+    let src = r#"
+panel(fill=#11223344) {
+    width = 2,
+    height = 1,
+    char = "@",
+}
+    "#;
+    if let Err(err) = manager.load_styles("test", src) {
+        let stdout = std::io::stdout();
+        format_parse_error(stdout.lock(), src.lines(), err).unwrap();
+        panic!("Styles failed to parse");
+    }
+    manager.add_node(node);
+
+    manager.layout(3, 1);
+    let mut render = AsciiRender::new(3, 1);
+    manager.render(&mut render);
+    assert_eq!(render.as_string(), "@@#");
+}
+
+#[test]
+fn test_convert_wide_integers() {
+    let node: Node<TestExt> = node!(panel(neg = -5, pos = 7, big = 1.0e12, neg_float = -0.5, frac = 2.9));
+
+    // Negative values are rejected for unsigned types
+    assert_eq!(node.get_property::<u32>("neg"), None);
+    assert_eq!(node.get_property::<usize>("neg"), None);
+    assert_eq!(node.get_property::<u32>("neg_float"), None);
+    assert_eq!(node.get_property::<i64>("neg"), Some(-5));
+
+    assert_eq!(node.get_property::<u32>("pos"), Some(7));
+    assert_eq!(node.get_property::<usize>("pos"), Some(7));
+    assert_eq!(node.get_property::<u32>("frac"), Some(2));
+
+    // Large floats are truncated, saturating where needed
+    assert_eq!(node.get_property::<i64>("big"), Some(1_000_000_000_000));
+    assert_eq!(node.get_property::<u32>("big"), Some(u32::MAX));
+
+    // Values that don't fit an integer round trip as floats
+    node.set_property("wide", 5_000_000_000i64);
+    assert_eq!(node.get_property::<Value<TestExt>>("wide"), Some(Value::Float(5.0e9)));
+    assert_eq!(node.get_property::<i64>("wide"), Some(5_000_000_000));
+    node.set_property("small", 3u32);
+    assert_eq!(node.get_property::<Value<TestExt>>("small"), Some(Value::Integer(3)));
+    node.set_property("max", u32::MAX);
+    assert_eq!(node.get_property::<u32>("max"), Some(u32::MAX));
+}
+
+#[test]
+fn test_reload_styles() {
+    let mut manager: Manager<TestExt> = Manager::new();
+    let load = |manager: &mut Manager<TestExt>, name, src: &str| {
+        if let Err(err) = manager.load_styles(name, src) {
+            let stdout = std::io::stdout();
+            format_parse_error(stdout.lock(), src.lines(), err).unwrap();
+            panic!("Styles failed to parse");
+        }
+    };
+    load(&mut manager, "base", r#"
+panel {
+    width = 2,
+    height = 1,
+}
+    "#);
+    load(&mut manager, "theme", r#"
+panel {
+    char = "@",
+}
+    "#);
+    manager.add_node(node!(panel));
+
+    let render = |manager: &mut Manager<TestExt>| {
+        manager.layout(4, 1);
+        let mut render = AsciiRender::new(4, 1);
+        manager.render(&mut render);
+        render.as_string()
+    };
+    assert_eq!(render(&mut manager), "@@##");
+
+    // Only the new rules apply after reloading
+    load(&mut manager, "theme", r#"
+panel {
+    width = 3,
+}
+    "#);
+    assert_eq!(render(&mut manager), "~~~#");
+
+    // A failed reload keeps the previous rules
+    let src = r#"
+panel {
+    width = 1,
+}
+root(a=x) panel {
+    width = 1,
+}
+    "#;
+    assert!(manager.load_styles("theme", src).is_err());
+    assert_eq!(render(&mut manager), "~~~#");
+
+    manager.remove_styles("theme");
+    assert_eq!(render(&mut manager), "~~##");
+}
+
+#[test]
+fn test_watch_property() {
+    use std::cell::Cell;
+    let node: Node<TestExt> = node!(panel);
+    let width_changes = Rc::new(Cell::new(0));
+    let height_changes = Rc::new(Cell::new(0));
+    {
+        let width_changes = width_changes.clone();
+        node.watch_property("width", move |n| {
+            assert_eq!(n.get_property::<i32>("width"), Some(width_changes.get() + 1));
+            width_changes.set(width_changes.get() + 1);
+        });
+    }
+    {
+        let height_changes = height_changes.clone();
+        node.watch_property("height", move |_| height_changes.set(height_changes.get() + 1));
+    }
+
+    node.set_property("width", 1);
+    assert_eq!((width_changes.get(), height_changes.get()), (1, 0));
+    node.set_property("width", 2);
+    node.set_property("other", 5);
+    assert_eq!((width_changes.get(), height_changes.get()), (2, 0));
+    node.set_property("height", 5);
+    assert_eq!((width_changes.get(), height_changes.get()), (2, 1));
+    node.remove_property("height");
+    node.remove_property("height");
+    assert_eq!((width_changes.get(), height_changes.get()), (2, 2));
+}
+
+#[test]
+fn test_list() {
+    let mut manager: Manager<TestExt> = Manager::new();
+    manager.add_func_raw("total", |args| -> Result<_, _> {
+        let vals: Vec<i32> = args.next()
+            .ok_or(Error::MissingParameter {
+                position: 0,
+                name: "values"
+            })
+            .and_then(|v| v)?
+            .convert()
+            .ok_or(Error::CustomStatic {
+                reason: "Expected a list of integers"
+            })?;
+        Ok(Value::Integer(vals.iter().sum()))
+    });
+    let src = r#"
+panel {
+    width = total(list(1, 2, 3)),
+    height = 1,
+}
+    "#;
+    if let Err(err) = manager.load_styles("test", src) {
+        let stdout = std::io::stdout();
+        format_parse_error(stdout.lock(), src.lines(), err).unwrap();
+        panic!("Styles failed to parse");
+    }
+    manager.add_node(node!(panel));
+    manager.layout(7, 1);
+    let mut render = AsciiRender::new(7, 1);
+    manager.render(&mut render);
+    assert_eq!(render.as_string(), "~~~~~~#");
+
+    let node: Node<TestExt> = node!(panel);
+    let mut args = vec![Ok(Value::Integer(1)), Ok(Value::Integer(2)), Ok(Value::Integer(3))].into_iter();
+    node.set_property("items", funcs::list::<TestExt>(&mut args).unwrap());
+    assert_eq!(node.get_property::<Vec<i32>>("items"), Some(vec![1, 2, 3]));
+    assert_eq!(node.get_property::<Vec<bool>>("items"), None);
+    assert_eq!(node.get_property_ref::<Vec<i32>>("items").map(|v| v.len()), Some(3));
+
+    node.set_property("names", vec!["a".to_owned(), "b".to_owned()]);
+    assert_eq!(node.get_property::<Vec<String>>("names"), Some(vec!["a".to_owned(), "b".to_owned()]));
+}
+
+#[test]
+fn test_load_styles_document() {
+    let mut manager: Manager<TestExt> = Manager::new();
+    let src = r#"
+panel {
+    width = 2,
+    height = 1,
+}
+panel > item {
+    width = 1,
+}
+    "#;
+    let mut doc = match Manager::<TestExt>::parse_styles(src) {
+        Ok(v) => v,
+        Err(err) => {
+            let stdout = std::io::stdout();
+            format_parse_error(stdout.lock(), src.lines(), err).unwrap();
+            panic!("Styles failed to parse");
+        }
+    };
+    assert_eq!(doc.rules.len(), 2);
+    // Drop the nested rule before loading
+    doc.rules.retain(|r| r.matchers.len() == 1);
+    manager.load_styles_document("test", doc).unwrap();
+
+    manager.add_node(node!(panel));
+    manager.layout(3, 1);
+    let mut render = AsciiRender::new(3, 1);
+    manager.render(&mut render);
+    assert_eq!(render.as_string(), "~~#");
+
+    // Hand built matchers must use a value that suits the operator
+    use syntax::style::MatchOp;
+    let mut doc = Manager::<TestExt>::parse_styles("panel(a=1) { width = 1, }").unwrap();
+    doc.rules[0].matchers[0].1[0].op = MatchOp::Absent;
+    assert!(manager.load_styles_document("bad", doc).is_err());
+    let mut doc = Manager::<TestExt>::parse_styles("panel(!a) { width = 1, }").unwrap();
+    doc.rules[0].matchers[0].1[0].op = MatchOp::Equal;
+    assert!(manager.load_styles_document("bad", doc).is_err());
+}
+
+#[test]
+fn test_layout_within() {
+    use std::time::Duration;
+    let mut manager: Manager<TestExt> = Manager::new();
+    let src = r#"
+panel(x=x) {
+    x = x,
+    width = 1,
+    height = 1,
+    char = "@",
+}
+    "#;
+    if let Err(err) = manager.load_styles("test", src) {
+        let stdout = std::io::stdout();
+        format_parse_error(stdout.lock(), src.lines(), err).unwrap();
+        panic!("Styles failed to parse");
+    }
+    for x in 0 .. 4 {
+        manager.add_node(node!(panel(x=x)));
+    }
+
+    assert_eq!(manager.layout_within(4, 1, Duration::from_secs(0)), LayoutStatus::Incomplete);
+    let mut calls = 1;
+    while manager.layout_within(4, 1, Duration::from_secs(0)) == LayoutStatus::Incomplete {
+        calls += 1;
+        assert!(calls < 100, "Layout never completed");
+    }
+    assert!(calls > 1);
+
+    let mut render = AsciiRender::new(4, 1);
+    manager.render(&mut render);
+    assert_eq!(render.as_string(), "@@@@");
+
+    // A large budget finishes in one call
+    manager.add_node(node!(panel(x=5)));
+    assert_eq!(manager.layout_within(4, 1, Duration::from_secs(60)), LayoutStatus::Complete);
+}
+
+#[test]
+fn test_layout_within_nested() {
+    use std::time::Duration;
+    let mut manager: Manager<TestExt> = Manager::new();
+    let src = r#"
+wrapper {
+    width = 8,
+    height = 1,
+}
+panel(x=x) {
+    x = x,
+    width = 1,
+    height = 1,
+    char = "@",
+}
+    "#;
+    if let Err(err) = manager.load_styles("test", src) {
+        let stdout = std::io::stdout();
+        format_parse_error(stdout.lock(), src.lines(), err).unwrap();
+        panic!("Styles failed to parse");
+    }
+    let wrapper = node!(wrapper);
+    for x in 0 .. 8 {
+        wrapper.add_child(node!(panel(x=x)));
+    }
+    manager.add_node(wrapper);
+
+    // A single large subtree is still split between calls
+    let mut calls = 1;
+    while manager.layout_within(8, 1, Duration::from_secs(0)) == LayoutStatus::Incomplete {
+        calls += 1;
+        assert!(calls < 200, "Layout never completed");
+    }
+    assert!(calls > 8);
+
+    let mut render = AsciiRender::new(8, 1);
+    manager.render(&mut render);
+    assert_eq!(render.as_string(), "@@@@@@@@");
+}
+
+#[test]
+fn test_layout_within_tree_changed() {
+    use std::time::Duration;
+    let src = r#"
+wrapper {
+    x = 4,
+    width = 4,
+    height = 1,
+}
+panel(x=x) {
+    x = x,
+    width = 1,
+    height = 1,
+    char = "@",
+}
+    "#;
+    // Nodes are removed at every point a layout can be paused
+    for steps in 0 .. 40 {
+        let mut manager: Manager<TestExt> = Manager::new();
+        if let Err(err) = manager.load_styles("test", src) {
+            let stdout = std::io::stdout();
+            format_parse_error(stdout.lock(), src.lines(), err).unwrap();
+            panic!("Styles failed to parse");
+        }
+        let panels: Vec<Node<TestExt>> = (0 .. 4)
+            .map(|x| node!(panel(x=x)))
+            .collect();
+        for p in &panels {
+            manager.add_node(p.clone());
+        }
+        let wrapper = node!(wrapper);
+        let inner: Vec<Node<TestExt>> = (0 .. 4)
+            .map(|x| node!(panel(x=x)))
+            .collect();
+        for p in &inner {
+            wrapper.add_child(p.clone());
+        }
+        manager.add_node(wrapper.clone());
+
+        for _ in 0 .. steps {
+            if manager.layout_within(8, 1, Duration::from_secs(0)) == LayoutStatus::Complete {
+                break;
+            }
+        }
+        manager.remove_node(panels[3].clone());
+        wrapper.remove_child(inner[3].clone());
+        manager.layout(8, 1);
+
+        let mut render = AsciiRender::new(8, 1);
+        manager.render(&mut render);
+        assert_eq!(render.as_string(), "@@@#@@@~", "Removed after {} steps", steps);
+    }
+}
+
+#[test]
+fn test_check_invariants() {
+    let mut manager: Manager<TestExt> = Manager::new();
+    let child = node!(item);
+    let a = node!(a);
+    let b = node!(b);
+    a.add_child(child.clone());
+    manager.add_node(a.clone());
+    manager.add_node(b.clone());
+    manager.check_invariants().unwrap();
+
+    // Listed under two parents
+    if let NodeValue::Element(ref mut e) = b.inner.borrow_mut().value {
+        e.children.push(child.clone());
+    }
+    match manager.check_invariants() {
+        Err(ref v) if v.len() == 1 => match v[0] {
+            InvariantViolation::MultipleParents { ref node } => assert!(node.is_same(&child)),
+            ref v => panic!("Unexpected violation: {:?}", v),
+        },
+        v => panic!("Unexpected result: {:?}", v),
+    }
+    if let NodeValue::Element(ref mut e) = b.inner.borrow_mut().value {
+        e.children.clear();
+    }
+
+    // Dangling parent link
+    child.inner.borrow_mut().parent = None;
+    match manager.check_invariants() {
+        Err(ref v) if v.len() == 1 => match v[0] {
+            InvariantViolation::InvalidParent { ref node, ref parent } => {
+                assert!(node.is_same(&child));
+                assert!(parent.is_same(&a));
+            },
+            ref v => panic!("Unexpected violation: {:?}", v),
+        },
+        v => panic!("Unexpected result: {:?}", v),
+    }
+    child.inner.borrow_mut().parent = Some(Rc::downgrade(&a.inner));
+
+    // Cycle
+    if let NodeValue::Element(ref mut e) = child.inner.borrow_mut().value {
+        e.children.push(a.clone());
+    }
+    match manager.check_invariants() {
+        Err(ref v) if v.len() == 1 => match v[0] {
+            InvariantViolation::Cycle { ref node } => assert!(node.is_same(&a)),
+            ref v => panic!("Unexpected violation: {:?}", v),
+        },
+        v => panic!("Unexpected result: {:?}", v),
+    }
+    // Break the cycle so the nodes can be freed
+    if let NodeValue::Element(ref mut e) = child.inner.borrow_mut().value {
+        e.children.clear();
+    }
+    manager.check_invariants().unwrap();
+}
+
+#[test]
+#[cfg(debug_assertions)]
+#[should_panic(expected = "Broken node tree")]
+fn test_layout_checks_invariants() {
+    let mut manager: Manager<TestExt> = Manager::new();
+    let child = node!(item);
+    let a = node!(a);
+    let b = node!(b);
+    a.add_child(child.clone());
+    manager.add_node(a);
+    manager.add_node(b.clone());
+    if let NodeValue::Element(ref mut e) = b.inner.borrow_mut().value {
+        e.children.push(child);
+    }
+    manager.layout(10, 10);
+}
+
+#[test]
+fn test_property_operators() {
+    let mut manager: Manager<TestExt> = Manager::new();
+    let src = r#"
+item {
+    width = 1,
+    height = 1,
+    char = ".",
+}
+item(enabled!=true) {
+    char = "n",
+}
+item(value>50) {
+    char = "g",
+}
+item(value<=10) {
+    char = "l",
+}
+item(!value, enabled=true) {
+    char = "a",
+}
+    "#;
+    if let Err(err) = manager.load_styles("test", src) {
+        let stdout = std::io::stdout();
+        format_parse_error(stdout.lock(), src.lines(), err).unwrap();
+        panic!("Styles failed to parse");
+    }
+    let chars = ["n", "n", "g", "l", "l", "a", "."];
+    let nodes: Vec<Node<TestExt>> = vec![
+        node!(item),
+        node!(item(enabled=false, value=20)),
+        node!(item(enabled=true, value=50.5)),
+        node!(item(enabled=true, value=10)),
+        node!(item(enabled=true, value=-3.0)),
+        node!(item(enabled=true)),
+        node!(item(enabled=true, value=30)),
+    ];
+    for (x, node) in nodes.into_iter().enumerate() {
+        node.set_property("x", x as i32);
+        manager.add_node(node);
+    }
+    manager.load_styles("pos", "item(x=x) { x = x, }").unwrap();
+
+    manager.layout(7, 1);
+    let mut render = AsciiRender::new(7, 1);
+    manager.render(&mut render);
+    assert_eq!(render.as_string(), chars.concat());
+
+    // Only numbers can be ordered and variables need `=`
+    assert!(manager.load_styles("bad", "item(value>\"a\") { x = 1, }").is_err());
+    assert!(manager.load_styles("bad", "item(value!=v) { x = 1, }").is_err());
+}
+
+#[test]
+fn test_ext_binary_op() {
+    let mut manager: Manager<TestExt> = Manager::new();
+    manager.add_func_raw("ext", |args| -> Result<_, _> {
+        match args.next() {
+            Some(Ok(Value::Integer(i))) => Ok(Value::ExtValue(i)),
+            _ => Err(Error::CustomStatic { reason: "Expected integer" }),
+        }
+    });
+    manager.add_func_raw("unwrap", |args| -> Result<_, _> {
+        match args.next() {
+            Some(Ok(Value::ExtValue(i))) => Ok(Value::Integer(i)),
+            Some(Err(err)) => Err(err),
+            _ => Err(Error::CustomStatic { reason: "Expected extension value" }),
+        }
+    });
+    let src = r#"
+a {
+    width = unwrap(ext(1) + ext(2)),
+    height = 1,
+}
+b {
+    y = 1,
+    width = unwrap(ext(2) * 2),
+    height = 1,
+}
+c {
+    width = unwrap(ext(1) - ext(2)),
+}
+    "#;
+    if let Err(err) = manager.load_styles("test", src) {
+        let stdout = std::io::stdout();
+        format_parse_error(stdout.lock(), src.lines(), err).unwrap();
+        panic!("Styles failed to parse");
+    }
+    manager.add_node(node!(a));
+    manager.add_node(node!(b));
+    manager.add_node(node!(c));
+
+    manager.layout(5, 2);
+    let mut render = AsciiRender::new(5, 2);
+    manager.render(&mut render);
+    assert_eq!(render.as_string(), "~~~##\n~~~~#");
+
+    // Operators the extension doesn't handle still fail
+    let errors = manager.take_errors();
+    assert_eq!(errors.len(), 1);
+    match errors[0] {
+        LayoutError::Style(StyleError { error: Error::IncompatibleTypesOp { op: "-", .. }, .. }) => {},
+        ref err => panic!("Unexpected error: {:?}", err),
+    }
+}
+
+#[test]
+fn test_rule_specificity() {
+    let mut manager: Manager<TestExt> = Manager::new();
+    let src = r#"
+panel > button(focused=true) {
+    char = "@",
+}
+button {
+    width = 1,
+    height = 1,
+    char = "+",
+}
+button(focused=true) {
+    char = "!",
+}
+other {
+    x = 1,
+}
+third {
+    x = 2,
+}
+    "#;
+    if let Err(err) = manager.load_styles("test", src) {
+        let stdout = std::io::stdout();
+        format_parse_error(stdout.lock(), src.lines(), err).unwrap();
+        panic!("Styles failed to parse");
+    }
+    manager.add_node(node!(panel {
+        button(focused=true)
+    }));
+    manager.add_node(node!(other {
+        button(focused=true)
+    }));
+    manager.add_node(node!(third {
+        button
+    }));
+
+    manager.layout(3, 1);
+    let mut render = AsciiRender::new(3, 1);
+    manager.render(&mut render);
+    // The deeper selector wins despite being declared first
+    assert_eq!(render.as_string(), "@!+");
+}
+
+#[test]
+fn test_important() {
+    let mut manager: Manager<TestExt> = Manager::new();
+    let src = r#"
+button {
+    width = 1 !important,
+    height = 1,
+    char = "+",
+}
+root > panel > button(focused=true) {
+    width = 3,
+    char = "@",
+}
+    "#;
+    if let Err(err) = manager.load_styles("test", src) {
+        let stdout = std::io::stdout();
+        format_parse_error(stdout.lock(), src.lines(), err).unwrap();
+        panic!("Styles failed to parse");
+    }
+    manager.add_node(node!(root {
+        panel {
+            button(focused=true)
+        }
+    }));
+
+    manager.layout(3, 1);
+    let mut render = AsciiRender::new(3, 1);
+    manager.render(&mut render);
+    // The important width wins, the other styles still apply
+    assert_eq!(render.as_string(), "@##");
+}
+
+#[test]
+fn test_set_expr_property() {
+    let mut manager: Manager<TestExt> = Manager::new();
+    let src = r#"
+panel {
+    width = 1,
+    height = 1,
+    char = "@",
+}
+    "#;
+    if let Err(err) = manager.load_styles("test", src) {
+        let stdout = std::io::stdout();
+        format_parse_error(stdout.lock(), src.lines(), err).unwrap();
+        panic!("Styles failed to parse");
+    }
+    let node = node!(panel(scale=2));
+    manager.add_node(node.clone());
+
+    assert!(node.set_expr_property("width", "parent_width /").is_err());
+    // Compiled when set, not only parsed
+    assert!(node.set_expr_property("width", "var(scale)").is_err());
+    node.set_expr_property("width", "parent_width / scale").unwrap();
+
+    manager.layout(6, 1);
+    let mut render = AsciiRender::new(6, 1);
+    manager.render(&mut render);
+    assert_eq!(render.as_string(), "@@@###");
+
+    // Re-evaluated when the parent is resized
+    manager.layout(4, 1);
+    let mut render = AsciiRender::new(4, 1);
+    manager.render(&mut render);
+    assert_eq!(render.as_string(), "@@##");
+
+    node.remove_expr_property("width");
+    manager.layout(4, 1);
+    let mut render = AsciiRender::new(4, 1);
+    manager.render(&mut render);
+    assert_eq!(render.as_string(), "@###");
+    assert!(manager.take_errors().is_empty());
+}
+
+#[test]
+fn test_reload_styles_invalid() {
+    let mut manager: Manager<TestExt> = Manager::new();
+    let src = r#"
+panel {
+    width = 2,
+    height = 1,
+    char = "@",
+}
+    "#;
+    if let Err(err) = manager.load_styles("test", src) {
+        let stdout = std::io::stdout();
+        format_parse_error(stdout.lock(), src.lines(), err).unwrap();
+        panic!("Styles failed to parse");
+    }
+    manager.add_node(node!(panel));
+
+    let render = |manager: &mut Manager<TestExt>| {
+        manager.layout(4, 1);
+        let mut render = AsciiRender::new(4, 1);
+        manager.render(&mut render);
+        render.as_string()
+    };
+    assert_eq!(render(&mut manager), "@@##");
+
+    // Fails to parse so the old rules keep matching
+    assert!(manager.reload_styles("test", "panel { width = 3,").is_err());
+    assert_eq!(render(&mut manager), "@@##");
+
+    manager.reload_styles("test", r#"
+panel {
+    width = 3,
+    height = 1,
+    char = "@",
+}
+    "#).unwrap();
+    assert_eq!(render(&mut manager), "@@@#");
+}
+
+#[test]
+fn test_loaded_style_names() {
+    let mut manager: Manager<TestExt> = Manager::new();
+    manager.load_styles("base", r#"
+panel {
+    width = 2,
+}
+button {
+    height = 1 !important,
+}
+    "#).unwrap();
+    manager.load_styles("theme", r#"
+panel {
+    char = "@",
+}
+    "#).unwrap();
+    assert_eq!(manager.loaded_style_names(), vec!["base".to_owned(), "theme".to_owned()]);
+    assert_eq!(manager.rule_count("base"), 2);
+    assert_eq!(manager.rule_count("theme"), 1);
+
+    manager.remove_styles("base");
+    assert_eq!(manager.loaded_style_names(), vec!["theme".to_owned()]);
+    assert_eq!(manager.rule_count("base"), 0);
+    assert_eq!(manager.rule_count("theme"), 1);
+}
+
+#[test]
+fn test_remove_node() {
+    let mut manager: Manager<TestExt> = Manager::new();
+    let node = node!(panel);
+    manager.add_node(node.clone());
+
+    assert!(manager.remove_node(node.clone()));
+    assert!(!manager.remove_node(node.clone()));
+    match manager.remove_node_checked(node) {
+        Err(Error::NodeNotFound) => {},
+        v => panic!("Expected NodeNotFound, got {:?}", v),
+    }
+}
+
+#[test]
+fn test_layout_node() {
+    let mut manager: Manager<TestExt> = Manager::new();
+    let src = r#"
+panel {
+    width = 8,
+    height = 8,
+}
+item(pos=p) {
+    x = p,
+    width = 1,
+    height = 1,
+    char = "@",
+}
+    "#;
+    if let Err(err) = manager.load_styles("test", src) {
+        let stdout = std::io::stdout();
+        format_parse_error(stdout.lock(), src.lines(), err).unwrap();
+        panic!("Styles failed to parse");
+    }
+
+    // A deep chain of panels each with a sibling item
+    let mut nodes = Vec::new();
+    let mut parent = node!(panel);
+    manager.add_node(parent.clone());
+    nodes.push(parent.clone());
+    for _ in 0 .. 32 {
+        let item = node!(item(pos=0));
+        let next = node!(panel);
+        parent.add_child(item.clone());
+        parent.add_child(next.clone());
+        nodes.push(item);
+        nodes.push(next.clone());
+        parent = next;
+    }
+    let target = node!(item(pos=0));
+    parent.add_child(target.clone());
+    manager.layout(8, 8);
+
+    // Marks the nodes so a layout pass over them can be detected
+    let marker = Rect { x: -1, y: -1, width: -1, height: -1 };
+    for n in &nodes {
+        n.inner.borrow_mut().prev_rect = marker;
+    }
+
+    target.set_property("pos", 3);
+    manager.layout_node(&target);
+    assert_eq!(target.raw_position().x, 3);
+    assert_eq!(target.render_position().map(|v| v.x), Some(3));
+    for n in &nodes {
+        assert_eq!(n.inner.borrow().prev_rect, marker);
+    }
+}
+
+#[test]
+fn test_reverse_layout() {
+    let mut manager: Manager<TestExt> = Manager::new();
+    let src = r#"
+panel {
+    layout = "row",
+    reverse = true,
+    spacing = 1,
+    width = 6,
+    height = 1,
+    char = ".",
+}
+a {
+    width = 1,
+    char = "a",
+}
+b {
+    width = 2,
+    char = "b",
+}
+c {
+    width = 1,
+    char = "c",
+}
+    "#;
+    if let Err(err) = manager.load_styles("test", src) {
+        let stdout = std::io::stdout();
+        format_parse_error(stdout.lock(), src.lines(), err).unwrap();
+        panic!("Styles failed to parse");
+    }
+    let panel = node!(panel {
+        a
+        b
+        c
+    });
+    manager.add_node(panel.clone());
+
+    manager.layout(7, 1);
+    let mut render = AsciiRender::new(7, 1);
+    manager.render(&mut render);
+    assert_eq!(render.as_string(), "c.bb.a#");
+
+    // Document order is unchanged
+    let names: Vec<_> = panel.children().iter()
+        .filter_map(|v| v.name())
+        .collect();
+    assert_eq!(names, vec!["a", "b", "c"]);
+}
+
+#[test]
+fn test_layout_changed() {
+    let mut manager: Manager<TestExt> = Manager::new();
+    let src = r#"
+panel(pos=p) {
+    x = p,
+    width = 1,
+    height = 1,
+}
+    "#;
+    if let Err(err) = manager.load_styles("test", src) {
+        let stdout = std::io::stdout();
+        format_parse_error(stdout.lock(), src.lines(), err).unwrap();
+        panic!("Styles failed to parse");
+    }
+    let node = node!(panel(pos=0));
+    manager.add_node(node.clone());
+
+    assert!(manager.layout(4, 4));
+    // Nothing changed so there is nothing to redraw
+    assert!(!manager.layout(4, 4));
+
+    node.set_property("pos", 2);
+    assert!(manager.layout(4, 4));
+    assert!(!manager.layout(4, 4));
+
+    assert!(manager.layout(5, 4));
+
+    // Removing a node changes what is drawn
+    let inner = node!(panel(pos=1));
+    node.add_child(inner.clone());
+    assert!(manager.layout(5, 4));
+    assert!(!manager.layout(5, 4));
+    assert!(node.remove_child(inner));
+    assert!(manager.layout(5, 4));
+    assert!(!manager.layout(5, 4));
+    assert!(manager.remove_node(node));
+    assert!(manager.layout(5, 4));
+    assert!(!manager.layout(5, 4));
+}
+
+#[test]
+fn test_value_hash() {
+    use std::collections::HashSet;
+    let mut set: HashSet<Value<TestExt>> = HashSet::new();
+    assert!(set.insert(Value::Integer(5)));
+    assert!(set.insert(Value::Float(5.0)));
+    assert!(set.insert(Value::String("hello".into())));
+    assert!(set.insert(Value::Color(255, 0, 0, 255)));
+    assert!(set.insert(Value::List(vec![Value::Integer(1), Value::Boolean(true)])));
+    assert!(set.insert(Value::ExtValue(3)));
+    assert!(set.insert(Value::Float(0.0)));
+
+    // Duplicates are ignored
+    assert!(!set.insert(Value::Integer(5)));
+    assert!(!set.insert(Value::String("hello".into())));
+    assert!(!set.insert(Value::List(vec![Value::Integer(1), Value::Boolean(true)])));
+    assert!(!set.insert(Value::Float(-0.0)));
+    assert_eq!(set.len(), 7);
+
+    assert!(set.contains(&Value::Color(255, 0, 0, 255)));
+    assert!(set.contains(&Value::ExtValue(3)));
+    assert!(!set.contains(&Value::Integer(6)));
+    assert!(!set.contains(&Value::Boolean(true)));
+}
+
+#[test]
+fn test_render_visible() {
+    struct Visited(Vec<String>);
+    impl RenderVisitor<TestExt> for Visited {
+        fn visit(&mut self, node: &mut NodeInner<TestExt>) {
+            if let NodeValue::Element(ref e) = node.value {
+                self.0.push(e.name.clone());
+            }
+        }
+        fn visit_end(&mut self, _node: &mut NodeInner<TestExt>) {}
+    }
+
+    let mut manager: Manager<TestExt> = Manager::new();
+    let src = r#"
+panel {
+    clip_overflow = true,
+    width = 4,
+    height = 4,
+}
+hidden {
+    x = 5,
+    width = 2,
+    height = 2,
+}
+partial {
+    x = 2,
+    y = 2,
+    width = 4,
+    height = 4,
+}
+offscreen {
+    x = 10,
+    width = 2,
+    height = 2,
+}
+wrapper {
+    y = 6,
+}
+item {
+    x = 1,
+    width = 2,
+    height = 1,
+}
+    "#;
+    if let Err(err) = manager.load_styles("test", src) {
+        let stdout = std::io::stdout();
+        format_parse_error(stdout.lock(), src.lines(), err).unwrap();
+        panic!("Styles failed to parse");
+    }
+    manager.add_node(node!(panel {
+        hidden {
+            inner
+        }
+        partial
+    }));
+    manager.add_node(node!(offscreen));
+    // Unsized but doesn't clip its child
+    manager.add_node(node!(wrapper {
+        item
+    }));
+    manager.layout(8, 8);
+
+    let mut visited = Visited(Vec::new());
+    manager.render(&mut visited);
+    assert_eq!(visited.0, vec!["root", "panel", "hidden", "inner", "partial", "offscreen", "wrapper", "item"]);
+
+    let mut visited = Visited(Vec::new());
+    manager.render_visible(&mut visited);
+    assert_eq!(visited.0, vec!["root", "panel", "partial", "wrapper", "item"]);
+
+    let mut all = AsciiRender::new(8, 8);
+    manager.render(&mut all);
+    let mut visible = AsciiRender::new(8, 8);
+    manager.render_visible(&mut visible);
+    assert_eq!(visible.as_string(), all.as_string());
+}
+
+#[test]
+fn test_nearest_ancestor() {
+    let mut manager: Manager<TestExt> = Manager::new();
+    let target = node!(button);
+    let list = node!(list(can_scroll=true) {
+        item {
+            label
+        }
+    });
+    let label = list.children()[0].children()[0].clone();
+    label.add_child(target.clone());
+    manager.add_node(node!(window(can_scroll=true) {
+        panel
+    }));
+    manager.query()
+        .name("panel")
+        .matches()
+        .next()
+        .unwrap()
+        .add_child(list.clone());
+
+    let can_scroll = |v: &Node<TestExt>| v.get_property::<bool>("can_scroll").unwrap_or(false);
+    let found = target.nearest_ancestor(can_scroll).unwrap();
+    assert_eq!(found.name().as_deref(), Some("list"));
+    // The node itself isn't checked
+    let found = list.nearest_ancestor(can_scroll).unwrap();
+    assert_eq!(found.name().as_deref(), Some("window"));
+    assert!(target.nearest_ancestor(|v| v.name().as_deref() == Some("missing")).is_none());
+}
+
+#[test]
+fn test_dirty_rects() {
+    let mut manager: Manager<TestExt> = Manager::new();
+    let src = r#"
+panel(pos=p) {
+    x = p,
+    width = 2,
+    height = 1,
+}
+    "#;
+    if let Err(err) = manager.load_styles("test", src) {
+        let stdout = std::io::stdout();
+        format_parse_error(stdout.lock(), src.lines(), err).unwrap();
+        panic!("Styles failed to parse");
+    }
+    let moving = node!(panel(pos=0));
+    let child = node!(panel(pos=1));
+    let other = node!(panel(pos=4));
+    other.add_child(child.clone());
+    manager.add_node(moving.clone());
+    manager.add_node(other.clone());
+    manager.layout(8, 2);
+    manager.take_dirty_rects();
+
+    // A stationary tree has nothing to redraw
+    manager.layout(8, 2);
+    assert!(manager.take_dirty_rects().is_empty());
+
+    moving.set_property("pos", 1);
+    manager.layout(8, 2);
+    assert_eq!(manager.take_dirty_rects(), vec![
+        Rect { x: 0, y: 0, width: 2, height: 1 },
+        Rect { x: 1, y: 0, width: 2, height: 1 },
+    ]);
+    assert!(manager.take_dirty_rects().is_empty());
+
+    // Removed nodes report the areas they and their children covered
+    manager.remove_node(other.clone());
+    assert_eq!(manager.take_dirty_rects(), vec![
+        Rect { x: 4, y: 0, width: 2, height: 1 },
+        Rect { x: 5, y: 0, width: 2, height: 1 },
+    ]);
+    // and their whole area again once added back
+    manager.add_node(other.clone());
+    manager.layout(8, 2);
+    assert_eq!(manager.take_dirty_rects(), vec![
+        Rect { x: 4, y: 0, width: 2, height: 1 },
+        Rect { x: 5, y: 0, width: 2, height: 1 },
+    ]);
+    other.remove_child(child.clone());
+    manager.layout(8, 2);
+    assert_eq!(manager.take_dirty_rects(), vec![
+        Rect { x: 5, y: 0, width: 2, height: 1 },
+    ]);
+
+    // Too many areas are replaced by the whole viewport
+    for i in 0 .. 200 {
+        moving.set_property("pos", i % 2);
+        manager.layout(8, 2);
+    }
+    assert_eq!(manager.take_dirty_rects(), vec![
+        Rect { x: 0, y: 0, width: 8, height: 2 },
+    ]);
+}
+
+#[test]
+fn test_set_properties() {
+    use std::cell::Cell;
+    let mut manager: Manager<TestExt> = Manager::new();
+    let updates = Rc::new(Cell::new(0));
+    let counter = updates.clone();
+    manager.add_func_raw("count", move |args| -> Result<_, _> {
+        counter.set(counter.get() + 1);
+        let mut total = 0;
+        for v in args {
+            total += v?.convert::<i32>().unwrap_or(0);
+        }
+        Ok(Value::Integer(total))
+    });
+    let src = r#"
+panel(a=a, b=b) {
+    x = count(a, b),
+    width = 1,
+    height = 1,
+}
+    "#;
+    if let Err(err) = manager.load_styles("test", src) {
+        let stdout = std::io::stdout();
+        format_parse_error(stdout.lock(), src.lines(), err).unwrap();
+        panic!("Styles failed to parse");
+    }
+    let node = node!(panel(a=0, b=0));
+    manager.add_node(node.clone());
+    manager.layout(8, 1);
+    assert_eq!(updates.get(), 1);
+
+    node.set_properties(vec![
+        ("a".to_owned(), Value::Integer(2)),
+        ("b".to_owned(), Value::Integer(3)),
+        ("c".to_owned(), Value::Boolean(true)),
+    ]);
+    assert!(manager.layout(8, 1));
+    assert_eq!(updates.get(), 2);
+    assert_eq!(node.raw_position().x, 5);
+    assert_eq!(node.get_property::<bool>("c"), Some(true));
+
+    // Setting the same values doesn't flag the node
+    node.set_properties(vec![
+        ("a".to_owned(), Value::Integer(2)),
+        ("b".to_owned(), Value::Integer(3)),
+    ]);
+    assert!(!manager.layout(8, 1));
+    assert_eq!(updates.get(), 2);
+}
+
+#[test]
+fn test_rule_usage() {
+    let mut manager: Manager<TestExt> = Manager::new();
+    let src = r#"
+a(c=c, d=d) {
+    x = c + d,
+}
+panel(flag=true) > a {
+    width = parent_width !important,
+    height = 1,
+}
+    "#;
+    if let Err(err) = manager.load_styles("test", src) {
+        let stdout = std::io::stdout();
+        format_parse_error(stdout.lock(), src.lines(), err).unwrap();
+        panic!("Styles failed to parse");
+    }
+    assert_eq!(manager.rule_usage(), vec![
+        RuleUsage {
+            name: "test".into(),
+            sets: vec!["x"],
+            reads: vec!["c".into(), "d".into()],
+        },
+        RuleUsage {
+            name: "test".into(),
+            sets: vec!["height", "width"],
+            reads: vec!["flag".into(), "parent_width".into()],
+        },
+    ]);
+}
+
+#[test]
+fn test_set_same_property() {
+    let mut manager: Manager<TestExt> = Manager::new();
+    let src = r#"
+panel(pos=p) {
+    x = p,
+    width = 1,
+    height = 1,
+}
+    "#;
+    if let Err(err) = manager.load_styles("test", src) {
+        let stdout = std::io::stdout();
+        format_parse_error(stdout.lock(), src.lines(), err).unwrap();
+        panic!("Styles failed to parse");
+    }
+    let node = node!(panel(pos=1));
+    manager.add_node(node.clone());
+    manager.layout(4, 1);
+    let updates = || UPDATES.with(|v| v.get());
+
+    let before = updates();
+    node.set_property("pos", 1);
+    manager.layout(4, 1);
+    assert_eq!(updates(), before);
+
+    node.set_property("pos", 2);
+    manager.layout(4, 1);
+    assert_eq!(updates(), before + 1);
+    assert_eq!(node.raw_position().x, 2);
+}
+
+#[test]
+fn test_clone_deep() {
+    let original: Node<TestExt> = node!(menu(open=true) {
+        entry(id=1) {
+            @text("First")
+        }
+        entry(id=2)
+    });
+    let copy = original.clone_deep();
+    assert!(copy.parent().is_none());
+    assert_eq!(copy.name().as_deref(), Some("menu"));
+    assert_eq!(copy.get_property::<bool>("open"), Some(true));
+
+    let entries = copy.children();
+    assert_eq!(entries.len(), 2);
+    assert_eq!(entries[0].get_property::<i32>("id"), Some(1));
+    assert_eq!(entries[0].children()[0].text().as_deref(), Some("First"));
+    assert!(Rc::ptr_eq(&entries[0].parent().unwrap().inner, &copy.inner));
+
+    copy.set_property("open", false);
+    entries[1].set_property("id", 5);
+    assert_eq!(original.get_property::<bool>("open"), Some(true));
+    assert_eq!(original.children()[1].get_property::<i32>("id"), Some(2));
+
+    // The copy can be added next to the original
+    let mut manager: Manager<TestExt> = Manager::new();
+    manager.add_node(original);
+    manager.add_node(copy);
+    assert!(manager.check_invariants().is_ok());
+}
+
+#[test]
+fn test_style_type_warnings() {
+    let mut manager: Manager<TestExt> = Manager::new();
+    let src = r#"
+panel {
+    x = 5,
+    width = int(4.5 + float(2)),
+    height = 1 + 10 / 2.0,
+}
+    "#;
+    if let Err(err) = manager.load_styles("test", src) {
+        let stdout = std::io::stdout();
+        format_parse_error(stdout.lock(), src.lines(), err).unwrap();
+        panic!("Styles failed to parse");
+    }
+    // Still loaded as only a warning
+    assert_eq!(manager.rule_count("test"), 1);
+
+    let warnings = manager.take_style_warnings();
+    assert_eq!(warnings.len(), 1);
+    assert_eq!(warnings[0].name, "test");
+    assert_eq!(warnings[0].position.line_number, 5);
+
+    let mut out = Vec::new();
+    format_error(&mut out, src.lines(), warnings[0].position, 1, &warnings[0].message, "here").unwrap();
+    let out = String::from_utf8(out).unwrap();
+    assert!(out.contains("Can't use `/` on integer and float"));
+
+    assert!(manager.take_style_warnings().is_empty());
+}
+
+#[test]
+fn test_clone_shallow() {
+    let original: Node<TestExt> = node!(list(size=3, open=true) {
+        item
+        item
+    });
+    let copy = original.clone_shallow();
+    assert!(copy.parent().is_none());
+    assert_eq!(copy.name().as_deref(), Some("list"));
+    assert_eq!(copy.get_property::<i32>("size"), Some(3));
+    assert_eq!(copy.get_property::<bool>("open"), Some(true));
+    assert!(copy.children().is_empty());
+    assert_eq!(original.children().len(), 2);
+
+    let text: Node<TestExt> = node!(@text("hello"));
+    assert_eq!(text.clone_shallow().text().as_deref(), Some("hello"));
+}
+
+#[test]
+fn test_to_desc_string() {
+    let src = r#"
+root {
+    panel(x=5, y=-3, scale=1.5, whole=2.0, color=#FF8000CC, enabled=false) {
+        "Hello \"world\"\n\tindented\\"(size=12)
+        label(text="a \"quoted\" \\ string")
+        "  trimmed  "(trim)
+    }
+    empty
+}
+"#;
+    let node = Node::<TestExt>::from_str(src).unwrap();
+    let out = node.to_desc_string();
+    let parsed = match Node::<TestExt>::from_str(&out) {
+        Ok(v) => v,
+        Err(err) => {
+            let stdout = std::io::stdout();
+            format_parse_error(stdout.lock(), out.lines(), err).unwrap();
+            panic!("Serialized node failed to parse");
+        }
+    };
+    assert_eq!(parsed.export_layout(), node.export_layout());
+    assert_eq!(parsed.to_desc_string(), out);
+
+    let panel = parsed.children()[0].clone();
+    assert_eq!(panel.get_property::<f64>("whole"), Some(2.0));
+    assert_eq!(panel.get_property::<i32>("y"), Some(-3));
+    assert_eq!(
+        panel.children()[0].text().as_deref(),
+        Some("Hello \"world\"\n\tindented\\")
+    );
+    assert_eq!(
+        panel.children()[1].get_property::<String>("text").as_deref(),
+        Some("a \"quoted\" \\ string")
+    );
+    assert_eq!(panel.children()[2].text().as_deref(), Some("trimmed"));
+
+    // State and other keys that aren't identifiers are skipped
+    let mut manager: Manager<TestExt> = Manager::new();
+    let node = node!(root {
+        panel(x=1)
+    });
+    manager.add_node(node.clone());
+    let panel = node.children()[0].clone();
+    manager.set_hover(Some(panel.clone()));
+    manager.set_focus(Some(panel.clone()));
+    panel.set_property("my key", 2);
+    let out = node.to_desc_string();
+    assert_eq!(out, "root {\n    panel(x=1)\n}\n");
+    let parsed = Node::<TestExt>::from_str(&out).unwrap();
+    assert_eq!(parsed.to_desc_string(), out);
+}
+
+#[cfg(feature = "serde")]
+#[test]
+fn test_json_round_trip() {
+    let node = Node::<TestExt>::from_str(r#"
+root {
+    panel(x=5, scale=1.5, color=#FF8000CC, enabled=false) {
+        "Hello \"world\""(size=12)
+        label(text="a label")
+    }
+    empty
+}
+"#).unwrap();
+    node.children()[1].set_property("values", Value::List(vec![
+        Value::Integer(1),
+        Value::String("two".to_owned()),
+    ]));
+    node.children()[1].set_property("ext", Value::ExtValue(4));
+
+    let json = node.to_json().unwrap();
+    let parsed = Node::<TestExt>::from_json(&json).unwrap();
+    assert_eq!(parsed.to_json().unwrap(), json);
+    assert_eq!(parsed.to_desc_string(), node.to_desc_string());
+
+    let empty = parsed.children()[1].clone();
+    assert!(empty.get_property::<Value<TestExt>>("ext").is_none());
+    assert_eq!(
+        empty.get_property::<Value<TestExt>>("values"),
+        Some(Value::List(vec![Value::Integer(1), Value::String("two".to_owned())]))
+    );
+
+    let text = Node::<TestExt>::from_json(r#"{"kind": "text", "text": "hi", "properties": {"size": {"type": "int", "value": 3}}}"#).unwrap();
+    assert_eq!(text.text().as_deref(), Some("hi"));
+    assert_eq!(text.get_property::<i32>("size"), Some(3));
+    assert!(Node::<TestExt>::from_json(r#"{"kind": "element"}"#).is_err());
+
+    // JSON has no representation for non-finite floats
+    text.set_property("size", f64::NAN);
+    assert!(text.to_json().is_err());
+    text.set_property("size", f64::INFINITY);
+    assert!(text.to_json().is_err());
+}
+
+#[test]
+fn test_inherit() {
+    let mut manager: Manager<TestExt> = Manager::new();
+    let src = r#"
+panel(color=c) {
+    char = c,
+    width = 1,
+    height = 1,
+}
+item {
+    char = ".",
+    width = 1,
+    height = 1,
+}
+panel > item {
+    char = inherit,
+}
+item(pos=p) {
+    x = p,
+}
+orphan {
+    char = inherit,
+}
+    "#;
+    if let Err(err) = manager.load_styles("test", src) {
+        let stdout = std::io::stdout();
+        format_parse_error(stdout.lock(), src.lines(), err).unwrap();
+        panic!("Styles failed to parse");
+    }
+    let panel = node!(panel(color="@".to_owned()) {
+        item(pos=1)
+        item(pos=2)
+    });
+    manager.add_node(panel.clone());
+    manager.add_node(node!(item(pos=4)));
+    manager.layout(5, 1);
+    assert!(manager.take_errors().is_empty());
+    let mut render = AsciiRender::new(5, 1);
+    manager.render(&mut render);
+    assert_eq!(render.as_string(), "@@@#.");
+
+    // Children follow changes to the parent's value
+    panel.set_property("color", "+".to_owned());
+    manager.layout(5, 1);
+    let mut render = AsciiRender::new(5, 1);
+    manager.render(&mut render);
+    assert_eq!(render.as_string(), "+++#.");
+
+    // Nothing to inherit at the top level
+    manager.add_node(node!(orphan));
+    manager.layout(5, 1);
+    let errors = manager.take_errors();
+    assert_eq!(errors.len(), 1);
+    match errors[0] {
+        LayoutError::Style(StyleError { key: "char", error: Error::NothingToInherit { key: "char" }, .. }) => {},
+        ref err => panic!("Unexpected error: {:?}", err),
+    }
+}
+
+#[test]
+fn test_dirty_report() {
+    let mut manager: Manager<TestExt> = Manager::new();
+    let src = r#"
+panel(pos=p) {
+    x = p,
+    width = 2,
+    height = 1,
+}
+item {
+    width = 1,
+    height = 1,
+}
+    "#;
+    if let Err(err) = manager.load_styles("test", src) {
+        let stdout = std::io::stdout();
+        format_parse_error(stdout.lock(), src.lines(), err).unwrap();
+        panic!("Styles failed to parse");
+    }
+    let moved = node!(panel(pos=0) {
+        item
+    });
+    let other = node!(panel(pos=4) {
+        item
+    });
+    manager.add_node(moved.clone());
+    manager.add_node(other.clone());
+    manager.layout(8, 1);
+    manager.layout(8, 1);
+    assert!(manager.dirty_report().is_empty());
+
+    moved.set_property("pos", 2);
+    manager.layout(8, 1);
+    let report = manager.dirty_report();
+    assert_eq!(report.len(), 2);
+    assert!(report[0].0.is_same(&moved));
+    assert!(report[0].1.contains(DirtyFlags::POSITION));
+    assert!(report[1].0.is_same(&moved.children()[0]));
+    assert!(!report.iter().any(|(n, _)| n.is_same(&other) || n.is_same(&other.children()[0])));
+}
+
+#[test]
+fn test_trigger_action() {
+    let mut manager: Manager<TestExt> = Manager::new();
+    let calls = Rc::new(RefCell::new(Vec::new()));
+    {
+        let calls = calls.clone();
+        manager.register_action("visit", move |node, args| {
+            calls.borrow_mut().push((node.name(), args.to_vec()));
+        });
+    }
+
+    let link = node!(link(url="home".to_owned()));
+    manager.add_node(link.clone());
+    let action = manager.evaluate(&link, r#"action("visit", url, 3)"#).unwrap();
+    link.set_property("on_click", action);
+
+    assert!(link.trigger("on_click").unwrap());
+    assert_eq!(*calls.borrow(), vec![(
+        Some("link".to_owned()),
+        vec![Value::String("home".to_owned()), Value::Integer(3)],
+    )]);
+
+    // Unset properties are ignored
+    assert!(!link.trigger("on_hover").unwrap());
+    // The property must be an action
+    link.set_property("on_hover", 5);
+    assert!(link.trigger("on_hover").is_err());
+    // The action must have been registered
+    link.set_property("on_hover", Action::new("missing", vec![]));
+    assert!(link.trigger("on_hover").is_err());
+    // The node must be attached to a manager
+    let detached: Node<TestExt> = node!(link);
+    detached.set_property("on_click", Action::new("visit", vec![]));
+    assert!(detached.trigger("on_click").is_err());
+    assert_eq!(calls.borrow().len(), 1);
+
+    // Triggering an action from within itself fails instead of
+    // panicking
+    let inner = Rc::new(RefCell::new(None));
+    {
+        let inner = inner.clone();
+        manager.register_action("again", move |node, _args| {
+            *inner.borrow_mut() = Some(node.trigger("on_again").is_err());
+        });
+    }
+    link.set_property("on_again", Action::new("again", vec![]));
+    assert!(link.trigger("on_again").unwrap());
+    assert_eq!(*inner.borrow(), Some(true));
+}
+
+#[test]
+fn test_trigger_style_action() {
+    let mut manager: Manager<TestExt> = Manager::new();
+    let calls = Rc::new(RefCell::new(Vec::new()));
+    {
+        let calls = calls.clone();
+        manager.register_action("visit", move |_node, args| {
+            calls.borrow_mut().push(args.to_vec());
+        });
+    }
+    let src = r#"
+link(url=url) {
+    on_click = action("visit", url),
+}
+"#;
+    if let Err(err) = manager.load_styles("test", src) {
+        let stdout = std::io::stdout();
+        format_parse_error(stdout.lock(), src.lines(), err).unwrap();
+        panic!("Styles failed to parse");
+    }
+
+    let link = node!(link(url="home".to_owned()));
+    let other = node!(link);
+    manager.add_node(link.clone());
+    manager.add_node(other.clone());
+    manager.layout(20, 20);
+
+    assert!(link.trigger("on_click").unwrap());
+    assert_eq!(*calls.borrow(), vec![vec![Value::String("home".to_owned())]]);
+    // Rules that don't match don't provide the action
+    assert!(!other.trigger("on_click").unwrap());
+
+    // The node's own property takes priority
+    link.set_property("on_click", Action::new("visit", vec![Value::Integer(1)]));
+    assert!(link.trigger("on_click").unwrap());
+    assert_eq!(calls.borrow()[1], vec![Value::Integer(1)]);
+
+    // Follows the property the rule matches on
+    link.remove_property("on_click");
+    link.set_property("url", "away".to_owned());
+    manager.layout(20, 20);
+    assert!(link.trigger("on_click").unwrap());
+    assert_eq!(calls.borrow()[2], vec![Value::String("away".to_owned())]);
+}
+
+#[test]
+fn test_hover_focus() {
+    let mut manager: Manager<TestExt> = Manager::new();
+    let src = r#"
+button(pos=p) {
+    x = p,
+    width = 1,
+    height = 1,
+    char = "o",
+}
+button($hover=true) {
+    char = "h",
+}
+button($focus=true) {
+    char = "f",
+}
+    "#;
+    if let Err(err) = manager.load_styles("test", src) {
+        let stdout = std::io::stdout();
+        format_parse_error(stdout.lock(), src.lines(), err).unwrap();
+        panic!("Styles failed to parse");
+    }
+    let buttons: Vec<Node<TestExt>> = (0 .. 3)
+        .map(|i| node!(button(pos=i)))
+        .collect();
+    for b in &buttons {
+        manager.add_node(b.clone());
+    }
+    let render = |manager: &mut Manager<TestExt>| {
+        manager.layout(3, 1);
+        let mut render = AsciiRender::new(3, 1);
+        manager.render(&mut render);
+        render.as_string()
+    };
+    let updates = || UPDATES.with(|v| v.get());
+    assert_eq!(render(&mut manager), "ooo");
+
+    let before = updates();
+    manager.set_hover(Some(buttons[0].clone()));
+    assert_eq!(render(&mut manager), "hoo");
+    // The hovered button applies both of its rules
+    assert_eq!(updates(), before + 2);
+
+    // Only the old and new hovered buttons are updated
+    let before = updates();
+    manager.set_hover(Some(buttons[2].clone()));
+    assert_eq!(render(&mut manager), "ooh");
+    assert_eq!(updates(), before + 1 + 2);
+    assert!(buttons[0].get_property::<bool>("$hover").is_none());
+
+    // Setting the same node again changes nothing
+    let before = updates();
+    manager.set_hover(Some(buttons[2].clone()));
+    assert_eq!(render(&mut manager), "ooh");
+    assert_eq!(updates(), before);
+
+    manager.set_focus(Some(buttons[1].clone()));
+    assert_eq!(render(&mut manager), "ofh");
+    assert!(manager.focused().unwrap().is_same(&buttons[1]));
+
+    manager.set_hover(None);
+    manager.set_focus(None);
+    assert_eq!(render(&mut manager), "ooo");
+    assert!(manager.hovered().is_none());
+
+    // The node's own `hover` property is left alone
+    buttons[0].set_property("hover", 5);
+    manager.set_hover(Some(buttons[0].clone()));
+    manager.set_hover(None);
+    assert_eq!(buttons[0].get_property::<i32>("hover"), Some(5));
+    assert_eq!(render(&mut manager), "ooo");
+}
+
+#[test]
+fn test_hit_test() {
+    let mut manager: Manager<TestExt> = Manager::new();
+    let src = r#"
+panel(x=x, w=w) {
+    x = x,
+    width = w,
+    height = 2,
+}
+clip {
+    x = 6,
+    width = 2,
+    height = 2,
+    clip_overflow = true,
+}
+clip > panel {
+    y = 1,
+}
+    "#;
+    if let Err(err) = manager.load_styles("test", src) {
+        let stdout = std::io::stdout();
+        format_parse_error(stdout.lock(), src.lines(), err).unwrap();
+        panic!("Styles failed to parse");
+    }
+    let first = node!(panel(x=0, w=3, id=1));
+    let second = node!(panel(x=2, w=3, id=2) {
+        panel(x=1, w=1, id=3)
+    });
+    let clip = node!(clip {
+        panel(x=1, w=4, id=4)
+    });
+    manager.add_node(first.clone());
+    manager.add_node(second.clone());
+    manager.add_node(clip.clone());
+    manager.layout(10, 2);
+
+    let hit = |x, y| manager.hit_test(x, y).and_then(|v| v.get_property::<i32>("id"));
+    assert_eq!(hit(0, 0), Some(1));
+    // The later sibling is drawn over the earlier one
+    assert_eq!(hit(2, 0), Some(2));
+    // Children are drawn over their parents
+    assert_eq!(hit(3, 0), Some(3));
+    assert_eq!(hit(4, 1), Some(2));
+    assert_eq!(hit(5, 0), None);
+    // Clipped by the parent
+    assert_eq!(hit(7, 1), Some(4));
+    assert_eq!(hit(8, 1), None);
+    assert!(manager.hit_test(6, 0).unwrap().is_same(&clip));
+}
+
+#[test]
+fn test_append_text() {
+    let text: Node<TestExt> = node!(@text("log:"));
+    let changed = || ::std::mem::replace(&mut text.borrow_mut().text_changed, false);
+    changed();
+
+    for line in &[" one", " two", " three"] {
+        assert!(text.append_text(line));
+        assert!(changed());
+        assert!(!changed());
+    }
+    assert_eq!(text.text().as_deref(), Some("log: one two three"));
+
+    assert!(text.append_text(""));
+    assert!(!changed());
+
+    let element: Node<TestExt> = node!(log);
+    assert!(!element.append_text("ignored"));
+    assert!(!element.borrow().text_changed);
+}
+
+#[test]
+fn test_query_at_scrolled() {
+    let mut manager: Manager<TestExt> = Manager::new();
+    let src = r#"
+outer {
+    x = 1,
+    y = 1,
+    width = 4,
+    height = 3,
+    clip_overflow = true,
+}
+list {
+    x = -1,
+    y = -1,
+    width = 4,
+    height = 4,
+    clip_overflow = true,
+    scroll_y = -2.0,
+}
+item(idx=i) {
+    y = i,
+    width = 4,
+    height = 1,
+}
+    "#;
+    if let Err(err) = manager.load_styles("test", src) {
+        let stdout = std::io::stdout();
+        format_parse_error(stdout.lock(), src.lines(), err).unwrap();
+        panic!("Styles failed to parse");
+    }
+    let list = node!(list);
+    for i in 0 .. 8 {
+        list.add_child(node!(item(idx=i)));
+    }
+    let outer = node!(outer);
+    outer.add_child(list);
+    manager.add_node(outer);
+    manager.layout(10, 10);
+
+    let hit = |x, y| manager.query_at(x, y)
+        .name("item")
+        .next()
+        .and_then(|v| v.get_property::<i32>("idx"));
+    // The list starts one row above the outer node and is
+    // scrolled by two rows
+    assert_eq!(hit(1, 1), Some(3));
+    assert_eq!(hit(1, 2), Some(4));
+    assert_eq!(hit(3, 3), Some(5));
+    assert_eq!(hit(1, 4), None);
+    assert_eq!(hit(0, 1), None);
+
+    for x in 0 .. 10 {
+        for y in 0 .. 10 {
+            let by_query = manager.query_at(x, y).name("item").next();
+            let by_position = manager.query().name("item").matches()
+                .find(|v| v.render_position().is_some_and(|r| r.contains(x, y)));
+            assert_eq!(
+                by_query.and_then(|v| v.get_property::<i32>("idx")),
+                by_position.and_then(|v| v.get_property::<i32>("idx")),
+                "Mismatch at {}, {}", x, y
+            );
+        }
+    }
+}
+
+#[test]
+fn test_focus_traversal() {
+    let mut manager: Manager<TestExt> = Manager::new();
+    let src = r#"
+button(pos=p) {
+    x = p,
+    width = 1,
+    height = 1,
+}
+hidden {
+    width = 0,
+    height = 0,
+    clip_overflow = true,
+}
+    "#;
+    if let Err(err) = manager.load_styles("test", src) {
+        let stdout = std::io::stdout();
+        format_parse_error(stdout.lock(), src.lines(), err).unwrap();
+        panic!("Styles failed to parse");
+    }
+    manager.add_node_str(r#"
+root {
+    button(pos=0, focusable=true, id=0)
+    button(pos=1, id=1)
+    panel {
+        button(pos=2, focusable=true, id=2)
+    }
+    hidden {
+        button(pos=3, focusable=true, id=3)
+    }
+    button(pos=4, focusable=true, id=4)
+}
+    "#).unwrap();
+    manager.layout(5, 1);
+
+    let focused = |manager: &Manager<TestExt>| manager.focused()
+        .and_then(|v| v.get_property::<i32>("id"));
+    let mut forward = Vec::new();
+    for _ in 0 .. 4 {
+        manager.focus_next();
+        forward.push(focused(&manager));
+    }
+    assert_eq!(forward, vec![Some(0), Some(2), Some(4), Some(0)]);
+
+    let mut backward = Vec::new();
+    for _ in 0 .. 4 {
+        manager.focus_prev();
+        backward.push(focused(&manager));
+    }
+    assert_eq!(backward, vec![Some(4), Some(2), Some(0), Some(4)]);
+    assert_eq!(manager.focused().unwrap().get_property::<bool>("$focus"), Some(true));
+}
+
+#[test]
+fn test_measure_column() {
+    let mut manager: Manager<TestExt> = Manager::new();
+    let src = r#"
+root > column {
+    layout = "column",
+    x = 1,
+    y = 1,
+    spacing = 1,
+    char = "c",
+}
+item(w=w, h=h) {
+    width = w,
+    height = h,
+    char = "a",
+}
+row {
+    layout = "row",
+}
+row > item {
+    width = 1,
+    height = 1,
+    char = "b",
+}
+    "#;
+    if let Err(err) = manager.load_styles("test", src) {
+        let stdout = std::io::stdout();
+        format_parse_error(stdout.lock(), src.lines(), err).unwrap();
+        panic!("Styles failed to parse");
+    }
+    manager.add_node(node! {
+        column {
+            item(w=3, h=1)
+            item(w=1, h=2)
+            row {
+                item
+                item
+            }
+        }
+    });
+    manager.layout(5, 8);
+
+    let mut render = AsciiRender::new(5, 8);
+    manager.render(&mut render);
+    // The column is as wide as its widest child and as tall
+    // as its children and spacing. The row has no height
+    // of its own so takes the height of its items.
+    assert_eq!(render.as_string(), r##"
+#####
+#aaa#
+#ccc#
+#acc#
+#acc#
+#ccc#
+#bb~#
+#####
+"##.trim());
+}
+
+#[test]
+fn test_add_node_str_ret() {
+    let mut manager: Manager<TestExt> = Manager::new();
+    let src = r#"
+panel(on=true) {
+    width = 1,
+    height = 1,
+    char = "@",
+}
+    "#;
+    if let Err(err) = manager.load_styles("test", src) {
+        let stdout = std::io::stdout();
+        format_parse_error(stdout.lock(), src.lines(), err).unwrap();
+        panic!("Styles failed to parse");
+    }
+    let panel = manager.add_node_str_ret("panel(on=false)").unwrap();
+    let render = |manager: &mut Manager<TestExt>| {
+        manager.layout(2, 1);
+        let mut render = AsciiRender::new(2, 1);
+        manager.render(&mut render);
+        render.as_string()
+    };
+    assert_eq!(render(&mut manager), "##");
+
+    panel.set_property("on", true);
+    assert_eq!(render(&mut manager), "@#");
+    assert!(manager.query().name("panel").next().unwrap().is_same(&panel));
+}
+
+#[test]
+fn test_request_parent_layout() {
+    // Requests an extra parent pass while `requests` is above zero
+    struct RequestLayout {
+        requests: Rc<::std::cell::Cell<usize>>,
+        passes: Rc<::std::cell::Cell<usize>>,
+    }
+
+    impl LayoutEngine<TestExt> for RequestLayout {
+        type ChildData = ();
+
+        fn name() -> &'static str { "request" }
+        fn style_properties<'a, F>(_prop: F)
+            where F: FnMut(StaticKey) + 'a
+        {}
+        fn new_child_data() {}
+
+        fn finish_layout(&mut self, _ext: &mut TestData, current: Rect, _flags: DirtyFlags, _children: ChildAccess<Self, TestExt>) -> Rect {
+            self.passes.set(self.passes.get() + 1);
+            current
+        }
+
+        fn requested_flags(&mut self) -> DirtyFlags {
+            if self.requests.get() > 0 {
+                self.requests.set(self.requests.get() - 1);
+                DirtyFlags::REQUEST_PARENT
+            } else {
+                DirtyFlags::empty()
+            }
+        }
+    }
+
+    let mut manager: Manager<TestExt> = Manager::new();
+    let requests = Rc::new(::std::cell::Cell::new(0));
+    let passes = Rc::new(::std::cell::Cell::new(0));
+    {
+        let requests = requests.clone();
+        let passes = passes.clone();
+        manager.add_layout_engine(move || RequestLayout {
+            requests: requests.clone(),
+            passes: passes.clone(),
+        });
+    }
+    let src = r#"
+outer {
+    char = "o",
+}
+content {
+    layout = "request",
+}
+    "#;
+    if let Err(err) = manager.load_styles("test", src) {
+        let stdout = std::io::stdout();
+        format_parse_error(stdout.lock(), src.lines(), err).unwrap();
+        panic!("Styles failed to parse");
+    }
+    manager.add_node(node! {
+        outer {
+            content
+        }
+    });
+    let updates = || UPDATES.with(|v| v.get());
+    manager.layout(4, 4);
+    assert_eq!(passes.get(), 1);
+
+    // A single request runs one extra pass updating the parent
+    requests.set(1);
+    let before = updates();
+    manager.layout(4, 4);
+    assert_eq!(passes.get(), 3);
+    assert!(updates() > before);
+
+    // Without a request nothing is updated again
+    let before = updates();
+    manager.layout(4, 4);
+    assert_eq!(passes.get(), 4);
+    assert_eq!(updates(), before);
+}
+
+#[test]
+fn test_match_bound_variable() {
+    let mut manager: Manager<TestExt> = Manager::new();
+    let src = r#"
+list {
+    width = 4,
+    height = 1,
+}
+list > item(index=i) {
+    x = i,
+    width = 1,
+    height = 1,
+    char = ".",
+}
+list(selected=s) > item(index=s) {
+    char = "@",
+}
+item(a=v, b=v) {
+    char = "=",
+}
+    "#;
+    if let Err(err) = manager.load_styles("test", src) {
+        let stdout = std::io::stdout();
+        format_parse_error(stdout.lock(), src.lines(), err).unwrap();
+        panic!("Styles failed to parse");
+    }
+    let list = node! {
+        list(selected=2) {
+            item(index=0)
+            item(index=1, a=1, b=1)
+            item(index=2)
+            item(index=3, a=1, b=2)
+        }
+    };
+    manager.add_node(list.clone());
+    let render = |manager: &mut Manager<TestExt>| {
+        manager.layout(4, 1);
+        let mut render = AsciiRender::new(4, 1);
+        manager.render(&mut render);
+        render.as_string()
+    };
+    assert_eq!(render(&mut manager), ".=@.");
+
+    list.set_property("selected", 0);
+    assert_eq!(render(&mut manager), "@=..");
+
+    // A value of a different type never matches
+    list.set_property("selected", "2".to_owned());
+    assert_eq!(render(&mut manager), ".=..");
+}
+
+#[test]
+fn test_set_styles_enabled() {
+    let mut manager: Manager<TestExt> = Manager::new();
+    let base = r#"
+panel {
+    width = 2,
+    height = 1,
+    char = "o",
+}
+    "#;
+    let theme = r#"
+panel {
+    char = "@",
+}
+    "#;
+    for &(name, src) in &[("base", base), ("theme", theme)] {
+        if let Err(err) = manager.load_styles(name, src) {
+            let stdout = std::io::stdout();
+            format_parse_error(stdout.lock(), src.lines(), err).unwrap();
+            panic!("Styles failed to parse");
+        }
+    }
+    manager.add_node(node!(panel));
+    let render = |manager: &mut Manager<TestExt>| {
+        manager.layout(3, 1);
+        let mut render = AsciiRender::new(3, 1);
+        manager.render(&mut render);
+        render.as_string()
+    };
+    assert_eq!(render(&mut manager), "@@#");
+
+    manager.set_styles_enabled("theme", false);
+    assert_eq!(render(&mut manager), "oo#");
+    assert_eq!(manager.rule_count("theme"), 1);
+
+    manager.set_styles_enabled("theme", true);
+    assert_eq!(render(&mut manager), "@@#");
+}
+
+#[test]
+fn test_grid_layout() {
+    let mut manager: Manager<TestExt> = Manager::new();
+    let src = r#"
+grid {
+    layout = "grid",
+    x = 1,
+    y = 1,
+    width = 7,
+    height = 5,
+    columns = 2,
+    rows = 2,
+    spacing = 1,
+    margin = 1,
+}
+grid(forced=true) {
+    force_size = true,
+}
+item(c=c) {
+    char = c,
+}
+item(narrow=true) {
+    width = 1,
+}
+    "#;
+    if let Err(err) = manager.load_styles("test", src) {
+        let stdout = std::io::stdout();
+        format_parse_error(stdout.lock(), src.lines(), err).unwrap();
+        panic!("Styles failed to parse");
+    }
+    let grid = node! {
+        grid {
+            item(c="a".to_owned())
+            item(c="b".to_owned())
+            item(c="c".to_owned())
+            item(c="d".to_owned(), narrow=true)
+        }
+    };
+    manager.add_node(grid.clone());
+    let render = |manager: &mut Manager<TestExt>| {
+        manager.layout(9, 7);
+        let mut render = AsciiRender::new(9, 7);
+        manager.render(&mut render);
+        render.as_string()
+    };
+    assert_eq!(render(&mut manager), r##"
+#########
+#~~~~~~~#
+#~aa~bb~#
+#~~~~~~~#
+#~cc~d~~#
+#~~~~~~~#
+#########
+"##.trim());
+
+    // Every child fills its cell when forced
+    grid.set_property("forced", true);
+    assert_eq!(render(&mut manager), r##"
+#########
+#~~~~~~~#
+#~aa~bb~#
+#~~~~~~~#
+#~cc~dd~#
+#~~~~~~~#
+#########
+"##.trim());
+
+    // Huge counts shrink the cells instead of overflowing
+    manager.load_styles("huge", r#"
+grid(huge=true) {
+    rows = 2147483647,
+    spacing = 2,
+}
+    "#).unwrap();
+    grid.set_property("huge", true);
+    render(&mut manager);
+    for c in grid.children() {
+        assert_eq!(c.borrow().draw_rect.height, 0);
+    }
+}
+
+#[test]
+fn test_grid_layout_node() {
+    let mut manager: Manager<TestExt> = Manager::new();
+    let src = r#"
+grid {
+    layout = "grid",
+    width = 7,
+    height = 5,
+    columns = 2,
+    rows = 2,
+    spacing = 1,
+    margin = 1,
+}
+    "#;
+    if let Err(err) = manager.load_styles("test", src) {
+        let stdout = std::io::stdout();
+        format_parse_error(stdout.lock(), src.lines(), err).unwrap();
+        panic!("Styles failed to parse");
+    }
+    let grid = node! {
+        grid {
+            item
+            item
+            item
+        }
+    };
+    manager.add_node(grid.clone());
+    manager.layout(9, 7);
+
+    // Laying out a single child keeps it in its own cell
+    let first = grid.first_child().unwrap();
+    manager.layout_node(&first);
+    let rect = first.borrow().draw_rect;
+    assert_eq!((rect.x, rect.y, rect.width, rect.height), (1, 1, 2, 1));
+}
+
+#[test]
+fn test_text_measure() {
+    // Every glyph is two wide and lines are three tall
+    struct Stub;
+    impl TextMeasure for Stub {
+        fn measure(&self, text: &str, _font: &str, _size: i32) -> (i32, i32) {
+            (text.chars().count() as i32 * 2, 3)
+        }
+    }
+
+    // Wraps text children to the width of the node
+    #[derive(Default)]
+    struct WrapLayout;
+    impl LayoutEngine<TestExt> for WrapLayout {
+        type ChildData = Rect;
+
+        fn name() -> &'static str { "wrap" }
+        fn style_properties<'a, F>(_prop: F)
+            where F: FnMut(StaticKey) + 'a
+        {}
+        fn new_child_data() -> Rect {
+            Rect { x: 0, y: 0, width: 0, height: 0 }
+        }
+
+        fn start_layout(&mut self, _ext: &mut TestData, current: Rect, _constraints: SizeConstraints, _flags: DirtyFlags, children: ChildAccess<Self, TestExt>) -> Rect {
+            let mut y = 0;
+            for idx in 0 .. children.len() {
+                let (_, _, mut node) = children.get(idx).expect("Missing child");
+                let (value, data) = node.split();
+                if let NodeValue::Text(ref t) = *value {
+                    let lines = children.text_measure().wrap(t, "mono", 1, current.width);
+                    let width = lines.iter()
+                        .map(|l| children.text_measure().measure(&t[l.clone()], "mono", 1).0)
+                        .max()
+                        .unwrap_or(0);
+                    *data = Rect { x: 0, y, width, height: lines.len() as i32 * 3 };
+                    y += data.height;
+                }
+            }
+            current
+        }
+
+        fn do_layout(&mut self, _value: &NodeValue<TestExt>, _ext: &mut TestData, data: &mut Rect, _current: Rect, _flags: DirtyFlags) -> Rect {
+            *data
+        }
+    }
+
+    let text = "aa bb cc\ndd";
+    assert_eq!(Stub.wrap(text, "mono", 1, 10), vec![0 .. 5, 6 .. 8, 9 .. 11]);
+    assert_eq!(Stub.wrap(text, "mono", 1, 4), vec![0 .. 2, 3 .. 5, 6 .. 8, 9 .. 11]);
+    assert_eq!(Stub.wrap("toolong a", "mono", 1, 4), vec![0 .. 7, 8 .. 9]);
+    // Without a measurer set every glyph is `size` wide
+    assert_eq!(MonospaceMeasure.wrap(text, "mono", 1, 10), vec![0 .. 8, 9 .. 11]);
+
+    let mut manager: Manager<TestExt> = Manager::new();
+    manager.add_layout_engine(WrapLayout::default);
+    manager.set_text_measure(Stub);
+    let src = r#"
+panel {
+    layout = "wrap",
+    width = 10,
+    height = 20,
+}
+    "#;
+    if let Err(err) = manager.load_styles("test", src) {
+        let stdout = std::io::stdout();
+        format_parse_error(stdout.lock(), src.lines(), err).unwrap();
+        panic!("Styles failed to parse");
+    }
+    manager.add_node(node! {
+        panel {
+            @text("aa bb cc\ndd")
+            @text("e")
+        }
+    });
+    manager.layout(10, 20);
+
+    let rects: Vec<_> = manager.query()
+        .text()
+        .matches()
+        .map(|v| v.render_position().unwrap())
+        .collect();
+    // Matches are returned last child first
+    assert_eq!(rects, vec![
+        Rect { x: 0, y: 9, width: 2, height: 3 },
+        Rect { x: 0, y: 0, width: 10, height: 9 },
+    ]);
+}
+
+#[test]
+fn test_surfaces() {
+    let mut manager: Manager<TestExt> = Manager::new();
+    let src = r#"
+root > panel {
+    width = parent_width - 1,
+    height = 1,
+    char = "@",
+}
+    "#;
+    if let Err(err) = manager.load_styles("test", src) {
+        let stdout = std::io::stdout();
+        format_parse_error(stdout.lock(), src.lines(), err).unwrap();
+        panic!("Styles failed to parse");
+    }
+    let window = manager.add_surface("window");
+    let tooltip = manager.add_surface("tooltip");
+    assert!(manager.surface("window").unwrap().is_same(&window));
+    let window_panel: Node<TestExt> = node!(panel);
+    let tooltip_panel: Node<TestExt> = node!(panel);
+    window.add_child(window_panel.clone());
+    tooltip.add_child(tooltip_panel.clone());
+
+    assert!(manager.layout_surface("window", 6, 2));
+    assert!(manager.layout_surface("tooltip", 3, 1));
+    assert!(!manager.layout_surface("missing", 3, 1));
+    assert_eq!(window_panel.inner.borrow().draw_rect.width, 5);
+    assert_eq!(tooltip_panel.inner.borrow().draw_rect.width, 2);
+
+    let mut render = AsciiRender::new(3, 1);
+    assert!(manager.render_surface("tooltip", &mut render));
+    assert_eq!(render.as_string(), "@@#");
+
+    // Style changes reach every surface
+    manager.load_styles("extra", r#"
+root > panel {
+    char = "+",
+}
+    "#).unwrap();
+    assert!(manager.layout_surface("tooltip", 3, 1));
+    let mut render = AsciiRender::new(3, 1);
+    manager.render_surface("tooltip", &mut render);
+    assert_eq!(render.as_string(), "++#");
+    assert!(!manager.layout_surface("tooltip", 3, 1));
+
+    assert!(manager.remove_surface("window"));
+    assert!(manager.surface("window").is_none());
+
+    // The manager's own root is the main surface
+    let root = manager.surface(MAIN_SURFACE).unwrap();
+    assert!(root.is_same(&manager.add_surface(MAIN_SURFACE)));
+    let main_panel: Node<TestExt> = node!(panel);
+    manager.add_node(main_panel.clone());
+    assert!(main_panel.parent().unwrap().is_same(&root));
+    assert!(manager.layout_surface(MAIN_SURFACE, 4, 1));
+    assert_eq!(main_panel.inner.borrow().draw_rect.width, 3);
+    assert!(!manager.layout_surface(MAIN_SURFACE, 4, 1));
+    let mut render = AsciiRender::new(4, 1);
+    assert!(manager.render_surface(MAIN_SURFACE, &mut render));
+    assert_eq!(render.as_string(), "+++#");
+    assert!(!manager.remove_surface(MAIN_SURFACE));
+    assert!(manager.surface(MAIN_SURFACE).is_some());
+}
+
+#[test]
+fn test_surface_viewports() {
+    let mut manager: Manager<TestExt> = Manager::new();
+    let src = r#"
+root > panel {
+    width = viewport_width / 2,
+    height = viewport_height,
+}
+    "#;
+    if let Err(err) = manager.load_styles("test", src) {
+        let stdout = std::io::stdout();
+        format_parse_error(stdout.lock(), src.lines(), err).unwrap();
+        panic!("Styles failed to parse");
+    }
+    let main_panel: Node<TestExt> = node!(panel);
+    manager.add_node(main_panel.clone());
+    let window = manager.add_surface("window");
+    let overlay = manager.add_surface("overlay");
+    let window_panel: Node<TestExt> = node!(panel);
+    let overlay_panel: Node<TestExt> = node!(panel);
+    window.add_child(window_panel.clone());
+    overlay.add_child(overlay_panel.clone());
+
+    manager.layout(20, 10);
+    manager.layout_surface("window", 8, 4);
+    manager.layout_surface("overlay", 4, 2);
+    assert_eq!(main_panel.borrow().draw_rect, Rect { x: 0, y: 0, width: 10, height: 10 });
+    assert_eq!(window_panel.borrow().draw_rect, Rect { x: 0, y: 0, width: 4, height: 4 });
+    assert_eq!(overlay_panel.borrow().draw_rect, Rect { x: 0, y: 0, width: 2, height: 2 });
+
+    // Resizing one surface leaves the others alone
+    manager.layout_surface("overlay", 6, 3);
+    manager.layout(20, 10);
+    assert_eq!(main_panel.borrow().draw_rect, Rect { x: 0, y: 0, width: 10, height: 10 });
+    assert_eq!(window_panel.borrow().draw_rect, Rect { x: 0, y: 0, width: 4, height: 4 });
+    assert_eq!(overlay_panel.borrow().draw_rect, Rect { x: 0, y: 0, width: 3, height: 3 });
+}
+
+#[test]
+fn test_svg_render() {
+    let mut manager: Manager<TestExt> = Manager::new();
+    let src = r#"
+panel(c=c) {
+    width = 3,
+    height = 2,
+    char = c,
+}
+panel(second=true) {
+    x = 4,
+    y = 1,
+}
+@text {
+    char = "@",
+}
+    "#;
+    if let Err(err) = manager.load_styles("test", src) {
+        let stdout = std::io::stdout();
+        format_parse_error(stdout.lock(), src.lines(), err).unwrap();
+        panic!("Styles failed to parse");
+    }
+    manager.add_node(node!(panel(c="a".to_owned())));
+    manager.add_node(node! {
+        panel(c="b".to_owned(), second=true) {
+            @text("a < b")
+        }
+    });
+    manager.layout(8, 4);
+
+    let mut render = SvgRenderer::new(8, 4, |node: &NodeInner<TestExt>| match node.ext.render_char {
+        'a' => SvgStyle {
+            fill: Some((255, 0, 0, 255)),
+            stroke: None,
+        },
+        'b' => SvgStyle {
+            fill: None,
+            stroke: Some(((0, 0, 0, 255), 1)),
+        },
+        '@' => SvgStyle {
+            fill: Some((0, 0, 255, 51)),
+            stroke: None,
+        },
+        _ => SvgStyle::default(),
+    }).with_shapes(|node, rect, out| if node.ext.render_char == 'b' {
+        out.push_str(&format!("<circle cx=\"{}\" cy=\"{}\" r=\"1\"/>\n", rect.x, rect.y));
+    });
+    manager.render(&mut render);
+    assert_eq!(render.as_string(), r#"<svg xmlns="http://www.w3.org/2000/svg" width="8" height="4" viewBox="0 0 8 4">
+<rect x="0" y="0" width="3" height="2" fill="rgb(255,0,0)"/>
+<rect x="4" y="1" width="3" height="2" fill="none" stroke="rgb(0,0,0)" stroke-width="1"/>
+<circle cx="4" cy="1" r="1"/>
+<text x="4" y="1" dominant-baseline="hanging" fill="rgb(0,0,255)" fill-opacity="0.2">a &lt; b</text>
+</svg>
+"#);
+}
+
+#[test]
+fn test_z_index() {
+    let mut manager: Manager<TestExt> = Manager::new();
+    let src = r#"
+panel(c=c, pos=p) {
+    x = p,
+    width = 2,
+    height = 1,
+    char = c,
+}
+panel(z=z) {
+    z_index = z,
+}
+    "#;
+    if let Err(err) = manager.load_styles("test", src) {
+        let stdout = std::io::stdout();
+        format_parse_error(stdout.lock(), src.lines(), err).unwrap();
+        panic!("Styles failed to parse");
+    }
+    let last = node!(panel(c="c".to_owned(), pos=2));
+    manager.add_node(node!(panel(c="a".to_owned(), pos=0)));
+    manager.add_node(node!(panel(c="b".to_owned(), pos=1)));
+    manager.add_node(last.clone());
+    let render = |manager: &mut Manager<TestExt>| {
+        manager.layout(4, 1);
+        let mut render = AsciiRender::new(4, 1);
+        manager.render(&mut render);
+        let mut visible = AsciiRender::new(4, 1);
+        manager.render_visible(&mut visible);
+        assert_eq!(render.as_string(), visible.as_string());
+        render.as_string()
+    };
+    assert_eq!(render(&mut manager), "abcc");
+
+    // Drawn before the other panels despite being added last
+    last.set_property("z", -1);
+    assert_eq!(render(&mut manager), "abbc");
+
+    last.set_property("z", 0);
+    assert_eq!(render(&mut manager), "abcc");
+}
+
+#[test]
+fn test_z_index_hit_test() {
+    let mut manager: Manager<TestExt> = Manager::new();
+    let src = r#"
+panel {
+    width = 2,
+    height = 1,
+}
+panel(z=z) {
+    z_index = z,
+}
+    "#;
+    if let Err(err) = manager.load_styles("test", src) {
+        let stdout = std::io::stdout();
+        format_parse_error(stdout.lock(), src.lines(), err).unwrap();
+        panic!("Styles failed to parse");
+    }
+    manager.add_node(node!(panel(id=1, z=1)));
+    manager.add_node(node!(panel(id=2)));
+    manager.layout(4, 1);
+
+    // Drawn on top despite being added first
+    let hit = manager.hit_test(0, 0).and_then(|v| v.get_property::<i32>("id"));
+    assert_eq!(hit, Some(1));
+}
+
+#[test]
+fn test_pick_interactive() {
+    let mut manager: Manager<TestExt> = Manager::new();
+    manager.add_interactive_property("on_click");
+    manager.add_interactive_property("can_hover");
+    let src = r#"
+wrapper {
+    width = 8,
+    height = 2,
+}
+button(pos=p) {
+    x = p,
+    width = 2,
+    height = 2,
+}
+icon {
+    width = 1,
+    height = 1,
+}
+    "#;
+    if let Err(err) = manager.load_styles("test", src) {
+        let stdout = std::io::stdout();
+        format_parse_error(stdout.lock(), src.lines(), err).unwrap();
+        panic!("Styles failed to parse");
+    }
+    manager.add_node_str(r#"
+wrapper {
+    wrapper {
+        button(pos=0, on_click="ok", id=1) {
+            icon
+        }
+        button(pos=2, can_hover=false, id=2)
+        button(pos=4, can_hover=true, id=3)
+    }
+}
+    "#).unwrap();
+    manager.layout(8, 2);
+
+    let pick = |x, y| manager.pick_interactive(x, y)
+        .and_then(|v| v.get_property::<i32>("id"));
+    // Over the icon and the rest of the first button
+    assert_eq!(pick(0, 0), Some(1));
+    assert_eq!(pick(1, 1), Some(1));
+    // `false` doesn't count as interactive
+    assert_eq!(pick(2, 0), None);
+    assert_eq!(pick(5, 1), Some(3));
+    // Only the wrappers are here
+    assert_eq!(pick(6, 0), None);
+    assert_eq!(manager.hit_test(6, 0).and_then(|v| v.name()), Some("wrapper".to_owned()));
+}
+
+#[test]
+fn test_opacity() {
+    let mut manager: Manager<TestExt> = Manager::new();
+    let src = r#"
+panel(o=o) {
+    width = 3,
+    height = 1,
+    opacity = o,
+}
+panel > panel {
+    width = 2,
+}
+panel > panel > panel {
+    width = 1,
+}
+    "#;
+    if let Err(err) = manager.load_styles("test", src) {
+        let stdout = std::io::stdout();
+        format_parse_error(stdout.lock(), src.lines(), err).unwrap();
+        panic!("Styles failed to parse");
+    }
+    let root = node! {
+        panel(o=0.5) {
+            panel(o=0.5) {
+                panel
+            }
+        }
+    };
+    let child = root.first_child().unwrap();
+    manager.add_node(root);
+    manager.layout(4, 1);
+
+    // Draws each node with its opacity in quarters
+    let opacity = |node: &NodeInner<TestExt>| (b'0' + (node.render_opacity * 4.0) as u8) as char;
+    let mut render = AsciiRenderer::new(4, 1, opacity);
+    manager.render(&mut render);
+    assert_eq!(render.as_string(), "1124");
+    let mut render = AsciiRenderer::new(4, 1, opacity);
+    manager.render_visible(&mut render);
+    assert_eq!(render.as_string(), "1124");
+
+    child.set_property("o", 1.0);
+    manager.layout(4, 1);
+    let mut render = AsciiRenderer::new(4, 1, opacity);
+    manager.render(&mut render);
+    assert_eq!(render.as_string(), "2224");
+}
+
+#[test]
+fn test_ascii_renderer_empty() {
+    let mut manager: Manager<TestExt> = Manager::new();
+    manager.add_node(node!(panel));
+    manager.layout(0, 2);
+    for &(width, height) in &[(0, 2), (3, 0), (0, 0)] {
+        let mut render = AsciiRender::new(width, height);
+        manager.render(&mut render);
+        assert_eq!(render.as_string(), "");
+    }
+}
+
+#[test]
+fn test_from_str_limited() {
+    use syntax::desc::{Limits, LimitedParseError, LimitExceeded};
+    let limits = Limits {
+        max_nodes: 3,
+        .. Limits::default()
+    };
+    let node: Node<TestExt> = Node::from_str_limited(r#"root { panel "text" }"#, limits).unwrap();
+    assert_eq!(node.children().len(), 2);
+
+    // A very large document is stopped once it reaches the limit
+    let source = format!("root {{ {} }}", "panel ".repeat(100_000));
+    match Node::<TestExt>::from_str_limited(&source, limits) {
+        Err(LimitedParseError::Limit(LimitExceeded::Nodes { max: 3, position })) => {
+            assert_eq!(position.column, 20);
+        },
+        v => panic!("Unexpected result: {:?}", v.map(|_| ())),
+    }
+
+    // As is a deeply nested one
+    let source = format!("{}{}", "panel {".repeat(100_000), "}".repeat(100_000));
+    match Node::<TestExt>::from_str_limited(&source, Limits::default()) {
+        Err(LimitedParseError::Limit(LimitExceeded::Depth { max: 64, .. })) => {},
+        v => panic!("Unexpected result: {:?}", v.map(|_| ())),
+    }
+    // Deeply nested comments fail to parse instead of overflowing
+    // the stack
+    let source = format!("root {{ {}", "/*".repeat(200_000));
+    match Node::<TestExt>::from_str_limited(&source, Limits::default()) {
+        Err(LimitedParseError::Parse(_)) => {},
+        v => panic!("Unexpected result: {:?}", v.map(|_| ())),
+    }
+}
+
+#[test]
+fn test_parent_variables() {
+    let mut manager: Manager<TestExt> = Manager::new();
+    let src = r#"
+panel > panel > panel {
+    width = ancestor(2).size * 2,
+    height = parent.size,
+}
+    "#;
+    if let Err(err) = manager.load_styles("test", src) {
+        let stdout = std::io::stdout();
+        format_parse_error(stdout.lock(), src.lines(), err).unwrap();
+        panic!("Styles failed to parse");
+    }
+    let root = node! {
+        panel(size=3) {
+            panel(size=1) {
+                panel
+            }
+        }
+    };
+    let inner = root.first_child().unwrap();
+    let node = inner.first_child().unwrap();
+    manager.add_node(root.clone());
+    manager.layout(10, 10);
+    assert_eq!(node.borrow().draw_rect, Rect { x: 0, y: 0, width: 6, height: 1 });
+
+    // Changing an ancestor's property updates the node
+    root.set_property("size", 4);
+    inner.set_property("size", 2);
+    manager.layout(10, 10);
+    assert_eq!(node.borrow().draw_rect, Rect { x: 0, y: 0, width: 8, height: 2 });
+
+    // Parent variables can't be used to match
+    let res = manager.load_styles("bad", "panel(size=parent.size) { width = 1, }");
+    assert!(res.is_err());
+}
+
+#[test]
+fn test_layout_not_converged() {
+    let mut manager: Manager<TestExt> = Manager::new();
+    let src = r#"
+row {
+    layout = "row",
+    height = 1,
+}
+row > item {
+    width = parent_width + 1,
+}
+    "#;
+    if let Err(err) = manager.load_styles("test", src) {
+        let stdout = std::io::stdout();
+        format_parse_error(stdout.lock(), src.lines(), err).unwrap();
+        panic!("Styles failed to parse");
+    }
+    manager.set_max_layout_passes(10);
+    let row = node! {
+        row {
+            item
+        }
+    };
+    let item = row.first_child().unwrap();
+    manager.add_node(row);
+    // The row sizes itself to fit the item which is always
+    // one larger than the row
+    manager.layout(100, 100);
+
+    let errors = manager.take_errors();
+    assert_eq!(errors.len(), 1);
+    match errors[0] {
+        LayoutError::NotConverged { passes, ref nodes } => {
+            assert_eq!(passes, 10);
+            assert_eq!(nodes.len(), 1);
+            assert!(Rc::ptr_eq(&nodes[0].inner, &item.inner));
+        },
+        ref err => panic!("Unexpected error: {:?}", err),
+    }
+    assert!(manager.take_errors().is_empty());
+}
+
+#[test]
+fn test_var_default() {
+    let mut manager: Manager<TestExt> = Manager::new();
+    let src = r#"
+panel {
+    width = var("size", 2) * 2,
+    height = var("size", var("other", 1) + 1),
+}
+    "#;
+    if let Err(err) = manager.load_styles("test", src) {
+        let stdout = std::io::stdout();
+        format_parse_error(stdout.lock(), src.lines(), err).unwrap();
+        panic!("Styles failed to parse");
+    }
+    let with = node!(panel(size=3));
+    let without = node!(panel);
+    manager.add_node(with.clone());
+    manager.add_node(without.clone());
+    manager.layout(20, 20);
+
+    assert_eq!(with.borrow().draw_rect, Rect { x: 0, y: 0, width: 6, height: 3 });
+    assert_eq!(without.borrow().draw_rect, Rect { x: 0, y: 0, width: 4, height: 2 });
+    without.set_property("other", 4);
+    manager.layout(20, 20);
+    assert_eq!(without.borrow().draw_rect, Rect { x: 0, y: 0, width: 4, height: 5 });
+
+    // Setting the property later is picked up
+    without.set_property("size", 1);
+    manager.layout(20, 20);
+    assert_eq!(without.borrow().draw_rect, Rect { x: 0, y: 0, width: 2, height: 1 });
+    assert!(manager.take_errors().is_empty());
+
+    // The default can't use unknown variables
+    assert!(manager.load_styles("bad", r#"panel { width = var("size", missing), }"#).is_err());
+    assert!(manager.load_styles("bad", r#"panel { width = var(size, 1), }"#).is_err());
+    assert!(manager.load_styles("bad", r#"panel { width = var("size"), }"#).is_err());
+}
+
+#[test]
+fn test_func_ctx() {
+    let mut manager: Manager<TestExt> = Manager::new();
+    manager.add_func_ctx("name_len", |node, _args| {
+        Ok(Value::Integer(node.name().map_or(0, |v| v.len() as i32)))
+    });
+    manager.add_func_ctx("parent_prop", |node, args| {
+        let name = match args.next() {
+            Some(Ok(Value::String(name))) => name,
+            _ => return Err(Error::CustomStatic { reason: "Expected a property name" }),
+        };
+        node.parent()
+            .and_then(|p| p.get_property::<Value<TestExt>>(&name))
+            .ok_or(Error::CustomStatic { reason: "Missing property" })
+    });
+    let src = r#"
+panel {
+    width = name_len(),
+}
+panel > longer_panel {
+    width = name_len(),
+    height = parent_prop("size"),
+}
+    "#;
+    if let Err(err) = manager.load_styles("test", src) {
+        let stdout = std::io::stdout();
+        format_parse_error(stdout.lock(), src.lines(), err).unwrap();
+        panic!("Styles failed to parse");
+    }
+    let panel = node! {
+        panel(size=3) {
+            longer_panel
+        }
+    };
+    let inner = panel.first_child().unwrap();
+    manager.add_node(panel.clone());
+    manager.layout(20, 20);
+    assert_eq!(panel.borrow().draw_rect.width, 5);
+    assert_eq!(inner.borrow().draw_rect, Rect { x: 0, y: 0, width: 12, height: 3 });
+    assert!(manager.take_errors().is_empty());
+
+    // Plain functions still work alongside them
+    assert_eq!(manager.evaluate(&inner, "name_len() + max(1, 2)").ok(), Some(Value::Integer(14)));
+}
+
+#[test]
+fn test_has_property_prop_type() {
+    let mut manager: Manager<TestExt> = Manager::new();
+    let src = r#"
+panel {
+    width = if(has_property("w"), 5, 2),
+    height = 1,
+}
+    "#;
+    if let Err(err) = manager.load_styles("test", src) {
+        let stdout = std::io::stdout();
+        format_parse_error(stdout.lock(), src.lines(), err).unwrap();
+        panic!("Styles failed to parse");
+    }
+    let with = node!(panel(w=false));
+    let without = node!(panel);
+    manager.add_node(with.clone());
+    manager.add_node(without.clone());
+    manager.layout(10, 10);
+    // Only whether the property exists matters, not its value
+    assert_eq!(with.borrow().draw_rect.width, 5);
+    assert_eq!(without.borrow().draw_rect.width, 2);
+    assert!(manager.evaluate(&without, r#"prop_type("value")"#).is_err());
+    assert_eq!(manager.evaluate(&without, r#"if(has_property("value"), prop_type("value"), "none")"#).ok(), Some(Value::String("none".into())));
+
+    let mut ty = |v: Value<TestExt>| {
+        let node: Node<TestExt> = node!(panel);
+        node.set_property("value", v);
+        manager.add_node(node.clone());
+        let ty = manager.evaluate(&node, r#"prop_type("value")"#);
+        manager.remove_node(node);
+        match ty {
+            Ok(Value::String(s)) => s,
+            v => panic!("Unexpected result: {:?}", v),
+        }
+    };
+    assert_eq!(ty(Value::Integer(1)), "integer");
+    assert_eq!(ty(Value::Float(1.0)), "float");
+    assert_eq!(ty(Value::Boolean(true)), "boolean");
+    assert_eq!(ty(Value::String("a".into())), "string");
+    assert_eq!(ty(Value::Color(0, 0, 0, 255)), "color");
+    assert_eq!(ty(Value::List(vec![])), "list");
+    assert_eq!(ty(Value::ExtValue(0)), "ext");
+
+    assert!(manager.evaluate(&with, "has_property(5)").is_err());
+}
+
+#[test]
+fn test_string_conversions() {
+    let mut manager: Manager<TestExt> = Manager::new();
+    let node: Node<TestExt> = node!(panel(count="12".to_owned(), scale=" 0.5 ".to_owned()));
+    manager.add_node(node.clone());
+    let eval = |expr| manager.evaluate(&node, expr);
+
+    assert_eq!(eval("str(5)").ok(), Some(Value::String("5".into())));
+    assert_eq!(eval("str(1.5)").ok(), Some(Value::String("1.5".into())));
+    assert_eq!(eval("str(true)").ok(), Some(Value::String("true".into())));
+    assert_eq!(eval("str(#FF0000)").ok(), Some(Value::String("#FF0000FF".into())));
+    assert!(eval("str(list(1))").is_err());
+
+    assert_eq!(eval(r#"to_int("007")"#).ok(), Some(Value::Integer(7)));
+    assert_eq!(eval(r#"to_int("-3")"#).ok(), Some(Value::Integer(-3)));
+    assert_eq!(eval(r#"to_float("2.25")"#).ok(), Some(Value::Float(2.25)));
+    match eval(r#"to_float("x")"#) {
+        Err(Error::Custom { reason }) => assert_eq!(reason, r#""x" isn't a valid float"#),
+        v => panic!("Unexpected result: {:?}", v),
+    }
+    assert!(eval(r#"to_int("1.5")"#).is_err());
+    assert!(eval("to_int(5)").is_err());
+
+    // Properties from a data source arrive as strings
+    let src = r#"
+panel(count=count, scale=scale) {
+    width = to_int(count) + 1,
+    height = int(to_float(scale) * 10.0),
+}
+    "#;
+    if let Err(err) = manager.load_styles("test", src) {
+        let stdout = std::io::stdout();
+        format_parse_error(stdout.lock(), src.lines(), err).unwrap();
+        panic!("Styles failed to parse");
+    }
+    manager.layout(20, 20);
+    assert_eq!(node.borrow().draw_rect, Rect { x: 0, y: 0, width: 13, height: 5 });
+}
+
+#[test]
+fn test_dump_tree() {
+    let mut manager: Manager<TestExt> = Manager::new();
+    let src = r#"
+window {
+    x = 2,
+    y = 1,
+    width = 10,
+    height = 5,
+}
+window > title {
+    width = 8,
+    height = 1,
+}
+    "#;
+    if let Err(err) = manager.load_styles("test", src) {
+        let stdout = std::io::stdout();
+        format_parse_error(stdout.lock(), src.lines(), err).unwrap();
+        panic!("Styles failed to parse");
+    }
+    let window = node! {
+        window(id="main".to_owned(), open=true) {
+            title {
+                @text("A very long title that gets cut short")
+            }
+        }
+    };
+    manager.add_node(window.clone());
+    manager.layout(20, 20);
+
+    let dump = manager.dump_tree();
+    assert_eq!(dump, r#"window(id="main", open=true) [2, 1, 10x5]
+  title [0, 0, 8x1]
+    "A very long title th..." [0, 0, 0x0]
+"#);
+    assert_eq!(window.first_child().unwrap().dump(), r#"title [0, 0, 8x1]
+  "A very long title th..." [0, 0, 0x0]
+"#);
+}
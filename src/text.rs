@@ -0,0 +1,65 @@
+//! Text measurement used by layouts that size or wrap text
+use std::ops::Range;
+
+/// Measures the size of text so layouts can size and wrap
+/// text nodes without depending on a renderer.
+///
+/// Set on a manager via
+/// [`Manager::set_text_measure`](struct.Manager.html#method.set_text_measure)
+/// and accessed by layouts through `ChildAccess`. Without one set
+/// `MonospaceMeasure` is used.
+pub trait TextMeasure {
+    /// Returns the width and height of the text drawn with the
+    /// named font at the given size
+    fn measure(&self, text: &str, font: &str, size: i32) -> (i32, i32);
+
+    /// Splits the text into lines that fit within `width`,
+    /// returning the byte range of each line.
+    ///
+    /// Lines are broken at spaces and always at `\n`. The spaces a
+    /// line is broken at aren't included in either line. A word
+    /// too wide to fit is placed on a line of its own.
+    fn wrap(&self, text: &str, font: &str, size: i32, width: i32) -> Vec<Range<usize>> {
+        let mut lines = Vec::new();
+        let mut line_start = 0;
+        for line in text.split('\n') {
+            let mut current: Option<Range<usize>> = None;
+            let mut pos = line_start;
+            for word in line.split(' ') {
+                let start = pos;
+                pos += word.len() + 1;
+                if word.is_empty() {
+                    continue;
+                }
+                let end = start + word.len();
+                current = Some(match current {
+                    Some(cur) if self.measure(&text[cur.start .. end], font, size).0 <= width => cur.start .. end,
+                    Some(cur) => {
+                        lines.push(cur);
+                        start .. end
+                    },
+                    None => start .. end,
+                });
+            }
+            lines.push(current.unwrap_or(line_start .. line_start));
+            line_start += line.len() + 1;
+        }
+        lines
+    }
+}
+
+/// Estimates the size of text by treating every character as
+/// `size` wide and each line as `size` tall
+#[derive(Debug, Clone, Copy, Default)]
+pub struct MonospaceMeasure;
+
+impl TextMeasure for MonospaceMeasure {
+    fn measure(&self, text: &str, _font: &str, size: i32) -> (i32, i32) {
+        let width = text.lines()
+            .map(|v| v.chars().count() as i32)
+            .max()
+            .unwrap_or(0);
+        let lines = text.lines().count().max(1) as i32;
+        (width * size, lines * size)
+    }
+}
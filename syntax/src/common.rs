@@ -28,7 +28,7 @@ pub(crate) fn parse_bool<'a, I>() -> impl Parser<Input = I, Output = bool>
         I: Debug + Stream<Item=char, Position=SourcePosition, Range = &'a str> + RangeStream + 'a,
         <I as StreamOnce>::Error: combine::ParseError<I::Item, I::Range, I::Position>,
 {
-    try(string("true").map(|_| true))
+    attempt(string("true").map(|_| true))
         .or(string("false").map(|_| false))
 }
 
@@ -45,15 +45,65 @@ pub(crate) fn parse_float<'a, I>() -> impl Parser<Input = I, Output = f64> + 'a
         } ))
 }
 
-pub(crate) fn parse_integer<'a, I>() -> impl Parser<Input = I, Output = i32>
+/// Parses a decimal or `0x` prefixed hexadecimal integer.
+///
+/// Digits may be separated by a single `_`. Once a `0x` prefix has been
+/// parsed missing digits are an error instead of backtracking.
+pub(crate) fn parse_integer<'a, I>() -> impl Parser<Input = I, Output = i32> + 'a
     where
         I: Debug + Stream<Item=char, Position=SourcePosition, Range = &'a str> + RangeStream + 'a,
         <I as StreamOnce>::Error: combine::ParseError<I::Item, I::Range, I::Position>,
 {
-    from_str(
-        Parser::expected(take_while1(|c: char| c.is_digit(10) || c == '-'), "integer")
-    )
+    let hex = (
+        attempt((optional(token('-')), string("0x"))),
+        Parser::expected(take_while1(|c: char| c.is_ascii_hexdigit() || c == '_'), "hexadecimal digits"),
+    ).and_then(|((neg, _), v): (_, &str)| {
+        let v = strip_separators(v, |c| c.is_ascii_hexdigit())
+            .ok_or_else(|| StreamErrorFor::<I>::expected_static_message("hexadecimal integer"))?;
+        let v = if neg.is_some() { format!("-{}", v) } else { v };
+        i32::from_str_radix(&v, 16)
+            .map_err(|_| StreamErrorFor::<I>::expected_static_message("hexadecimal integer"))
+    });
 
+    let decimal = recognize((
+        attempt((optional(token('-')), digit())),
+        skip_many(satisfy(|c: char| c.is_digit(10) || c == '_')),
+    ))
+        .and_then(|v: &str| strip_separators(v, |c| c.is_ascii_digit())
+            .and_then(|v| v.parse::<i32>().ok())
+            .ok_or_else(|| StreamErrorFor::<I>::expected_static_message("integer")));
+
+    hex.or(Parser::expected(decimal, "integer"))
+}
+
+// Removes the `_` separating digits. `None` unless every `_` is
+// between two digits.
+fn strip_separators(v: &str, is_digit: fn(char) -> bool) -> Option<String> {
+    let mut out = String::with_capacity(v.len());
+    let mut prev = None;
+    let mut chars = v.chars().peekable();
+    while let Some(c) = chars.next() {
+        if c == '_' {
+            match (prev, chars.peek()) {
+                (Some(p), Some(&n)) if is_digit(p) && is_digit(n) => {},
+                _ => return None,
+            }
+        } else {
+            out.push(c);
+        }
+        prev = Some(c);
+    }
+    Some(out)
+}
+
+pub(crate) fn parse_color<'a, I>() -> impl Parser<Input = I, Output = (u8, u8, u8, u8)> + 'a
+    where
+        I: Debug + Stream<Item=char, Position=SourcePosition, Range = &'a str> + RangeStream + 'a,
+        <I as StreamOnce>::Error: combine::ParseError<I::Item, I::Range, I::Position>,
+{
+    recognize((token('#'), skip_many1(satisfy(|c: char| c.is_ascii_hexdigit()))))
+        .and_then(|v: &str| ::parse_color(v)
+            .ok_or_else(|| StreamErrorFor::<I>::expected_static_message("color of the form #RRGGBB or #RRGGBBAA")))
 }
 
 pub(crate) fn parse_string<'a, I>() -> impl Parser<Input = I, Output = &'a str>
@@ -64,24 +114,60 @@ pub(crate) fn parse_string<'a, I>() -> impl Parser<Input = I, Output = &'a str>
     (
         token('"'),
         recognize(skip_many(
-            try(string(r#"\""#).map(|_| '"'))
-                .or(try(string(r#"\t"#).map(|_| '\t')))
-                .or(try(string(r#"\n"#).map(|_| '\n')))
-                .or(try(string(r#"\r"#).map(|_| '\r')))
-                .or(try(string(r#"\\"#).map(|_| '\\')))
+            attempt(string(r#"\""#).map(|_| '"'))
+                .or(attempt(string(r#"\t"#).map(|_| '\t')))
+                .or(attempt(string(r#"\n"#).map(|_| '\n')))
+                .or(attempt(string(r#"\r"#).map(|_| '\r')))
+                .or(attempt(string(r#"\\"#).map(|_| '\\')))
                 .or(satisfy(|c| c != '"')),
         )),
         token('"'),
     ).map(|v| v.1)
 }
 
-pub(crate) fn skip_comment<'a, I>() -> impl Parser<Input = I, Output = ()>
+/// Skips whitespace followed by any number of comments
+pub(crate) fn skip_trivia<'a, I>() -> impl Parser<Input = I, Output = ()> + 'a
     where
         I: Debug + Stream<Item=char, Position=SourcePosition, Range = &'a str> + RangeStream + 'a,
         <I as StreamOnce>::Error: combine::ParseError<I::Item, I::Range, I::Position>,
 {
-    string("//")
-        .with(skip_many(satisfy(|c| c != '\n')))
+    spaces().with(skip_many(skip_comment()))
+}
+
+pub(crate) fn skip_comment<'a, I>() -> impl Parser<Input = I, Output = ()> + 'a
+    where
+        I: Debug + Stream<Item=char, Position=SourcePosition, Range = &'a str> + RangeStream + 'a,
+        <I as StreamOnce>::Error: combine::ParseError<I::Item, I::Range, I::Position>,
+{
+    let line = string("//")
+        .with(skip_many(satisfy(|c| c != '\n')));
+
+    attempt(line)
+        .or(parser(block_comment))
         .with(spaces())
         .map(|_| ())
-}
\ No newline at end of file
+}
+
+/// Skips a `/* */` comment, which may contain other
+/// block comments.
+fn block_comment<'a, I>(input: &mut I) -> ParseResult<(), I>
+    where
+        I: Debug + Stream<Item=char, Position=SourcePosition, Range = &'a str> + RangeStream + 'a,
+        <I as StreamOnce>::Error: combine::ParseError<I::Item, I::Range, I::Position>,
+{
+    let ((), mut consumed) = attempt(string("/*")).map(|_| ()).parse_stream(input)?;
+    // Nesting is counted instead of recursing so deeply nested
+    // comments can't overflow the stack
+    let mut depth = 1;
+    while depth > 0 {
+        let (step, c) = attempt(string("/*")).map(|_| 1)
+            .or(attempt(string("*/")).map(|_| -1))
+            .or(any().map(|_| 0))
+            .expected("end of block comment `*/`")
+            .parse_stream(input)
+            .map_err(|err| Consumed::Consumed(err.into_inner()))?;
+        depth += step;
+        consumed = consumed.merge(c);
+    }
+    Ok(((), consumed))
+}
@@ -0,0 +1,193 @@
+//! Limits applied while parsing untrusted documents
+
+use std::cell::RefCell;
+use std::error;
+use std::fmt::{self, Display, Formatter};
+use super::super::{PError, Position};
+
+/// Limits on the size of a document parsed via
+/// [`Document::parse_limited`](struct.Document.html#method.parse_limited).
+///
+/// Used when loading descriptions from untrusted sources
+/// (e.g. mods) to stop a document from using an unbounded
+/// amount of memory or stack.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Limits {
+    /// The maximum number of nodes in the document including
+    /// the root element
+    pub max_nodes: usize,
+    /// The maximum depth of a node, the root element has a
+    /// depth of `1`. Nested comments don't count as they are
+    /// parsed without recursing.
+    pub max_depth: usize,
+    /// The maximum length in bytes of a text node or a string
+    /// value
+    pub max_string_length: usize,
+}
+
+impl Default for Limits {
+    fn default() -> Limits {
+        Limits {
+            max_nodes: 10_000,
+            max_depth: 64,
+            max_string_length: 16 * 1024,
+        }
+    }
+}
+
+/// The limit that stopped a document from being parsed
+#[derive(Debug, Clone, Copy)]
+pub enum LimitExceeded {
+    /// The document has more nodes than `Limits::max_nodes`
+    Nodes {
+        /// The limit that was exceeded
+        max: usize,
+        /// The position of the first node over the limit
+        position: Position,
+    },
+    /// A node is nested deeper than `Limits::max_depth`
+    Depth {
+        /// The limit that was exceeded
+        max: usize,
+        /// The position of the first node over the limit
+        position: Position,
+    },
+    /// A string is longer than `Limits::max_string_length`
+    StringLength {
+        /// The limit that was exceeded
+        max: usize,
+        /// The length of the string
+        length: usize,
+        /// The position of the string
+        position: Position,
+    },
+}
+
+impl LimitExceeded {
+    /// Returns the position in the source the limit was exceeded at
+    pub fn position(&self) -> Position {
+        match *self {
+            LimitExceeded::Nodes { position, .. }
+            | LimitExceeded::Depth { position, .. }
+            | LimitExceeded::StringLength { position, .. } => position,
+        }
+    }
+}
+
+impl Display for LimitExceeded {
+    fn fmt(&self, fmt: &mut Formatter) -> fmt::Result {
+        match *self {
+            LimitExceeded::Nodes { max, position } => write!(fmt, "{}: more than {} nodes", position, max),
+            LimitExceeded::Depth { max, position } => write!(fmt, "{}: nodes nested deeper than {}", position, max),
+            LimitExceeded::StringLength { max, length, position } =>
+                write!(fmt, "{}: string of length {} is longer than {}", position, length, max),
+        }
+    }
+}
+
+impl error::Error for LimitExceeded {}
+
+/// The error returned by
+/// [`Document::parse_limited`](struct.Document.html#method.parse_limited)
+#[derive(Debug)]
+pub enum LimitedParseError<'a> {
+    /// The document contains a syntax error
+    Parse(PError<'a>),
+    /// The document was stopped by one of its limits
+    Limit(LimitExceeded),
+}
+
+impl <'a> From<LimitExceeded> for LimitedParseError<'a> {
+    fn from(v: LimitExceeded) -> LimitedParseError<'a> {
+        LimitedParseError::Limit(v)
+    }
+}
+
+struct Tracker {
+    limits: Limits,
+    nodes: usize,
+    depth: usize,
+    exceeded: Option<LimitExceeded>,
+}
+
+thread_local! {
+    // The limits of the document currently being parsed, if any.
+    // The parsers are built from combinators so there isn't a
+    // place to pass this through them.
+    static TRACKER: RefCell<Option<Tracker>> = const { RefCell::new(None) };
+}
+
+/// Runs the parse with the limits applied, returning the limit
+/// that stopped the parse if there was one
+pub(super) fn with_limits<R, F>(limits: Limits, f: F) -> (R, Option<LimitExceeded>)
+    where F: FnOnce() -> R
+{
+    let previous = TRACKER.with(|t| t.borrow_mut().replace(Tracker {
+        limits,
+        // The root element
+        nodes: 1,
+        depth: 0,
+        exceeded: None,
+    }));
+    let ret = f();
+    let tracker = TRACKER.with(|t| ::std::mem::replace(&mut *t.borrow_mut(), previous));
+    (ret, tracker.and_then(|v| v.exceeded))
+}
+
+fn check<F>(f: F) -> Result<(), LimitExceeded>
+    where F: FnOnce(&mut Tracker) -> Option<LimitExceeded>
+{
+    TRACKER.with(|t| {
+        let mut t = t.borrow_mut();
+        if let Some(t) = t.as_mut() {
+            if let Some(err) = f(t) {
+                t.exceeded = Some(err);
+                return Err(err);
+            }
+        }
+        Ok(())
+    })
+}
+
+/// Marks the start of an element's body
+pub(super) fn enter_body() {
+    let _ = check(|t| {
+        t.depth += 1;
+        None
+    });
+}
+
+/// Marks the end of an element's body
+pub(super) fn leave_body() {
+    let _ = check(|t| {
+        t.depth -= 1;
+        None
+    });
+}
+
+/// Counts a new node within the current body
+pub(super) fn add_node(position: Position) -> Result<(), LimitExceeded> {
+    check(|t| {
+        t.nodes += 1;
+        if t.nodes > t.limits.max_nodes {
+            Some(LimitExceeded::Nodes { max: t.limits.max_nodes, position })
+        } else if t.depth + 1 > t.limits.max_depth {
+            Some(LimitExceeded::Depth { max: t.limits.max_depth, position })
+        } else {
+            None
+        }
+    })
+}
+
+/// Checks the length of a text node or string value
+pub(super) fn check_string(s: &str, position: Position) -> Result<(), LimitExceeded> {
+    check(|t| if s.len() > t.limits.max_string_length {
+        Some(LimitExceeded::StringLength {
+            max: t.limits.max_string_length,
+            length: s.len(),
+            position,
+        })
+    } else {
+        None
+    })
+}
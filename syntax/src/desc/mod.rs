@@ -6,7 +6,7 @@
 //! The format is as follows:
 //!
 //! ```text,ignore
-//! // Comments (only single line)
+//! // Comments, /* block comments */ can also be used
 //!
 //! // Name of an element. Can be made up from any
 //! // letter, number or _
@@ -38,9 +38,13 @@ use combine::error::*;
 use combine::Stream;
 use combine::easy::{ParseError,};
 use combine::stream::state::{State, SourcePosition};
+use combine::stream::StreamErrorFor;
 use super::{Ident, Position};
 use std::fmt::Debug;
 
+mod limits;
+pub use self::limits::{Limits, LimitExceeded, LimitedParseError};
+
 /// A UI description document
 ///
 /// Currently a document is made up of a single element.
@@ -73,6 +77,33 @@ impl <'a> Document<'a> {
         let (doc, _) = parse_document().easy_parse(State::new(source))?;
         Ok(doc)
     }
+
+    /// Attempts to parse the given string as a document while
+    /// enforcing the given limits.
+    ///
+    /// Parsing stops as soon as a limit is exceeded so untrusted
+    /// documents can't build arbitrarily large trees.
+    ///
+    /// # Example
+    ///
+    /// ```
+    /// # use fungui_syntax::desc::*;
+    /// let limits = Limits {
+    ///     max_depth: 2,
+    ///     .. Limits::default()
+    /// };
+    /// assert!(Document::parse_limited("root { panel }", limits).is_ok());
+    /// match Document::parse_limited("root { panel { panel } }", limits) {
+    ///     Err(LimitedParseError::Limit(LimitExceeded::Depth { max: 2, .. })) => {},
+    ///     v => panic!("Unexpected result: {:?}", v),
+    /// }
+    /// ```
+    pub fn parse_limited(source: &str, limits: Limits) -> Result<Document<'_>, LimitedParseError<'_>> {
+        match limits::with_limits(limits, || Document::parse(source)) {
+            (_, Some(err)) => Err(LimitedParseError::Limit(err)),
+            (ret, None) => ret.map_err(LimitedParseError::Parse),
+        }
+    }
 }
 
 /// An element which can contain other elements and/or
@@ -128,6 +159,8 @@ pub enum Value<'a> {
     Float(f64),
     /// A quoted string
     String(&'a str),
+    /// A color of the form `#RRGGBB` or `#RRGGBBAA`
+    Color(u8, u8, u8, u8),
 }
 
 fn parse_document<'a, I>() -> impl Parser<Input = I, Output = Document<'a>>
@@ -172,47 +205,69 @@ fn body<'a, I>(input: &mut I) -> ParseResult<Vec<Node<'a>>, I>
 {
     let (_, _) = char('{').parse_stream(input)?;
 
-    enum Flow<T> {
-        Continue(T),
-        Break,
-    }
+    limits::enter_body();
+    let ret = body_nodes(input);
+    limits::leave_body();
+    ret
+}
 
+fn body_nodes<'a, I>(input: &mut I) -> ParseResult<Vec<Node<'a>>, I>
+    where
+        I: Debug + Stream<Item=char, Position=SourcePosition, Range = &'a str> + RangeStream + 'a,
+        <I as StreamOnce>::Error: combine::ParseError<I::Item, I::Range, I::Position>,
+{
     let mut nodes = Vec::new();
     loop {
-        let (ret, _) = spaces()
+        let (end, _) = spaces()
                 .with(skip_many(skip_comment()))
-                .with(
-                    try(char('}').map(|_| Flow::Break))
-                        .or(
-                            (
-                                position(),
-                                parse_string(),
-                                optional(properties()),
-                            ).map(|v| {
-                                Node::Text(v.1, SourcePosition::into(v.0), v.2.unwrap_or_default())
-                            })
-                            .or(parse_element().map(Node::Element))
-                            .map(|v| Flow::Continue(v))
-                        ),
-                )
+                .with(optional(char('}')))
                 .parse_stream(input)?;
-        if let Flow::Continue(node) = ret {
-            nodes.push(node);
-        } else {
+        if end.is_some() {
             break;
         }
+        // Checked before parsing the node so that deeply nested
+        // nodes are stopped before they are parsed
+        limits::add_node(input.position().into())
+            .map_err(|err| limit_error(input, err))?;
+        let (node, _) = (
+                position(),
+                parse_string(),
+                optional(properties()),
+            ).map(|v| {
+                Node::Text(v.1, SourcePosition::into(v.0), v.2.unwrap_or_default())
+            })
+            .or(parse_element().map(Node::Element))
+            .parse_stream(input)?;
+        if let Node::Text(text, position, _) = node {
+            limits::check_string(text, position)
+                .map_err(|err| limit_error(input, err))?;
+        }
+        nodes.push(node);
     }
     Ok((nodes, Consumed::Consumed(())))
 }
 
+fn limit_error<I>(input: &I, err: LimitExceeded) -> Consumed<Tracked<I::Error>>
+    where
+        I: Stream<Item=char, Position=SourcePosition>,
+        <I as StreamOnce>::Error: combine::ParseError<I::Item, I::Range, I::Position>,
+{
+    let err: I::Error = combine::ParseError::from_error(
+        input.position(),
+        StreamErrorFor::<I>::message_message(err),
+    );
+    Consumed::Consumed(err.into())
+}
+
 fn properties<'a, I>() -> impl Parser<Input = I, Output = FnvHashMap<Ident<'a>, ValueType<'a>>>
     where
         I: Debug + Stream<Item=char, Position=SourcePosition, Range = &'a str> + RangeStream + 'a,
         <I as StreamOnce>::Error: combine::ParseError<I::Item, I::Range, I::Position>,
 {
+    // Comments may follow any property or comma
     (
-        token('('),
-        sep_end_by(property(), token(',')),
+        token('(').skip(skip_trivia()),
+        sep_end_by(property().skip(skip_trivia()), token(',').skip(skip_trivia())),
         spaces().with(token(')')),
     ).map(|(_, l, _)| l)
 }
@@ -224,9 +279,18 @@ fn property<'a, I>() -> impl Parser<Input = I, Output = (Ident<'a>, ValueType<'a
 {
     (
         spaces().with(ident()),
-        spaces().with(token('=')),
-        spaces().with(value()),
-    ).map(|v| (v.0, v.2))
+        optional(
+            attempt(spaces().with(token('=')))
+                .with(spaces().with(value()))
+        ),
+    ).map(|(name, value)| {
+        // A property without a value is shorthand for `name=true`
+        let value = value.unwrap_or(ValueType {
+            value: Value::Boolean(true),
+            position: name.position,
+        });
+        (name, value)
+    })
 }
 
 fn value<'a, I>() -> impl Parser<Input = I, Output = ValueType<'a>>
@@ -239,15 +303,21 @@ fn value<'a, I>() -> impl Parser<Input = I, Output = ValueType<'a>>
     let integer = parse_integer().map(|v| Value::Integer(v));
 
     let string = parse_string().map(|v| Value::String(v));
+    let color = parse_color().map(|v| Value::Color(v.0, v.1, v.2, v.3));
 
     (
         position(),
-        try(boolean).or(try(float)).or(try(integer)).or(string),
-    ).map(|v| {
-            ValueType {
-                value: v.1,
-                position: SourcePosition::into(v.0),
+        attempt(boolean).or(attempt(float)).or(integer).or(color).or(string),
+    ).and_then(|v| {
+            let position = SourcePosition::into(v.0);
+            if let Value::String(s) = v.1 {
+                limits::check_string(s, position)
+                    .map_err(StreamErrorFor::<I>::message_message)?;
             }
+            Ok::<_, StreamErrorFor<I>>(ValueType {
+                value: v.1,
+                position,
+            })
         })
 }
 
@@ -255,6 +325,7 @@ fn value<'a, I>() -> impl Parser<Input = I, Output = ValueType<'a>>
 mod tests {
     use format_parse_error;
     use super::*;
+
     #[test]
     fn test() {
         let source = r#"
@@ -318,4 +389,156 @@ root(
             panic!("Expected error");
         }
     }
+
+    #[test]
+    fn test_block_comments() {
+        let source = r#"
+/* Comment /* nested */ before the root */
+root {
+    /* between
+       nodes */
+    panel
+    "text"
+}
+        "#;
+        let doc = match Document::parse(source) {
+            Ok(doc) => doc,
+            Err(err) => {
+                format_parse_error(::std::io::stdout(), source.lines(), err).unwrap();
+                panic!("^^");
+            }
+        };
+        assert_eq!(doc.root.nodes.len(), 2);
+
+        assert!(Document::parse("root { /* unterminated }").is_err());
+
+        // Deep nesting doesn't overflow the stack
+        let deep = format!("root {{ {}{} panel }}", "/*".repeat(200_000), "*/".repeat(200_000));
+        assert_eq!(Document::parse(&deep).unwrap().root.nodes.len(), 1);
+    }
+
+    #[test]
+    fn test_integer_literals() {
+        let doc = Document::parse("root(hex=0xdead, sep=1_234, neg=-0x10)").unwrap();
+        let prop = |name| match doc.root.properties.iter().find(|v| (v.0).name == name) {
+            Some((_, &ValueType { value: Value::Integer(i), .. })) => i,
+            v => panic!("Unexpected value: {:?}", v),
+        };
+        assert_eq!(prop("hex"), 0xdead);
+        assert_eq!(prop("sep"), 1234);
+        assert_eq!(prop("neg"), -16);
+
+        assert!(Document::parse("root(hex=0x)").is_err());
+        for src in &["5_", "1__2", "0xFF_", "0x_FF"] {
+            assert!(Document::parse(&format!("root(v={})", src)).is_err(), "{} parsed", src);
+        }
+    }
+
+    #[test]
+    fn test_color_literals() {
+        let doc = Document::parse("root(color=#11223344, string=\"#11223344\")").unwrap();
+        let prop = |name| &doc.root.properties.iter()
+            .find(|v| (v.0).name == name)
+            .unwrap().1.value;
+        match prop("color") {
+            Value::Color(r, g, b, a) => assert_eq!((*r, *g, *b, *a), (0x11, 0x22, 0x33, 0x44)),
+            v => panic!("Unexpected value: {:?}", v),
+        }
+        match prop("string") {
+            Value::String(s) => assert_eq!(*s, "#11223344"),
+            v => panic!("Unexpected value: {:?}", v),
+        }
+
+        assert!(Document::parse("root(color=#1122)").is_err());
+    }
+
+    #[test]
+    fn test_boolean_shorthand() {
+        let doc = Document::parse("root(focused, other = false, last) { button(focused) }").unwrap();
+        let prop = |props: &FnvHashMap<Ident, ValueType>, name| match props.iter().find(|v| (v.0).name == name) {
+            Some((_, &ValueType { value: Value::Boolean(b), .. })) => b,
+            v => panic!("Unexpected value: {:?}", v),
+        };
+        assert!(prop(&doc.root.properties, "focused"));
+        assert!(!prop(&doc.root.properties, "other"));
+        assert!(prop(&doc.root.properties, "last"));
+        match doc.root.nodes.first() {
+            Some(Node::Element(e)) => {
+                assert_eq!(e.name.name, "button");
+                assert!(prop(&e.properties, "focused"));
+            },
+            v => panic!("Unexpected node: {:?}", v),
+        }
+    }
+
+    #[test]
+    fn test_trailing_comments() {
+        let source = r#"
+root(
+    width=500, // after the comma
+    height=300 // before the comma
+    ,
+    name="test" /* block */,
+    last // without a comma
+) {
+    panel(x=1 /* inline */, y=2) // after the properties
+    "text"(size=3 // before the bracket
+    )
+}
+        "#;
+        let doc = match Document::parse(source) {
+            Ok(doc) => doc,
+            Err(err) => {
+                format_parse_error(::std::io::stdout(), source.lines(), err).unwrap();
+                panic!("^^");
+            }
+        };
+        assert_eq!(doc.root.properties.len(), 4);
+        assert_eq!(doc.root.nodes.len(), 2);
+        match doc.root.nodes[0] {
+            Node::Element(ref e) => assert_eq!(e.properties.len(), 2),
+            ref v => panic!("Unexpected node: {:?}", v),
+        }
+    }
+
+    #[test]
+    fn test_limits() {
+        let source = r#"
+root(name="ab") {
+    panel {
+        "text"
+        inner
+    }
+    "more"
+}
+        "#;
+        let limits = Limits {
+            max_nodes: 5,
+            max_depth: 3,
+            max_string_length: 4,
+        };
+        let doc = Document::parse_limited(source, limits).unwrap();
+        assert_eq!(doc.root.nodes.len(), 2);
+
+        let check = |limits, expected: &str| match Document::parse_limited(source, limits) {
+            Err(LimitedParseError::Limit(err)) => assert_eq!(err.to_string(), expected),
+            v => panic!("Unexpected result: {:?}", v),
+        };
+        check(Limits { max_nodes: 4, .. limits }, "7:5: more than 4 nodes");
+        check(Limits { max_depth: 2, .. limits }, "4:9: nodes nested deeper than 2");
+        check(Limits { max_string_length: 3, .. limits }, "4:9: string of length 4 is longer than 3");
+        check(Limits { max_string_length: 1, .. limits }, "2:11: string of length 2 is longer than 1");
+
+        match Document::parse_limited("root { panel(", limits) {
+            Err(LimitedParseError::Parse(_)) => {},
+            v => panic!("Unexpected result: {:?}", v),
+        }
+        // Nested comments aren't bounded by the depth limit but
+        // can't overflow the stack either
+        let source = format!("root {{ {}", "/*".repeat(200_000));
+        match Document::parse_limited(&source, Limits::default()) {
+            Err(LimitedParseError::Parse(_)) => {},
+            v => panic!("Unexpected result: {:?}", v),
+        }
+    }
 }
@@ -17,6 +17,29 @@ pub type PError<'a> = ParseError<State<&'a str, SourcePosition>>;
 
 pub use combine::easy::{Errors, Error, Info};
 
+/// Parses a color in the form `#RRGGBB` or `#RRGGBBAA`
+/// into its red, green, blue and alpha channels.
+///
+/// The alpha defaults to `255` when not specified.
+///
+/// ```
+/// # use fungui_syntax::parse_color;
+/// assert_eq!(parse_color("#11223344"), Some((0x11, 0x22, 0x33, 0x44)));
+/// assert_eq!(parse_color("#FF0000"), Some((255, 0, 0, 255)));
+/// assert_eq!(parse_color("red"), None);
+/// ```
+pub fn parse_color(s: &str) -> Option<(u8, u8, u8, u8)> {
+    if !s.starts_with('#') || !s[1..].chars().all(|c| c.is_ascii_hexdigit()) {
+        return None;
+    }
+    let channel = |idx: usize| u8::from_str_radix(&s[1 + idx * 2 .. 3 + idx * 2], 16).ok();
+    match s.len() {
+        7 => Some((channel(0)?, channel(1)?, channel(2)?, 255)),
+        9 => Some((channel(0)?, channel(1)?, channel(2)?, channel(3)?)),
+        _ => None,
+    }
+}
+
 /// An identifier.
 ///
 /// An identifier is made up of either letters, numbers
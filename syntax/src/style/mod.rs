@@ -6,7 +6,7 @@
 //! The format is as follows:
 //!
 //! ```text,ignore
-//! // Comments (only single line)
+//! // Comments, /* block comments */ can also be used
 //!
 //! // Name of an element. Can be made up from any
 //! // letter, number or _
@@ -20,6 +20,21 @@
 //! panel > @text {
 //!     color = "#0050AA",
 //! }
+//! // Separating with whitespace instead of `>` matches
+//! // at any depth
+//! form(valid=false) button {
+//!     color = "#FF0000",
+//! }
+//! // `!important` styles override styles from any
+//! // other rule
+//! dialog > button {
+//!     width = 100 !important,
+//! }
+//! // Properties can also be compared with `!=`, `<`,
+//! // `<=`, `>` and `>=` or required to be unset with `!`
+//! slider(value>50, !disabled) {
+//!     color = "#00FF00",
+//! }
 //! ```
 
 use fnv::FnvHashMap;
@@ -27,6 +42,7 @@ use common::*;
 
 use combine::*;
 use combine::parser::char::*;
+use combine::parser::range::{take_while1, recognize};
 use combine::error::*;
 use combine::Stream;
 use combine::easy::{ParseError,};
@@ -68,8 +84,55 @@ impl <'a> Document<'a> {
 
 #[derive(Debug, Clone)]
 pub struct Rule<'a> {
-    pub matchers: Vec<(Matcher<'a>, FnvHashMap<Ident<'a>, ValueType<'a>>)>,
+    pub matchers: Vec<(Matcher<'a>, Vec<PropertyMatcher<'a>>)>,
+    /// How each matcher relates to the one before it.
+    ///
+    /// `combinators[i]` is between `matchers[i]` and `matchers[i + 1]`
+    pub combinators: Vec<Combinator>,
     pub styles: FnvHashMap<Ident<'a>, ExprType<'a>>,
+    /// Styles marked with `!important` which take priority
+    /// over styles from any other rule
+    pub important: FnvHashMap<Ident<'a>, ExprType<'a>>,
+}
+
+/// The relationship between two matchers in a rule
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Combinator {
+    /// `a > b`, `b` must be a direct child of `a`
+    Child,
+    /// `a b`, `b` may be at any depth below `a`
+    Descendant,
+}
+
+/// A check against a property of a matched node
+#[derive(Debug, Clone)]
+pub struct PropertyMatcher<'a> {
+    /// The name of the property
+    pub name: Ident<'a>,
+    /// How the property is compared
+    pub op: MatchOp,
+    /// The value to compare against, `None` for
+    /// `MatchOp::Absent`
+    pub value: Option<ValueType<'a>>,
+}
+
+/// The comparison used by a `PropertyMatcher`
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum MatchOp {
+    /// `name=value`
+    Equal,
+    /// `name!=value`
+    NotEqual,
+    /// `name>value`
+    Greater,
+    /// `name>=value`
+    GreaterEqual,
+    /// `name<value`
+    Less,
+    /// `name<=value`
+    LessEqual,
+    /// `!name`, the property must not be set
+    Absent,
 }
 
 #[derive(Debug, Clone)]
@@ -113,8 +176,15 @@ pub enum Value<'a> {
     Float(f64),
     /// A quoted string
     String(&'a str),
+    /// A color of the form `#RRGGBB` or `#RRGGBBAA`
+    Color(u8, u8, u8, u8),
     /// A variable name
     Variable(Ident<'a>),
+    /// A variable read from an ancestor of the node.
+    ///
+    /// Written as `parent.name` for a depth of `1` or as
+    /// `ancestor(depth).name`. Only valid in expressions.
+    ParentVariable(usize, Ident<'a>),
 }
 
 #[derive(Debug, Clone)]
@@ -127,6 +197,26 @@ pub struct ExprType<'a> {
     pub position: Position,
 }
 
+impl <'a> ExprType<'a> {
+    /// Attempts to parse the given string as a single expression
+    /// in the same format as the value of a style property.
+    ///
+    /// # Example
+    ///
+    /// ```
+    /// # use fungui_syntax::style::ExprType;
+    /// assert!(ExprType::parse("parent_width / 2 + max(5, 6)").is_ok());
+    /// assert!(ExprType::parse("5 +").is_err());
+    /// ```
+    pub fn parse(source: &'a str) -> Result<ExprType<'a>, ParseError<State<&'a str, SourcePosition>>> {
+        let (expr, _) = parser(expr)
+            .skip(spaces())
+            .skip(eof())
+            .easy_parse(State::new(source))?;
+        Ok(expr)
+    }
+}
+
 #[derive(Debug, Clone)]
 pub enum Expr<'a> {
     Value(Value<'a>),
@@ -174,24 +264,39 @@ fn parse_rule<'a, I>() -> impl Parser<Input = I, Output = Rule<'a>>
 {
     let comments = skip_many(skip_comment());
 
-    let matcher = (
-        try(spaces().with(string("@text").map(|_| Matcher::Text)))
+    let matcher = || (
+        attempt(spaces().with(string("@text").map(|_| Matcher::Text)))
             .or(parse_element().map(|v| Matcher::Element(v))),
         optional(properties()).map(|v| v.unwrap_or_default()),
     );
 
+    let combinator = attempt(spaces().with(token('>')).map(|_| Combinator::Child))
+        .or(attempt(skip_many1(space())
+            .skip(look_ahead(letter().or(char('_')).or(char('@'))))
+            .map(|_| Combinator::Descendant)));
+
     let rule = (
-        sep_by1(try(matcher), try(spaces().with(token('>')))),
+        attempt(matcher()),
+        many::<Vec<_>, _>((combinator, attempt(matcher()))),
         spaces().with(parser(styles)),
     );
 
     spaces()
         .with(comments)
         .with(rule)
-        .map(|v| {
+        .map(|(first, rest, (styles, important))| {
+            let mut matchers = Vec::with_capacity(rest.len() + 1);
+            let mut combinators = Vec::with_capacity(rest.len());
+            matchers.push(first);
+            for (c, m) in rest {
+                combinators.push(c);
+                matchers.push(m);
+            }
             Rule {
-                matchers: v.0,
-                styles: v.1,
+                matchers,
+                combinators,
+                styles,
+                important,
             }
         })
 }
@@ -211,7 +316,9 @@ fn parse_element<'a, I>() -> impl Parser<Input = I, Output = Element<'a>>
         .map(|v| Element { name: v })
 }
 
-fn styles<'a, I>(input: &mut I) -> ParseResult<FnvHashMap<Ident<'a>, ExprType<'a>>, I>
+type StyleMaps<'a> = (FnvHashMap<Ident<'a>, ExprType<'a>>, FnvHashMap<Ident<'a>, ExprType<'a>>);
+
+fn styles<'a, I>(input: &mut I) -> ParseResult<StyleMaps<'a>, I>
     where
         I: Debug + Stream<Item=char, Position=SourcePosition, Range = &'a str> + RangeStream + 'a,
         <I as StreamOnce>::Error: combine::ParseError<I::Item, I::Range, I::Position>,
@@ -224,12 +331,14 @@ fn styles<'a, I>(input: &mut I) -> ParseResult<FnvHashMap<Ident<'a>, ExprType<'a
     }
 
     let mut styles = FnvHashMap::default();
+    let mut important = FnvHashMap::default();
     loop {
-        let prop = (style_property(), optional(token(',')));
+        // Comments may sit between the value and the comma
+        let prop = (style_property(), skip_trivia(), optional(token(',')));
         let (ret, _) = spaces()
                 .with(skip_many(skip_comment()))
                 .with(
-                    try(char('}').map(|_| Flow::Break))
+                    attempt(char('}').map(|_| Flow::Break))
                         .or(
                             prop
                             .map(|v| Flow::Continue(v.0))
@@ -237,24 +346,32 @@ fn styles<'a, I>(input: &mut I) -> ParseResult<FnvHashMap<Ident<'a>, ExprType<'a
                 )
                 .parse_stream(input)?;
         if let Flow::Continue(s) = ret {
-            styles.insert(s.0, s.1);
+            if s.2 {
+                styles.remove(&s.0);
+                important.insert(s.0, s.1);
+            } else {
+                important.remove(&s.0);
+                styles.insert(s.0, s.1);
+            }
         } else {
             break;
         }
     }
-    Ok((styles, Consumed::Consumed(())))
+    Ok(((styles, important), Consumed::Consumed(())))
 }
 
-fn style_property<'a, I>() -> impl Parser<Input = I, Output = (Ident<'a>, ExprType<'a>)>
+fn style_property<'a, I>() -> impl Parser<Input = I, Output = (Ident<'a>, ExprType<'a>, bool)>
     where
         I: Debug + Stream<Item=char, Position=SourcePosition, Range = &'a str> + RangeStream + 'a,
         <I as StreamOnce>::Error: combine::ParseError<I::Item, I::Range, I::Position>,
 {
+    let important = attempt(spaces().with(token('!')).with(spaces()).with(string("important")));
     (
         spaces().with(ident()),
         spaces().with(token('=')),
         spaces().with(parser(expr)),
-    ).map(|v| (v.0, v.2))
+        optional(important),
+    ).map(|v| (v.0, v.2, v.3.is_some()))
 }
 
 fn expr<'a, I>(input: &mut I) -> ParseResult<ExprType<'a>, I>
@@ -391,7 +508,9 @@ fn term2<'a, I>(input: &mut I) -> ParseResult<ExprType<'a>, I>
         .parse_stream(input)?;
 
     loop {
-        let (op, _) = match (position(), choice((char('*'), char('/'), char('%'))))
+        // `//` and `/*` start a comment instead of a division
+        let div = attempt(char('/').skip(not_followed_by(one_of("/*".chars()))));
+        let (op, _) = match (position(), choice((char('*'), div, char('%'))))
             .skip(skip_spaces())
             .parse_stream(input)
         {
@@ -435,6 +554,17 @@ fn factor<'a, I>() -> impl Parser<Input = I, Output = ExprType<'a>>
         .skip(char(')'))
     ).map(|v| Expr::Call(v.0, v.1));
 
+    let parent_variable = (
+        string("parent").map(|_| 1)
+            .or((
+                string("ancestor").skip(skip_spaces()),
+                char('(').skip(skip_spaces()),
+                from_str(take_while1(|c: char| c.is_ascii_digit())).skip(skip_spaces()),
+                char(')'),
+            ).map(|v| v.2)),
+        char('.').with(ident()),
+    ).map(|(depth, name)| Expr::Value(Value::ParentVariable(depth, name)));
+
     let float_to_int = string("int")
         .expected("int cast")
         .skip(string("("))
@@ -468,6 +598,7 @@ fn factor<'a, I>() -> impl Parser<Input = I, Output = ExprType<'a>>
             attempt(float_to_int),
             attempt(int_to_float),
             attempt(brackets.map(|v| v.expr)),
+            attempt(parent_variable),
             attempt(call),
             attempt(value().map(|v| Expr::Value(v.value))),
             attempt(not),
@@ -479,28 +610,70 @@ fn factor<'a, I>() -> impl Parser<Input = I, Output = ExprType<'a>>
     })
 }
 
-fn properties<'a, I>() -> impl Parser<Input = I, Output = FnvHashMap<Ident<'a>, ValueType<'a>>>
+fn properties<'a, I>() -> impl Parser<Input = I, Output = Vec<PropertyMatcher<'a>>>
     where
         I: Debug + Stream<Item=char, Position=SourcePosition, Range = &'a str> + RangeStream + 'a,
         <I as StreamOnce>::Error: combine::ParseError<I::Item, I::Range, I::Position>,
 {
+    // Comments may follow any property or comma
     (
-        token('('),
-        sep_end_by(property(), token(',')),
+        token('(').skip(skip_trivia()),
+        sep_end_by(property().skip(skip_trivia()), token(',').skip(skip_trivia())),
         spaces().with(token(')')),
     ).map(|(_, l, _)| l)
 }
 
-fn property<'a, I>() -> impl Parser<Input = I, Output = (Ident<'a>, ValueType<'a>)>
+fn property<'a, I>() -> impl Parser<Input = I, Output = PropertyMatcher<'a>>
     where
         I: Debug + Stream<Item=char, Position=SourcePosition, Range = &'a str> + RangeStream + 'a,
         <I as StreamOnce>::Error: combine::ParseError<I::Item, I::Range, I::Position>,
 {
-    (
-        spaces().with(ident()),
-        spaces().with(token('=')),
+    let absent = token('!')
+        .with(spaces().with(property_name()))
+        .map(|name| PropertyMatcher {
+            name,
+            op: MatchOp::Absent,
+            value: None,
+        });
+    let op = choice((
+        attempt(string("!=")).map(|_| MatchOp::NotEqual),
+        attempt(string(">=")).map(|_| MatchOp::GreaterEqual),
+        attempt(string("<=")).map(|_| MatchOp::LessEqual),
+        token('=').map(|_| MatchOp::Equal),
+        token('>').map(|_| MatchOp::Greater),
+        token('<').map(|_| MatchOp::Less),
+    ));
+    let compare = (
+        property_name(),
+        spaces().with(op),
         spaces().with(value()),
-    ).map(|v| (v.0, v.2))
+    ).map(|(name, op, value)| PropertyMatcher {
+        name,
+        op,
+        value: Some(value),
+    });
+    spaces().with(absent.or(compare))
+}
+
+// Like `ident` but also accepts a leading `$` so rules can match
+// the state properties set by the manager (e.g. `$hover`)
+fn property_name<'a, I>() -> impl Parser<Input = I, Output = Ident<'a>>
+    where
+        I: Debug + Stream<Item=char, Position=SourcePosition, Range = &'a str> + RangeStream + 'a,
+        <I as StreamOnce>::Error: combine::ParseError<I::Item, I::Range, I::Position>,
+{
+    (
+        position(),
+        recognize((
+            optional(token('$')),
+            take_while1(|c: char| c.is_alphanumeric() || c == '_'),
+        )),
+    ).map(|(pos, name): (_, &str)| {
+        Ident {
+            name,
+            position: SourcePosition::into(pos),
+        }
+    })
 }
 
 fn value<'a, I>() -> impl Parser<Input = I, Output = ValueType<'a>>
@@ -513,15 +686,17 @@ fn value<'a, I>() -> impl Parser<Input = I, Output = ValueType<'a>>
     let integer = parse_integer().map(|v| Value::Integer(v));
 
     let string = parse_string().map(|v| Value::String(v));
+    let color = parse_color().map(|v| Value::Color(v.0, v.1, v.2, v.3));
 
     let variable = ident().map(|v| Value::Variable(v));
 
     (
         position(),
-        try(boolean)
-            .or(try(float))
-            .or(try(integer))
-            .or(try(variable))
+        attempt(boolean)
+            .or(attempt(float))
+            .or(integer)
+            .or(color)
+            .or(attempt(variable))
             .or(string),
     ).map(|v| {
             ValueType {
@@ -535,6 +710,26 @@ fn value<'a, I>() -> impl Parser<Input = I, Output = ValueType<'a>>
 mod tests {
     use format_parse_error;
     use super::*;
+
+    #[test]
+    fn test_parse_expr() {
+        let expr = ExprType::parse(" width + 6 * 2 ").unwrap();
+        match expr.expr {
+            Expr::Add(ref l, ref r) => {
+                match l.expr {
+                    Expr::Value(Value::Variable(ref v)) => assert_eq!(v.name, "width"),
+                    ref e => panic!("Unexpected expression: {:?}", e),
+                }
+                match r.expr {
+                    Expr::Mul(..) => {},
+                    ref e => panic!("Unexpected expression: {:?}", e),
+                }
+            }
+            ref e => panic!("Unexpected expression: {:?}", e),
+        }
+        assert!(ExprType::parse("width + 6 }").is_err());
+    }
+
     #[test]
     fn test() {
         let source = r##"
@@ -568,4 +763,216 @@ panel > @text {
             panic!("^^");
         }
     }
+
+    #[test]
+    fn test_block_comments() {
+        let source = r##"
+/* A block comment
+   over multiple lines */
+panel {
+    /* before a property */
+    width = 5,
+}
+/* Block comments /* can be nested */
+   and continue after */
+panel > @text {
+    color = "#0050AA",
+}
+        "##;
+        let doc = match Document::parse(source) {
+            Ok(doc) => doc,
+            Err(err) => {
+                format_parse_error(::std::io::stdout(), source.lines(), err).unwrap();
+                panic!("^^");
+            }
+        };
+        assert_eq!(doc.rules.len(), 2);
+        assert_eq!(doc.rules[0].styles.len(), 1);
+
+        let source = r##"
+/* Never /* closed */
+panel {
+    width = 5,
+}
+        "##;
+        let err = match Document::parse(source) {
+            Ok(_) => panic!("Expected error"),
+            Err(err) => err,
+        };
+        let mut out: Vec<u8> = Vec::new();
+        format_parse_error(&mut out, source.lines(), err).unwrap();
+        let out = String::from_utf8_lossy(&out);
+        assert!(out.contains("end of block comment `*/`"), "{}", out);
+
+        // Deep nesting doesn't overflow the stack
+        let deep = format!("{}{}\npanel {{ width = 5, }}", "/*".repeat(200_000), "*/".repeat(200_000));
+        assert_eq!(Document::parse(&deep).unwrap().rules.len(), 1);
+        assert!(Document::parse(&"/*".repeat(200_000)).is_err());
+    }
+
+    #[test]
+    fn test_integer_literals() {
+        let int = |src| match ExprType::parse(src).map(|v| v.expr) {
+            Ok(Expr::Value(Value::Integer(i))) => i,
+            Ok(e) => panic!("Unexpected expression: {:?}", e),
+            Err(err) => panic!("Failed to parse {:?}: {}", src, err),
+        };
+        assert_eq!(int("0xdead"), 0xdead);
+        assert_eq!(int("0xFF_00"), 0xFF00);
+        assert_eq!(int("1_234"), 1234);
+        assert_eq!(int("-0x10"), -16);
+        assert_eq!(int("-5"), -5);
+
+        assert!(ExprType::parse("0x").is_err());
+        assert!(Document::parse("panel { width = 0x, }").is_err());
+        // Separators only go between two digits
+        for src in &["5_", "1__2", "1_-2", "0x_FF", "0xFF_", "0xF__F"] {
+            let doc = format!("panel {{ width = {}, }}", src);
+            assert!(Document::parse(&doc).is_err(), "{} parsed", src);
+        }
+    }
+
+    #[test]
+    fn test_color_literals() {
+        let color = |src| match ExprType::parse(src).map(|v| v.expr) {
+            Ok(Expr::Value(Value::Color(r, g, b, a))) => (r, g, b, a),
+            Ok(e) => panic!("Unexpected expression: {:?}", e),
+            Err(err) => panic!("Failed to parse {:?}: {}", src, err),
+        };
+        assert_eq!(color("#11223344"), (0x11, 0x22, 0x33, 0x44));
+        assert_eq!(color("#ff8000"), (0xFF, 0x80, 0x00, 0xFF));
+
+        assert!(ExprType::parse("#12345").is_err());
+        assert!(Document::parse("panel { color = #xyzxyz, }").is_err());
+    }
+
+    #[test]
+    fn test_property_operators() {
+        let doc = Document::parse(r#"
+button(enabled!=true, value>50, low<=1.5, !hidden, a>=-2, b<3, c=x) {
+    width = 1,
+}
+"#).unwrap();
+        let props = &doc.rules[0].matchers[0].1;
+        let ops: Vec<_> = props.iter()
+            .map(|v| (v.name.name, v.op))
+            .collect();
+        assert_eq!(ops, vec![
+            ("enabled", MatchOp::NotEqual),
+            ("value", MatchOp::Greater),
+            ("low", MatchOp::LessEqual),
+            ("hidden", MatchOp::Absent),
+            ("a", MatchOp::GreaterEqual),
+            ("b", MatchOp::Less),
+            ("c", MatchOp::Equal),
+        ]);
+        assert!(props[3].value.is_none());
+        match props[4].value {
+            Some(ValueType { value: Value::Integer(-2), .. }) => {},
+            ref v => panic!("Unexpected value: {:?}", v),
+        }
+
+        assert!(Document::parse("button(value=>1) { width = 1, }").is_err());
+        assert!(Document::parse("button(!value=1) { width = 1, }").is_err());
+    }
+
+    #[test]
+    fn test_state_properties() {
+        let doc = Document::parse(r#"
+button($hover=true, !$focus) {
+    width = 1,
+}
+"#).unwrap();
+        let names: Vec<_> = doc.rules[0].matchers[0].1.iter()
+            .map(|v| v.name.name)
+            .collect();
+        assert_eq!(names, vec!["$hover", "$focus"]);
+
+        // Only matchers accept the `$` prefix
+        assert!(Document::parse("button { $hover = 1, }").is_err());
+        assert!(Document::parse("button(hover=$focus) { width = 1, }").is_err());
+    }
+
+    #[test]
+    fn test_important() {
+        let doc = Document::parse(r#"
+panel {
+    width = 5 + 1 !important,
+    height = x != 2,
+    x = 3 ! important,
+    x = 4,
+}
+"#).unwrap();
+        let rule = &doc.rules[0];
+        fn keys<'a>(map: &FnvHashMap<Ident<'a>, ExprType<'a>>) -> Vec<&'a str> {
+            let mut keys: Vec<_> = map.keys().map(|v| v.name).collect();
+            keys.sort();
+            keys
+        }
+        assert_eq!(keys(&rule.important), vec!["width"]);
+        assert_eq!(keys(&rule.styles), vec!["height", "x"]);
+
+        assert!(Document::parse("panel { width = 5 !imp, }").is_err());
+    }
+
+    #[test]
+    fn test_trailing_comments() {
+        let source = r#"
+panel(x=1, // after a matcher
+      y=2 /* before the comma */, z=3) {
+    width = 5, // after the comma
+    height = 10 / 2 // before the comma
+    ,
+    x = 3 /* block */,
+    y = 4 !important // after important
+    z = 6 // without a comma
+}
+"#;
+        let doc = match Document::parse(source) {
+            Ok(doc) => doc,
+            Err(err) => {
+                format_parse_error(::std::io::stdout(), source.lines(), err).unwrap();
+                panic!("^^");
+            }
+        };
+        let rule = &doc.rules[0];
+        assert_eq!(rule.matchers[0].1.len(), 3);
+        let mut keys: Vec<_> = rule.styles.keys().map(|v| v.name).collect();
+        keys.sort();
+        assert_eq!(keys, vec!["height", "width", "x", "z"]);
+        match rule.styles.iter().find(|v| v.0.name == "height").unwrap().1.expr {
+            Expr::Div(..) => {},
+            ref e => panic!("Unexpected expression: {:?}", e),
+        }
+        assert_eq!(rule.important.len(), 1);
+    }
+
+    #[test]
+    fn test_parent_variables() {
+        let var = |src| match ExprType::parse(src).map(|v| v.expr) {
+            Ok(Expr::Value(Value::ParentVariable(depth, ref v))) => (depth, v.name.to_owned()),
+            Ok(e) => panic!("Unexpected expression: {:?}", e),
+            Err(err) => panic!("Failed to parse {:?}: {}", src, err),
+        };
+        assert_eq!(var("parent.width"), (1, "width".to_owned()));
+        assert_eq!(var("ancestor(2).value"), (2, "value".to_owned()));
+        assert_eq!(var("ancestor( 3 ).value"), (3, "value".to_owned()));
+
+        // Without a property these are still plain variables and calls
+        match ExprType::parse("parent + parent_width").map(|v| v.expr) {
+            Ok(Expr::Add(ref l, ref r)) => match (&l.expr, &r.expr) {
+                (Expr::Value(Value::Variable(l)), Expr::Value(Value::Variable(r))) => {
+                    assert_eq!(l.name, "parent");
+                    assert_eq!(r.name, "parent_width");
+                },
+                e => panic!("Unexpected expression: {:?}", e),
+            },
+            e => panic!("Unexpected expression: {:?}", e),
+        }
+        match ExprType::parse("ancestor(2)").map(|v| v.expr) {
+            Ok(Expr::Call(ref name, _)) => assert_eq!(name.name, "ancestor"),
+            e => panic!("Unexpected expression: {:?}", e),
+        }
+        assert!(ExprType::parse("parent.").is_err());
+    }
 }